@@ -34,11 +34,45 @@ fn main() {
             argument provided to it and executed in an interactive environment(a shell WILL be opened after they \
             are done executing).\n")
         ])
+        .text([
+            bold("-r, --restricted"), roman("\tRun in restricted mode, which is also used when the shell is invoked \
+            as rsesh. Changing directories, changing PATH, SHELL, ENV, HISTFILE, TMOUT, AUDIT_LOG or PAGER, \
+            running programs by path, sourcing files by path, redirecting output and loading plugins are all \
+            refused, and help isn't paged.\n")
+        ])
+        .text([
+            bold("--profile-startup"), roman("\tTime each phase of startup, such as loading plugins, running \
+            ~/.seshrc and reading the history, along with each statement evaluated during them, and print a \
+            breakdown to standard error before the first prompt.\n")
+        ])
+        .text([
+            bold("--resume "), italic("[name]"), roman("\tRestore a saved session, its working directory, \
+            directory stack, variables, aliases and focus, once ~/.seshrc has run. Without a name, restore \
+            the session last, which interactive shells keep up to date before every prompt.\n")
+        ])
+        .text([
+            bold("--completions "), italic("shell"), roman("\tPrint a completion script for the options of \
+            sesh to standard output and exit. The shell may be bash, zsh, fish, elvish or powershell.\n")
+        ])
+        .text([
+            bold("--list-builtins [--json]"), roman("\tPrint the names of the builtins one per line and exit. With \
+            --json, print a JSON array of objects with the name, usage and description of each instead.\n")
+        ])
+        .text([
+            bold("--no-greeting"), roman("\tDon't print the greeting before the first prompt. The greeting is the \
+            GREETING variable, which supports the same escapes as prompts, followed by a summary of loaded plugins and \
+            restricted mode.\n")
+        ])
+        .text([
+            bold("--no-rc"), roman("\tDon't run ~/.seshrc. Background jobs are run this way, with the variables and \
+            aliases of the shell that started them instead.\n")
+        ])
         .control("SH", ["ARGUMENTS"])
         .text(
             [
                 roman("If arguments remain after option processing and neither -c nor -b have been supplied, \
-                the first argument is assumed to be the name of a shell file.")
+                the first argument is assumed to be the name of a shell file. Any arguments after it are passed to \
+                the script in the variables $1 and up. Scripts that aren't valid UTF-8 are read as latin-1.")
             ]
         )
         .control("SH", ["FILES"])
@@ -54,21 +88,173 @@ fn main() {
             [bold(".sesh_history"), roman(" - Contains commands previously ran, one per line. \
                 Read upon startup in an interactive shell and written to after each command.\n")]
         )
+        .text(
+            [bold(".config/sesh/plugins/"), roman(" - Plugins loaded upon startup. Shared libraries (.so) run \
+                unrestricted; WASM modules (.wasm) may only use the capabilities listed in a matching .allow file.\n")]
+        )
+        .text(
+            [bold(".local/state/sesh/sessions/"), roman(" - Sessions saved with the session builtin, one JSON \
+                file each. Secret variables are left out.\n")]
+        )
+        .text(
+            [bold(".local/state/sesh/trusted"), roman(" - The project configs trusted with the trust builtin, \
+                one fingerprint and project root per line.\n")]
+        )
+        .text(
+            [bold(".sesh/config.sesh"), roman(" - In the root of a project, executed when entering it once it's \
+                trusted. The aliases and variables it set are put back when leaving the project.\n")]
+        )
+        .text(
+            [bold(".config/sesh/locale/"), roman(" - Message catalogs. Messages are translated using the JSON \
+                object from English messages to translations in the file named after the language in $LC_ALL, \
+                $LC_MESSAGES or $LANG, such as de_DE.json or de.json.\n")]
+        )
+        .text(
+            [bold("$AUDIT_LOG"), roman(" - If set, every command run is appended to this file as a line of JSON \
+                with the time, user, working directory, expanded command, status and duration. Set it to syslog to \
+                send the records to syslog instead.\n")]
+        )
+        .text(
+            [bold("$AUTOPAIR"), roman(" - If set to true, typing an opening bracket or quote also inserts the \
+                closing one, typing the closing one when it's next skips over it and deleting the opening one of an \
+                empty pair deletes both.\n")]
+        )
+        .text(
+            [bold("$CD_PHYSICAL"), roman(" - If set to true, cd resolves symlinks in the new directory, like cd -P, \
+                instead of keeping them in PWD and the prompt.\n")]
+        )
+        .text(
+            [bold("$CD_SUMMARY"), roman(" - If set to true, summary shows the new directory after every cd, pushd \
+                and popd at the prompt. Set to anything else, that statement is run instead.\n")]
+        )
+        .text(
+            [bold("$CHECK_JOBS"), roman(" - If set to false, exit and Ctrl-D end running and stopped jobs straight \
+                away instead of warning about them first.\n")]
+        )
+        .text(
+            [bold("$HISTDEDUP"), roman(" - If set to true, a line the same as the one before it isn't added to \
+                the history again, and when the shell exits, ~/.sesh_history loses entries other sessions \
+                added right after the same one.\n")]
+        )
+        .text(
+            [bold("$HISTFILESIZE"), roman(" - The most entries ~/.sesh_history keeps, with the oldest dropped when \
+                the shell exits. If unset, it grows without limit.\n")]
+        )
+        .text(
+            [bold("$HISTIGNORESPACE"), roman(" - If set to true, lines starting with a space aren't added to the \
+                history.\n")]
+        )
+        .text(
+            [bold("$HISTSIZE"), roman(" - The most entries the history of the shell keeps. The oldest go first. If \
+                unset, it grows without limit.\n")]
+        )
+        .text(
+            [bold("$HISTTIMESTAMPS"), roman(" - If set to true, when each line is run is written down in the \
+                history, and history shows it.\n")]
+        )
+        .text(
+            [bold("$IGNOREEOF"), roman(" - If set to true, Ctrl-D on an empty line doesn't exit the shell.\n")]
+        )
+        .text(
+            [bold("$NOCLOBBER"), roman(" - If set to true, redirecting with from@>path refuses to overwrite a \
+                file that's already there. from@>|path overwrites it anyway.\n")]
+        )
+        .text(
+            [bold("$PRECMD_TIMEOUT"), roman(" - How long the precmd hooks may run before they're stopped and the \
+                prompt is marked instead, as a delay like 1s or 1m30s. Defaults to 2s.\n")]
+        )
+        .text(
+            [bold("$PROMPT_CHECK"), roman(" - If set to true, the last character of the prompt is coloured while \
+                typing: green if the line parses and its commands are found, yellow if a bracket or quote is still \
+                open and red otherwise.\n")]
+        )
+        .text(
+            [bold("$RUSAGE"), roman(" - If set to true, the CPU time and memory each program run in the foreground \
+                used are put in $RUSAGE_USER and $RUSAGE_SYSTEM, in seconds, and $RUSAGE_MAXRSS, in KiB. If set to \
+                report, they're also printed along with its status once it exits.\n")]
+        )
+        .text(
+            [bold("$TMOUT"), roman(" - If set to a number of seconds or a delay like 15m, the shell exits after \
+                waiting that long at the prompt without any input. It warns first, when a tenth of the time or 10 \
+                seconds are left, whichever is less. Restricted shells can't change it.\n")]
+        )
         .text(
             [bold("Other files"), roman(" - Scripts may write to files via other methods, \
             including outside tools. Scripts may be read from the path in the first argument of the shell after options.")]
         )
         .render();
-    std::fs::write(
-        PathBuf::from(env::var_os("OUT_DIR").unwrap())
-            .parent()
-            .unwrap()
-            .parent()
-            .unwrap()
-            .parent()
-            .unwrap()
-            .join("sesh.1"),
-        page,
-    )
-    .unwrap();
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap())
+        .parent()
+        .unwrap()
+        .parent()
+        .unwrap()
+        .parent()
+        .unwrap()
+        .to_path_buf();
+    std::fs::write(out_dir.join("sesh.1"), page).unwrap();
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/builtins.rs");
+    let mut builtins = read_builtins(&std::fs::read_to_string("src/builtins.rs").unwrap());
+    builtins.sort();
+    let mut page = Roff::new();
+    page.control("TH", ["SESH-BUILTINS", "7"])
+        .control("SH", ["NAME"])
+        .text([roman("sesh-builtins - commands built into sesh")])
+        .control("SH", ["DESCRIPTION"])
+        .text([
+            roman("These commands are run by "),
+            bold("sesh"),
+            roman(" itself rather than as programs. Plugins may add more; "),
+            bold("help"),
+            roman(" lists them too."),
+        ])
+        .control("SH", ["BUILTINS"]);
+    for (name, usage, description) in builtins {
+        if name == "gay" {
+            continue;
+        }
+        page.control("TP", [] as [&str; 0])
+            .text([bold(name), roman(" "), italic(usage)])
+            .text([roman(description)]);
+    }
+    page.control("SH", ["SEE ALSO"])
+        .text([bold("sesh"), roman("(1)")]);
+    std::fs::write(out_dir.join("sesh-builtins.7"), page.render()).unwrap();
+}
+
+/// Pull the name, usage and description of each builtin out of the BUILTINS table, which has
+/// exactly three string literals per entry.
+fn read_builtins(source: &str) -> Vec<(String, String, String)> {
+    let table = &source[source.find("pub const BUILTINS").unwrap()..];
+    let table = &table[table.find("] = [").unwrap()..table.find("\n];").unwrap()];
+    let mut strings = Vec::new();
+    let mut chars = table.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '"' {
+            continue;
+        }
+        let mut string = String::new();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '"' => break,
+                '\\' => match chars.next() {
+                    // a line continuation skips the newline and the indentation after it
+                    Some('\n') => {
+                        let rest = chars.as_str().trim_start();
+                        chars = rest.chars();
+                    }
+                    Some('n') => string.push('\n'),
+                    Some(ch) => string.push(ch),
+                    None => break,
+                },
+                ch => string.push(ch),
+            }
+        }
+        strings.push(string);
+    }
+    strings
+        .chunks_exact(3)
+        .map(|v| (v[0].clone(), v[1].clone(), v[2].clone()))
+        .collect()
 }
@@ -0,0 +1,72 @@
+//! Audit logging
+//!
+//! Opt-in: when the AUDIT_LOG variable is set, every statement evaluated is recorded as one line of
+//! JSON, appended to the file it names or sent to syslog if it's `syslog`.
+
+use std::{io::Write, time::Duration};
+
+use super::i18n::tr;
+
+/// Record a statement that was just evaluated.
+pub fn record(statement: &str, duration: Duration, state: &super::State) {
+    let Some(target) = state
+        .shell_env
+        .iter()
+        .find(|v| v.name == "AUDIT_LOG")
+        .map(|v| v.value.clone())
+        .filter(|v| !v.is_empty())
+    else {
+        return;
+    };
+    if statement.trim().is_empty() {
+        return;
+    }
+    let status = state
+        .specials
+        .get("STATUS")
+        .and_then(|v| v.parse::<i32>().ok());
+    let entry = serde_json::json!({
+        "time": chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+        "user": super::username(),
+        "pid": std::process::id(),
+        "cwd": state.working_dir.to_string_lossy(),
+        "command": super::mask_secrets(statement, state),
+        "status": status,
+        "duration_ms": duration.as_secs_f64() * 1000.0,
+    })
+    .to_string();
+
+    if target == "syslog" {
+        syslog(&entry);
+        return;
+    }
+    let written = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(state.working_dir.join(super::os_str::to_path(&target)))
+        .and_then(|mut file| file.write_all((entry + "\n").as_bytes()));
+    if let Err(e) = written {
+        println!(
+            "{}",
+            tr!("sesh: audit: writing to {} failed: {}", target, e)
+        );
+    }
+}
+
+/// Send an entry to syslog.
+#[cfg(unix)]
+fn syslog(entry: &str) {
+    static OPENED: std::sync::Once = std::sync::Once::new();
+    OPENED.call_once(|| unsafe { libc::openlog(c"sesh".as_ptr(), libc::LOG_PID, libc::LOG_USER) });
+    let entry = std::ffi::CString::new(entry).unwrap_or_default();
+    unsafe { libc::syslog(libc::LOG_INFO, c"%s".as_ptr(), entry.as_ptr()) };
+}
+
+/// Send an entry to syslog.
+#[cfg(not(unix))]
+fn syslog(_: &str) {
+    println!(
+        "{}",
+        tr!("sesh: audit: syslog isn't supported on this platform")
+    );
+}
@@ -0,0 +1,104 @@
+//! Talk to a running sesh session over its control socket.
+
+use std::path::PathBuf;
+#[cfg(unix)]
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+};
+
+use clap::Parser;
+
+#[path = "../ctl/paths.rs"]
+mod paths;
+#[cfg(unix)]
+use paths::socket_dir;
+
+/// seshctl sends requests to the control socket of a running sesh session
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Path of the socket. Defaults to $SESH_SOCKET, or the only running session.
+    #[arg(long, short = 's', env = "SESH_SOCKET")]
+    socket: Option<PathBuf>,
+    /// PID of the session to talk to.
+    #[arg(long, short = 'p', conflicts_with = "socket")]
+    pid: Option<u32>,
+    /// The request: `get name`, `set name=value`, `alias name value`, `run statement`, `focus`,
+    /// `then statement` to run it once the command running in the session is done, or
+    /// `prefill statement` to start the next line with it
+    #[arg(required = true, trailing_var_arg = true)]
+    request: Vec<String>,
+}
+
+/// Figure out which socket to talk to.
+#[cfg(unix)]
+fn find_socket(args: &Args) -> Result<PathBuf, String> {
+    if let Some(pid) = args.pid {
+        return Ok(socket_dir().join(format!("{}.sock", pid)));
+    }
+    if let Some(socket) = &args.socket {
+        return Ok(socket.clone());
+    }
+    let sockets = std::fs::read_dir(socket_dir())
+        .map_err(|e| format!("no sessions found: {}", e))?
+        .filter_map(|v| v.ok())
+        .map(|v| v.path())
+        .filter(|v| v.extension().is_some_and(|v| v == "sock"))
+        .filter(|v| UnixStream::connect(v).is_ok())
+        .collect::<Vec<PathBuf>>();
+    match sockets.len() {
+        0 => Err("no sessions found".to_string()),
+        1 => Ok(sockets[0].clone()),
+        _ => Err("more than one session running; pass --pid or --socket".to_string()),
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    let _ = Args::parse();
+    eprintln!("seshctl: control sockets aren't supported on this platform");
+    std::process::exit(2);
+}
+
+#[cfg(unix)]
+fn main() {
+    let args = Args::parse();
+    let socket = match find_socket(&args) {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("seshctl: {}", e);
+            std::process::exit(2);
+        }
+    };
+    let mut stream = match UnixStream::connect(&socket) {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("seshctl: connecting to {} failed: {}", socket.display(), e);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = writeln!(stream, "{}", args.request.join(" ")) {
+        eprintln!("seshctl: sending request failed: {}", e);
+        std::process::exit(2);
+    }
+    let mut reply = String::new();
+    if let Err(e) = BufReader::new(stream).read_line(&mut reply) {
+        eprintln!("seshctl: reading reply failed: {}", e);
+        std::process::exit(2);
+    }
+    let reply: serde_json::Value = serde_json::from_str(&reply).unwrap_or_default();
+    if reply["ok"] == true {
+        match &reply["value"] {
+            serde_json::Value::Null => (),
+            serde_json::Value::String(s) => println!("{}", s),
+            v => println!("{}", v),
+        }
+    } else {
+        eprintln!(
+            "seshctl: {}",
+            reply["error"].as_str().unwrap_or("invalid reply")
+        );
+        std::process::exit(1);
+    }
+}
@@ -1,22 +1,269 @@
 //! builtins to sesh
 #![allow(clippy::type_complexity)]
 
-use std::hint::unreachable_unchecked;
+use std::{
+    fmt::Display,
+    io::{Read, Write},
+    path::PathBuf,
+};
+
+use super::i18n::tr;
+
+/// The status a builtin exits with.
+pub type ExitStatus = i32;
+
+/// Why a builtin failed.
+#[derive(Debug)]
+pub enum BuiltinError {
+    /// The arguments were wrong. The usage line of the builtin is shown after the reason, which
+    /// may be empty.
+    Usage(String),
+    /// The shell is restricted and the builtin was asked to do something it refuses to.
+    Restricted(String),
+    /// Reading or writing one of the streams failed.
+    Io(std::io::Error),
+    /// Anything else, with the status to exit with.
+    Failed(ExitStatus, String),
+}
+
+impl BuiltinError {
+    /// The status the builtin exits with.
+    pub fn status(&self) -> ExitStatus {
+        match self {
+            Self::Usage(_) | Self::Restricted(_) => 1,
+            Self::Io(_) => 2,
+            Self::Failed(status, _) => *status,
+        }
+    }
+}
+
+impl Display for BuiltinError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Usage(reason) => f.write_str(reason),
+            Self::Restricted(what) => f.write_str(&tr!("restricted: {}", what)),
+            Self::Io(e) => e.fmt(f),
+            Self::Failed(_, message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for BuiltinError {}
+
+impl From<std::io::Error> for BuiltinError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// The streams a builtin reads from and writes to.
+pub struct Io<'a> {
+    /// Standard input
+    pub stdin: &'a mut dyn Read,
+    /// Standard output
+    pub stdout: &'a mut dyn Write,
+    /// Standard error
+    pub stderr: &'a mut dyn Write,
+    /// Whether stdout is the terminal, so output can be paged or made bold.
+    pub terminal: bool,
+}
+
+/// Read a line a byte at a time, so nothing after it is taken from the stream. Returns None at the
+/// end of the stream.
+fn read_line(input: &mut dyn Read) -> std::io::Result<Option<String>> {
+    let mut line = Vec::new();
+    let mut byte = [0u8];
+    loop {
+        match input.read(&mut byte) {
+            Ok(0) if line.is_empty() => return Ok(None),
+            Ok(0) => break,
+            Ok(_) if byte[0] == b'\n' => break,
+            Ok(_) => line.push(byte[0]),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(Some(String::from_utf8_lossy(&line).to_string()))
+}
+
+/// Print why a builtin failed to its stderr, followed by its usage if the arguments were wrong.
+pub fn report(name: &str, error: &BuiltinError, io: &mut Io) {
+    if !matches!(error, BuiltinError::Usage(reason) if reason.is_empty()) {
+        let _ = writeln!(io.stderr, "{}", tr!("sesh: {}: {}", name, error));
+    }
+    if let BuiltinError::Usage(_) = error
+        && let Some(builtin) = BUILTINS.iter().find(|v| v.0 == name)
+    {
+        let _ = writeln!(
+            io.stderr,
+            "{}",
+            tr!("sesh: {0}: usage: {0} {1}", name, builtin.2)
+        );
+    }
+}
+
+/// A builtin. Gets the words of the statement, the statement itself and the streams to use.
+pub type Builtin = fn(
+    args: Vec<String>,
+    unsplit_args: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError>;
 
 /// List of builtins
-pub const BUILTINS: [(
-    &str,
-    fn(args: Vec<String>, unsplit_args: String, state: &mut super::State) -> i32,
-    &str,
-    &str,
-); 21] = [
+pub const BUILTINS: [(&str, Builtin, &str, &str); 82] = [
     (
         "cd",
         cd,
+        "[-L | -P] [dir | -]",
+        "Change the current directory into the specified one. If unspecified, change the directory into the user's home directory, \
+        and with -, into the previous one. PWD and OLDPWD follow along. Symlinks are kept in the path, so .. goes back \
+        the way it came, unless -P is passed or CD_PHYSICAL is true, which resolve them.",
+    ),
+    (
+        "pushd",
+        pushd,
         "[dir]",
-        "Change the current directory into the specified one. If unspecified, change the directory into the user's home directory.",
+        "Remember the current directory on the directory stack and change into the specified one, or the \
+        user's home directory. The depth of the stack is $d in the prompt.",
+    ),
+    (
+        "popd",
+        popd,
+        "",
+        "Change back into the directory last remembered by pushd and take it off the directory stack.",
+    ),
+    (
+        "dirs",
+        dirs,
+        "",
+        "List the current directory followed by the directory stack, most recent first.",
+    ),
+    (
+        "trust",
+        trust,
+        "[list | revoke [dir] | dir]",
+        "Trust the .sesh/config.sesh of a project, by default the one the current directory is in, so its \
+        aliases and variables apply while inside it. Changing the config takes the trust back. list shows \
+        the trusted projects and revoke stops trusting one.",
+    ),
+    (
+        "exit",
+        exit,
+        "[status]",
+        "Exit the shell with [status], or 0, running the statements registered with onexit first. \
+        With jobs running or stopped, it only warns the first time; exiting again straight after ends \
+        them. In a subshell, only the subshell is left.",
+    ),
+    (
+        "jobs",
+        jobs,
+        "",
+        "List the jobs started by ending a statement with &, and whether each is running, stopped or done.",
+    ),
+    (
+        "fg",
+        fg,
+        "[%id]",
+        "Bring a job, by default the most recent one, to the foreground and wait for it. Ctrl-Z stops it \
+        and puts it back in the background.",
+    ),
+    (
+        "bg",
+        bg,
+        "[%id]",
+        "Continue a stopped job, by default the most recent one, in the background.",
+    ),
+    (
+        "wait",
+        wait,
+        "[%id ...]",
+        "Wait for jobs to finish, by default all of them, and return the status of the last one. Ctrl-C \
+        stops waiting.",
+    ),
+    (
+        "onexit",
+        onexit,
+        "[list | clear | (statement)]",
+        "Register a statement to run when the shell exits, whether through exit, the end of input or a \
+        terminating signal. Without arguments or with list, print the registered statements; clear \
+        forgets them.",
+    ),
+    (
+        "precmd",
+        precmd,
+        "[list | clear | (statement)]",
+        "Register a statement to run before each prompt. It runs in this shell, so it can set variables the \
+        prompt shows, but if it's still running after $PRECMD_TIMEOUT (2s by default) it's stopped, along with \
+        what it's running and the statements after it, and the prompt is marked instead. Without arguments or \
+        with list, print the registered statements; clear forgets them.",
+    ),
+    (
+        "chpwd",
+        chpwd,
+        "[list | clear | (statement)]",
+        "Register a statement to run in this shell after cd, pushd or popd changes directory, with \
+        $OLDPWD the directory it left. Without arguments or with list, print the registered statements; \
+        clear forgets them.",
+    ),
+    (
+        "summary",
+        summary,
+        "[directory]",
+        "Show how many entries a directory, the working one by default, has along with the first few \
+        of their names, and the git branch and how many files changed if it's in a repository. With \
+        $CD_SUMMARY set to true, it's shown after changing directory at the prompt.",
+    ),
+    (
+        "defer",
+        defer,
+        "[delay] (statement)",
+        "Put off running a statement until the first prompt is up, to keep slow parts of ~/.seshrc from \
+        delaying it. Outside of an interactive shell, the statement runs once ~/.seshrc is done. With a \
+        delay like 30s, 10m or 1h30m, run it that much later instead, while the shell waits at the prompt.",
+    ),
+    (
+        "scope-exit",
+        scope_exit,
+        "(statement)",
+        "Register a statement to clean up after the script it's in, like removing a temporary file or a \
+        lock. Statements registered this way run last first when the file being sourced or the script sesh \
+        runs ends, whether it ends normally or through exit. At the prompt, they run when the shell exits.",
+    ),
+    (
+        "at",
+        at,
+        "[list | cancel id | time (statement)]",
+        "Run a statement the next time the clock shows a time like 14:30, while the shell waits at the \
+        prompt. Without arguments or with list, print the pending statements from at and defer with a \
+        delay; cancel drops one.",
+    ),
+    (
+        "datef",
+        datef,
+        "[--parse date] [--add delay] [--var name | --focus] [+format]",
+        "Print the time now, or the date passed to --parse, like 2024-05-01, 2024-05-01 14:30 or a number of \
+        seconds since 1970. --add moves it by a delay like 3d or -1h30m. The format is like strftime's and \
+        defaults to %Y-%m-%d %H:%M:%S. With --var the result goes into a variable and with --focus into \
+        the focus instead of being printed.",
+    ),
+    (
+        "then",
+        then,
+        "[list | clear | (statement)]",
+        "Queue a statement to run once the current command line is done, after the ones queued before it. \
+        seshctl then statement queues one from elsewhere while a command runs. With ERREXIT set to true, a \
+        failing command drops the rest of the queue. Without arguments or with list, print the queue; clear \
+        empties it.",
+    ),
+    (
+        "prefill",
+        prefill,
+        "[clear | (statement)]",
+        "Start the next line typed at the prompt with a statement, to be edited and run or cleared, such as a \
+        fix suggested by a hook after a command fails. seshctl prefill statement does it from elsewhere. \
+        Without arguments, print what the line will start with; clear forgets it.",
     ),
-    ("exit", exit, "", "Exit the shell."),
     (
         "echo",
         echo,
@@ -26,20 +273,71 @@ pub const BUILTINS: [(
     (
         "alias",
         alias,
-        "name=value [name=value ...]",
-        "Create one or more command aliases. Command line arguments may be passed to the value.",
+        "name=value [name=value ...] [--desc text] | name value [--desc text] | --edit name",
+        "Create one or more command aliases. Command line arguments may be passed to the value. With \
+        --desc, describe what they're for; help, completion and listing the aliases show it. With \
+        --edit, change what an alias stands for in place and save it with Enter.",
     ),
     (
         "help",
         help,
-        "[command]",
-        "Hey, that's me! Get help on a specified builtin or without arguments list all of the available builtin commands.",
+        "[-m] [command] | --json",
+        "Hey, that's me! Get help on a specified builtin or without arguments list all of the available builtin \
+        commands. With -m, show a full manual page for the builtin. Output too long for the terminal goes \
+        through $PAGER, or less if it isn't set. With --json, output the name, usage and description of every \
+        builtin as JSON.",
+    ),
+    (
+        "introspect",
+        introspect,
+        "[--focus]",
+        "Output the builtins, aliases, variables, options and key bindings of the shell as a JSON object, \
+        or put it in the focus with --focus. The values of secret variables are masked.",
     ),
     (
         "source",
         eval,
         "filename [arguments]",
-        "Evaluate the contents of a file, optionally passing arguments in variables $1 and up.",
+        "Evaluate the contents of a file, optionally passing arguments in variables $1 and up. Files \
+        sourcing each other, like other builtins that run statements, can only nest as deep as \
+        $MAXDEPTH, or 100 if it isn't set.",
+    ),
+    (
+        "reload",
+        reload,
+        "",
+        "Run ~/.seshrc again, first undoing the aliases and variables it set the last time. With \
+        AUTORELOAD set to true, the shell offers to do this before the prompt when the file changes.",
+    ),
+    (
+        "session",
+        session,
+        "list | save [name] | restore [name]",
+        "List saved sessions, save the working directory, variables, aliases, focus and bookmarks as a \
+        session, or restore them from one. The name defaults to default. Interactive shells keep the \
+        session last up to date, which sesh --resume restores.",
+    ),
+    (
+        "basename",
+        basename,
+        "[-s suffix] [path ...] | path suffix",
+        "Print the last component of each path, or replace each string in the focus with its last \
+        component if none are given. With -s, or a suffix after a single path like POSIX basename, the \
+        suffix is taken off the end of each component that's longer than it.",
+    ),
+    (
+        "dirname",
+        dirname,
+        "[path ...]",
+        "Print each path without its last component, or replace each string in the focus with that if \
+        none are given.",
+    ),
+    (
+        "realpath",
+        realpath,
+        "[--relative-to dir] [path ...]",
+        "Print the absolute path of each path with symlinks, . and .. resolved, or replace each string in \
+        the focus with it if none are given. With --relative-to, print it relative to dir instead.",
     ),
     (
         "loadf",
@@ -56,8 +354,101 @@ pub const BUILTINS: [(
     (
         "set",
         set,
-        "name=value [name=value ...]",
-        "Set one or more variables to values.",
+        "[--secret] name=value [name=value ...]",
+        "Set one or more variables to values. With --secret, the values are masked in dumpvars, guard \
+        prompts and the audit log, and the command line isn't saved to the history.",
+    ),
+    (
+        "export",
+        export,
+        "[name[=value] ...]",
+        "Give variables, setting them first if given values, to the programs the shell runs. Other \
+        variables stay in the shell, except for ones named like a variable in the environment it \
+        started with, such as PATH. Without arguments, list the exported variables.",
+    ),
+    (
+        "capture",
+        capture,
+        "var (statement)",
+        "Run a statement and put what it writes to stdout in [var], what it writes to stderr in [var]_ERR \
+        and its status in [var]_STATUS, without the newlines at the end. $(statement) anywhere in a \
+        statement is replaced by what the statement writes to stdout in the same way.",
+    ),
+    (
+        "filef",
+        filef,
+        "var path",
+        "Look at a file without following symlinks and put its type in [var] (file, dir, symlink or \
+        other), its size in bytes in [var]_SIZE, when it was last modified in seconds since 1970 in \
+        [var]_MTIME, its permissions in octal in [var]_MODE and its owner in [var]_OWNER. Fails if it \
+        doesn't exist.",
+    ),
+    (
+        "mktempf",
+        mktempf,
+        "[-d] [--cleanup] [--var var] [prefix]",
+        "Create a temporary file, or a directory with -d, that only you can get at and put its path in \
+        the focus, or in var with --var. Its name is the prefix, sesh. if not given, followed by random \
+        characters. With --cleanup, it's removed when the script ends, along with scope-exit statements.",
+    ),
+    (
+        "units",
+        units,
+        "[--human] [--var var] size|duration value",
+        "Turn a size like 1.5G or 20MB into bytes, or a duration like 90m or 1h30m into seconds, and put \
+        it in the focus, or in var with --var. K, M, G, T and P are powers of 1024, and KB, MB and so on \
+        powers of 1000. Durations go in w, d, h, m and s. With --human, turn a number back into units.",
+    ),
+    (
+        "procs",
+        procs,
+        "[--user user] [pattern]",
+        "Put the processes running in the focus, each as a list of its pid, user, CPU and memory usage \
+        in percent, and command line, with the pid of the first one at index 0.0. With a pattern, only \
+        the ones whose command line matches the regular expression, and with --user, only the ones \
+        running as user. Fails if none are found. Only available on Linux.",
+    ),
+    (
+        "dfp",
+        dfp,
+        "[path ...]",
+        "Put the mounted filesystems in the focus, each as a list of where it's mounted, its type, what's \
+        mounted, its size, how much is used and how much is available in bytes, and how much is used in \
+        percent. With paths, only the filesystems they're on, in the same order. Only available on \
+        Linux.",
+    ),
+    (
+        "netif",
+        netif,
+        "[name]",
+        "Put the network interfaces in the focus, each as a list of its name, up or down, its hardware \
+        address or nothing, and a list of its addresses with their prefix length, like 10.0.0.2/24. \
+        With a name, only that interface. Fails if none are found.",
+    ),
+    (
+        "vared",
+        vared,
+        "name",
+        "Change the value of a variable in place and save it with Enter, instead of typing it all out \
+        again with set. Ctrl-C or Escape leaves it as it was.",
+    ),
+    (
+        "pushvar",
+        pushvar,
+        "name value [value ...]",
+        "Add values to the end of a list variable, whose elements are its lines.",
+    ),
+    (
+        "popvar",
+        popvar,
+        "name [var]",
+        "Take the last element off a list variable and print it, or put it in [var].",
+    ),
+    (
+        "shiftvar",
+        shiftvar,
+        "name [var]",
+        "Take the first element off a list variable and print it, or put it in [var].",
     ),
     ("dumpvars", dumpvars, "", "List all variables."),
     (
@@ -66,6 +457,13 @@ pub const BUILTINS: [(
         "var [var ...]",
         "Unset one or more variables.",
     ),
+    (
+        "sane",
+        sane,
+        "",
+        "Put the terminal back into cooked mode with echo, show the cursor and reset colours, like stty \
+        sane, for when a program left it in a strange state.",
+    ),
     (
         "copyf",
         copyf,
@@ -78,6 +476,13 @@ pub const BUILTINS: [(
         "",
         "Paste the contents of your clipboard into the focus.",
     ),
+    (
+        "clip",
+        clip,
+        "[-o] [text ...]",
+        "Copy the text, or standard input if there is none, to your clipboard. With -o, output what's on it. \
+        Without access to the clipboard, copying asks the terminal to do it.",
+    ),
     (
         "setf",
         setf,
@@ -90,6 +495,14 @@ pub const BUILTINS: [(
         "var",
         "Set the focus to the contents of a variable.",
     ),
+    (
+        "showf",
+        showf,
+        "[-d depth]",
+        "Show the focus. Text is shown as it is, through $PAGER if it's too long for the terminal. Lists \
+        are shown one element per line, indented by how deeply they're nested, with the index of each \
+        element in front of it. With -d, lists nested deeper than depth are collapsed to their length.",
+    ),
     ("()", nop, "", "Do nothing and return a status code of 0."),
     ("nop", nop, "", "Do nothing and return a status code of 0."),
     (
@@ -104,6 +517,60 @@ pub const BUILTINS: [(
         "condition (statement)",
         "While [condition] returns a status of 0, do (statement).",
     ),
+    (
+        "for",
+        _for,
+        "var in [item ...] (statement)",
+        "Do (statement) with [var] set to each item in turn, or to each element of the focus if there are \
+        no items. Unquoted wildcards in the items expand to the paths they match. Afterwards [var] has the \
+        value it had before.",
+    ),
+    (
+        "break",
+        _break,
+        "",
+        "Stop the innermost while or for loop, skipping the rest of its statement.",
+    ),
+    (
+        "continue",
+        _continue,
+        "",
+        "Skip the rest of the statement of the innermost while or for loop and go on to the next time \
+        around.",
+    ),
+    (
+        "subshell",
+        subshell,
+        "(statements)",
+        "Run statements against a copy of the shell, so changes to the directory, variables and focus \
+        don't last past it. The status is that of the last statement. (statements) on its own does the \
+        same.",
+    ),
+    (
+        "lock",
+        lock,
+        "[-n | -t delay] name (statement)",
+        "Run a statement while holding a lock, waiting until no other sesh holds it, so scripts run by \
+        cron or CI at the same time take turns. A name without slashes is a lock shared by all of your \
+        shells; otherwise it's the path of the lock file. With -n, fail straight away if the lock is \
+        held, and with -t, after waiting as long as a delay like 30s or 5m. Ctrl-C stops waiting.",
+    ),
+    (
+        "par",
+        par,
+        "[-j count] (statement) (statement) ...",
+        "Run statements at the same time as background jobs, at most count of them at once if given, \
+        and wait for all of them. The status of each is put in $PAR_STATUS_1, $PAR_STATUS_2 and so on, \
+        and the status is that of the first one to fail, or 0. Ctrl-C interrupts the ones still \
+        running.",
+    ),
+    (
+        "onchange",
+        onchange,
+        "path [path ...] (statement)",
+        "Run a statement, then run it again whenever one of the files changes or something in one of the \
+        directories does, until Ctrl+C is pressed. Changes close together only run it once.",
+    ),
     (
         "gay",
         gay,
@@ -113,216 +580,1442 @@ pub const BUILTINS: [(
     (
         "history",
         history,
-        "",
-        "Output the full history being used by this shell, prefixed by numbers.",
+        "[list [count] | search [-r] pattern | delete number | clear]",
+        "Output the full history being used by this shell, prefixed by numbers, or with list only the \
+        last count entries. search shows the entries containing a pattern, or matching it as a regular \
+        expression with -r. delete forgets an entry by its number and clear forgets them all, in \
+        ~/.sesh_history too. Entries run with $HISTTIMESTAMPS set to true are listed with when they ran.",
+    ),
+    (
+        "remember",
+        remember,
+        "[--forget] [name] [(statement)]",
+        "Save a statement as a bookmark to recall later, or the last command line if none is given. \
+        Without a name the bookmark is numbered. Bookmarks are saved with the session. --forget deletes \
+        one.",
+    ),
+    (
+        "recall",
+        recall,
+        "[list | --run name | name]",
+        "Without arguments or with list, print the bookmarks saved with remember. With a name, edit the \
+        bookmarked statement on the terminal and run it with Enter; Ctrl-C or Escape doesn't run it. \
+        --run runs it straight away.",
+    ),
+    (
+        "sync",
+        sync,
+        "[name ...]",
+        "Hand aliases and variables to the other sesh sessions of this user through their control sockets, so \
+        they can be used there straight away. Without names, every alias is sent. Secret variables \
+        aren't sent. Sessions busy running something pick them up once they're back at the prompt.",
+    ),
+    (
+        "plugin",
+        plugin,
+        "list | load path [--allow capability,...] | unload name",
+        "List loaded plugins, load a plugin from a shared library or WASM module, or unload a plugin by name. \
+        WASM plugins can only use the capabilities passed to --allow: vars, focus and fs.",
+    ),
+    (
+        "transcript",
+        transcript,
+        "[start [--cast [--stdin]] [file] | stop]",
+        "Record everything shown in the terminal to a file, by default sesh-transcript.txt, with the time \
+        it started and ended. --cast records in asciinema's format instead, timing all the output, by \
+        default to sesh-transcript.cast. --stdin records every keypress as well, passwords included, like \
+        asciinema's option of the same name. The recording runs in a new sesh that \
+        carries on the session without jobs or secret variables, and stop hands the session back. \
+        Exiting without stopping exits this shell too. sesh --replay file [--speed times] plays one \
+        back. Without arguments, print where the recording goes. Only supported on unix.",
+    ),
+    (
+        "sandbox",
+        sandbox,
+        "[--net] [--rw path ...] program [arguments ...]",
+        "Run a program with a read-only view of the filesystem and no network access. $XDG_RUNTIME_DIR and \
+        the directory control sockets are in look empty to it, so it can't reach this shell or the desktop \
+        through them. Paths passed to --rw stay writable and --net keeps the network. Only supported on \
+        Linux.",
+    ),
+    (
+        "nice",
+        nice,
+        "[-n adjustment] [--io class[:level]] program [arguments ...]",
+        "Run a program with the adjustment added to its niceness, 10 by default, and in an IO class: idle, \
+        best-effort or realtime, with a level from 0, first, to 7. Only root can make programs less nice or \
+        realtime. IO classes are only supported on Linux.",
+    ),
+    (
+        "cpus",
+        cpus,
+        "list program [arguments ...]",
+        "Run a program only on the CPUs in the list, like 0-3,6. Only supported on Linux.",
+    ),
+    (
+        "remote",
+        remote,
+        "[--var name ...] [--focus] host (statement)",
+        "Run a statement with sesh on another host over ssh. Variables passed to --var are given to it in \
+        its environment. With --focus, what it outputs goes into the focus instead of being printed.",
+    ),
+    (
+        "mux",
+        mux,
+        "window | split [-h] | send pane",
+        "Inside tmux, open a new window or pane in the current directory with the variables of this shell \
+        in its environment, or type the focus into another pane. split stacks the panes unless -h is passed.",
+    ),
+    (
+        "guard",
+        guard,
+        "[list | add pattern | remove pattern]",
+        "List, add or remove patterns of dangerous commands. Matching commands have to be confirmed in an \
+        interactive shell and are refused otherwise. Patterns are matched word by word: a * word matches \
+        any number of words, and within a word * matches anything and ? a single character, except /. \
+        Arguments are also matched as the paths they name, with the home directory as ~.",
+    ),
+    (
+        "choose",
+        choose,
+        "[--var name] [--prompt text] [item ...]",
+        "Pick one of the items, or one of the elements of the focus if there are none, in an interactive \
+        list. Type to filter, use the arrow keys to move and enter to pick. The choice goes into the focus, \
+        or into a variable if --var is passed.",
+    ),
+    (
+        "select",
+        select,
+        "var in item [item ...] (statement)",
+        "Print a numbered menu of the items and read the number of one from the user, asking again until \
+        it's valid. The item is put in [var], what was typed in REPLY, and then (statement) is run.",
+    ),
+    (
+        "confirm",
+        confirm,
+        "[--default y|n] question",
+        "Ask a yes or no question, asking again until the answer is one. The status is 0 for yes and 1 \
+        for no, or if there's nothing left to read. With --default, an empty answer gives it.",
+    ),
+    (
+        "prompt",
+        prompt,
+        "text var [--default value] [--validate regex]",
+        "Ask for a value and put it in [var]. With --default, an empty answer gives it, and with \
+        --validate, the value has to match the whole of the regular expression or it's asked for \
+        again. The status is 1 if there's nothing left to read.",
     ),
 ];
 
-/// Change the directory
-pub fn cd(args: Vec<String>, _: String, state: &mut super::State) -> i32 {
-    if args.len() == 1 {
-        state.working_dir = std::env::home_dir().unwrap();
-        return 0;
-    }
-    if args[1] == ".." {
-        state.working_dir.pop();
-        return 0;
-    }
-    state.working_dir.push(args[1].clone());
-    0
-}
+/// Variables that can't be changed in restricted mode.
+pub const RESTRICTED_VARS: [&str; 7] = [
+    "PATH",
+    "SHELL",
+    "ENV",
+    "HISTFILE",
+    "TMOUT",
+    "AUDIT_LOG",
+    "PAGER",
+];
 
-/// Exit the shell
-pub fn exit(_: Vec<String>, _: String, state: &mut super::State) -> i32 {
-    if let Some(raw_term) = state.raw_term.clone() {
-        let writer = raw_term.write().unwrap();
-        let _ = writer.suspend_raw_mode();
-        state.raw_term = None;
+/// Fail if the shell is restricted.
+fn restricted(state: &super::State, what: &str) -> Result<(), BuiltinError> {
+    if state.restricted {
+        return Err(BuiltinError::Restricted(
+            super::i18n::translate(what).to_string(),
+        ));
     }
-    std::process::exit(0);
+    Ok(())
 }
 
-/// Echo a string
-pub fn echo(args: Vec<String>, mut unsplit_args: String, _: &mut super::State) -> i32 {
-    if args.len() == 1 {
-        println!();
-        return 0;
+/// Variables the shell keeps up to date itself, which can't be changed.
+pub const SPECIAL_VARS: [&str; 12] = [
+    "STATUS",
+    "STATUS_SIGNAL",
+    "?",
+    "$",
+    "PPID",
+    "_",
+    "SHLVL",
+    "PWD",
+    "OLDPWD",
+    "RUSAGE_USER",
+    "RUSAGE_SYSTEM",
+    "RUSAGE_MAXRSS",
+];
+
+/// Fail if a variable is special, or if the shell is restricted and the variable is protected.
+pub fn restricted_var(state: &super::State, var: &str) -> Result<(), BuiltinError> {
+    if SPECIAL_VARS.contains(&var) {
+        return Err(BuiltinError::Failed(1, tr!("{} is read-only", var)));
     }
-    unsplit_args = unsplit_args[(args[0].len() + 1)..].to_string();
-    if args.len() != 1 && args[1] == "-e" {
-        unsplit_args = unsplit_args[3..].to_string();
-        let escaped = crate::escapes::interpret_escaped_string(&unsplit_args);
-        if escaped.is_err() {
-            println!("sesh: echo: invalid escape: {}", escaped.unwrap_err());
-            return 1;
-        }
-        unsplit_args = escaped.unwrap();
+    if state.restricted && RESTRICTED_VARS.contains(&var) {
+        return Err(BuiltinError::Restricted(tr!("cannot change {}", var)));
     }
-    println!("{}", unsplit_args);
-    0
+    Ok(())
 }
 
-/// Add an alias
-pub fn alias(args: Vec<String>, _: String, state: &mut super::State) -> i32 {
-    if args.len() == 1 {
-        for alias in &state.aliases {
-            println!("`{}`: `{}`", alias.name, alias.to);
-        }
-        return 0;
+/// Change the directory
+pub fn cd(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    restricted(state, "cannot change directory")?;
+    let mut physical = super::get_var(state, "CD_PHYSICAL").as_deref() == Some("true");
+    let mut args = &args[1..];
+    while let Some(flag) = args.first().filter(|v| ["-L", "-P"].contains(&v.as_str())) {
+        physical = flag == "-P";
+        args = &args[1..];
     }
-    if args.len() == 2 {
-        for alias in &state.aliases {
-            if alias.name != args[1] {
-                continue;
-            }
-            println!("`{}`: `{}`", alias.name, alias.to);
+    let dir = match args {
+        [] => std::env::home_dir().unwrap_or_default(),
+        [dir] if dir == "-" => {
+            let Some(old) = state.specials.get("OLDPWD") else {
+                return Err(BuiltinError::Failed(1, tr!("OLDPWD not set")));
+            };
+            PathBuf::from(old)
         }
-        return 0;
+        [dir] => super::os_str::to_path(dir),
+        _ => return Err(BuiltinError::Usage(String::new())),
+    };
+    super::change_dir(&dir, physical, state)
+        .map_err(|e| BuiltinError::Failed(1, tr!("{}: {}", dir.display(), e)))?;
+    super::run_chpwd_hooks(state);
+    Ok(0)
+}
+
+/// Remember the current directory and change directory.
+pub fn pushd(
+    args: Vec<String>,
+    unsplit: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    restricted(state, "cannot change directory")?;
+    if args.len() > 2 {
+        return Err(BuiltinError::Usage(String::new()));
     }
+    let old = state.working_dir.clone();
+    cd(args, unsplit, io, state)?;
+    state.dir_stack.push(old);
+    Ok(0)
+}
 
-    state.aliases.push(super::Alias {
-        name: args[1].clone(),
-        to: args[2].clone(),
-    });
+/// Change back into the directory last remembered by pushd.
+pub fn popd(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    restricted(state, "cannot change directory")?;
+    if args.len() > 1 {
+        return Err(BuiltinError::Usage(String::new()));
+    }
+    let Some(dir) = state.dir_stack.pop() else {
+        return Err(BuiltinError::Failed(1, tr!("directory stack empty")));
+    };
+    super::change_dir(&dir, false, state)
+        .map_err(|e| BuiltinError::Failed(1, tr!("{}: {}", dir.display(), e)))?;
+    super::run_chpwd_hooks(state);
+    Ok(0)
+}
 
-    0
+/// List the directory stack.
+pub fn dirs(
+    _: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    writeln!(io.stdout, "{}", state.working_dir.display())?;
+    for dir in state.dir_stack.iter().rev() {
+        writeln!(io.stdout, "{}", dir.display())?;
+    }
+    Ok(0)
 }
 
-/// Output help on builtins.
-pub fn help(args: Vec<String>, _: String, state: &mut super::State) -> i32 {
-    if args.len() >= 2 {
-        for builtin in BUILTINS {
-            if builtin.0 == args[1] {
-                println!("{} {}: {}", builtin.0, builtin.2, builtin.3);
-            }
-        }
-        return 0;
+/// Trust project configs.
+pub fn trust(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    /// The project a directory argument, or the working directory, is in.
+    fn root_of(dir: Option<&String>, state: &super::State) -> Result<PathBuf, BuiltinError> {
+        let dir = dir.map_or(state.working_dir.clone(), |v| {
+            state.working_dir.join(super::os_str::to_path(v))
+        });
+        super::project::find_root(&dir).ok_or_else(|| {
+            BuiltinError::Failed(
+                1,
+                tr!(
+                    "no {} in {} or above",
+                    super::project::CONFIG,
+                    dir.display()
+                ),
+            )
+        })
     }
-    println!(
-        "{}sesh, version {} ({})",
-        if state.in_mode { "\x1b[31;1m" } else { "" },
-        env!("CARGO_PKG_VERSION"),
-        env!("TARGET")
-    );
-    println!(
-        "{}This provides a list of built-in shell commands.",
-        if state.in_mode {
-            "\x1b[38;2;255;165;0m"
-        } else {
-            ""
+    restricted(state, "cannot trust project configs")?;
+    match args.get(1).map(|v| v.as_str()) {
+        Some("list") if args.len() == 2 => {
+            for root in super::project::trusted() {
+                writeln!(io.stdout, "{}", root.display())?;
+            }
+            Ok(0)
         }
-    );
-    println!(
-        "{}Use `man sesh` to find out more about the shell in general.",
-        if state.in_mode { "\x1b[33;1m" } else { "" }
-    );
-    println!(
-        "{}Use `man -k' or `info' to find out more about commands not in this list.",
-        if state.in_mode { "\x1b[32;1m" } else { "" }
-    );
-    println!();
-    let mut builtins = BUILTINS;
-    builtins.sort_by(|v1, v2| v1.0.cmp(v2.0));
-
-    for (i, builtin) in builtins.iter().enumerate() {
-        if builtin.0 == "gay" {
-            continue;
+        Some("revoke") if args.len() <= 3 => {
+            let root = root_of(args.get(2), state)?;
+            if !super::project::revoke(&root)? {
+                return Err(BuiltinError::Failed(
+                    1,
+                    tr!("{} is not trusted", root.display()),
+                ));
+            }
+            super::project::leave(state);
+            Ok(0)
         }
-        if state.in_mode {
-            let table = [
-                "\x1b[34;1m",
-                "\x1b[36;1m",
-                "\x1b[35;1m",
-                "\x1b[31;1m",
-                "\x1b[38;2;255;165;0m",
-                "\x1b[33;1m",
-                "\x1b[32;1m",
-            ];
-            let idx = i % table.len();
-            print!("{}", table[idx]);
+        _ if args.len() <= 2 => {
+            let root = root_of(args.get(1), state)?;
+            super::project::trust(&root)?;
+            super::project::leave(state);
+            Ok(0)
         }
-        println!("{} {}", builtin.0, builtin.2);
+        _ => Err(BuiltinError::Usage(String::new())),
     }
-    0
 }
 
-/// Run a file.
-pub fn eval(args: Vec<String>, _: String, state: &mut super::State) -> i32 {
-    if args.len() < 2 {
-        println!("sesh: {}: filename argument required", args[0]);
-        println!("sesh: {0}: usage: {0} filename [arguments]", args[0]);
-        return 1;
+/// Exit the shell
+pub fn exit(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let status = match args.as_slice() {
+        [_] => 0,
+        [_, status] => status
+            .parse::<ExitStatus>()
+            .map_err(|_| BuiltinError::Usage(tr!("the status has to be a number")))?,
+        _ => return Err(BuiltinError::Usage(String::new())),
+    };
+    // in a subshell, only it is left
+    if state.subshell_depth > 0 {
+        state.exit_request = Some(status);
+        return Ok(status);
     }
+    if let Err(warning) = super::can_exit(state) {
+        writeln!(io.stderr, "{}", warning)?;
+        return Ok(1);
+    }
+    super::exit_shell(state, status);
+}
 
-    let file = std::fs::read(args[1].clone());
-    if file.is_err() {
-        println!(
-            "sesh: {}: error opening file: {}",
-            args[0],
-            file.unwrap_err()
-        );
-        return 2;
+/// List the jobs.
+pub fn jobs(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    if args.len() != 1 {
+        return Err(BuiltinError::Usage(String::new()));
     }
-    let file = String::from_utf8(file.unwrap());
-    if file.is_err() {
-        println!("sesh: {}: invalid UTF-8: {}", args[0], file.unwrap_err());
-        return 3;
+    super::job::update(state);
+    for job in &state.jobs {
+        writeln!(io.stdout, "{}", job)?;
     }
-    let file = file.unwrap();
+    Ok(0)
+}
 
-    let mut state2 = state.clone();
+/// Find the job a builtin was given, or the most recent one.
+fn find_job(spec: Option<&String>, state: &super::State) -> Result<usize, BuiltinError> {
+    super::job::find(spec.map(|v| v.as_str()), state).ok_or_else(|| {
+        BuiltinError::Failed(
+            1,
+            spec.map_or(tr!("no jobs"), |v| tr!("no such job: {}", v)),
+        )
+    })
+}
 
-    for (i, arg) in args[1..].iter().enumerate() {
-        state2.shell_env.push(super::ShellVar {
-            name: format!("{}", i),
-            value: arg.clone(),
-        });
+/// Bring a job to the foreground.
+pub fn fg(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    if args.len() > 2 {
+        return Err(BuiltinError::Usage(String::new()));
     }
-
-    super::eval(&file, &mut state2);
-
-    0
+    super::job::update(state);
+    let index = find_job(args.get(1), state)?;
+    if let super::job::JobStatus::Done(status) = state.jobs[index].status {
+        state.jobs.remove(index);
+        return Ok(status);
+    }
+    writeln!(io.stdout, "{}", state.jobs[index].statement)?;
+    // the job has the terminal from here on
+    io.stdout.flush()?;
+    Ok(super::job::foreground(index, state))
 }
 
-/// Load a file into the focused variable.
-pub fn loadf(args: Vec<String>, _: String, state: &mut super::State) -> i32 {
-    if args.len() < 2 {
-        println!("sesh: {}: filename argument required", args[0]);
-        println!("sesh: {0}: usage: {0} filename", args[0]);
-        return 1;
+/// Continue a job in the background.
+pub fn bg(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    if args.len() > 2 {
+        return Err(BuiltinError::Usage(String::new()));
     }
-    let path = args[1..].concat().clone();
+    super::job::update(state);
+    let index = find_job(args.get(1), state)?;
+    super::job::resume(index, state);
+    Ok(0)
+}
 
-    let file = std::fs::read(path);
-    if file.is_err() {
-        println!(
-            "sesh: {}: error opening file: {}",
-            args[0],
-            file.unwrap_err()
-        );
-        return 2;
+/// Wait for jobs to finish.
+pub fn wait(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let mut status = 0;
+    if args.len() == 1 {
+        while !state.jobs.is_empty() {
+            let Some(done) = super::job::wait(0, state) else {
+                return Ok(130);
+            };
+            status = done;
+        }
+        return Ok(status);
     }
-    let file = String::from_utf8(file.unwrap());
-    if file.is_err() {
-        println!("sesh: {}: invalid UTF-8: {}", args[0], file.unwrap_err());
-        return 3;
+    for spec in &args[1..] {
+        let index = find_job(Some(spec), state)?;
+        let Some(done) = super::job::wait(index, state) else {
+            return Ok(130);
+        };
+        status = done;
     }
-    let file = file.unwrap();
-
-    state.focus = super::Focus::Str(file);
-
-    0
+    Ok(status)
 }
 
-/// Split the focus on a character.
-pub fn splitf(mut args: Vec<String>, _: String, state: &mut super::State) -> i32 {
-    if args.len() >= 3 && args[2] == "-e" {
-        let unescaped = super::escapes::interpret_escaped_string(&args[1]);
-        if unescaped.is_err() {
-            println!("sesh: splitf: invalid escape: {}", unescaped.unwrap_err());
-            return 1;
+/// List, clear or add to hooks, for onexit and precmd.
+fn manage_hooks(
+    args: &[String],
+    hooks: &mut Vec<String>,
+    io: &mut Io,
+) -> Result<ExitStatus, BuiltinError> {
+    match args.get(1).map(|v| v.as_str()) {
+        None | Some("list") if args.len() <= 2 => {
+            for hook in hooks.iter() {
+                writeln!(io.stdout, "{}", hook)?;
+            }
+            Ok(0)
+        }
+        Some("clear") if args.len() == 2 => {
+            hooks.clear();
+            Ok(0)
+        }
+        Some(statement) if args.len() == 2 => {
+            hooks.push(statement.to_string());
+            Ok(0)
         }
-        args[1] = unescaped.unwrap();
+        _ => Err(BuiltinError::Usage(String::new())),
+    }
+}
+
+/// Register statements to run on exit.
+pub fn onexit(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    manage_hooks(&args, &mut state.exit_hooks, io)
+}
+
+/// Register statements to run before each prompt.
+pub fn precmd(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    manage_hooks(&args, &mut state.precmd_hooks, io)
+}
+
+/// Register statements to run after changing directory.
+pub fn chpwd(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    manage_hooks(&args, &mut state.chpwd_hooks, io)
+}
+
+/// Summarise a directory
+pub fn summary(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    /// How many names are shown.
+    const SHOWN: usize = 5;
+    if args.len() > 2 {
+        return Err(BuiltinError::Usage(String::new()));
+    }
+    let dir = state.working_dir.join(
+        args.get(1)
+            .map_or(PathBuf::new(), |v| super::os_str::to_path(v)),
+    );
+    let mut names = std::fs::read_dir(&dir)
+        .map_err(|e| BuiltinError::Failed(1, tr!("{}: {}", dir.display(), e)))?
+        .filter_map(|v| v.ok())
+        .filter(|v| !v.file_name().to_string_lossy().starts_with('.'))
+        .map(|v| {
+            let name = v.file_name().to_string_lossy().to_string();
+            if v.path().is_dir() { name + "/" } else { name }
+        })
+        .collect::<Vec<String>>();
+    names.sort();
+    let mut line = match names.len() {
+        0 => tr!("empty"),
+        1 => tr!("1 entry: {}", names[0]),
+        count => tr!(
+            "{} entries: {}",
+            count,
+            names[..count.min(SHOWN)].join(", ")
+        ),
+    };
+    if names.len() > SHOWN {
+        line += &tr!(", and {} more", names.len() - SHOWN);
+    }
+    writeln!(io.stdout, "{}", line)?;
+    // git is only asked about directories in a repository
+    if dir.ancestors().any(|v| v.join(".git").exists())
+        && let Ok(output) = std::process::Command::new("git")
+            .args(["status", "--porcelain", "--branch"])
+            .current_dir(&dir)
+            .stderr(std::process::Stdio::null())
+            .output()
+        && output.status.success()
+    {
+        let status = String::from_utf8_lossy(&output.stdout).to_string();
+        let mut lines = status.lines();
+        let branch = lines
+            .next()
+            .and_then(|v| v.strip_prefix("## "))
+            .unwrap_or_default();
+        let changed = lines.count();
+        if changed == 0 {
+            writeln!(io.stdout, "{}", tr!("git: {}, clean", branch))?;
+        } else {
+            writeln!(io.stdout, "{}", tr!("git: {}, {} changed", branch, changed))?;
+        }
+    }
+    Ok(0)
+}
+
+/// Put off a statement until the shell is up
+pub fn defer(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    match args.len() {
+        2 => state.deferred.push(args[1].clone()),
+        3 => {
+            let delay = crate::timer::parse_delay(&args[1])
+                .ok_or_else(|| BuiltinError::Usage(tr!("invalid delay {}", args[1])))?;
+            let when = chrono::TimeDelta::from_std(delay)
+                .ok()
+                .and_then(|v| chrono::Local::now().checked_add_signed(v))
+                .ok_or_else(|| BuiltinError::Usage(tr!("delay {} is too long", args[1])))?;
+            crate::timer::add(when, args[2].clone(), state);
+        }
+        _ => return Err(BuiltinError::Usage(String::new())),
+    }
+    Ok(0)
+}
+
+/// Register a statement to run when the script ends.
+pub fn scope_exit(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let [_, statement] = args.as_slice() else {
+        return Err(BuiltinError::Usage(String::new()));
+    };
+    state
+        .scope_exits
+        .push(super::ScopeExit::Statement(statement.clone()));
+    Ok(0)
+}
+
+/// Queue a statement to run after the current command line
+pub fn then(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    _: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    match args.get(1).map(|v| v.as_str()) {
+        None | Some("list") if args.len() <= 2 => {
+            for statement in super::queued() {
+                writeln!(io.stdout, "{}", statement)?;
+            }
+            Ok(0)
+        }
+        Some("clear") if args.len() == 2 => {
+            super::clear_queue();
+            Ok(0)
+        }
+        Some(statement) if args.len() == 2 => {
+            super::enqueue(statement.to_string());
+            Ok(0)
+        }
+        _ => Err(BuiltinError::Usage(String::new())),
+    }
+}
+
+/// Start the next line with a statement
+pub fn prefill(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    match args.get(1).map(|v| v.as_str()) {
+        None => {
+            if let Some(prefill) = &state.prefill {
+                writeln!(io.stdout, "{}", prefill)?;
+            }
+            Ok(0)
+        }
+        Some("clear") if args.len() == 2 => {
+            state.prefill = None;
+            Ok(0)
+        }
+        Some(statement) if args.len() == 2 => {
+            state.prefill = Some(statement.to_string());
+            Ok(0)
+        }
+        _ => Err(BuiltinError::Usage(String::new())),
+    }
+}
+
+/// Run a statement at a time, or list or cancel them
+pub fn at(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    match args.get(1).map(|v| v.as_str()) {
+        None | Some("list") if args.len() <= 2 => {
+            let mut timers = state.timers.clone();
+            timers.sort_by_key(|v| v.when);
+            for timer in timers {
+                writeln!(
+                    io.stdout,
+                    "{}\t{}\t{}",
+                    timer.id,
+                    timer.when.format("%Y-%m-%d %H:%M:%S"),
+                    timer.statement
+                )?;
+            }
+            Ok(0)
+        }
+        Some("cancel") if args.len() == 3 => {
+            let len = state.timers.len();
+            state.timers.retain(|v| v.id.to_string() != args[2]);
+            if state.timers.len() == len {
+                return Err(BuiltinError::Failed(2, tr!("no timer {}", args[2])));
+            }
+            Ok(0)
+        }
+        Some(time) if args.len() == 3 => {
+            let when = crate::timer::parse_time(time)
+                .ok_or_else(|| BuiltinError::Usage(tr!("invalid time {}", time)))?;
+            crate::timer::add(when, args[2].clone(), state);
+            Ok(0)
+        }
+        _ => Err(BuiltinError::Usage(String::new())),
+    }
+}
+
+/// Format and move dates
+pub fn datef(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let mut date = chrono::Local::now();
+    let mut format = "%Y-%m-%d %H:%M:%S".to_string();
+    let mut var = None;
+    let mut focus = false;
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--parse" if i + 1 < args.len() => {
+                i += 1;
+                date = crate::timer::parse_date(&args[i])
+                    .ok_or_else(|| BuiltinError::Usage(tr!("invalid date {}", args[i])))?;
+            }
+            "--add" if i + 1 < args.len() => {
+                i += 1;
+                let (sign, delay) = match args[i].strip_prefix('-') {
+                    Some(delay) => (-1, delay),
+                    None => (1, args[i].as_str()),
+                };
+                let delay = crate::timer::parse_delay(delay)
+                    .ok_or_else(|| BuiltinError::Usage(tr!("invalid delay {}", args[i])))?;
+                date = i64::try_from(delay.as_secs())
+                    .ok()
+                    .and_then(|v| chrono::TimeDelta::try_seconds(sign * v))
+                    .and_then(|v| date.checked_add_signed(v))
+                    .ok_or_else(|| BuiltinError::Usage(tr!("date out of range")))?;
+            }
+            "--var" if i + 1 < args.len() => {
+                i += 1;
+                restricted_var(state, &args[i])?;
+                var = Some(args[i].clone());
+            }
+            "--focus" => focus = true,
+            arg if arg.starts_with('+') && i == args.len() - 1 => format = arg[1..].to_string(),
+            _ => return Err(BuiltinError::Usage(String::new())),
+        }
+        i += 1;
+    }
+
+    let mut out = String::new();
+    // an invalid format shows up as an error here rather than a panic
+    std::fmt::Write::write_fmt(&mut out, format_args!("{}", date.format(&format)))
+        .map_err(|_| BuiltinError::Usage(tr!("invalid format {}", format)))?;
+    if let Some(var) = var {
+        super::swap_vars(vec![(var, Some(out))], state);
+    } else if focus {
+        state.focus = super::Focus::Str(out);
+    } else {
+        writeln!(io.stdout, "{}", out)?;
+    }
+    Ok(0)
+}
+
+/// Echo a string
+pub fn echo(
+    args: Vec<String>,
+    mut unsplit_args: String,
+    io: &mut Io,
+    _: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    if args.len() == 1 {
+        writeln!(io.stdout)?;
+        return Ok(0);
+    }
+    unsplit_args = unsplit_args[(args[0].len() + 1)..].to_string();
+    if args.len() != 1 && args[1] == "-e" {
+        unsplit_args = unsplit_args[3..].to_string();
+        unsplit_args = crate::escapes::interpret_escaped_string(&unsplit_args)
+            .map_err(|e| BuiltinError::Failed(1, tr!("invalid escape: {}", e)))?;
+    }
+    writeln!(io.stdout, "{}", unsplit_args)?;
+    Ok(0)
+}
+
+/// Add an alias
+pub fn alias(
+    mut args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let show = |alias: &super::Alias| match &alias.desc {
+        Some(desc) => format!("`{}`: `{}` - {}", alias.name, alias.to, desc),
+        None => format!("`{}`: `{}`", alias.name, alias.to),
+    };
+    if args.len() == 1 {
+        for alias in &state.aliases {
+            writeln!(io.stdout, "{}", show(alias))?;
+        }
+        return Ok(0);
+    }
+    if args[1] == "--edit" {
+        let [_, _, name] = args.as_slice() else {
+            return Err(BuiltinError::Usage(String::new()));
+        };
+        let Some(old) = state.aliases.iter().rfind(|v| &v.name == name).cloned() else {
+            return Err(BuiltinError::Failed(1, tr!("no alias named {}", name)));
+        };
+        let Some(to) = edit_value(&format!("alias {} = ", name), &old.to, io, state)? else {
+            return Ok(1);
+        };
+        state.aliases.retain(|v| &v.name != name);
+        state.aliases.push(super::Alias { to, ..old });
+        return Ok(0);
+    }
+    let desc = match args.iter().position(|v| v == "--desc") {
+        Some(i) if i >= 2 && i + 1 < args.len() => {
+            let desc = args.remove(i + 1);
+            args.remove(i);
+            Some(desc)
+        }
+        Some(_) => return Err(BuiltinError::Usage(String::new())),
+        None => None,
+    };
+    if args.len() == 2 && !args[1].contains('=') {
+        for alias in &state.aliases {
+            if alias.name != args[1] {
+                continue;
+            }
+            writeln!(io.stdout, "{}", show(alias))?;
+        }
+        return Ok(0);
+    }
+
+    let pairs = if args[1].contains('=') {
+        args[1..]
+            .iter()
+            .map(|v| {
+                v.split_once('=')
+                    .map(|(name, to)| (name.to_string(), to.to_string()))
+            })
+            .collect::<Option<Vec<(String, String)>>>()
+            .ok_or(BuiltinError::Usage(String::new()))?
+    } else {
+        vec![(args[1].clone(), args[2].clone())]
+    };
+    for (name, to) in pairs {
+        state.aliases.push(super::Alias {
+            name,
+            to,
+            desc: desc.clone(),
+        });
+    }
+
+    Ok(0)
+}
+
+/// Output help on builtins.
+pub fn help(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let mut out = String::new();
+    if args.get(1).is_some_and(|v| v == "--json") {
+        if args.len() != 2 {
+            return Err(BuiltinError::Usage(String::new()));
+        }
+        writeln!(io.stdout, "{}", builtins_json(state))?;
+        return Ok(0);
+    }
+    if args.len() >= 2 {
+        let manual = args[1] == "-m";
+        let Some(name) = args.get(if manual { 2 } else { 1 }) else {
+            return Err(BuiltinError::Usage(String::new()));
+        };
+        let mut found = BUILTINS
+            .iter()
+            .filter(|v| v.0 == name)
+            .map(|v| (v.0.to_string(), v.2.to_string(), v.3.to_string()))
+            .collect::<Vec<(String, String, String)>>();
+        if let Some((plugin, i)) = crate::plugins::find(&state.plugins, name) {
+            let builtin = &plugin.builtins[i];
+            found.push((
+                builtin.name.clone(),
+                builtin.usage.clone(),
+                builtin.help.clone(),
+            ));
+        }
+        if let Some(alias) = state.aliases.iter().rfind(|v| &v.name == name) {
+            found.push((
+                alias.name.clone(),
+                tr!("(alias for `{}`)", alias.to),
+                alias.desc.clone().unwrap_or_default(),
+            ));
+        }
+        if found.is_empty() {
+            return Err(BuiltinError::Failed(1, tr!("no help for {}", name)));
+        }
+        for (name, usage, description) in found {
+            if manual {
+                out += &manual_page(&name, &usage, &description, io.terminal);
+            } else {
+                out += &format!("{} {}: {}\n", name, usage, description);
+            }
+        }
+        page(&out, io, state)?;
+        return Ok(0);
+    }
+    let color = |code: &'static str| if state.in_mode { code } else { "" };
+    out += &format!(
+        "{}{}\n",
+        color("\x1b[31;1m"),
+        tr!(
+            "sesh, version {} ({})",
+            env!("CARGO_PKG_VERSION"),
+            env!("TARGET")
+        )
+    );
+    out += &format!(
+        "{}{}\n",
+        color("\x1b[38;2;255;165;0m"),
+        tr!("This provides a list of built-in shell commands.")
+    );
+    out += &format!(
+        "{}{}\n",
+        color("\x1b[33;1m"),
+        tr!("Use `help -m name' or `man sesh-builtins' to read about one in full.")
+    );
+    out += &format!(
+        "{}{}\n",
+        color("\x1b[32;1m"),
+        tr!("Use `man sesh` to find out more about the shell in general.")
+    );
+    out += &format!(
+        "{}{}\n",
+        color("\x1b[34;1m"),
+        tr!("Use `man -k' or `info' to find out more about commands not in this list.")
+    );
+    out += "\n";
+    let mut builtins = BUILTINS;
+    builtins.sort_by(|v1, v2| v1.0.cmp(v2.0));
+
+    for (i, builtin) in builtins.iter().enumerate() {
+        if builtin.0 == "gay" {
+            continue;
+        }
+        if state.in_mode {
+            let table = [
+                "\x1b[34;1m",
+                "\x1b[36;1m",
+                "\x1b[35;1m",
+                "\x1b[31;1m",
+                "\x1b[38;2;255;165;0m",
+                "\x1b[33;1m",
+                "\x1b[32;1m",
+            ];
+            let idx = i % table.len();
+            out += table[idx];
+        }
+        out += &format!("{} {}\n", builtin.0, builtin.2);
+    }
+    for plugin in &state.plugins {
+        out += &format!("\n{}\n", tr!("From plugin {}:", plugin.name));
+        for builtin in &plugin.builtins {
+            out += &format!("{} {}\n", builtin.name, builtin.usage);
+        }
+    }
+    if !state.aliases.is_empty() {
+        out += &format!("\n{}\n", tr!("Aliases:"));
+        for alias in &state.aliases {
+            match &alias.desc {
+                Some(desc) => out += &format!("{}: {}\n", alias.name, desc),
+                None => out += &format!("{} {}\n", alias.name, tr!("(alias for `{}`)", alias.to)),
+            }
+        }
+    }
+    page(&super::color::fit(&out), io, state)?;
+    Ok(0)
+}
+
+/// The builtins, including the ones from plugins, as JSON.
+fn builtins_json(state: &super::State) -> serde_json::Value {
+    let mut list = BUILTINS
+        .iter()
+        .filter(|v| v.0 != "gay")
+        .map(|v| serde_json::json!({"name": v.0, "usage": v.2, "description": v.3}))
+        .collect::<Vec<serde_json::Value>>();
+    for plugin in &state.plugins {
+        for builtin in &plugin.builtins {
+            list.push(serde_json::json!({
+                "name": builtin.name,
+                "usage": builtin.usage,
+                "description": builtin.help,
+                "plugin": plugin.name,
+            }));
+        }
+    }
+    serde_json::Value::Array(list)
+}
+
+/// Print the builtins for other programs to read, one name per line or as a JSON array.
+pub fn print_list(json: bool) {
+    let builtins = BUILTINS.iter().filter(|v| v.0 != "gay");
+    if json {
+        let list = builtins
+            .map(|v| serde_json::json!({"name": v.0, "usage": v.2, "description": v.3}))
+            .collect::<Vec<serde_json::Value>>();
+        println!("{}", serde_json::Value::Array(list));
+    } else {
+        for builtin in builtins {
+            println!("{}", builtin.0);
+        }
+    }
+}
+
+/// Render a man-style page for a builtin, in bold where it makes sense if it's going to a
+/// terminal.
+fn manual_page(name: &str, usage: &str, description: &str, terminal: bool) -> String {
+    let width = crossterm::terminal::size()
+        .map_or(80, |v| v.0 as usize)
+        .clamp(40, 100)
+        - 7;
+    let (bold, roman) = if terminal {
+        ("\x1b[1m", "\x1b[0m")
+    } else {
+        ("", "")
+    };
+    let mut out = format!("{bold}NAME{roman}\n       {name} - sesh builtin\n\n");
+    out += &format!("{bold}SYNOPSIS{roman}\n       {bold}{name}{roman} {usage}\n\n");
+    out += &format!("{bold}DESCRIPTION{roman}\n");
+    let mut line = String::new();
+    for word in description.split_whitespace() {
+        if !line.is_empty() && line.len() + word.len() + 1 > width {
+            out += &format!("       {}\n", line);
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line += word;
+    }
+    out += &format!("       {}\n", line);
+    out
+}
+
+/// Write some text, through $PAGER (or less) if it's going to the terminal and is too long to fit
+/// on it, and the shell isn't restricted.
+fn page(text: &str, io: &mut Io, state: &super::State) -> std::io::Result<()> {
+    let fits = crossterm::terminal::size().is_ok_and(|v| text.lines().count() < v.1 as usize);
+    // pagers can run programs and write files, like less with ! and s
+    if fits || !io.terminal || state.restricted {
+        return io.stdout.write_all(text.as_bytes());
+    }
+    let pager = state
+        .shell_env
+        .iter()
+        .find(|v| v.name == "PAGER")
+        .map(|v| v.value.clone())
+        .or(std::env::var("PAGER").ok())
+        .filter(|v| !v.trim().is_empty())
+        .unwrap_or("less -R".to_string());
+    let mut words = pager.split_whitespace();
+    let mut command = std::process::Command::new(words.next().unwrap());
+    super::export_env(&mut command, state);
+    let child = command
+        .args(words)
+        .current_dir(&state.working_dir)
+        .stdin(std::process::Stdio::piped())
+        .spawn();
+    match child {
+        Ok(mut child) => {
+            // the pager may quit before reading everything
+            let _ = child.stdin.take().unwrap().write_all(text.as_bytes());
+            child.wait()?;
+            Ok(())
+        }
+        Err(_) => io.stdout.write_all(text.as_bytes()),
+    }
+}
+
+/// Describe the shell as JSON
+pub fn introspect(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let focus = match args.get(1).map(|v| v.as_str()) {
+        None => false,
+        Some("--focus") if args.len() == 2 => true,
+        _ => return Err(BuiltinError::Usage(String::new())),
+    };
+    let aliases = state
+        .aliases
+        .iter()
+        .map(|v| serde_json::json!({"name": v.name, "to": v.to, "desc": v.desc}))
+        .collect::<Vec<serde_json::Value>>();
+    let variables = state
+        .shell_env
+        .iter()
+        .map(|v| {
+            let secret = state.secrets.contains(&v.name);
+            serde_json::json!({
+                "name": v.name,
+                "value": if secret { super::SECRET_MASK } else { &v.value },
+                "secret": secret,
+            })
+        })
+        .chain(state.specials.iter().map(
+            |(name, value)| serde_json::json!({"name": name, "value": value, "special": true}),
+        ))
+        .collect::<Vec<serde_json::Value>>();
+    let keybindings = super::KEYBINDINGS
+        .iter()
+        .map(|v| serde_json::json!({"key": v.0, "action": v.1}))
+        .collect::<Vec<serde_json::Value>>();
+    let json = serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "builtins": builtins_json(state),
+        "aliases": aliases,
+        "variables": variables,
+        "options": {
+            "restricted": state.restricted,
+            "guards": state.guards,
+            "plugins": state.plugins.iter().map(|v| v.name.clone()).collect::<Vec<String>>(),
+        },
+        "keybindings": keybindings,
+    });
+    if focus {
+        state.focus = super::Focus::from_json(&json);
+    } else {
+        writeln!(io.stdout, "{}", json)?;
+    }
+    Ok(0)
+}
+
+/// Run a file.
+pub fn eval(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    if args.len() < 2 {
+        return Err(BuiltinError::Usage(tr!("filename argument required")));
+    }
+    if args[1].contains(['/', '\\']) {
+        restricted(state, "cannot source by path")?;
+    }
+
+    let file = std::fs::read(super::os_str::to_path(&args[1]))
+        .map_err(|e| BuiltinError::Failed(2, tr!("error opening file: {}", e)))?;
+    let file = super::decode_script(file);
+
+    let mut state2 = state.clone();
+
+    for (i, arg) in args[1..].iter().enumerate() {
+        state2.shell_env.push(super::ShellVar {
+            name: format!("{}", i),
+            value: arg.clone(),
+            exported: false,
+        });
+    }
+
+    super::eval(&file, &mut state2);
+    super::run_scope_exits(state.scope_exits.len(), &mut state2);
+
+    Ok(0)
+}
+
+/// Rerun the rc file
+pub fn reload(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    if args.len() != 1 {
+        return Err(BuiltinError::Usage(String::new()));
+    }
+    super::reload_rc(state)
+        .map_err(|e| BuiltinError::Failed(1, tr!("reading ~/.seshrc failed: {}", e)))?;
+    Ok(0)
+}
+
+/// Save and restore sessions
+pub fn session(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let name = args.get(2).map_or("default", |v| v.as_str());
+    match args.get(1).map(|v| v.as_str()) {
+        None | Some("list") if args.len() <= 2 => {
+            let names = match super::session::list() {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+                names => names?,
+            };
+            for name in names {
+                writeln!(io.stdout, "{}", name)?;
+            }
+            Ok(0)
+        }
+        Some("save") if args.len() <= 3 => {
+            restricted(state, "cannot save sessions")?;
+            super::session::save(name, state).map_err(|e| {
+                BuiltinError::Failed(1, tr!("saving session {} failed: {}", name, e))
+            })?;
+            Ok(0)
+        }
+        Some("restore") if args.len() <= 3 => {
+            restricted(state, "cannot restore sessions")?;
+            super::session::restore(name, state).map_err(|e| {
+                BuiltinError::Failed(1, tr!("restoring session {} failed: {}", name, e))
+            })?;
+            Ok(0)
+        }
+        _ => Err(BuiltinError::Usage(String::new())),
+    }
+}
+
+/// Record a transcript of the session, or stop recording it
+pub fn transcript(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    use super::transcript::{Ended, recording};
+    match args.get(1).map(|v| v.as_str()) {
+        None => match recording() {
+            Some(path) => {
+                writeln!(io.stdout, "{}", path.display())?;
+                Ok(0)
+            }
+            None => Ok(1),
+        },
+        Some("start") => {
+            restricted(state, "cannot record transcripts")?;
+            let cast = args.get(2).is_some_and(|v| v == "--cast");
+            let stdin = cast && args.get(3).is_some_and(|v| v == "--stdin");
+            let rest = &args[2 + cast as usize + stdin as usize..];
+            // typing is only kept apart in asciinema's format
+            if rest.len() > 1 || rest.first().is_some_and(|v| v == "--stdin") {
+                return Err(BuiltinError::Usage(String::new()));
+            }
+            if let Some(path) = recording() {
+                return Err(BuiltinError::Failed(
+                    1,
+                    tr!("already recording to {}", path.display()),
+                ));
+            }
+            if state.raw_term.is_none() {
+                return Err(BuiltinError::Failed(
+                    1,
+                    tr!("transcripts can only be recorded in an interactive shell"),
+                ));
+            }
+            let path = PathBuf::from(rest.first().map_or(
+                if cast {
+                    "sesh-transcript.cast"
+                } else {
+                    "sesh-transcript.txt"
+                },
+                |v| v.as_str(),
+            ));
+            let ended = super::transcript::record(&path, cast, stdin, state)
+                .map_err(|e| BuiltinError::Failed(1, tr!("recording failed: {}", e)))?;
+            writeln!(
+                io.stderr,
+                "{}",
+                tr!("sesh: transcript saved to {}", path.display())
+            )?;
+            match ended {
+                Ended::Stopped(session) => {
+                    let restored = super::session::restore(&session, state);
+                    let _ =
+                        std::fs::remove_file(super::session::session_dir().join(session + ".json"));
+                    restored.map_err(|e| {
+                        BuiltinError::Failed(1, tr!("taking the session back failed: {}", e))
+                    })?;
+                    Ok(0)
+                }
+                Ended::Exited(status) => super::exit_shell(state, status),
+            }
+        }
+        #[cfg(unix)]
+        Some("stop") if args.len() == 2 => {
+            if recording().is_none() {
+                return Err(BuiltinError::Failed(1, tr!("not recording a transcript")));
+            }
+            if let Err(warning) = super::can_exit(state) {
+                writeln!(io.stderr, "{}", warning)?;
+                return Ok(1);
+            }
+            super::session::save(&super::transcript::handed_back(), state).map_err(|e| {
+                BuiltinError::Failed(1, tr!("handing the session back failed: {}", e))
+            })?;
+            super::exit_shell(state, 0);
+        }
+        _ => Err(BuiltinError::Usage(String::new())),
+    }
+}
+
+/// Print what `f` makes of each path, or replace each string in the focus with it if there are
+/// none.
+fn map_paths(
+    paths: &[String],
+    io: &mut Io,
+    state: &mut super::State,
+    f: impl Fn(&str, &super::State) -> Result<String, BuiltinError>,
+) -> Result<ExitStatus, BuiltinError> {
+    fn map_focus(
+        focus: &super::Focus,
+        state: &super::State,
+        f: &impl Fn(&str, &super::State) -> Result<String, BuiltinError>,
+    ) -> Result<super::Focus, BuiltinError> {
+        Ok(match focus {
+            super::Focus::Str(s) => super::Focus::Str(f(s, state)?),
+            super::Focus::Vec(v) => super::Focus::Vec(
+                v.iter()
+                    .map(|v| map_focus(v, state, f))
+                    .collect::<Result<Vec<super::Focus>, BuiltinError>>()?,
+            ),
+        })
+    }
+
+    if paths.is_empty() {
+        state.focus = map_focus(&state.focus, state, &f)?;
+        return Ok(0);
+    }
+    for path in paths {
+        writeln!(io.stdout, "{}", f(path, state)?)?;
+    }
+    Ok(0)
+}
+
+/// The last component of a path.
+pub fn basename(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let (suffix, paths) = match &args[1..] {
+        [flag, suffix, paths @ ..] if flag == "-s" => (suffix.as_str(), paths),
+        [flag] if flag == "-s" => return Err(BuiltinError::Usage(String::new())),
+        [path, suffix] => (suffix.as_str(), std::slice::from_ref(path)),
+        paths => ("", paths),
+    };
+    map_paths(paths, io, state, |path, _| {
+        let path = super::os_str::to_path(path);
+        let name = match path.file_name() {
+            Some(name) => super::os_str::to_string(name),
+            None => super::os_str::to_string(path.as_os_str()),
+        };
+        Ok(match name.strip_suffix(suffix) {
+            Some(stem) if !stem.is_empty() => stem.to_string(),
+            _ => name,
+        })
+    })
+}
+
+/// A path without its last component.
+pub fn dirname(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    map_paths(&args[1..], io, state, |path, _| {
+        let path = super::os_str::to_path(path);
+        Ok(match path.parent() {
+            Some(parent) if parent.as_os_str().is_empty() => ".".to_string(),
+            Some(parent) => super::os_str::to_string(parent.as_os_str()),
+            None => super::os_str::to_string(path.as_os_str()),
+        })
+    })
+}
+
+/// The way from one absolute path to another.
+fn relative_path(path: &std::path::Path, base: &std::path::Path) -> PathBuf {
+    let path = path.components().collect::<Vec<_>>();
+    let base = base.components().collect::<Vec<_>>();
+    let common = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
+    let mut out = PathBuf::new();
+    for _ in common..base.len() {
+        out.push("..");
+    }
+    out.extend(&path[common..]);
+    if out.as_os_str().is_empty() {
+        out.push(".");
+    }
+    out
+}
+
+/// The absolute path of a path, or the way to it from another.
+pub fn realpath(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let canonical = |path: &str, state: &super::State| {
+        state
+            .working_dir
+            .join(super::os_str::to_path(path))
+            .canonicalize()
+            .map_err(|e| BuiltinError::Failed(1, tr!("{}: {}", path, e)))
+    };
+    let (base, paths) = match args.get(1).map(|v| v.as_str()) {
+        Some("--relative-to") => {
+            let Some(base) = args.get(2) else {
+                return Err(BuiltinError::Usage(tr!("directory required")));
+            };
+            (Some(canonical(base, state)?), &args[3..])
+        }
+        _ => (None, &args[1..]),
+    };
+    map_paths(paths, io, state, |path, state| {
+        let path = canonical(path, state)?;
+        let path = match &base {
+            Some(base) => relative_path(&path, base),
+            None => path,
+        };
+        Ok(super::os_str::to_string(path.as_os_str()))
+    })
+}
+
+/// Load a file into the focused variable.
+pub fn loadf(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    if args.len() < 2 {
+        return Err(BuiltinError::Usage(tr!("filename argument required")));
+    }
+    let path = args[1..].concat().clone();
+
+    let file = std::fs::read(super::os_str::to_path(&path))
+        .map_err(|e| BuiltinError::Failed(2, tr!("error opening file: {}", e)))?;
+    let file = super::decode_script(file);
+
+    state.focus = super::Focus::Str(file);
+
+    Ok(0)
+}
+
+/// Split the focus on a character.
+pub fn splitf(
+    mut args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    if args.len() >= 3 && args[2] == "-e" {
+        args[1] = super::escapes::interpret_escaped_string(&args[1])
+            .map_err(|e| BuiltinError::Failed(1, tr!("invalid escape: {}", e)))?;
     }
     let split = args.get(1).unwrap_or(&" ".to_string()).clone();
 
@@ -341,94 +2034,676 @@ pub fn splitf(mut args: Vec<String>, _: String, state: &mut super::State) -> i32
         }
     }
 
-    state.focus = split_into(state.focus.clone(), split);
+    state.focus = split_into(state.focus.clone(), split);
+
+    Ok(0)
+}
+
+/// Set variable(s)
+pub fn set(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let secret = args.get(1).is_some_and(|v| v == "--secret");
+    let first = if secret { 2 } else { 1 };
+    if args.len() <= first {
+        return Err(BuiltinError::Usage(tr!("at least one variable required")));
+    }
+    for var in &args[first..] {
+        let Some((name, value)) = var.split_once("=") else {
+            return Err(BuiltinError::Usage(tr!("var=name pairs required")));
+        };
+        restricted_var(state, name)?;
+        if secret && !state.secrets.iter().any(|v| v == name) {
+            state.secrets.push(name.to_string());
+        }
+        state.shell_env.push(super::ShellVar {
+            name: name.to_string(),
+            value: value.to_string(),
+            exported: false,
+        });
+    }
+
+    Ok(0)
+}
+
+/// Capture the output and status of a statement.
+pub fn capture(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let [_, name, statement] = args.as_slice() else {
+        return Err(BuiltinError::Usage(String::new()));
+    };
+    let names = [
+        name.clone(),
+        name.clone() + "_ERR",
+        name.clone() + "_STATUS",
+    ];
+    for name in &names {
+        restricted_var(state, name)?;
+    }
+    let (stdout, stderr) = super::capture_output(statement, state);
+    let status = super::get_status(state);
+    let [name, err_name, status_name] = names;
+    super::swap_vars(
+        vec![
+            (name, Some(stdout.trim_end_matches('\n').to_string())),
+            (err_name, Some(stderr.trim_end_matches('\n').to_string())),
+            (status_name, Some(status.to_string())),
+        ],
+        state,
+    );
+    Ok(status)
+}
+
+/// Put what's known about a file in variables.
+pub fn filef(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let [_, name, path] = args.as_slice() else {
+        return Err(BuiltinError::Usage(String::new()));
+    };
+    let names = ["", "_SIZE", "_MTIME", "_MODE", "_OWNER"].map(|v| name.clone() + v);
+    for name in &names {
+        restricted_var(state, name)?;
+    }
+    let metadata = state
+        .working_dir
+        .join(super::os_str::to_path(path))
+        .symlink_metadata()
+        .map_err(|e| BuiltinError::Failed(1, tr!("{}: {}", path, e)))?;
+    let kind = if metadata.is_symlink() {
+        "symlink"
+    } else if metadata.is_dir() {
+        "dir"
+    } else if metadata.is_file() {
+        "file"
+    } else {
+        "other"
+    };
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|v| v.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |v| v.as_secs());
+    #[cfg(unix)]
+    let (mode, owner) = {
+        use std::os::unix::fs::MetadataExt;
+        let owner = users::get_user_by_uid(metadata.uid())
+            .map_or(metadata.uid().to_string(), |v| {
+                v.name().to_string_lossy().to_string()
+            });
+        (format!("{:o}", metadata.mode() & 0o7777), owner)
+    };
+    #[cfg(not(unix))]
+    let (mode, owner) = (String::new(), String::new());
+    let values = [
+        kind.to_string(),
+        metadata.len().to_string(),
+        mtime.to_string(),
+        mode,
+        owner,
+    ];
+    super::swap_vars(
+        names
+            .into_iter()
+            .zip(values)
+            .map(|(name, value)| (name, Some(value)))
+            .collect(),
+        state,
+    );
+    Ok(0)
+}
+
+/// Create a temporary file, or a directory if `dir`, that only the user can get at, named the
+/// prefix followed by random characters.
+#[cfg(unix)]
+fn make_temp(prefix: &str, dir: bool) -> std::io::Result<std::path::PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+    let template = std::env::temp_dir().join(format!("{}XXXXXX", prefix));
+    let mut template = std::ffi::CString::new(template.into_os_string().into_vec())
+        .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidInput))?
+        .into_bytes_with_nul();
+    let ptr = template.as_mut_ptr().cast();
+    if dir {
+        if unsafe { libc::mkdtemp(ptr) }.is_null() {
+            return Err(std::io::Error::last_os_error());
+        }
+    } else {
+        let fd = unsafe { libc::mkstemp(ptr) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        unsafe { libc::close(fd) };
+    }
+    template.pop();
+    Ok(std::ffi::OsString::from_vec(template).into())
+}
+
+/// Create a temporary file, or a directory if `dir`, named the prefix followed by random
+/// characters.
+#[cfg(not(unix))]
+fn make_temp(prefix: &str, dir: bool) -> std::io::Result<std::path::PathBuf> {
+    loop {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |v| v.subsec_nanos());
+        let name = format!("{}{:x}{:x}", prefix, std::process::id(), nanos);
+        let path = std::env::temp_dir().join(name);
+        let made = if dir {
+            std::fs::create_dir(&path)
+        } else {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .map(|_| ())
+        };
+        match made {
+            Ok(()) => return Ok(path),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Convert sizes and durations to and from human units.
+pub fn units(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let (mut human, mut var, mut rest) = (false, None, Vec::new());
+    let mut args = args[1..].iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--human" => human = true,
+            "--var" => {
+                var = Some(
+                    args.next()
+                        .ok_or(BuiltinError::Usage(tr!("variable required")))?,
+                )
+            }
+            _ => rest.push(arg.as_str()),
+        }
+    }
+    let [kind, value] = rest.as_slice() else {
+        return Err(BuiltinError::Usage(String::new()));
+    };
+    if let Some(var) = var {
+        restricted_var(state, var)?;
+    }
+    let invalid = || BuiltinError::Failed(1, tr!("invalid {} {}", kind, value));
+    let out = match (*kind, human) {
+        ("size", false) => super::units::parse_size(value)
+            .ok_or_else(invalid)?
+            .to_string(),
+        ("size", true) => super::units::format_size(value.parse().map_err(|_| invalid())?),
+        ("duration", false) => {
+            super::units::trim(super::units::parse_duration(value).ok_or_else(invalid)?)
+        }
+        ("duration", true) => match value.parse::<f64>() {
+            Ok(seconds) if seconds >= 0.0 => super::units::format_duration(seconds),
+            _ => return Err(invalid()),
+        },
+        _ => return Err(BuiltinError::Usage(String::new())),
+    };
+    match var {
+        Some(var) => {
+            super::swap_vars(vec![(var.clone(), Some(out))], state);
+        }
+        None => state.focus = super::Focus::Str(out),
+    }
+    Ok(0)
+}
+
+/// List processes into the focus.
+pub fn procs(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let (user, pattern) = match args.as_slice() {
+        [_] => (None, None),
+        [_, pattern] => (None, Some(pattern)),
+        [_, flag, user] if flag == "--user" => (Some(user), None),
+        [_, flag, user, pattern] if flag == "--user" => (Some(user), Some(pattern)),
+        _ => return Err(BuiltinError::Usage(String::new())),
+    };
+    let pattern = pattern
+        .map(|v| regex::Regex::new(v))
+        .transpose()
+        .map_err(|e| BuiltinError::Failed(1, tr!("invalid regular expression: {}", e)))?;
+    let rows = super::procs::list()?
+        .into_iter()
+        .filter(|v| user.is_none_or(|user| v.user == *user))
+        .filter(|v| {
+            pattern
+                .as_ref()
+                .is_none_or(|pattern| pattern.is_match(&v.command))
+        })
+        .map(|v| {
+            super::Focus::Vec(
+                [
+                    v.pid.to_string(),
+                    v.user,
+                    format!("{:.1}", v.cpu),
+                    format!("{:.1}", v.mem),
+                    v.command,
+                ]
+                .into_iter()
+                .map(super::Focus::Str)
+                .collect(),
+            )
+        })
+        .collect::<Vec<super::Focus>>();
+    let found = !rows.is_empty();
+    state.focus = super::Focus::Vec(rows);
+    Ok(if found { 0 } else { 1 })
+}
+
+/// List filesystems and their usage into the focus.
+pub fn dfp(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let mounted = super::machine::filesystems()?;
+    let found = if args.len() == 1 {
+        mounted
+    } else {
+        args[1..]
+            .iter()
+            .map(|v| {
+                super::machine::filesystem_of(std::path::Path::new(v), &mounted)
+                    .ok_or_else(|| BuiltinError::Failed(1, tr!("no filesystem found for {}", v)))
+            })
+            .collect::<Result<Vec<super::machine::Filesystem>, BuiltinError>>()?
+    };
+    let rows = found
+        .into_iter()
+        .map(|v| {
+            super::Focus::Vec(
+                [
+                    v.mount.to_string_lossy().to_string(),
+                    v.kind.clone(),
+                    v.source.clone(),
+                    v.size.to_string(),
+                    v.used.to_string(),
+                    v.available.to_string(),
+                    v.percent().to_string(),
+                ]
+                .into_iter()
+                .map(super::Focus::Str)
+                .collect(),
+            )
+        })
+        .collect();
+    state.focus = super::Focus::Vec(rows);
+    Ok(0)
+}
+
+/// List network interfaces and their addresses into the focus.
+pub fn netif(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let name = match args.as_slice() {
+        [_] => None,
+        [_, name] => Some(name),
+        _ => return Err(BuiltinError::Usage(String::new())),
+    };
+    let rows = super::machine::interfaces()?
+        .into_iter()
+        .filter(|v| name.is_none_or(|name| v.name == *name))
+        .map(|v| {
+            super::Focus::Vec(vec![
+                super::Focus::Str(v.name),
+                super::Focus::Str(if v.up { "up" } else { "down" }.to_string()),
+                super::Focus::Str(v.mac.unwrap_or_default()),
+                super::Focus::Vec(v.addresses.into_iter().map(super::Focus::Str).collect()),
+            ])
+        })
+        .collect::<Vec<super::Focus>>();
+    let found = !rows.is_empty();
+    state.focus = super::Focus::Vec(rows);
+    Ok(if found { 0 } else { 1 })
+}
+
+/// Create a temporary file or directory and put its path in the focus or a variable.
+pub fn mktempf(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let (mut dir, mut cleanup, mut var, mut prefix) = (false, false, None, None);
+    let mut args = args[1..].iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-d" => dir = true,
+            "--cleanup" => cleanup = true,
+            "--var" => {
+                var = Some(
+                    args.next()
+                        .ok_or(BuiltinError::Usage(tr!("variable required")))?,
+                )
+            }
+            _ if prefix.is_none() && !arg.starts_with('-') => prefix = Some(arg.as_str()),
+            _ => return Err(BuiltinError::Usage(String::new())),
+        }
+    }
+    let prefix = prefix.unwrap_or("sesh.");
+    if prefix.contains(['/', '\\']) {
+        return Err(BuiltinError::Usage(tr!("the prefix can't contain slashes")));
+    }
+    if let Some(var) = var {
+        restricted_var(state, var)?;
+    }
+    let path = make_temp(prefix, dir)?;
+    if cleanup {
+        state
+            .scope_exits
+            .push(super::ScopeExit::Remove(path.clone()));
+    }
+    let path = super::os_str::to_string(path.as_os_str());
+    match var {
+        Some(var) => {
+            super::swap_vars(vec![(var.clone(), Some(path))], state);
+        }
+        None => state.focus = super::Focus::Str(path),
+    }
+    Ok(0)
+}
+
+/// Let the user edit a value on the terminal, for vared and alias --edit. Returns None if they gave
+/// up.
+fn edit_value(
+    prompt: &str,
+    value: &str,
+    io: &mut Io,
+    state: &super::State,
+) -> Result<Option<String>, BuiltinError> {
+    if !io.terminal {
+        return Err(BuiltinError::Failed(1, tr!("not a terminal")));
+    }
+    let autopair = super::get_var(state, "AUTOPAIR").as_deref() == Some("true");
+    Ok(super::editor::edit(prompt, value, autopair)?)
+}
+
+/// Edit a variable in place.
+pub fn vared(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let [_, name] = args.as_slice() else {
+        return Err(BuiltinError::Usage(String::new()));
+    };
+    restricted_var(state, name)?;
+    if state.secrets.contains(name) {
+        return Err(BuiltinError::Failed(1, tr!("{} is secret", name)));
+    }
+    let value = super::get_var(state, name).unwrap_or_default();
+    let Some(value) = edit_value(&format!("{}=", name), &value, io, state)? else {
+        return Ok(1);
+    };
+    super::swap_vars(vec![(name.clone(), Some(value))], state);
+    Ok(0)
+}
+
+/// Add elements to the end of a list variable.
+pub fn pushvar(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    if args.len() < 3 {
+        return Err(BuiltinError::Usage(String::new()));
+    }
+    let name = &args[1];
+    restricted_var(state, name)?;
+    let mut elements = super::get_var(state, name)
+        .map(|v| v.lines().map(|v| v.to_string()).collect::<Vec<String>>())
+        .unwrap_or_default();
+    elements.extend(args[2..].iter().cloned());
+    super::swap_vars(vec![(name.clone(), Some(elements.join("\n")))], state);
+    Ok(0)
+}
+
+/// Take an element off either end of a list variable, for popvar and shiftvar.
+fn take_element(
+    args: &[String],
+    front: bool,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let (name, into) = match args {
+        [_, name] => (name, None),
+        [_, name, into] => (name, Some(into)),
+        _ => return Err(BuiltinError::Usage(String::new())),
+    };
+    restricted_var(state, name)?;
+    if let Some(into) = into {
+        restricted_var(state, into)?;
+    }
+    let mut elements = super::get_var(state, name)
+        .map(|v| v.lines().map(|v| v.to_string()).collect::<Vec<String>>())
+        .unwrap_or_default();
+    if elements.is_empty() {
+        return Err(BuiltinError::Failed(1, tr!("{} is empty", name)));
+    }
+    let element = if front {
+        elements.remove(0)
+    } else {
+        elements.pop().unwrap()
+    };
+    let mut vars = vec![(name.clone(), Some(elements.join("\n")))];
+    match into {
+        Some(into) => vars.push((into.clone(), Some(element))),
+        None => writeln!(io.stdout, "{}", element)?,
+    }
+    super::swap_vars(vars, state);
+    Ok(0)
+}
 
-    0
+/// Take the last element off a list variable.
+pub fn popvar(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    take_element(&args, false, io, state)
 }
 
-/// Set variable(s)
-pub fn set(args: Vec<String>, _: String, state: &mut super::State) -> i32 {
-    if args.len() < 2 {
-        println!("sesh: {}: at least one variable required", args[0]);
-        println!("sesh: {0}: usage: {0} name=value [name=value ...]", args[0]);
-        return 1;
+/// Take the first element off a list variable.
+pub fn shiftvar(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    take_element(&args, true, io, state)
+}
+
+/// Dump all variables.
+pub fn dumpvars(
+    _: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    for (name, value) in &state.specials {
+        writeln!(io.stdout, "{}: \"{}\"", name, value)?;
     }
-    for var in &args[1..] {
-        let split = var.split_once("=");
-        if split.is_none() {
-            println!("sesh: {}: var=name pairs required", args[0]);
-            println!("sesh: {0}: usage: {0} name=value [name=value ...]", args[0]);
-            return 2;
+    for super::ShellVar { name, value, .. } in &state.shell_env {
+        if state.secrets.contains(name) {
+            writeln!(io.stdout, "{}: {}", name, super::SECRET_MASK)?;
+        } else {
+            writeln!(io.stdout, "{}: \"{}\"", name, value)?;
         }
-        let (name, value) = split.unwrap();
-        state.shell_env.push(super::ShellVar {
-            name: name.to_string(),
-            value: value.to_string(),
-        });
     }
-
-    0
+    Ok(0)
 }
 
-/// Dump all variables.
-pub fn dumpvars(_: Vec<String>, _: String, state: &mut super::State) -> i32 {
-    for super::ShellVar { name, value } in &state.shell_env {
-        println!("{}: \"{}\"", name, value);
+/// Export variables to programs
+pub fn export(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    if args.len() == 1 {
+        super::garbage_collect_vars(state);
+        for var in &state.shell_env {
+            if super::is_exported(state, &var.name) {
+                writeln!(io.stdout, "{}", var.name)?;
+            }
+        }
+        return Ok(0);
+    }
+    for arg in &args[1..] {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+        restricted_var(state, name)?;
+        let value = match value {
+            Some(value) => value,
+            None => super::get_var(state, name)
+                .ok_or_else(|| BuiltinError::Failed(1, tr!("{} isn't set", name)))?,
+        };
+        state.shell_env.push(super::ShellVar {
+            name: name.to_string(),
+            value,
+            exported: true,
+        });
     }
-    0
+    super::garbage_collect_vars(state);
+    Ok(0)
 }
 
 /// Unset variable(s)
-pub fn unset(args: Vec<String>, _: String, state: &mut super::State) -> i32 {
+pub fn unset(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
     if args.len() < 2 {
-        println!("sesh: {}: at least one variable required", args[0]);
-        println!("sesh: {0}: usage: {0} name [name ...]", args[0]);
-        return 1;
+        return Err(BuiltinError::Usage(tr!("at least one variable required")));
+    }
+    for var in &args[1..] {
+        restricted_var(state, var)?;
     }
     for (i, ele) in state.shell_env.clone().into_iter().enumerate() {
         if args[1..].contains(&ele.name) {
             state.shell_env.remove(i);
         }
     }
+    state.secrets.retain(|v| !args[1..].contains(v));
+    for var in &args[1..] {
+        if !state.unset_vars.contains(var) {
+            state.unset_vars.push(var.clone());
+        }
+    }
+
+    Ok(0)
+}
 
-    0
+/// Fix up the terminal.
+pub fn sane(
+    _: Vec<String>,
+    _: String,
+    _: &mut Io,
+    _: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    super::term::sane()?;
+    Ok(0)
 }
 
 /// Copy the focus to the clipboard.
-pub fn copyf(_: Vec<String>, _: String, state: &mut super::State) -> i32 {
-    let mut clipboard = arboard::Clipboard::new().unwrap();
-    clipboard
-        .set_text(match &state.focus {
-            super::Focus::Str(s) => s.clone(),
-            super::Focus::Vec(_) => format!("{}", state.focus),
-        })
-        .unwrap();
-    0
+pub fn copyf(
+    _: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    super::clipboard::set(&match &state.focus {
+        super::Focus::Str(s) => s.clone(),
+        super::Focus::Vec(_) => format!("{}", state.focus),
+    })
+    .map_err(|e| BuiltinError::Failed(1, tr!("set clipboard text error: {}", e)))?;
+    Ok(0)
 }
 
 /// Paste from the clipboard into the focus.
-pub fn pastef(args: Vec<String>, _: String, state: &mut super::State) -> i32 {
-    let mut clipboard = arboard::Clipboard::new().unwrap();
-    let text = clipboard.get_text();
-    if let Err(e) = text {
-        println!("sesh: {}: get clipboard text error: {}", args[0], e);
-        1
-    } else if let Ok(text) = text {
-        state.focus = super::Focus::Str(text);
-        0
-    } else {
-        unsafe {
-            unreachable_unchecked();
+pub fn pastef(
+    _: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let text = super::clipboard::get().map_err(|e| BuiltinError::Failed(1, e))?;
+    state.focus = super::Focus::Str(text);
+    Ok(0)
+}
+
+/// Copy text or stdin to the clipboard, or print what's on it.
+pub fn clip(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    _: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    if args.get(1).is_some_and(|v| v == "-o") {
+        if args.len() != 2 {
+            return Err(BuiltinError::Usage(String::new()));
         }
+        let text = super::clipboard::get().map_err(|e| BuiltinError::Failed(1, e))?;
+        io.stdout.write_all(text.as_bytes())?;
+        return Ok(0);
     }
+    let text = if args.len() > 1 {
+        args[1..].join(" ")
+    } else {
+        let mut text = String::new();
+        io.stdin.read_to_string(&mut text)?;
+        text
+    };
+    super::clipboard::set(&text)
+        .map_err(|e| BuiltinError::Failed(1, tr!("set clipboard text error: {}", e)))?;
+    Ok(0)
 }
 
 /// Set a variable to the contents of the focus.
-pub fn setf(args: Vec<String>, _: String, state: &mut super::State) -> i32 {
+pub fn setf(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
     if args.len() < 2 {
-        println!("sesh: {}: at least one variable required", args[0]);
-        println!("sesh: {0}: usage: {0} var [var ...]", args[0]);
-        return 1;
+        return Err(BuiltinError::Usage(tr!("at least one variable required")));
+    }
+    for var in &args[1..] {
+        restricted_var(state, var)?;
     }
     for var in &args[1..] {
         state.shell_env.push(super::ShellVar {
@@ -437,17 +2712,21 @@ pub fn setf(args: Vec<String>, _: String, state: &mut super::State) -> i32 {
                 super::Focus::Str(s) => s.clone(),
                 super::Focus::Vec(_) => format!("{}", state.focus),
             },
+            exported: false,
         });
     }
-    0
+    Ok(0)
 }
 
 /// Set the focus to the contents of a variable
-pub fn getf(args: Vec<String>, _: String, state: &mut super::State) -> i32 {
+pub fn getf(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
     if args.len() != 2 {
-        println!("sesh: {}: exactly one variable required", args[0]);
-        println!("sesh: {0}: usage: {0} var", args[0]);
-        return 1;
+        return Err(BuiltinError::Usage(tr!("exactly one variable required")));
     }
     let mut val = String::new();
     for var in &state.shell_env {
@@ -457,78 +2736,650 @@ pub fn getf(args: Vec<String>, _: String, state: &mut super::State) -> i32 {
         }
     }
     state.focus = super::Focus::Str(val);
-    0
+    Ok(0)
+}
+
+/// Lay out a focus for showf. Lists nested deeper than `depth` are collapsed.
+fn show_focus(focus: &super::Focus, depth: Option<usize>, terminal: bool) -> String {
+    /// Lay out the elements of a list, indented by its level.
+    fn show_list(
+        items: &[super::Focus],
+        level: usize,
+        depth: Option<usize>,
+        terminal: bool,
+        out: &mut String,
+    ) {
+        for (i, item) in items.iter().enumerate() {
+            let index = if terminal {
+                format!("\x1b[2m[{}]\x1b[22m", i)
+            } else {
+                format!("[{}]", i)
+            };
+            out.push_str(&"  ".repeat(level));
+            match item {
+                super::Focus::Str(s) => {
+                    *out += &format!("{} \"{}\"\n", index, s.replace('\n', "\\n"));
+                }
+                super::Focus::Vec(v) if depth.is_some_and(|v| level + 1 >= v) => {
+                    *out += &format!("{} {}\n", index, tr!("list ({}) ...", v.len()));
+                }
+                super::Focus::Vec(v) => {
+                    *out += &format!("{} {}\n", index, tr!("list ({})", v.len()));
+                    show_list(v, level + 1, depth, terminal, out);
+                }
+            }
+        }
+    }
+    match focus {
+        super::Focus::Str(s) if s.ends_with('\n') || s.is_empty() => s.clone(),
+        super::Focus::Str(s) => s.clone() + "\n",
+        super::Focus::Vec(v) => {
+            let mut out = tr!("list ({})", v.len()) + "\n";
+            if depth != Some(0) {
+                show_list(v, 0, depth, terminal, &mut out);
+            }
+            out
+        }
+    }
+}
+
+/// Show the focus.
+pub fn showf(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let depth = match args.as_slice() {
+        [_] => None,
+        [_, flag, depth] if flag == "-d" => Some(
+            depth
+                .parse::<usize>()
+                .map_err(|_| BuiltinError::Usage(tr!("invalid depth: {}", depth)))?,
+        ),
+        _ => return Err(BuiltinError::Usage(String::new())),
+    };
+    page(&show_focus(&state.focus, depth, io.terminal), io, state)?;
+    Ok(0)
 }
 
 /// Empty function that does nothing. Mainly used for benchmarking evaluating.
-pub fn nop(_: Vec<String>, _: String, _: &mut super::State) -> i32 {
-    0
+pub fn nop(
+    _: Vec<String>,
+    _: String,
+    _: &mut Io,
+    _: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    Ok(0)
 }
 
 /// if statement
-pub fn _if(args: Vec<String>, _: String, state: &mut super::State) -> i32 {
+pub fn _if(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
     if args.len() < 3 {
-        println!(
-            "sesh: {0}: usage: {0} condition (statement) [ (else_statement) ]",
-            args[0]
-        );
-        return 1;
+        return Err(BuiltinError::Usage(String::new()));
     }
     super::eval(&args[1].clone(), state);
-    state.shell_env.reverse();
-    let mut status = 0i32;
-    for var in &state.shell_env {
-        if var.name == "STATUS" {
-            status = var.value.parse().unwrap();
-        }
-    }
-    state.shell_env.sort_by(|v1, v2| v1.name.cmp(&v2.name));
-    if status == 0 {
+    if super::get_status(state) == 0 {
         super::eval(&args[2].clone(), state);
-    } else if args.len() == 8 {
+    } else if args.len() > 3 {
         super::eval(&args[3].clone(), state);
     }
 
-    0
+    Ok(0)
 }
 
 /// loop while a condition is true
-pub fn _while(args: Vec<String>, _: String, state: &mut super::State) -> i32 {
+pub fn _while(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
     if args.len() < 3 {
-        println!("sesh: {0}: usage: {0} condition (statement)", args[0]);
-        return 1;
+        return Err(BuiltinError::Usage(String::new()));
     }
 
     fn test(condition: String, state: &mut super::State) -> bool {
         super::eval(&condition, state);
-        state.shell_env.reverse();
-        let mut status = 0i32;
-        for var in &state.shell_env {
-            if var.name == "STATUS" {
-                status = var.value.parse().unwrap();
-            }
-        }
-        state.shell_env.sort_by(|v1, v2| v1.name.cmp(&v2.name));
-        status == 0
+        super::get_status(state) == 0
     }
 
+    state.loop_depth += 1;
+    let mut status = 0;
     while test(args[1].clone(), state) {
         super::eval(&args[2].clone(), state);
+        if super::abandoned() {
+            status = 130;
+            break;
+        }
+        if state.loop_control.take() == Some(super::LoopControl::Break)
+            || state.exit_request.is_some()
+        {
+            break;
+        }
+    }
+    state.loop_depth -= 1;
+
+    Ok(status)
+}
+
+/// The elements of the focus as strings, or its lines if it's a string.
+fn focus_items(focus: &super::Focus) -> Vec<String> {
+    match focus {
+        super::Focus::Str(s) => s.lines().map(|v| v.to_string()).collect(),
+        super::Focus::Vec(v) => v
+            .iter()
+            .map(|v| match v {
+                super::Focus::Str(s) => s.clone(),
+                super::Focus::Vec(_) => format!("{}", v),
+            })
+            .collect(),
+    }
+}
+
+/// Run a statement for each item
+pub fn _for(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    if args.len() < 4 || args[2] != "in" {
+        return Err(BuiltinError::Usage(String::new()));
+    }
+    let var = &args[1];
+    restricted_var(state, var)?;
+    let statement = &args[args.len() - 1];
+    let items = if args.len() > 4 {
+        args[3..args.len() - 1].to_vec()
+    } else {
+        focus_items(&state.focus)
+    };
+
+    let old = super::get_var(state, var);
+    let mut status = 0;
+    state.loop_depth += 1;
+    for item in items {
+        super::swap_vars(vec![(var.clone(), Some(item))], state);
+        super::eval(statement, state);
+        status = super::get_status(state);
+        if super::take_interrupt() || super::abandoned() {
+            status = 130;
+            break;
+        }
+        if state.loop_control.take() == Some(super::LoopControl::Break)
+            || state.exit_request.is_some()
+        {
+            break;
+        }
+    }
+    state.loop_depth -= 1;
+    // the variable only lasts for the loop, so a statement using it isn't expanded early next time
+    super::swap_vars(vec![(var.clone(), old)], state);
+    Ok(status)
+}
+
+/// Stop the innermost loop or go on to its next time around, for break and continue.
+fn loop_control(
+    args: &[String],
+    control: super::LoopControl,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    if args.len() != 1 {
+        return Err(BuiltinError::Usage(String::new()));
+    }
+    if state.loop_depth == 0 {
+        return Err(BuiltinError::Failed(1, tr!("not in a loop")));
+    }
+    state.loop_control = Some(control);
+    Ok(0)
+}
+
+/// Stop the innermost loop
+pub fn _break(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    loop_control(&args, super::LoopControl::Break, state)
+}
+
+/// Go on to the next time around the innermost loop
+pub fn _continue(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    loop_control(&args, super::LoopControl::Continue, state)
+}
+
+/// Run statements in a copy of the shell
+pub fn subshell(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    if args.len() != 2 {
+        return Err(BuiltinError::Usage(String::new()));
+    }
+    let mut state2 = state.clone();
+    state2.subshell_depth += 1;
+    super::eval(&args[1], &mut state2);
+    Ok(state2
+        .exit_request
+        .unwrap_or_else(|| super::get_status(&state2)))
+}
+
+/// The lock file of a lock, by name or path.
+pub fn lock_path(name: &str, state: &super::State) -> std::io::Result<std::path::PathBuf> {
+    if name.contains(['/', '\\']) {
+        return Ok(state.working_dir.join(super::os_str::to_path(name)));
+    }
+    #[cfg(unix)]
+    let dir = std::env::temp_dir().join(format!("sesh-locks-{}", users::get_current_uid()));
+    #[cfg(not(unix))]
+    let dir = std::env::temp_dir().join("sesh-locks");
+    let mut builder = std::fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.recursive(true).create(&dir)?;
+    // anyone can make it first in a shared temporary directory, to swap out the locks in it
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = std::fs::symlink_metadata(&dir)?;
+        if !metadata.is_dir()
+            || metadata.uid() != users::get_current_uid()
+            || metadata.mode() & 0o077 != 0
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                tr!("{} isn't a directory only you can get at", dir.display()),
+            ));
+        }
+    }
+    Ok(dir.join(name.to_string() + ".lock"))
+}
+
+/// Run a statement while holding a lock.
+pub fn lock(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let (wait, name, statement) = match args.as_slice() {
+        [_, name, statement] => (None, name, statement),
+        [_, flag, name, statement] if flag == "-n" => {
+            (Some(std::time::Duration::ZERO), name, statement)
+        }
+        [_, flag, delay, name, statement] if flag == "-t" => {
+            let delay = crate::timer::parse_delay(delay)
+                .ok_or_else(|| BuiltinError::Usage(tr!("invalid delay {}", delay)))?;
+            (Some(delay), name, statement)
+        }
+        _ => return Err(BuiltinError::Usage(String::new())),
+    };
+    if name.contains(['/', '\\']) {
+        restricted(state, "cannot lock by path")?;
+    }
+    let path = lock_path(name, state)?;
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)?;
+    let start = std::time::Instant::now();
+    super::take_interrupt();
+    loop {
+        match file.try_lock() {
+            Ok(()) => break,
+            Err(std::fs::TryLockError::WouldBlock) => (),
+            Err(std::fs::TryLockError::Error(e)) => return Err(e.into()),
+        }
+        if wait.is_some_and(|v| start.elapsed() >= v) {
+            return Err(BuiltinError::Failed(1, tr!("{} is locked", name)));
+        }
+        if super::take_interrupt() {
+            return Err(BuiltinError::Failed(1, tr!("stopped waiting for {}", name)));
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    super::eval(statement, state);
+    // closing the file lets go of the lock
+    drop(file);
+    Ok(super::get_status(state))
+}
+
+/// Run statements at the same time
+pub fn par(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let mut limit = usize::MAX;
+    let mut statements = Vec::new();
+    let mut args = args.into_iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "-j" {
+            let count = args.next().unwrap_or_default();
+            limit = count
+                .parse()
+                .ok()
+                .filter(|v| *v > 0)
+                .ok_or_else(|| BuiltinError::Usage(tr!("invalid count {}", count)))?;
+        } else {
+            statements.push(arg);
+        }
+    }
+    if statements.is_empty() {
+        return Err(BuiltinError::Usage(String::new()));
+    }
+
+    state
+        .shell_env
+        .retain(|v| !v.name.starts_with("PAR_STATUS_"));
+    let mut statuses = vec![None; statements.len()];
+    // job ids and which statement they're running
+    let mut running = Vec::<(usize, usize)>::new();
+    let mut next = 0;
+    super::take_interrupt();
+    while next < statements.len() || !running.is_empty() {
+        while next < statements.len() && running.len() < limit {
+            match super::job::start(&statements[next], state) {
+                Ok(id) => running.push((id, next)),
+                Err(e) => {
+                    let ids = running.iter().map(|v| v.0).collect::<Vec<usize>>();
+                    super::job::cancel(&ids, state);
+                    return Err(BuiltinError::Failed(1, tr!("starting job failed: {}", e)));
+                }
+            }
+            next += 1;
+        }
+        let ids = running.iter().map(|v| v.0).collect::<Vec<usize>>();
+        let Some((id, status)) = super::job::wait_any(&ids, state) else {
+            super::job::cancel(&ids, state);
+            return Ok(130);
+        };
+        let index = running.iter().position(|v| v.0 == id).unwrap();
+        statuses[running.remove(index).1] = Some(status);
+    }
+
+    let statuses = statuses
+        .into_iter()
+        .map(|v| v.unwrap_or(255))
+        .collect::<Vec<i32>>();
+    super::swap_vars(
+        statuses
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (format!("PAR_STATUS_{}", i + 1), Some(v.to_string())))
+            .collect(),
+        state,
+    );
+    Ok(statuses.into_iter().find(|v| *v != 0).unwrap_or(0))
+}
+
+/// Rerun a statement when files change
+pub fn onchange(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    /// How long to wait for more changes after one before running the statement.
+    const DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(100);
+
+    if args.len() < 3 {
+        return Err(BuiltinError::Usage(String::new()));
+    }
+    let statement = &args[args.len() - 1];
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)
+        .map_err(|e| BuiltinError::Failed(1, tr!("watching failed: {}", e)))?;
+    for path in &args[1..args.len() - 1] {
+        notify::Watcher::watch(
+            &mut watcher,
+            &state.working_dir.join(super::os_str::to_path(path)),
+            notify::RecursiveMode::Recursive,
+        )
+        .map_err(|e| BuiltinError::Failed(1, tr!("watching {} failed: {}", path, e)))?;
+    }
+
+    super::take_interrupt();
+    loop {
+        super::eval(statement, state);
+        // wait for a change, looking out for Ctrl+C
+        loop {
+            if super::take_interrupt() {
+                return Ok(super::get_status(state));
+            }
+            match rx.recv_timeout(DEBOUNCE) {
+                Ok(Ok(event)) if !matches!(event.kind, notify::EventKind::Access(_)) => break,
+                Ok(_) | Err(std::sync::mpsc::RecvTimeoutError::Timeout) => (),
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(BuiltinError::Failed(1, tr!("watching stopped")));
+                }
+            }
+        }
+        // saving a file is often more than one change
+        while rx.recv_timeout(DEBOUNCE).is_ok() {}
+    }
+}
+
+/// Pick from a numbered menu, then run a statement
+pub fn select(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    if args.len() < 5 || args[2] != "in" {
+        return Err(BuiltinError::Usage(String::new()));
+    }
+    restricted_var(state, &args[1])?;
+    let items = &args[3..args.len() - 1];
+    let width = items.len().to_string().len();
+    let item = loop {
+        for (i, item) in items.iter().enumerate() {
+            writeln!(io.stdout, "{:>width$}) {}", i + 1, item)?;
+        }
+        write!(io.stdout, "#? ")?;
+        io.stdout.flush()?;
+        let Some(reply) = read_line(io.stdin)? else {
+            writeln!(io.stdout)?;
+            return Ok(1);
+        };
+        let reply = reply.trim().to_string();
+        state.shell_env.push(super::ShellVar {
+            name: "REPLY".to_string(),
+            value: reply.clone(),
+            exported: false,
+        });
+        match reply.parse::<usize>() {
+            Ok(n) if (1..=items.len()).contains(&n) => break items[n - 1].clone(),
+            _ => continue,
+        }
+    };
+    state.shell_env.push(super::ShellVar {
+        name: args[1].clone(),
+        value: item,
+        exported: false,
+    });
+    super::garbage_collect_vars(state);
+    super::eval(&args[args.len() - 1].clone(), state);
+    Ok(super::get_status(state))
+}
+
+/// Ask a yes or no question
+pub fn confirm(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    _: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let (default, question) = match args.as_slice() {
+        [_, question] => (None, question),
+        [_, flag, default, question] if flag == "--default" => match default.as_str() {
+            "y" | "yes" => (Some(0), question),
+            "n" | "no" => (Some(1), question),
+            _ => return Err(BuiltinError::Usage(tr!("the default is y or n"))),
+        },
+        _ => return Err(BuiltinError::Usage(String::new())),
+    };
+    let choices = match default {
+        None => "y/n",
+        Some(0) => "Y/n",
+        Some(_) => "y/N",
+    };
+    loop {
+        write!(io.stdout, "{} [{}] ", question, choices)?;
+        io.stdout.flush()?;
+        let Some(reply) = read_line(io.stdin)? else {
+            writeln!(io.stdout)?;
+            return Ok(1);
+        };
+        match (reply.trim().to_lowercase().as_str(), default) {
+            ("y" | "yes", _) => return Ok(0),
+            ("n" | "no", _) => return Ok(1),
+            ("", Some(default)) => return Ok(default),
+            _ => (),
+        }
     }
+}
 
-    0
+/// Ask for a value
+pub fn prompt(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let mut default = None;
+    let mut pattern = None;
+    let mut rest = Vec::new();
+    let mut args = args.into_iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--default" => default = Some(args.next().ok_or(BuiltinError::Usage(String::new()))?),
+            "--validate" => {
+                let regex = args.next().ok_or(BuiltinError::Usage(String::new()))?;
+                // the whole answer has to match, not just part of it
+                pattern = Some(regex::Regex::new(&format!("^(?:{})$", regex)).map_err(|e| {
+                    BuiltinError::Failed(1, tr!("invalid regular expression: {}", e))
+                })?);
+            }
+            _ => rest.push(arg),
+        }
+    }
+    let [text, var] = rest.as_slice() else {
+        return Err(BuiltinError::Usage(String::new()));
+    };
+    restricted_var(state, var)?;
+    let value = loop {
+        match &default {
+            Some(default) => write!(io.stdout, "{} [{}] ", text, default)?,
+            None => write!(io.stdout, "{} ", text)?,
+        }
+        io.stdout.flush()?;
+        let Some(reply) = read_line(io.stdin)? else {
+            writeln!(io.stdout)?;
+            return Ok(1);
+        };
+        let reply = match (reply.trim(), &default) {
+            ("", Some(default)) => default.clone(),
+            (reply, _) => reply.to_string(),
+        };
+        match &pattern {
+            Some(pattern) if !pattern.is_match(&reply) => {
+                writeln!(io.stderr, "{}", tr!("sesh: prompt: {} isn't valid", reply))?;
+            }
+            _ => break reply,
+        }
+    };
+    state.shell_env.push(super::ShellVar {
+        name: var.clone(),
+        value,
+        exported: false,
+    });
+    super::garbage_collect_vars(state);
+    Ok(0)
 }
 
 /// shh
-pub fn gay(_: Vec<String>, _: String, state: &mut super::State) -> i32 {
+pub fn gay(
+    _: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
     state.in_mode = !state.in_mode;
     state.entries = 0;
-    0
+    Ok(0)
 }
 
-/// Output the history
-pub fn history(_: Vec<String>, _: String, state: &mut super::State) -> i32 {
-    for (i, item) in state.history.iter().enumerate() {
+/// Output, search or forget the history
+pub fn history(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let number = |v: &String| {
+        v.parse::<usize>()
+            .map_err(|_| BuiltinError::Failed(1, tr!("{} isn't a number", v)))
+    };
+    let shown = match (args.get(1).map(|v| v.as_str()), &args[args.len().min(2)..]) {
+        (None, _) => state.history.len(),
+        (Some("list"), []) => state.history.len(),
+        (Some("list"), [count]) => number(count)?,
+        (Some("search"), [pattern] | [_, pattern]) => {
+            let regex = match args[2].as_str() {
+                "-r" => Some(regex::Regex::new(pattern).map_err(|e| {
+                    BuiltinError::Failed(1, tr!("invalid regular expression: {}", e))
+                })?),
+                _ if args.len() == 4 => return Err(BuiltinError::Usage(String::new())),
+                _ => None,
+            };
+            let mut found = false;
+            for (i, item) in state.history.iter().enumerate() {
+                if regex
+                    .as_ref()
+                    .map_or(item.contains(pattern.as_str()), |v| v.is_match(item))
+                {
+                    found = true;
+                    writeln!(io.stdout, "{}: {}", i + 1, item)?;
+                }
+            }
+            return Ok(if found { 0 } else { 1 });
+        }
+        (Some("delete"), [entry]) => {
+            restricted(state, "cannot change the history")?;
+            let entry = number(entry)?;
+            if !(1..=state.history.len()).contains(&entry) {
+                return Err(BuiltinError::Failed(1, tr!("no history entry {}", entry)));
+            }
+            super::forget_history(Some(entry - 1), state)?;
+            return Ok(0);
+        }
+        (Some("clear"), []) => {
+            restricted(state, "cannot change the history")?;
+            super::forget_history(None, state)?;
+            return Ok(0);
+        }
+        _ => return Err(BuiltinError::Usage(String::new())),
+    };
+    let first = state.history.len().saturating_sub(shown);
+    for (i, item) in state.history.iter().enumerate().skip(first) {
         let item = item.trim_matches(|c: char| c.is_control());
         if state.in_mode {
             let table = [
@@ -541,9 +3392,554 @@ pub fn history(_: Vec<String>, _: String, state: &mut super::State) -> i32 {
                 "\x1b[35;1m",
             ];
             let idx = i % table.len();
-            print!("{}", table[idx]);
+            write!(io.stdout, "{}", super::color::fit(table[idx]))?;
+        }
+        let time = state
+            .history_times
+            .get(i)
+            .copied()
+            .flatten()
+            .and_then(|v| chrono::DateTime::from_timestamp(v, 0))
+            .map(|v| {
+                v.with_timezone(&chrono::Local)
+                    .format("[%F %T] ")
+                    .to_string()
+            });
+        writeln!(io.stdout, "{}: {}{}", i + 1, time.unwrap_or_default(), item)?;
+    }
+    Ok(0)
+}
+
+/// Save a statement to recall later.
+pub fn remember(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    if let [_, flag, name] = args.as_slice()
+        && flag == "--forget"
+    {
+        let len = state.bookmarks.len();
+        state.bookmarks.retain(|v| &v.0 != name);
+        if state.bookmarks.len() == len {
+            return Err(BuiltinError::Failed(1, tr!("no bookmark {}", name)));
+        }
+        return Ok(0);
+    }
+    let (name, statement) = match args.as_slice() {
+        [_] => (None, None),
+        [_, name] => (Some(name.clone()), None),
+        [_, name, statement] => (Some(name.clone()), Some(statement.clone())),
+        _ => return Err(BuiltinError::Usage(String::new())),
+    };
+    let statement = match statement {
+        Some(statement) => statement,
+        // the command line this was run from is in the history too
+        None => state
+            .history
+            .iter()
+            .rev()
+            .find(|v| v.split_whitespace().next() != Some("remember"))
+            .cloned()
+            .ok_or_else(|| BuiltinError::Failed(1, tr!("no command line to remember")))?,
+    };
+    let name = name.unwrap_or_else(|| {
+        (1..)
+            .map(|v: usize| v.to_string())
+            .find(|v| !state.bookmarks.iter().any(|(name, _)| name == v))
+            .unwrap()
+    });
+    state.bookmarks.retain(|v| v.0 != name);
+    state.bookmarks.push((name, statement));
+    Ok(0)
+}
+
+/// List, edit or run bookmarked statements.
+pub fn recall(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let find = |name: &String, state: &super::State| {
+        state
+            .bookmarks
+            .iter()
+            .find(|v| &v.0 == name)
+            .map(|v| v.1.clone())
+            .ok_or_else(|| BuiltinError::Failed(1, tr!("no bookmark {}", name)))
+    };
+    let statement = match args.as_slice() {
+        [_] => None,
+        [_, list] if list == "list" => None,
+        [_, run, name] if run == "--run" => Some(find(name, state)?),
+        [_, name] => {
+            let statement = find(name, state)?;
+            let Some(statement) = edit_value(&format!("{}> ", name), &statement, io, state)? else {
+                return Ok(1);
+            };
+            Some(statement)
+        }
+        _ => return Err(BuiltinError::Usage(String::new())),
+    };
+    let Some(statement) = statement else {
+        for (name, statement) in &state.bookmarks {
+            writeln!(io.stdout, "{}\t{}", name, statement)?;
+        }
+        return Ok(0);
+    };
+    super::eval(&statement, state);
+    Ok(super::get_status(state))
+}
+
+/// Send aliases and variables to the other sessions.
+pub fn sync(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    restricted(state, "cannot change other sessions")?;
+    let mut requests = Vec::new();
+    if args.len() == 1 {
+        for alias in &state.aliases {
+            requests.push(format!("alias {} {}", alias.name, alias.to));
+        }
+    }
+    for name in &args[1..] {
+        let request = if let Some(alias) = state.aliases.iter().rfind(|v| &v.name == name) {
+            format!("alias {} {}", alias.name, alias.to)
+        } else if state.secrets.contains(name) {
+            // other sessions would keep it as a plain variable, and the socket isn't meant for them
+            return Err(BuiltinError::Failed(1, tr!("{} is secret", name)));
+        } else if let Some(value) = super::get_var(state, name) {
+            format!("set {}={}", name, value)
+        } else {
+            return Err(BuiltinError::Failed(
+                1,
+                tr!("no alias or variable named {}", name),
+            ));
+        };
+        // a request is a line
+        if request.contains('\n') {
+            return Err(BuiltinError::Failed(
+                1,
+                tr!("{} has more than one line", name),
+            ));
+        }
+        requests.push(request);
+    }
+    if requests.is_empty() {
+        return Ok(0);
+    }
+    let sent = crate::ctl::broadcast(&requests, std::time::Duration::from_secs(1))?;
+    writeln!(io.stdout, "{}", tr!("synced to {} sessions", sent.done))?;
+    if sent.pending > 0 {
+        let busy = tr!(
+            "{} sessions are busy and will sync at their prompt",
+            sent.pending
+        );
+        writeln!(io.stderr, "{}", busy)?;
+    }
+    if sent.failed > 0 {
+        return Err(BuiltinError::Failed(
+            1,
+            tr!("{} sessions refused some of it", sent.failed),
+        ));
+    }
+    Ok(0)
+}
+
+/// Manage plugins
+pub fn plugin(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    match args.get(1).map(|v| v.as_str()) {
+        None | Some("list") => {
+            for plugin in &state.plugins {
+                let kind = match &plugin.backend {
+                    crate::plugins::Backend::Native(_) => "native".to_string(),
+                    crate::plugins::Backend::Wasm(wasm) => format!(
+                        "wasm, allowed: {}",
+                        wasm.capabilities
+                            .iter()
+                            .map(|v| v.name())
+                            .collect::<Vec<&str>>()
+                            .join(",")
+                    ),
+                };
+                writeln!(
+                    io.stdout,
+                    "{} ({}, {}): {}",
+                    plugin.name,
+                    plugin.path.display(),
+                    kind,
+                    plugin
+                        .builtins
+                        .iter()
+                        .map(|v| v.name.clone())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                )?;
+            }
+            Ok(0)
+        }
+        Some("load") if args.len() == 3 || args.len() == 5 && args[3] == "--allow" => {
+            restricted(state, "cannot load plugins")?;
+            let capabilities = crate::plugins::wasm::Capability::parse_list(
+                args.get(4).map(|v| v.as_str()).unwrap_or_default(),
+            )
+            .map_err(|e| BuiltinError::Failed(1, e))?;
+            let plugin = crate::plugins::load(std::path::Path::new(&args[2]), &capabilities)
+                .map_err(|e| BuiltinError::Failed(2, tr!("loading {} failed: {}", args[2], e)))?;
+            state.plugins.retain(|v| v.name != plugin.name);
+            state.plugins.push(std::sync::Arc::new(plugin));
+            Ok(0)
+        }
+        Some("unload") if args.len() == 3 => {
+            let len = state.plugins.len();
+            state.plugins.retain(|v| v.name != args[2]);
+            if state.plugins.len() == len {
+                return Err(BuiltinError::Failed(2, tr!("no plugin named {}", args[2])));
+            }
+            Ok(0)
+        }
+        _ => Err(BuiltinError::Usage(String::new())),
+    }
+}
+
+/// Run a program in a sandbox.
+pub fn sandbox(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let mut options = crate::sandbox::Options::default();
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--net" => options.net = true,
+            "--rw" if i + 1 < args.len() => {
+                i += 1;
+                options
+                    .writable
+                    .push(state.working_dir.join(super::os_str::to_path(&args[i])));
+            }
+            _ => break,
+        }
+        i += 1;
+    }
+    run_program(&args[i..], state, |command| {
+        crate::sandbox::confine(command, &options)
+    })
+}
+
+/// Run a program with its arguments after setting up how it's run, and wait for it.
+fn run_program(
+    args: &[String],
+    state: &mut super::State,
+    set_up: impl FnOnce(&mut std::process::Command) -> std::io::Result<()>,
+) -> Result<ExitStatus, BuiltinError> {
+    let Some(program) = args.first() else {
+        return Err(BuiltinError::Usage(String::new()));
+    };
+    if program.contains(['/', '\\']) {
+        restricted(state, "cannot run programs by path")?;
+    }
+
+    let mut command = std::process::Command::new(super::os_str::to_os(program));
+    super::export_env(&mut command, state);
+    command
+        .args(args[1..].iter().map(|v| super::os_str::to_os(v)))
+        .current_dir(state.working_dir.clone());
+    set_up(&mut command).map_err(|e| BuiltinError::Failed(1, e.to_string()))?;
+    if state.raw_term.is_some() {
+        super::job::in_foreground(&mut command, None);
+    }
+    match command.spawn() {
+        Ok(mut child) => Ok(super::wait_foreground(&mut child, &args.join(" "), state)?),
+        Err(e) => Err(BuiltinError::Failed(
+            127,
+            tr!("error spawning program: {}", e),
+        )),
+    }
+}
+
+/// Run a program at a different priority or IO class.
+pub fn nice(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let mut options = crate::sched::Options::default();
+    let mut i = 1;
+    while i + 1 < args.len() {
+        match args[i].as_str() {
+            "-n" => {
+                let nice = args[i + 1].parse().map_err(|_| {
+                    BuiltinError::Failed(1, tr!("invalid adjustment: {}", args[i + 1]))
+                })?;
+                options.nice = Some(nice);
+            }
+            "--io" => {
+                let io = crate::sched::parse_io(&args[i + 1]).ok_or_else(|| {
+                    BuiltinError::Failed(1, tr!("invalid IO class: {}", args[i + 1]))
+                })?;
+                options.io = Some(io);
+            }
+            _ => break,
+        }
+        i += 2;
+    }
+    // like nice(1), it's 10 unless only the IO class is changed
+    if options.nice.is_none() && options.io.is_none() {
+        options.nice = Some(10);
+    }
+    run_program(&args[i..], state, |command| {
+        crate::sched::apply(command, &options)
+    })
+}
+
+/// Run a program on some of the CPUs.
+pub fn cpus(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    if args.len() < 3 {
+        return Err(BuiltinError::Usage(String::new()));
+    }
+    let cpus = crate::sched::parse_cpus(&args[1])
+        .ok_or_else(|| BuiltinError::Failed(1, tr!("invalid CPU list: {}", args[1])))?;
+    let options = crate::sched::Options {
+        cpus: Some(cpus),
+        ..Default::default()
+    };
+    run_program(&args[2..], state, |command| {
+        crate::sched::apply(command, &options)
+    })
+}
+
+/// Quote a string for a POSIX shell.
+pub fn sh_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// The command that runs a statement with sesh, for ssh to hand to the login shell of the remote
+/// user. Variables go through the environment, so nothing in their values is taken for syntax.
+pub fn remote_command(vars: &[(String, String)], statement: &str) -> String {
+    let mut command = String::new();
+    if !vars.is_empty() {
+        command += "env ";
+    }
+    for (name, value) in vars {
+        command += &sh_quote(&format!("{}={}", name, value));
+        command.push(' ');
+    }
+    command + "sesh -c " + &sh_quote(statement)
+}
+
+/// Run a statement on another host
+pub fn remote(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let mut vars = Vec::new();
+    let mut focus = false;
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--var" if i + 1 < args.len() => {
+                i += 1;
+                vars.push(args[i].clone());
+            }
+            "--focus" => focus = true,
+            _ => break,
+        }
+        i += 1;
+    }
+    if args.len() - i != 2 {
+        return Err(BuiltinError::Usage(String::new()));
+    }
+    restricted(state, "cannot run statements on other hosts")?;
+
+    let mut values = Vec::new();
+    for name in vars {
+        let value = super::get_var(state, &name)
+            .ok_or_else(|| BuiltinError::Failed(1, tr!("no variable {}", name)))?;
+        values.push((name, value));
+    }
+
+    let mut command = std::process::Command::new("ssh");
+    super::export_env(&mut command, state);
+    let mut child = command
+        .arg("--")
+        .arg(&args[i])
+        .arg(remote_command(&values, &args[i + 1]))
+        .current_dir(state.working_dir.clone())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| BuiltinError::Failed(127, tr!("error spawning ssh: {}", e)))?;
+    let mut stdout = child.stdout.take().unwrap();
+    if focus {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output)?;
+        let output = String::from_utf8_lossy(&output);
+        state.focus = super::Focus::Str(output.strip_suffix('\n').unwrap_or(&output).to_string());
+    } else {
+        std::io::copy(&mut stdout, io.stdout)?;
+    }
+    Ok(child.wait()?.code().unwrap_or(255))
+}
+
+/// Work with the tmux session the shell is in
+pub fn mux(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let mut tmux_args = match (args.get(1).map(|v| v.as_str()), args.len()) {
+        (Some("window"), 2) => vec!["new-window".to_string()],
+        (Some("split"), 2) => vec!["split-window".to_string(), "-v".to_string()],
+        (Some("split"), 3) if args[2] == "-h" => {
+            vec!["split-window".to_string(), "-h".to_string()]
+        }
+        (Some("send"), 3) => {
+            let focus = match &state.focus {
+                super::Focus::Str(s) => s.clone(),
+                super::Focus::Vec(_) => format!("{}", state.focus),
+            };
+            vec![
+                "send-keys".to_string(),
+                "-t".to_string(),
+                args[2].clone(),
+                "-l".to_string(),
+                focus,
+            ]
+        }
+        _ => return Err(BuiltinError::Usage(String::new())),
+    };
+    restricted(state, "cannot run tmux")?;
+    if std::env::var_os("TMUX").is_none_or(|v| v.is_empty()) {
+        return Err(BuiltinError::Failed(1, tr!("not running inside tmux")));
+    }
+    if args[1] != "send" {
+        tmux_args.push("-c".to_string());
+        tmux_args.push(state.working_dir.to_string_lossy().to_string());
+        for var in &state.shell_env {
+            // the arguments of tmux can be seen by everyone
+            if !state.secrets.contains(&var.name) {
+                tmux_args.push("-e".to_string());
+                tmux_args.push(format!("{}={}", var.name, var.value));
+            }
+        }
+    }
+    match std::process::Command::new("tmux").args(tmux_args).status() {
+        Ok(status) => Ok(status.code().unwrap_or(255)),
+        Err(e) => Err(BuiltinError::Failed(
+            127,
+            tr!("error spawning program: {}", e),
+        )),
+    }
+}
+
+/// Manage the dangerous command guard
+pub fn guard(
+    args: Vec<String>,
+    _: String,
+    io: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    // patterns are kept with their words separated by single spaces
+    let pattern = || {
+        args[2..]
+            .iter()
+            .flat_map(|v| v.split_whitespace())
+            .collect::<Vec<&str>>()
+            .join(" ")
+    };
+    match args.get(1).map(|v| v.as_str()) {
+        None | Some("list") => {
+            for pattern in &state.guards {
+                writeln!(io.stdout, "{}", pattern)?;
+            }
+            Ok(0)
+        }
+        Some("add") if args.len() > 2 => {
+            restricted(state, "cannot change guard patterns")?;
+            let pattern = pattern();
+            if !state.guards.contains(&pattern) {
+                state.guards.push(pattern);
+            }
+            Ok(0)
+        }
+        Some("remove") if args.len() > 2 => {
+            restricted(state, "cannot change guard patterns")?;
+            let pattern = pattern();
+            let len = state.guards.len();
+            state.guards.retain(|v| *v != pattern);
+            if state.guards.len() == len {
+                return Err(BuiltinError::Failed(2, tr!("no pattern {}", pattern)));
+            }
+            Ok(0)
+        }
+        _ => Err(BuiltinError::Usage(String::new())),
+    }
+}
+
+/// Interactively pick an item
+pub fn choose(
+    args: Vec<String>,
+    _: String,
+    _: &mut Io,
+    state: &mut super::State,
+) -> Result<ExitStatus, BuiltinError> {
+    let mut var = None;
+    let mut prompt = "> ".to_string();
+    let mut i = 1;
+    while i + 1 < args.len() {
+        match args[i].as_str() {
+            "--var" => var = Some(args[i + 1].clone()),
+            "--prompt" => prompt = args[i + 1].clone(),
+            _ => break,
+        }
+        i += 2;
+    }
+    if let Some(var) = &var {
+        restricted_var(state, var)?;
+    }
+    let items = if i < args.len() {
+        args[i..].to_vec()
+    } else {
+        focus_items(&state.focus)
+    };
+    if items.is_empty() {
+        return Err(BuiltinError::Usage(tr!("nothing to choose from")));
+    }
+    match crate::picker::pick(&items, &prompt) {
+        Ok(Some(picked)) => {
+            let picked = items[picked].clone();
+            match var {
+                Some(name) => state.shell_env.push(super::ShellVar {
+                    name,
+                    value: picked,
+                    exported: false,
+                }),
+                None => state.focus = super::Focus::Str(picked),
+            }
+            Ok(0)
         }
-        println!("{}: {}", i + 1, item);
+        Ok(None) => Ok(1),
+        Err(e) => Err(e.into()),
     }
-    0
 }
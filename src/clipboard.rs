@@ -0,0 +1,51 @@
+//! The clipboard
+//!
+//! The system clipboard is used through arboard. When that isn't available, like over ssh without
+//! a display, copying falls back to the OSC 52 escape sequence, which asks the terminal to set the
+//! clipboard instead.
+
+use std::io::Write;
+
+use super::i18n::tr;
+
+/// Encode bytes as base64.
+pub fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, v)| n | (*v as u32) << (16 - i * 8));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - i * 6)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Put text on the clipboard.
+pub fn set(text: &str) -> Result<(), String> {
+    let error = match arboard::Clipboard::new().and_then(|mut v| v.set_text(text)) {
+        Ok(()) => return Ok(()),
+        Err(e) => e,
+    };
+    if !std::io::IsTerminal::is_terminal(&std::io::stdout()) {
+        return Err(error.to_string());
+    }
+    let mut stdout = std::io::stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", base64(text.as_bytes()))
+        .and_then(|_| stdout.flush())
+        .map_err(|e| e.to_string())
+}
+
+/// Get the text on the clipboard.
+pub fn get() -> Result<String, String> {
+    arboard::Clipboard::new()
+        .and_then(|mut v| v.get_text())
+        .map_err(|e| tr!("get clipboard text error: {}", e))
+}
@@ -0,0 +1,240 @@
+//! How many colours the terminal can show
+//!
+//! `$COLORTERM` saying `truecolor` or `24bit` means any colour goes. Otherwise the terminfo entry
+//! for `$TERM` says how many there are, falling back on whether `$TERM` ends in `256color`.
+//! Colours the terminal can't show are swapped for the nearest one it can before the prompt and
+//! builtins write them.
+
+use std::{path::PathBuf, sync::OnceLock};
+
+/// How many colours a terminal can show.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Depth {
+    /// The 8 basic colours and their bright versions
+    Basic,
+    /// The 256 colour palette
+    Palette,
+    /// Any RGB colour
+    TrueColor,
+}
+
+/// The depth of the terminal, worked out once.
+static DEPTH: OnceLock<Depth> = OnceLock::new();
+
+/// The depth of the terminal the shell runs in.
+pub fn depth() -> Depth {
+    *DEPTH.get_or_init(|| {
+        let var = |name| std::env::var(name).unwrap_or_default();
+        detect(&var("COLORTERM"), &var("TERM"), read_colors)
+    })
+}
+
+/// Work out the depth from `$COLORTERM`, `$TERM` and the number of colours its terminfo entry
+/// gives, if there is one.
+pub fn detect(colorterm: &str, term: &str, colors: impl Fn(&str) -> Option<i32>) -> Depth {
+    if ["truecolor", "24bit"].contains(&colorterm) {
+        return Depth::TrueColor;
+    }
+    match colors(term) {
+        Some(colors) if colors >= 1 << 24 => Depth::TrueColor,
+        Some(colors) if colors >= 256 => Depth::Palette,
+        Some(_) => Depth::Basic,
+        None if term.ends_with("256color") => Depth::Palette,
+        None => Depth::Basic,
+    }
+}
+
+/// Read the number of colours from the terminfo entry of a terminal.
+fn read_colors(term: &str) -> Option<i32> {
+    let first = term.chars().next()?;
+    if term.contains('/') {
+        return None;
+    }
+    let mut dirs = Vec::new();
+    if let Some(dir) = std::env::var_os("TERMINFO") {
+        dirs.push(PathBuf::from(dir));
+    }
+    if let Some(home) = std::env::home_dir() {
+        dirs.push(home.join(".terminfo"));
+    }
+    dirs.extend(["/etc/terminfo", "/lib/terminfo", "/usr/share/terminfo"].map(PathBuf::from));
+    // some systems name the directories by the hex code of the first letter
+    let entry = dirs
+        .iter()
+        .flat_map(|v| {
+            [
+                v.join(first.to_string()),
+                v.join(format!("{:x}", first as u32)),
+            ]
+        })
+        .find_map(|v| std::fs::read(v.join(term)).ok())?;
+    parse_colors(&entry)
+}
+
+/// Get the number of colours, the 14th number, out of a compiled terminfo entry.
+pub fn parse_colors(entry: &[u8]) -> Option<i32> {
+    const MAX_COLORS: usize = 13;
+    let short = |i: usize| Some(i16::from_le_bytes([*entry.get(i)?, *entry.get(i + 1)?]));
+    // the newer format has 32-bit numbers
+    let wide = match short(0)? {
+        0o432 => false,
+        0o1036 => true,
+        _ => return None,
+    };
+    let (names, bools, numbers) = (short(2)? as usize, short(4)? as usize, short(6)? as usize);
+    if MAX_COLORS >= numbers {
+        return None;
+    }
+    // numbers start on an even byte
+    let start = (12 + names + bools).next_multiple_of(2);
+    let colors = if wide {
+        let at = start + MAX_COLORS * 4;
+        i32::from_le_bytes(entry.get(at..at + 4)?.try_into().ok()?)
+    } else {
+        short(start + MAX_COLORS * 2)? as i32
+    };
+    (colors >= 0).then_some(colors)
+}
+
+/// The colours of the 16 colour palette, as xterm shows them.
+const BASIC: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+/// The colour of an entry in the 256 colour palette.
+fn palette_rgb(index: u8) -> (u8, u8, u8) {
+    let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+    match index {
+        0..16 => BASIC[index as usize],
+        16..232 => {
+            let index = index - 16;
+            (level(index / 36), level(index / 6 % 6), level(index % 6))
+        }
+        _ => {
+            let grey = 8 + (index - 232) * 10;
+            (grey, grey, grey)
+        }
+    }
+}
+
+/// The closest entry in the 256 colour palette to a colour.
+fn to_palette((r, g, b): (u8, u8, u8)) -> u8 {
+    let step = |v: u8| ((v as u16 * 5 + 127) / 255) as u8;
+    let cube = 16 + 36 * step(r) + 6 * step(g) + step(b);
+    let grey = 232
+        + ((r as u16 + g as u16 + b as u16) / 3)
+            .saturating_sub(3)
+            .min(230) as u8
+            / 10;
+    let distance = |index: u8| {
+        let (r2, g2, b2) = palette_rgb(index);
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(r, r2) + d(g, g2) + d(b, b2)
+    };
+    if distance(grey) < distance(cube) {
+        grey
+    } else {
+        cube
+    }
+}
+
+/// The closest of the 16 basic colours to a colour.
+fn to_basic((r, g, b): (u8, u8, u8)) -> u8 {
+    let distance = |&(r2, g2, b2): &(u8, u8, u8)| {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(r, r2) + d(g, g2) + d(b, b2)
+    };
+    (0..16u8)
+        .min_by_key(|&i| distance(&BASIC[i as usize]))
+        .unwrap_or(7)
+}
+
+/// Rewrite the parameters of a single SGR sequence so its colours fit a depth.
+fn fit_params(params: &str, depth: Depth) -> String {
+    let parts = params.split(';').collect::<Vec<&str>>();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < parts.len() {
+        let colour = match (parts[i], parts.get(i + 1).copied()) {
+            (layer @ ("38" | "48"), Some("2")) if parts.len() >= i + 5 => {
+                let value = |v: &str| v.parse::<u8>().unwrap_or(0);
+                let rgb = (
+                    value(parts[i + 2]),
+                    value(parts[i + 3]),
+                    value(parts[i + 4]),
+                );
+                Some((layer, rgb, 5))
+            }
+            (layer @ ("38" | "48"), Some("5")) if parts.len() >= i + 3 => {
+                Some((layer, palette_rgb(parts[i + 2].parse().unwrap_or(0)), 3))
+            }
+            _ => None,
+        };
+        let Some((layer, rgb, len)) = colour else {
+            out.push(parts[i].to_string());
+            i += 1;
+            continue;
+        };
+        let original = parts[i..i + len].join(";");
+        out.push(match depth {
+            Depth::TrueColor => original,
+            Depth::Palette if len == 3 => original,
+            Depth::Palette => format!("{};5;{}", layer, to_palette(rgb)),
+            Depth::Basic => {
+                let basic = to_basic(rgb);
+                let base = if layer == "38" { 30 } else { 40 };
+                let base = if basic < 8 { base } else { base + 60 - 8 };
+                (base + basic).to_string()
+            }
+        });
+        i += len;
+    }
+    out.join(";")
+}
+
+/// Swap the colours in some text for the nearest ones a terminal of some depth can show.
+pub fn fit_to(text: &str, depth: Depth) -> String {
+    if depth == Depth::TrueColor || !text.contains("\x1b[") {
+        return text.to_string();
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("\x1b[") {
+        out += &rest[..start];
+        let after = &rest[start + 2..];
+        let end = after.find(|v: char| !(v.is_ascii_digit() || v == ';'));
+        match end {
+            Some(end) if after[end..].starts_with('m') => {
+                out += "\x1b[";
+                out += &fit_params(&after[..end], depth);
+                out += "m";
+                rest = &after[end + 1..];
+            }
+            _ => {
+                out += "\x1b[";
+                rest = after;
+            }
+        }
+    }
+    out + rest
+}
+
+/// Swap the colours in some text for the nearest ones the terminal can show.
+pub fn fit(text: &str) -> String {
+    fit_to(text, depth())
+}
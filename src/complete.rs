@@ -0,0 +1,192 @@
+//! Tab completion
+//!
+//! The word before the cursor is completed as a command when it starts a statement, from the
+//! builtins, aliases and programs on PATH, and as a file name relative to the working directory
+//! otherwise. If there's more than one way to complete it, as much as they have in common is
+//! filled in, and when that's nothing more the choices are listed under the prompt, along with
+//! the descriptions of aliases that have them.
+
+use std::path::Path;
+
+/// A completed line.
+pub struct Completion {
+    /// The line
+    pub line: String,
+    /// Where the cursor goes, as a character index
+    pub cursor: usize,
+    /// The choices to list, if the word couldn't be completed any further
+    pub choices: Vec<String>,
+}
+
+/// Characters that end a word being completed.
+const WORD_BREAKS: [char; 7] = [';', '(', ')', '{', '}', '"', '\''];
+
+/// Whether a program can be run.
+fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        path.metadata()
+            .is_ok_and(|v| v.is_file() && v.permissions().mode() & 0o111 != 0)
+    }
+    #[cfg(not(unix))]
+    path.is_file()
+}
+
+/// Commands starting with `word`.
+fn commands(word: &str, state: &super::State) -> Vec<String> {
+    let mut names = super::builtins::BUILTINS
+        .iter()
+        .map(|v| v.0.to_string())
+        .chain(state.aliases.iter().map(|v| v.name.clone()))
+        .chain(
+            state
+                .plugins
+                .iter()
+                .flat_map(|v| v.builtins.iter().map(|v| v.name.clone())),
+        )
+        .filter(|v| v.starts_with(word))
+        .collect::<Vec<String>>();
+    let path = super::get_var(state, "PATH")
+        .map(std::ffi::OsString::from)
+        .or_else(|| std::env::var_os("PATH"))
+        .unwrap_or_default();
+    for dir in std::env::split_paths(&path) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.filter_map(|v| v.ok()) {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with(word) && is_executable(&entry.path()) {
+                names.push(name);
+            }
+        }
+    }
+    names
+}
+
+/// Paths starting with `word`, relative to the working directory. Directories end in `/`.
+fn paths(word: &str, state: &super::State) -> Vec<String> {
+    let (dir, prefix) = match word.rsplit_once('/') {
+        Some((dir, prefix)) => (dir.to_string() + "/", prefix),
+        None => (String::new(), word),
+    };
+    let Ok(entries) =
+        std::fs::read_dir(
+            state
+                .working_dir
+                .join(if dir.is_empty() { "." } else { &dir }),
+        )
+    else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|v| v.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            // hidden files only when asked for
+            if !name.starts_with(prefix) || name.starts_with('.') && !prefix.starts_with('.') {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(dir.clone() + &name + slash)
+        })
+        .collect()
+}
+
+/// The longest start all the choices share.
+fn common_prefix(choices: &[String]) -> String {
+    let mut prefix = choices[0].chars().collect::<Vec<char>>();
+    for choice in &choices[1..] {
+        let len = prefix
+            .iter()
+            .zip(choice.chars())
+            .take_while(|(a, b)| **a == *b)
+            .count();
+        prefix.truncate(len);
+    }
+    prefix.into_iter().collect()
+}
+
+/// Complete the word before the cursor.
+pub fn complete(line: &str, cursor: usize, state: &super::State) -> Completion {
+    let chars = line.chars().collect::<Vec<char>>();
+    let start = chars[..cursor]
+        .iter()
+        .rposition(|v| v.is_whitespace() || WORD_BREAKS.contains(v))
+        .map_or(0, |v| v + 1);
+    let word = chars[start..cursor].iter().collect::<String>();
+    let before = chars[..start].iter().collect::<String>();
+    let is_command = before
+        .trim_end()
+        .chars()
+        .last()
+        .is_none_or(|v| [';', '(', '{', '!', '&'].contains(&v));
+
+    let is_command = is_command && !word.contains('/');
+    let mut choices = if is_command {
+        commands(&word, state)
+    } else {
+        paths(&word, state)
+    };
+    choices.sort();
+    choices.dedup();
+
+    let unchanged = Completion {
+        line: line.to_string(),
+        cursor,
+        choices: Vec::new(),
+    };
+    let replacement = match choices.as_slice() {
+        [] => return unchanged,
+        [choice] if choice.contains(char::is_whitespace) => format!("\"{}\" ", choice),
+        [choice] if choice.ends_with('/') => choice.clone(),
+        [choice] => choice.clone() + " ",
+        _ => {
+            let prefix = common_prefix(&choices);
+            if prefix.chars().count() > word.chars().count() {
+                prefix
+            } else {
+                // list them by the part after the last slash, like ls would, and aliases with
+                // what they're for
+                let dir_len = word.rfind('/').map_or(0, |v| v + 1);
+                let desc = |choice: &str| {
+                    let alias = state
+                        .aliases
+                        .iter()
+                        .rfind(|v| is_command && v.name == choice);
+                    alias.and_then(|v| v.desc.clone())
+                };
+                return Completion {
+                    choices: choices
+                        .into_iter()
+                        .map(|v| match desc(&v) {
+                            Some(desc) => format!("{} ({})", v, desc),
+                            None => v[dir_len..].to_string(),
+                        })
+                        .collect(),
+                    ..unchanged
+                };
+            }
+        }
+    };
+    Completion {
+        line: before + &replacement + &chars[cursor..].iter().collect::<String>(),
+        cursor: start + replacement.chars().count(),
+        choices: Vec::new(),
+    }
+}
+
+/// Lay out choices in columns that fit in `width` characters, one line per row.
+pub fn columns(choices: &[String], width: usize) -> String {
+    let column = choices.iter().map(|v| v.chars().count()).max().unwrap_or(0) + 2;
+    let per_row = (width / column).max(1);
+    let mut out = String::new();
+    for row in choices.chunks(per_row) {
+        for choice in row {
+            out += &format!("{:<column$}", choice);
+        }
+        out = out.trim_end().to_string() + "\n";
+    }
+    out
+}
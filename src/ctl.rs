@@ -0,0 +1,245 @@
+//! Control socket
+//!
+//! Every interactive session listens on a unix socket so that editors, scripts and `seshctl` can
+//! look at and drive it. The protocol is one request per line, one JSON reply per line. `sync`
+//! uses the same requests to hand aliases and variables to the other sessions.
+//!
+//! Only available on unix.
+
+mod paths;
+
+#[cfg(unix)]
+pub use paths::socket_dir;
+
+#[cfg(unix)]
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::{
+        fs::PermissionsExt,
+        net::{UnixListener, UnixStream},
+    },
+};
+use std::{
+    path::PathBuf,
+    sync::{OnceLock, mpsc},
+    time::Duration,
+};
+
+/// Path of the socket of this session, if one was opened.
+static SOCKET_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// A request from a client along with where to send the reply.
+struct Request {
+    /// The request line
+    line: String,
+    /// Where the reply goes
+    reply: mpsc::Sender<String>,
+}
+
+/// A listening control socket.
+pub struct Control {
+    /// Where the socket lives
+    pub path: PathBuf,
+    /// Incoming requests
+    rx: mpsc::Receiver<Request>,
+}
+
+/// Start listening on the socket of this session.
+#[cfg(not(unix))]
+pub fn listen() -> std::io::Result<Control> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Start listening on the socket of this session.
+#[cfg(unix)]
+pub fn listen() -> std::io::Result<Control> {
+    let dir = socket_dir();
+    std::fs::create_dir_all(&dir)?;
+    std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700))?;
+    let path = dir.join(format!("{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+    let _ = SOCKET_PATH.set(path.clone());
+
+    let (tx, rx) = mpsc::channel::<Request>();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let tx = tx.clone();
+            std::thread::spawn(move || {
+                let Ok(reader) = stream.try_clone() else {
+                    return;
+                };
+                let mut stream = stream;
+                for line in BufReader::new(reader).lines() {
+                    let Ok(line) = line else {
+                        return;
+                    };
+                    // queueing has to work while a command runs and requests wait
+                    if let Some(statement) = line.trim().strip_prefix("then ") {
+                        super::enqueue(statement.to_string());
+                        let ok = serde_json::json!({ "ok": true, "value": null });
+                        if writeln!(stream, "{}", ok).is_err() {
+                            return;
+                        }
+                        continue;
+                    }
+                    let (reply_tx, reply_rx) = mpsc::channel();
+                    if tx
+                        .send(Request {
+                            line,
+                            reply: reply_tx,
+                        })
+                        .is_err()
+                    {
+                        return;
+                    }
+                    let Ok(reply) = reply_rx.recv() else {
+                        return;
+                    };
+                    if writeln!(stream, "{}", reply).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    Ok(Control { path, rx })
+}
+
+/// Remove the socket of this session. Called when exiting.
+pub fn cleanup() {
+    if let Some(path) = SOCKET_PATH.get() {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// What sending requests to the other sessions came to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Broadcast {
+    /// Sessions that took every request
+    pub done: usize,
+    /// Sessions that didn't answer in time, usually because they're running something. They still
+    /// get the requests once they're back at the prompt.
+    pub pending: usize,
+    /// Sessions that refused a request
+    pub failed: usize,
+}
+
+/// Send requests to every other session, waiting up to `timeout` for each reply.
+#[cfg(not(unix))]
+pub fn broadcast(_: &[String], _: Duration) -> std::io::Result<Broadcast> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Send requests to every other session, waiting up to `timeout` for each reply.
+#[cfg(unix)]
+pub fn broadcast(requests: &[String], timeout: Duration) -> std::io::Result<Broadcast> {
+    let mut out = Broadcast::default();
+    let entries = match std::fs::read_dir(socket_dir()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(out),
+        Err(e) => return Err(e),
+    };
+    for path in entries.filter_map(|v| v.ok()).map(|v| v.path()) {
+        if path.extension().is_none_or(|v| v != "sock") || SOCKET_PATH.get() == Some(&path) {
+            continue;
+        }
+        // sockets left behind by sessions that crashed don't take connections
+        let Ok(mut stream) = UnixStream::connect(&path) else {
+            continue;
+        };
+        stream.set_read_timeout(Some(timeout))?;
+        for request in requests {
+            writeln!(stream, "{}", request)?;
+        }
+        let mut replies = BufReader::new(stream).lines();
+        let (mut answered, mut ok) = (0, true);
+        while answered < requests.len()
+            && let Some(Ok(reply)) = replies.next()
+        {
+            answered += 1;
+            ok &= serde_json::from_str::<serde_json::Value>(&reply).is_ok_and(|v| v["ok"] == true);
+        }
+        if answered < requests.len() {
+            out.pending += 1;
+        } else if ok {
+            out.done += 1;
+        } else {
+            out.failed += 1;
+        }
+    }
+    Ok(out)
+}
+
+impl Control {
+    /// Answer every pending request. Returns whether any were answered.
+    pub fn service(&self, state: &mut super::State) -> bool {
+        let mut any = false;
+        while let Ok(request) = self.rx.try_recv() {
+            any = true;
+            let _ = request
+                .reply
+                .send(respond(&request.line, state).to_string());
+        }
+        any
+    }
+}
+
+impl Drop for Control {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Build a reply to a single request.
+pub fn respond(line: &str, state: &mut super::State) -> serde_json::Value {
+    let (command, rest) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+    let ok = |value: serde_json::Value| serde_json::json!({ "ok": true, "value": value });
+    let err = |error: &str| serde_json::json!({ "ok": false, "error": error });
+    match command {
+        // anything running as this user can connect, so secrets stay in this shell
+        "get" if state.secrets.iter().any(|v| v == rest) => err("secret variable"),
+        "get" => match super::get_var(state, rest) {
+            Some(value) => ok(value.into()),
+            None => err("no such variable"),
+        },
+        "set" => match rest.split_once('=') {
+            Some((name, _)) if super::builtins::SPECIAL_VARS.contains(&name) => {
+                err("read-only variable")
+            }
+            Some((name, value)) => {
+                state.shell_env.push(super::ShellVar {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                    exported: false,
+                });
+                super::garbage_collect_vars(state);
+                ok(serde_json::Value::Null)
+            }
+            None => err("usage: set name=value"),
+        },
+        "alias" => match rest.split_once(' ') {
+            Some((name, to)) => {
+                state.aliases.push(super::Alias {
+                    name: name.to_string(),
+                    to: to.to_string(),
+                    desc: None,
+                });
+                ok(serde_json::Value::Null)
+            }
+            None => err("usage: alias name value"),
+        },
+        "run" => {
+            super::eval(rest, state);
+            ok(super::get_status(state).into())
+        }
+        "focus" => ok(state.focus.to_json()),
+        "prefill" => {
+            state.prefill = Some(rest.to_string());
+            ok(serde_json::Value::Null)
+        }
+        "" => err("empty request"),
+        _ => err("unknown request"),
+    }
+}
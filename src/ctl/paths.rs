@@ -0,0 +1,12 @@
+//! Where control sockets live
+//!
+//! Shared with `seshctl`, which has to find the sockets sessions open.
+
+/// The directory sockets are put in.
+#[cfg(unix)]
+pub fn socket_dir() -> std::path::PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(std::path::PathBuf::from)
+        .unwrap_or(std::env::temp_dir())
+        .join(format!("sesh-{}", users::get_current_uid()))
+}
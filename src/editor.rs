@@ -0,0 +1,72 @@
+//! Editing a value in place
+//!
+//! `vared` and `alias --edit` put a value on a line of its own, after a prompt, for the user to
+//! change with the same editing as the interactive line. Enter keeps the result and Ctrl-C or
+//! Escape gives up.
+
+use std::io::{IsTerminal, Write};
+
+/// Let the user edit `value` after `prompt`. Returns the new value, or None if they gave up.
+pub fn edit(prompt: &str, value: &str, autopair: bool) -> std::io::Result<Option<String>> {
+    if !std::io::stdin().is_terminal() {
+        return Err(std::io::Error::other("not a terminal"));
+    }
+    let mut out = super::term::raw_terminal()?;
+    let mut input = super::term::input();
+    let mut line = value.to_string();
+    let mut cursor = line.chars().count();
+    // bytes of a character that isn't complete yet
+    let mut pending = Vec::<u8>::new();
+    let edited = loop {
+        write!(
+            out,
+            "\r\x1b[0K{}{}",
+            prompt,
+            super::highlight_brackets(&line, cursor)
+        )?;
+        super::move_cursor(&mut out, &line, line.chars().count(), cursor)?;
+        out.flush()?;
+        match input.read_byte()? {
+            b'\r' | b'\n' => break Some(line),
+            0x03 | 0x04 => break None,
+            0x01 => cursor = 0,
+            0x05 => cursor = line.chars().count(),
+            0x7f | 0x08 => {
+                if !super::edit_line(&mut line, &mut cursor, '\x7F', autopair) {
+                    out.write_all(b"\x07")?;
+                }
+            }
+            0x1b => {
+                // a lone escape gives up, an escape sequence might be an arrow key
+                if !input.poll(std::time::Duration::from_millis(50))? {
+                    break None;
+                }
+                if input.read_byte()? != b'[' {
+                    continue;
+                }
+                match input.read_byte()? {
+                    b'C' => cursor = super::grapheme_boundary(&line, cursor, true),
+                    b'D' => cursor = super::grapheme_boundary(&line, cursor, false),
+                    b'H' => cursor = 0,
+                    b'F' => cursor = line.chars().count(),
+                    _ => (),
+                }
+            }
+            byte if byte >= 0x20 => {
+                pending.push(byte);
+                if let Ok(text) = std::str::from_utf8(&pending) {
+                    for ch in text.chars() {
+                        super::edit_line(&mut line, &mut cursor, ch, autopair);
+                    }
+                    pending.clear();
+                } else if pending.len() >= 4 {
+                    pending.clear();
+                }
+            }
+            _ => (),
+        }
+    };
+    write!(out, "\r\n")?;
+    out.flush()?;
+    Ok(edited)
+}
@@ -0,0 +1,108 @@
+//! Explaining exit statuses
+//!
+//! With `$EXPLAIN_STATUS` true, a program that fails gets a line saying what its status likely
+//! means, like `exit 137: killed (SIGKILL), maybe for running out of memory`, when it's one that
+//! means something beyond failing. The name of the signal that killed or stopped the last program
+//! is kept in `$STATUS_SIGNAL` either way.
+
+use super::i18n::tr;
+
+/// The signals, with what it means when one kills a program, if there's more to say than its name.
+#[cfg(unix)]
+const SIGNALS: [(i32, &str, &str); 29] = [
+    (libc::SIGHUP, "SIGHUP", "hung up"),
+    (libc::SIGINT, "SIGINT", "interrupted"),
+    (libc::SIGQUIT, "SIGQUIT", "quit"),
+    (libc::SIGILL, "SIGILL", "illegal instruction"),
+    (libc::SIGTRAP, "SIGTRAP", "trace trap"),
+    (libc::SIGABRT, "SIGABRT", "aborted"),
+    (libc::SIGBUS, "SIGBUS", "bus error"),
+    (libc::SIGFPE, "SIGFPE", "arithmetic error"),
+    (
+        libc::SIGKILL,
+        "SIGKILL",
+        "killed, maybe for running out of memory",
+    ),
+    (libc::SIGUSR1, "SIGUSR1", ""),
+    (libc::SIGSEGV, "SIGSEGV", "segmentation fault"),
+    (libc::SIGUSR2, "SIGUSR2", ""),
+    (libc::SIGPIPE, "SIGPIPE", "broken pipe"),
+    (libc::SIGALRM, "SIGALRM", "alarm clock"),
+    (libc::SIGTERM, "SIGTERM", "terminated"),
+    (libc::SIGCHLD, "SIGCHLD", ""),
+    (libc::SIGCONT, "SIGCONT", ""),
+    (libc::SIGSTOP, "SIGSTOP", "stopped"),
+    (libc::SIGTSTP, "SIGTSTP", "stopped"),
+    (libc::SIGTTIN, "SIGTTIN", "stopped for reading the terminal"),
+    (
+        libc::SIGTTOU,
+        "SIGTTOU",
+        "stopped for writing to the terminal",
+    ),
+    (libc::SIGURG, "SIGURG", ""),
+    (libc::SIGXCPU, "SIGXCPU", "CPU time limit exceeded"),
+    (libc::SIGXFSZ, "SIGXFSZ", "file size limit exceeded"),
+    (libc::SIGVTALRM, "SIGVTALRM", ""),
+    (libc::SIGPROF, "SIGPROF", ""),
+    (libc::SIGWINCH, "SIGWINCH", ""),
+    (libc::SIGIO, "SIGIO", ""),
+    (libc::SIGSYS, "SIGSYS", "bad system call"),
+];
+
+/// There are no signals to name.
+#[cfg(not(unix))]
+const SIGNALS: [(i32, &str, &str); 0] = [];
+
+/// What the exit statuses from sysexits.h and shells mean.
+const CODES: [(i32, &str); 17] = [
+    (64, "usage error"),
+    (65, "bad input data"),
+    (66, "input missing"),
+    (67, "unknown user"),
+    (68, "unknown host"),
+    (69, "service unavailable"),
+    (70, "internal error"),
+    (71, "system error"),
+    (72, "system file missing"),
+    (73, "can't create output"),
+    (74, "input/output error"),
+    (75, "temporary failure, try again"),
+    (76, "remote protocol error"),
+    (77, "permission denied"),
+    (78, "configuration error"),
+    (126, "permission denied or not executable"),
+    (127, "command not found"),
+];
+
+/// The name of a signal, like SIGKILL.
+pub fn signal_name(signal: i32) -> Option<&'static str> {
+    SIGNALS.iter().find(|v| v.0 == signal).map(|v| v.1)
+}
+
+/// Say what an exit status likely means, given the signal that killed or stopped the program if
+/// one did. Statuses that only mean it failed get nothing.
+pub fn explain(status: i32, signal: Option<i32>) -> Option<String> {
+    if let Some(signal) = signal {
+        let (_, name, meaning) = SIGNALS.iter().find(|v| v.0 == signal)?;
+        return Some(if meaning.is_empty() {
+            tr!("exit {}: killed by {}", status, name)
+        } else {
+            tr!(
+                "exit {}: {} ({})",
+                status,
+                super::i18n::translate(meaning),
+                name
+            )
+        });
+    }
+    if let Some((_, meaning)) = CODES.iter().find(|v| v.0 == status) {
+        return Some(tr!("exit {}: {}", status, super::i18n::translate(meaning)));
+    }
+    // shells give this when a program they ran was killed
+    let name = signal_name(status - 128)?;
+    Some(tr!(
+        "exit {}: likely from a program killed by {}",
+        status,
+        name
+    ))
+}
@@ -0,0 +1,134 @@
+//! Fuzzy finding for the line editor
+//!
+//! Ctrl-R picks a line from the history and Ctrl-T picks a file below the working directory.
+//! The choice is inserted into the line being edited. If the FINDER variable is set (e.g. to
+//! `fzf` or `sk`), that program is given the candidates on stdin and its output is used;
+//! otherwise the built-in picker is.
+
+use std::{
+    io::{Read, Write},
+    path::Path,
+    process::Stdio,
+};
+
+use super::i18n::tr;
+
+/// Most files listed for Ctrl-T.
+const MAX_FILES: usize = 10000;
+
+/// Pick a line from the history, most recent first.
+pub fn history(state: &super::State) -> Option<String> {
+    let mut lines = Vec::<String>::new();
+    for line in state.history.iter().rev() {
+        if !lines.contains(line) {
+            lines.push(line.clone());
+        }
+    }
+    find(&lines, "history> ", state)
+}
+
+/// Pick a file below the working directory. Names with whitespace come back quoted.
+pub fn files(state: &super::State) -> Option<String> {
+    let mut files = Vec::new();
+    list_files(&state.working_dir, Path::new(""), &mut files);
+    find(&files, "files> ", state).map(|v| {
+        if v.contains(char::is_whitespace) {
+            format!("\"{}\"", v)
+        } else {
+            v
+        }
+    })
+}
+
+/// List the files in a directory recursively, skipping hidden ones. Directories end in a slash.
+fn list_files(dir: &Path, relative: &Path, out: &mut Vec<String>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let mut entries = entries.flatten().collect::<Vec<std::fs::DirEntry>>();
+    entries.sort_by_key(|v| v.file_name());
+    for entry in entries {
+        if out.len() >= MAX_FILES {
+            return;
+        }
+        let name = entry.file_name();
+        if name.to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = relative.join(&name);
+        let shown = super::os_str::to_string(path.as_os_str());
+        if entry.file_type().is_ok_and(|v| v.is_dir()) {
+            out.push(shown + "/");
+            list_files(&entry.path(), &path, out);
+        } else {
+            out.push(shown);
+        }
+    }
+}
+
+/// Let the user pick one of the candidates with the configured finder.
+fn find(candidates: &[String], prompt: &str, state: &super::State) -> Option<String> {
+    let finder = state
+        .shell_env
+        .iter()
+        .find(|v| v.name == "FINDER")
+        .map(|v| v.value.clone())
+        .filter(|v| !v.trim().is_empty());
+    let Some(finder) = finder else {
+        return match super::picker::pick(candidates, prompt) {
+            Ok(picked) => picked.map(|v| candidates[v].clone()),
+            Err(e) => {
+                print!("\r\n{}\r\n", tr!("sesh: finder: {}", e));
+                None
+            }
+        };
+    };
+
+    if let Some(raw_term) = state.raw_term.clone() {
+        let _ = raw_term.write().unwrap().suspend_raw_mode();
+    }
+    let picked = run_external(&finder, candidates, state);
+    if let Some(raw_term) = state.raw_term.clone() {
+        let _ = raw_term.write().unwrap().activate_raw_mode();
+    }
+    match picked {
+        Ok(picked) => picked,
+        Err(e) => {
+            print!(
+                "\r\n{}\r\n",
+                tr!("sesh: finder: running {} failed: {}", finder, e)
+            );
+            None
+        }
+    }
+}
+
+/// Run an external finder. It gets the candidates one per line and prints the one picked.
+fn run_external(
+    finder: &str,
+    candidates: &[String],
+    state: &super::State,
+) -> std::io::Result<Option<String>> {
+    let mut words = finder.split_whitespace();
+    let mut command = std::process::Command::new(words.next().unwrap());
+    super::export_env(&mut command, state);
+    let mut child = command
+        .args(words)
+        .current_dir(&state.working_dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    let candidates = candidates.join("\n") + "\n";
+    // write from another thread so a finder that starts drawing before reading everything
+    // doesn't block us
+    let writer = std::thread::spawn(move || {
+        let _ = stdin.write_all(candidates.as_bytes());
+    });
+    let mut out = String::new();
+    child.stdout.take().unwrap().read_to_string(&mut out)?;
+    let status = child.wait()?;
+    let _ = writer.join();
+    let picked = out.trim_end_matches(['\n', '\r']).to_string();
+    Ok((status.success() && !picked.is_empty()).then_some(picked))
+}
@@ -0,0 +1,142 @@
+//! Glob expansion
+//!
+//! Words with `*`, `?` or `[...]` in them that weren't quoted are matched against the file names
+//! relative to the working directory, a path component at a time. Hidden files only match when the
+//! component starts with a `.` too, and a pattern that matches nothing is left as it was.
+
+use std::path::Path;
+
+/// Whether a word has any wildcards in it.
+pub fn is_pattern(word: &str) -> bool {
+    let mut escape = false;
+    for ch in word.chars() {
+        if escape {
+            escape = false;
+        } else if ch == '\\' {
+            escape = true;
+        } else if ['*', '?', '['].contains(&ch) {
+            return true;
+        }
+    }
+    false
+}
+
+/// Match a `[...]` class against a character, starting just after the `[`. Returns whether it
+/// matched and how many characters the class took, or None if it isn't closed.
+fn match_class(pattern: &[char], ch: char) -> Option<(bool, usize)> {
+    let mut i = 0;
+    let negated = matches!(pattern.first(), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    while i < pattern.len() {
+        let start = pattern[i];
+        // a `]` straight away is part of the class
+        if start == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+            matched |= (start..=pattern[i + 2]).contains(&ch);
+            i += 3;
+        } else {
+            matched |= start == ch;
+            i += 1;
+        }
+    }
+    None
+}
+
+/// Match a whole name against a pattern without any slashes.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    fn inner(pattern: &[char], name: &[char]) -> bool {
+        match pattern.first() {
+            None => name.is_empty(),
+            Some('*') => (0..=name.len()).any(|i| inner(&pattern[1..], &name[i..])),
+            Some('?') => !name.is_empty() && inner(&pattern[1..], &name[1..]),
+            Some('[') if !name.is_empty() => match match_class(&pattern[1..], name[0]) {
+                Some((matched, len)) => matched && inner(&pattern[len + 1..], &name[1..]),
+                None => name[0] == '[' && inner(&pattern[1..], &name[1..]),
+            },
+            Some('\\') if pattern.len() > 1 => {
+                name.first() == Some(&pattern[1]) && inner(&pattern[2..], &name[1..])
+            }
+            Some(ch) => name.first() == Some(ch) && inner(&pattern[1..], &name[1..]),
+        }
+    }
+    inner(
+        &pattern.chars().collect::<Vec<char>>(),
+        &name.chars().collect::<Vec<char>>(),
+    )
+}
+
+/// Take the backslashes out of a component that isn't a pattern.
+fn unescape(component: &str) -> String {
+    let mut out = String::new();
+    let mut escape = false;
+    for ch in component.chars() {
+        if ch == '\\' && !escape {
+            escape = true;
+            continue;
+        }
+        escape = false;
+        out.push(ch);
+    }
+    out
+}
+
+/// Expand a word into the paths it matches, sorted. None if it isn't a pattern or nothing matched.
+pub fn expand(word: &str, working_dir: &Path) -> Option<Vec<String>> {
+    if !is_pattern(word) {
+        return None;
+    }
+    let (mut found, rest) = match word.strip_prefix('/') {
+        Some(rest) => (vec![String::from("/")], rest),
+        None => (vec![String::new()], word),
+    };
+    let components = rest.split('/').collect::<Vec<&str>>();
+    for (i, component) in components.iter().enumerate() {
+        let last = i == components.len() - 1;
+        let mut next = Vec::new();
+        for prefix in &found {
+            if component.is_empty() {
+                // a trailing slash only keeps directories
+                if working_dir.join(super::os_str::to_path(prefix)).is_dir() {
+                    next.push(prefix.clone());
+                }
+                continue;
+            }
+            if !is_pattern(component) {
+                let path = prefix.clone() + &unescape(component);
+                if working_dir.join(super::os_str::to_path(&path)).exists() {
+                    next.push(path + if last { "" } else { "/" });
+                }
+                continue;
+            }
+            let dir = if prefix.is_empty() { "." } else { prefix };
+            let dir = working_dir.join(super::os_str::to_path(dir));
+            let Ok(entries) = std::fs::read_dir(dir) else {
+                continue;
+            };
+            for entry in entries.filter_map(|v| v.ok()) {
+                let name = super::os_str::to_string(&entry.file_name());
+                if name.starts_with('.') && !component.starts_with('.') {
+                    continue;
+                }
+                if !matches(component, &name) {
+                    continue;
+                }
+                if last {
+                    next.push(prefix.clone() + &name);
+                } else if entry.path().is_dir() {
+                    next.push(prefix.clone() + &name + "/");
+                }
+            }
+        }
+        found = next;
+    }
+    found.sort();
+    (!found.is_empty()).then_some(found)
+}
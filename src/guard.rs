@@ -0,0 +1,149 @@
+//! Dangerous command guard
+//!
+//! Commands matching one of the guard patterns have to be confirmed before they run in an
+//! interactive shell and are refused outright everywhere else. Patterns are matched word by word
+//! against the command after variables and wildcards are expanded, and again with each argument
+//! resolved to the path it names, the home directory being `~`, so `$HOME/`, `~/` and `/usr/..`
+//! are caught like `~` and `/`. A `*` word matches any number of words, and in other words `*`
+//! matches anything and `?` a single character, except for `/`, so `/*` only matches something
+//! right under the root.
+
+use std::{io::Write, path::PathBuf};
+
+use super::i18n::tr;
+
+/// Patterns guarded by default.
+pub const DEFAULT_PATTERNS: [&str; 12] = [
+    "rm * -*r* * /* *",
+    "rm * -*R* * /* *",
+    "rm * -*r* * ~ *",
+    "rm * -*R* * ~ *",
+    "dd * of=/dev/sd* *",
+    "dd * of=/dev/nvme* *",
+    "dd * of=/dev/hd* *",
+    "mkfs* *",
+    "chmod * -R * /* *",
+    "chown * -R * /* *",
+    ":()* *",
+    "shred * /dev/* *",
+];
+
+/// Whether a command matches a guard pattern.
+pub fn matches(pattern: &str, words: &[String]) -> bool {
+    matches_words(&pattern.split_whitespace().collect::<Vec<&str>>(), words)
+}
+
+/// Whether the words match the words of a pattern.
+fn matches_words(pattern: &[&str], words: &[String]) -> bool {
+    match pattern.split_first() {
+        None => words.is_empty(),
+        Some((&"*", rest)) => (0..=words.len()).any(|i| matches_words(rest, &words[i..])),
+        Some((first, rest)) => {
+            words.first().is_some_and(|v| matches_word(first, v))
+                && matches_words(rest, &words[1..])
+        }
+    }
+}
+
+/// Whether a word matches a glob pattern, with slashes matched one for one.
+fn matches_word(pattern: &str, word: &str) -> bool {
+    let pattern = pattern.split('/').collect::<Vec<&str>>();
+    let word = word.split('/').collect::<Vec<&str>>();
+    pattern.len() == word.len() && pattern.iter().zip(word).all(|(p, w)| matches_part(p, w))
+}
+
+/// Whether part of a word matches a glob pattern.
+fn matches_part(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let text = text.chars().collect::<Vec<char>>();
+    let (mut p, mut t) = (0, 0);
+    // where the last star was and the text position it's currently covering up to
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|v| *v == '*')
+}
+
+/// The path an argument names, as `~` if it's the home directory. Options are left as they are.
+fn resolve(word: &str, state: &super::State) -> String {
+    if word.starts_with('-') {
+        return word.to_string();
+    }
+    let home = super::get_var(state, "HOME")
+        .map(PathBuf::from)
+        .or_else(std::env::home_dir);
+    let path = match (word.strip_prefix('~'), &home) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            home.join(rest.trim_start_matches('/'))
+        }
+        _ => state.working_dir.join(word),
+    };
+    // what doesn't exist can't be followed through symlinks
+    let canonical = |path: &PathBuf| {
+        path.canonicalize()
+            .unwrap_or_else(|_| super::normalize_path(path))
+    };
+    let path = canonical(&path);
+    if home.is_some_and(|v| canonical(&v) == path) {
+        return "~".to_string();
+    }
+    path.to_string_lossy().to_string()
+}
+
+/// Check a command against the guard patterns. Returns whether it may run.
+pub fn check(words: &[String], state: &super::State) -> bool {
+    let resolved = words[..1]
+        .iter()
+        .cloned()
+        .chain(words[1..].iter().map(|v| resolve(v, state)))
+        .collect::<Vec<String>>();
+    let Some(pattern) = state
+        .guards
+        .iter()
+        .find(|v| matches(v, words) || matches(v, &resolved))
+    else {
+        return true;
+    };
+    let command = super::mask_secrets(&words.join(" "), state);
+    let Some(raw_term) = state.raw_term.clone() else {
+        eprintln!(
+            "{}",
+            tr!(
+                "sesh: {}: refusing to run command matching guard pattern `{}`",
+                words[0],
+                pattern
+            )
+        );
+        return false;
+    };
+    {
+        let mut writer = raw_term.write().unwrap();
+        let _ = write!(
+            writer,
+            "{}",
+            tr!(
+                "sesh: `{}` matches guard pattern `{}`. Run it anyway? [y/N] ",
+                command,
+                pattern
+            )
+        );
+        let _ = writer.flush();
+    }
+    let answer = super::term::input().read_byte().unwrap_or(b'n');
+    let mut writer = raw_term.write().unwrap();
+    let _ = write!(writer, "\r\n");
+    answer == b'y' || answer == b'Y'
+}
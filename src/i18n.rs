@@ -0,0 +1,101 @@
+//! Localization of shell messages
+//!
+//! Messages are written in English and looked up in a catalog for the language in `$LC_ALL`,
+//! `$LC_MESSAGES` or `$LANG`, in that order. A catalog is a JSON object from English messages to
+//! their translations, stored in `~/.config/sesh/locale/` as e.g. `de_DE.json` or `de.json`, the
+//! more specific one being preferred. Messages missing from the catalog stay in English.
+//!
+//! Placeholders are `{}` for the next argument or `{0}` and up for a specific one, so translations
+//! can reorder them. `{{` and `}}` are literal braces.
+
+use std::{collections::HashMap, fmt::Display, path::PathBuf, sync::OnceLock};
+
+/// The catalog for the current language.
+static CATALOG: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// Translate a message and fill in its arguments, like [format!].
+macro_rules! tr {
+    ($message:expr $(, $arg:expr)* $(,)?) => {
+        $crate::i18n::format($message, &[$(&$arg as &dyn std::fmt::Display),*])
+    };
+}
+pub(crate) use tr;
+
+/// The directory catalogs are loaded from.
+pub fn locale_dir() -> PathBuf {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .unwrap_or(std::env::home_dir().unwrap_or_default().join(".config"))
+        .join("sesh")
+        .join("locale")
+}
+
+/// The languages to look for a catalog for, most specific first.
+fn languages() -> Vec<String> {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|v| std::env::var(v).ok())
+        .find(|v| !v.is_empty())
+        .unwrap_or_default();
+    // drop the encoding and modifier, e.g. de_DE.UTF-8@euro
+    let locale = locale.split(['.', '@']).next().unwrap_or_default();
+    if locale.is_empty() || locale == "C" || locale == "POSIX" {
+        return Vec::new();
+    }
+    let mut out = vec![locale.to_string()];
+    if let Some((language, _)) = locale.split_once('_') {
+        out.push(language.to_string());
+    }
+    out
+}
+
+/// Load the catalog for the current language.
+fn load() -> HashMap<String, String> {
+    for language in languages() {
+        let Ok(catalog) = std::fs::read(locale_dir().join(language + ".json")) else {
+            continue;
+        };
+        match serde_json::from_slice(&catalog) {
+            Ok(catalog) => return catalog,
+            Err(e) => println!("sesh: reading message catalog failed: {}", e),
+        }
+    }
+    HashMap::new()
+}
+
+/// Translate a message.
+pub fn translate(message: &str) -> &str {
+    CATALOG
+        .get_or_init(load)
+        .get(message)
+        .map_or(message, |v| v.as_str())
+}
+
+/// Translate a message and fill in its arguments.
+pub fn format(message: &str, args: &[&dyn Display]) -> String {
+    let mut out = String::new();
+    let mut next = 0usize;
+    let mut chars = translate(message).chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '{' | '}' if chars.peek() == Some(&ch) => {
+                chars.next();
+                out.push(ch);
+            }
+            '{' => {
+                let index = chars.by_ref().take_while(|v| *v != '}').collect::<String>();
+                let index = if index.is_empty() {
+                    next += 1;
+                    next - 1
+                } else {
+                    index.parse().unwrap_or(usize::MAX)
+                };
+                if let Some(arg) = args.get(index) {
+                    out += &arg.to_string();
+                }
+            }
+            ch => out.push(ch),
+        }
+    }
+    out
+}
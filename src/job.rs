@@ -0,0 +1,363 @@
+//! Background jobs
+//!
+//! A statement ending in `&` runs in the background, in a new sesh of its own so builtins and
+//! groups work there too. It skips ~/.seshrc and gets the variables and aliases of the shell that
+//! started it instead. Each job gets its own process group, which is what lets `fg` hand it the
+//! terminal and Ctrl-Z stop it again. Programs run in the foreground of an interactive shell get
+//! process groups of their own too, and become jobs if Ctrl-Z stops them. SIGCHLD only marks that
+//! something changed; jobs are checked and finished ones reported before the prompt, or while
+//! waiting at it.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::i18n::tr;
+
+/// Whether a child changed state since the jobs were last checked.
+static CHILD_CHANGED: AtomicBool = AtomicBool::new(true);
+
+/// What a job is doing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobStatus {
+    /// Still running
+    Running,
+    /// Stopped, e.g. by Ctrl-Z or by reading the terminal in the background
+    Stopped,
+    /// Finished, with its exit status
+    Done(i32),
+}
+
+/// A job started with `&`.
+#[derive(Clone, Debug)]
+pub struct Job {
+    /// What it's referred to as, like `%1`
+    pub id: usize,
+    /// The process, which leads the job's process group
+    pub pid: i32,
+    /// What it's running
+    pub statement: String,
+    /// What it's doing
+    pub status: JobStatus,
+}
+
+impl std::fmt::Display for Job {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self.status {
+            JobStatus::Running => tr!("Running"),
+            JobStatus::Stopped => tr!("Stopped"),
+            JobStatus::Done(0) => tr!("Done"),
+            JobStatus::Done(status) => tr!("Exit {}", status),
+        };
+        write!(f, "[{}]  {:<8}  {}", self.id, status, self.statement)
+    }
+}
+
+/// Note that a child changed state.
+#[cfg(unix)]
+extern "C" fn on_child(_: libc::c_int) {
+    CHILD_CHANGED.store(true, Ordering::SeqCst);
+}
+
+/// Note that Ctrl-Z was pressed, which does nothing to the shell itself.
+#[cfg(unix)]
+extern "C" fn on_stop(_: libc::c_int) {}
+
+/// Get ready to run jobs: watch for SIGCHLD, and ignore SIGTTOU so the shell can take the terminal
+/// back from a job.
+pub fn init() {
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(libc::SIGCHLD, on_child as *const () as libc::sighandler_t);
+        libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+    }
+}
+
+/// Make an interactive shell the owner of the terminal, in a process group of its own, and catch
+/// SIGTSTP so Ctrl-Z stops the program in the foreground and not the shell. It's caught rather
+/// than ignored so the programs the shell runs don't inherit ignoring it.
+pub fn take_terminal() {
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(libc::SIGTSTP, on_stop as *const () as libc::sighandler_t);
+        // a session leader already has a group of its own
+        libc::setpgid(0, 0);
+        libc::tcsetpgrp(0, libc::getpgrp());
+    }
+}
+
+/// Give the terminal back to the shell once a job in the foreground finishes or stops.
+pub fn reclaim_terminal() {
+    #[cfg(unix)]
+    unsafe {
+        libc::tcsetpgrp(0, libc::getpgrp());
+    }
+}
+
+/// Set a program up to run in the foreground in a process group of its own, or in `group` with the
+/// programs of a pipeline it's part of, which the terminal is handed to before it starts.
+pub fn in_foreground(command: &mut std::process::Command, group: Option<i32>) {
+    #[cfg(unix)]
+    unsafe {
+        use std::os::unix::process::CommandExt;
+
+        command.pre_exec(move || {
+            libc::setpgid(0, group.unwrap_or(0));
+            // its stdin may not be the terminal
+            let terminal = libc::open(c"/dev/tty".as_ptr(), libc::O_RDWR | libc::O_CLOEXEC);
+            if terminal >= 0 {
+                libc::tcsetpgrp(terminal, libc::getpgrp());
+                libc::close(terminal);
+            }
+            libc::signal(libc::SIGTTOU, libc::SIG_DFL);
+            Ok(())
+        });
+    }
+    #[cfg(not(unix))]
+    let _ = (command, group);
+}
+
+/// The id the next job gets.
+fn next_id(state: &super::State) -> usize {
+    state.jobs.iter().map(|v| v.id + 1).max().unwrap_or(1)
+}
+
+/// Keep track of a program that was stopped in the foreground as a job. Returns the job's id.
+pub fn adopt(pid: i32, statement: &str, state: &mut super::State) -> usize {
+    let id = next_id(state);
+    state.jobs.push(Job {
+        id,
+        pid,
+        statement: statement.to_string(),
+        status: JobStatus::Stopped,
+    });
+    id
+}
+
+/// Start a statement in the background. Returns the job's id.
+pub fn start(statement: &str, state: &mut super::State) -> std::io::Result<usize> {
+    #[cfg(unix)]
+    {
+        use std::os::{fd::AsRawFd, unix::process::CommandExt};
+
+        let session = super::session::send(state)?;
+        let fd = session.as_raw_fd();
+        let mut command = std::process::Command::new(std::env::current_exe()?);
+        super::export_env(&mut command, state);
+        if state.restricted {
+            command.arg("--restricted");
+        }
+        command
+            .arg("--no-rc")
+            .arg("--session-fd")
+            .arg(fd.to_string())
+            .arg("-c")
+            .arg(statement)
+            .current_dir(&state.working_dir)
+            .process_group(0);
+        unsafe {
+            // it's closed on exec like every file the shell opens, but this one is for the job
+            command.pre_exec(move || {
+                if libc::fcntl(fd, libc::F_SETFD, 0) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = command.spawn()?;
+        let id = next_id(state);
+        state.jobs.push(Job {
+            id,
+            pid: child.id() as i32,
+            statement: statement.to_string(),
+            status: JobStatus::Running,
+        });
+        Ok(id)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (statement, state);
+        Err(std::io::Error::other(super::i18n::translate(
+            "background jobs aren't supported on this platform",
+        )))
+    }
+}
+
+/// Turn a status from waitpid into what a job is doing.
+#[cfg(unix)]
+fn decode(status: libc::c_int) -> JobStatus {
+    if libc::WIFSTOPPED(status) {
+        JobStatus::Stopped
+    } else if libc::WIFSIGNALED(status) {
+        JobStatus::Done(128 + libc::WTERMSIG(status))
+    } else if libc::WIFEXITED(status) {
+        JobStatus::Done(libc::WEXITSTATUS(status))
+    } else {
+        JobStatus::Running
+    }
+}
+
+/// Check on a job without waiting, or wait until it finishes or stops.
+fn check(job: &mut Job, block: bool) {
+    #[cfg(unix)]
+    {
+        let mut status = 0;
+        let flags = libc::WUNTRACED | libc::WCONTINUED | if block { 0 } else { libc::WNOHANG };
+        let ret = unsafe { libc::waitpid(job.pid, &mut status, flags) };
+        if ret == job.pid {
+            job.status = decode(status);
+        } else if ret < 0 {
+            // someone else reaped it
+            job.status = JobStatus::Done(255);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (job, block);
+}
+
+/// Check on all the jobs that haven't finished.
+pub fn update(state: &mut super::State) {
+    CHILD_CHANGED.store(false, Ordering::SeqCst);
+    for job in &mut state.jobs {
+        if !matches!(job.status, JobStatus::Done(_)) {
+            check(job, false);
+        }
+    }
+}
+
+/// End the jobs that haven't finished, as the shell exits.
+pub fn hang_up(state: &mut super::State) {
+    update(state);
+    for job in &state.jobs {
+        if matches!(job.status, JobStatus::Done(_)) {
+            continue;
+        }
+        // stopped ones only see the hangup once they're continued
+        #[cfg(unix)]
+        unsafe {
+            libc::kill(-job.pid, libc::SIGHUP);
+            libc::kill(-job.pid, libc::SIGCONT);
+        }
+    }
+}
+
+/// Report the jobs that finished and forget them. Returns whether any did.
+pub fn report(state: &mut super::State) -> bool {
+    if !CHILD_CHANGED.load(Ordering::SeqCst) {
+        return false;
+    }
+    update(state);
+    let (done, running) = std::mem::take(&mut state.jobs)
+        .into_iter()
+        .partition::<Vec<Job>, _>(|v| matches!(v.status, JobStatus::Done(_)));
+    state.jobs = running;
+    if !done.is_empty() {
+        print!("\r\x1b[2K");
+    }
+    for job in &done {
+        println!("{}\r", job);
+    }
+    !done.is_empty()
+}
+
+/// Find a job by `%id` or `id`, or the most recent one without a spec.
+pub fn find(spec: Option<&str>, state: &super::State) -> Option<usize> {
+    let Some(spec) = spec else {
+        return state.jobs.len().checked_sub(1);
+    };
+    let id = spec
+        .strip_prefix('%')
+        .unwrap_or(spec)
+        .parse::<usize>()
+        .ok()?;
+    state.jobs.iter().position(|v| v.id == id)
+}
+
+/// Continue a stopped job in the background.
+pub fn resume(index: usize, state: &mut super::State) {
+    let job = &mut state.jobs[index];
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(-job.pid, libc::SIGCONT);
+    }
+    job.status = JobStatus::Running;
+}
+
+/// Hand the terminal to a job and wait for it to finish or stop. Returns the status to give the
+/// statement.
+pub fn foreground(index: usize, state: &mut super::State) -> i32 {
+    let pid = state.jobs[index].pid;
+    #[cfg(unix)]
+    unsafe {
+        libc::tcsetpgrp(0, pid);
+        libc::kill(-pid, libc::SIGCONT);
+    }
+    state.jobs[index].status = JobStatus::Running;
+    // it may report being continued first
+    while state.jobs[index].status == JobStatus::Running {
+        check(&mut state.jobs[index], true);
+    }
+    reclaim_terminal();
+    let job = state.jobs[index].clone();
+    match job.status {
+        JobStatus::Done(status) => {
+            state.jobs.remove(index);
+            status
+        }
+        JobStatus::Stopped => {
+            println!("\n{}", job);
+            // as if killed by SIGTSTP
+            128 + 20
+        }
+        JobStatus::Running => 0,
+    }
+}
+
+/// Wait for a job to finish. Returns its status, or None if Ctrl-C was pressed first.
+pub fn wait(index: usize, state: &mut super::State) -> Option<i32> {
+    loop {
+        check(&mut state.jobs[index], false);
+        if let JobStatus::Done(status) = state.jobs[index].status {
+            state.jobs.remove(index);
+            return Some(status);
+        }
+        if super::take_interrupt() {
+            return None;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+}
+
+/// Wait for the first of some jobs to finish, by id. Returns its id and status, or None if Ctrl-C
+/// was pressed first.
+pub fn wait_any(ids: &[usize], state: &mut super::State) -> Option<(usize, i32)> {
+    loop {
+        for index in 0..state.jobs.len() {
+            if !ids.contains(&state.jobs[index].id) {
+                continue;
+            }
+            check(&mut state.jobs[index], false);
+            if let JobStatus::Done(status) = state.jobs[index].status {
+                return Some((state.jobs.remove(index).id, status));
+            }
+        }
+        if super::take_interrupt() {
+            return None;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+}
+
+/// Interrupt some jobs, by id, and wait for them to finish.
+pub fn cancel(ids: &[usize], state: &mut super::State) {
+    for job in state.jobs.iter_mut().filter(|v| ids.contains(&v.id)) {
+        // they have process groups of their own, so Ctrl-C didn't reach them
+        #[cfg(unix)]
+        unsafe {
+            libc::kill(-job.pid, libc::SIGINT);
+            libc::kill(-job.pid, libc::SIGCONT);
+        }
+        while !matches!(job.status, JobStatus::Done(_)) {
+            check(job, true);
+        }
+    }
+    state.jobs.retain(|v| !ids.contains(&v.id));
+}
@@ -0,0 +1,232 @@
+//! Disks and network interfaces
+//!
+//! `dfp` and `netif` put what `df` and `ip addr` show in the focus, read straight from the system,
+//! so checks and dashboards don't have to pick apart their output. Sizes are in bytes, for `units`
+//! and the arithmetic to work with. Filesystems can only be listed on Linux, and interfaces on
+//! unix.
+
+use std::path::{Path, PathBuf};
+
+/// How full a mounted filesystem is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Filesystem {
+    /// Where it's mounted
+    pub mount: PathBuf,
+    /// What kind it is, like ext4
+    pub kind: String,
+    /// What's mounted, like a device
+    pub source: String,
+    /// How big it is, in bytes
+    pub size: u64,
+    /// How much of it is used, in bytes
+    pub used: u64,
+    /// How much of it is left for anyone to use, in bytes
+    pub available: u64,
+}
+
+impl Filesystem {
+    /// How much of it is used, in percent rounded up, like df.
+    pub fn percent(&self) -> u64 {
+        let usable = self.used + self.available;
+        if usable == 0 {
+            0
+        } else {
+            (self.used * 100).div_ceil(usable)
+        }
+    }
+}
+
+/// Undo the octal escapes of spaces and the like in /proc/self/mounts.
+#[cfg(target_os = "linux")]
+fn unescape(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let code = text
+            .get(i + 1..i + 4)
+            .filter(|_| bytes[i] == b'\\')
+            .and_then(|v| u8::from_str_radix(v, 8).ok());
+        match code {
+            Some(code) => {
+                out.push(code);
+                i += 4;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+/// Get the usage of the filesystem a path is on.
+#[cfg(unix)]
+fn usage(path: &Path) -> std::io::Result<(u64, u64, u64)> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = unsafe { std::mem::zeroed::<libc::statvfs>() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let block = stat.f_frsize as u64;
+    let size = stat.f_blocks as u64 * block;
+    let used = size - stat.f_bfree as u64 * block;
+    Ok((size, used, stat.f_bavail as u64 * block))
+}
+
+/// List the mounted filesystems that take up space, leaving out ones like /proc.
+#[cfg(target_os = "linux")]
+pub fn filesystems() -> std::io::Result<Vec<Filesystem>> {
+    let mut out = Vec::<Filesystem>::new();
+    for line in std::fs::read_to_string("/proc/self/mounts")?.lines() {
+        let [source, mount, kind, ..] = line.split(' ').collect::<Vec<&str>>()[..] else {
+            continue;
+        };
+        let mount = PathBuf::from(unescape(mount));
+        // one it can't be looked at, like one it isn't allowed into, is left out
+        let Ok((size, used, available)) = usage(&mount) else {
+            continue;
+        };
+        if size == 0 {
+            continue;
+        }
+        // the last one mounted on a path hides the others
+        out.retain(|v| v.mount != mount);
+        out.push(Filesystem {
+            mount,
+            kind: kind.to_string(),
+            source: unescape(source),
+            size,
+            used,
+            available,
+        });
+    }
+    Ok(out)
+}
+
+/// List the mounted filesystems that take up space, leaving out ones like /proc.
+#[cfg(not(target_os = "linux"))]
+pub fn filesystems() -> std::io::Result<Vec<Filesystem>> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        super::i18n::translate("listing filesystems isn't supported on this platform"),
+    ))
+}
+
+/// The filesystem a path is on, out of the mounted ones.
+pub fn filesystem_of(path: &Path, mounted: &[Filesystem]) -> Option<Filesystem> {
+    let path = std::fs::canonicalize(path).ok()?;
+    mounted
+        .iter()
+        .filter(|v| path.starts_with(&v.mount))
+        .max_by_key(|v| v.mount.as_os_str().len())
+        .cloned()
+}
+
+/// A network interface.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Interface {
+    /// Its name, like eth0
+    pub name: String,
+    /// Whether it's up
+    pub up: bool,
+    /// Its hardware address, if it has one that's known
+    pub mac: Option<String>,
+    /// Its addresses, with the length of their prefix, like 192.168.1.2/24
+    pub addresses: Vec<String>,
+}
+
+/// The address in a socket address and the number of bits set in it, for netmasks, if it's an IP
+/// address.
+#[cfg(unix)]
+unsafe fn ip_of(addr: *const libc::sockaddr) -> Option<(std::net::IpAddr, u32)> {
+    if addr.is_null() {
+        return None;
+    }
+    unsafe {
+        match (*addr).sa_family as i32 {
+            libc::AF_INET => {
+                let addr = *(addr as *const libc::sockaddr_in);
+                let bits = u32::from_be(addr.sin_addr.s_addr);
+                Some((std::net::Ipv4Addr::from(bits).into(), bits.count_ones()))
+            }
+            libc::AF_INET6 => {
+                let bytes = (*(addr as *const libc::sockaddr_in6)).sin6_addr.s6_addr;
+                let ones = bytes.iter().map(|v| v.count_ones()).sum();
+                Some((std::net::Ipv6Addr::from(bytes).into(), ones))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The hardware address in a socket address, if it has one.
+#[cfg(target_os = "linux")]
+unsafe fn mac_of(addr: *const libc::sockaddr) -> Option<String> {
+    unsafe {
+        if addr.is_null() || (*addr).sa_family as i32 != libc::AF_PACKET {
+            return None;
+        }
+        let addr = *(addr as *const libc::sockaddr_ll);
+        let bytes = &addr.sll_addr[..(addr.sll_halen as usize).min(8)];
+        (!bytes.is_empty()).then(|| {
+            bytes
+                .iter()
+                .map(|v| format!("{:02x}", v))
+                .collect::<Vec<String>>()
+                .join(":")
+        })
+    }
+}
+
+/// List the network interfaces and their addresses.
+#[cfg(unix)]
+pub fn interfaces() -> std::io::Result<Vec<Interface>> {
+    let mut list = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut list) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let mut out = Vec::<Interface>::new();
+    let mut next = list;
+    while let Some(entry) = unsafe { next.as_ref() } {
+        next = entry.ifa_next;
+        let name = unsafe { std::ffi::CStr::from_ptr(entry.ifa_name) }
+            .to_string_lossy()
+            .to_string();
+        // an interface has an entry for each of its addresses
+        let index = match out.iter().position(|v| v.name == name) {
+            Some(index) => index,
+            None => {
+                out.push(Interface {
+                    name,
+                    up: entry.ifa_flags & libc::IFF_UP as u32 != 0,
+                    mac: None,
+                    addresses: Vec::new(),
+                });
+                out.len() - 1
+            }
+        };
+        if let Some((ip, _)) = unsafe { ip_of(entry.ifa_addr) } {
+            let prefix = unsafe { ip_of(entry.ifa_netmask) }.map_or(0, |v| v.1);
+            out[index].addresses.push(format!("{}/{}", ip, prefix));
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(mac) = unsafe { mac_of(entry.ifa_addr) } {
+            out[index].mac = Some(mac);
+        }
+    }
+    unsafe { libc::freeifaddrs(list) };
+    Ok(out)
+}
+
+/// List the network interfaces and their addresses.
+#[cfg(not(unix))]
+pub fn interfaces() -> std::io::Result<Vec<Interface>> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        super::i18n::translate("listing network interfaces isn't supported on this platform"),
+    ))
+}
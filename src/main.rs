@@ -6,22 +6,58 @@
 #![feature(test)]
 #![feature(let_chains)]
 
+#[cfg(unix)]
+use std::os::fd::FromRawFd;
 use std::{
-    ffi::OsStr,
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
     fmt::Display,
-    io::{Read, Write},
-    os::fd::FromRawFd,
+    io::{IsTerminal, Read, Write},
     path::PathBuf,
-    sync::{Arc, RwLock},
+    sync::{
+        Arc, LazyLock, Mutex, RwLock,
+        atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
 };
 
-use clap::Parser;
-use termion::raw::IntoRawMode;
+use clap::{CommandFactory, Parser};
+use i18n::tr;
+use parser::{Indirect, IndirectRes};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
+mod audit;
 mod builtins;
+mod clipboard;
+mod color;
+mod complete;
+mod ctl;
+mod editor;
 mod escapes;
+mod explain;
+mod finder;
+mod glob;
+mod guard;
+mod i18n;
+mod job;
+mod machine;
+mod os_str;
+mod parser;
+mod picker;
+mod plugins;
+mod procs;
+mod profile;
+mod project;
+mod sandbox;
+mod sched;
+mod session;
+mod term;
 #[cfg(test)]
 mod tests;
+mod timer;
+mod transcript;
+mod units;
 
 /// sesh is a shell designed to be as semantic to use as possible
 #[derive(Parser, Debug)]
@@ -33,6 +69,48 @@ struct Args {
     /// Run an expression before opening an interactive shell.
     #[arg(long="before", short='b', default_value_t=("".to_string()))]
     run_before: String,
+    /// A script to run. Ignored if --run or --before are passed.
+    script: Option<PathBuf>,
+    /// Run in restricted mode. Also enabled when invoked as rsesh.
+    #[arg(long, short = 'r')]
+    restricted: bool,
+    /// Don't print the greeting before the first prompt.
+    #[arg(long)]
+    no_greeting: bool,
+    /// Don't run ~/.seshrc.
+    #[arg(long)]
+    no_rc: bool,
+    /// Time each phase of startup and each statement of ~/.seshrc and print a breakdown.
+    #[arg(long)]
+    profile_startup: bool,
+    /// Restore a saved session after ~/.seshrc runs, by default the one the last interactive shell
+    /// left.
+    #[arg(long, value_name = "NAME", num_args = 0..=1, default_missing_value = session::LAST)]
+    resume: Option<String>,
+    /// Print a completion script for sesh's options and exit.
+    #[arg(long, value_name = "SHELL")]
+    completions: Option<clap_complete::Shell>,
+    /// List the builtins, one per line, and exit.
+    #[arg(long)]
+    list_builtins: bool,
+    /// List the builtins as JSON objects with their name, usage and description instead.
+    #[arg(long, requires = "list_builtins")]
+    json: bool,
+    /// Play back a transcript recorded with transcript start and exit.
+    #[arg(long, value_name = "FILE")]
+    replay: Option<PathBuf>,
+    /// How many times faster than it was recorded to play a transcript back.
+    #[arg(long, requires = "replay", default_value_t = 1.0)]
+    speed: f64,
+    /// Set by transcript start on the shell it records, with where it's recorded to.
+    #[arg(long, hide = true, value_name = "PATH")]
+    transcript: Option<PathBuf>,
+    /// Set on the shell running a background job, with the pipe its session comes through.
+    #[arg(long, hide = true, value_name = "FD")]
+    session_fd: Option<i32>,
+    /// Arguments to the script, available in $1 and up.
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    script_args: Vec<OsString>,
 }
 
 /// A single shell variable
@@ -42,6 +120,8 @@ struct ShellVar {
     name: String,
     /// The value of it
     value: String,
+    /// Whether programs the shell runs get it in their environment
+    exported: bool,
 }
 /// A lot of [ShellVar]s.
 type ShellVars = Vec<ShellVar>;
@@ -53,6 +133,8 @@ struct Alias {
     name: String,
     /// to
     to: String,
+    /// What it's for, shown by help and completion
+    desc: Option<String>,
 }
 
 /// A focus.
@@ -85,6 +167,25 @@ impl Display for Focus {
     }
 }
 
+impl Focus {
+    /// Convert the focus into JSON.
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Self::Str(s) => serde_json::Value::String(s.clone()),
+            Self::Vec(v) => serde_json::Value::Array(v.iter().map(|v| v.to_json()).collect()),
+        }
+    }
+
+    /// Convert JSON into a focus. Anything other than strings and arrays becomes a string.
+    fn from_json(json: &serde_json::Value) -> Self {
+        match json {
+            serde_json::Value::String(s) => Self::Str(s.clone()),
+            serde_json::Value::Array(v) => Self::Vec(v.iter().map(Self::from_json).collect()),
+            v => Self::Str(v.to_string()),
+        }
+    }
+}
+
 /// The state of the shell
 #[derive(Clone)]
 struct State {
@@ -97,243 +198,552 @@ struct State {
     /// The focused variable
     focus: Focus,
     /// Raw terminal.
-    raw_term: Option<Arc<RwLock<termion::raw::RawTerminal<std::io::Stdout>>>>,
+    raw_term: Option<Arc<RwLock<Box<dyn term::Terminal>>>>,
     /// sh
     in_mode: bool,
     /// sh
     entries: usize,
     /// The history
     history: Vec<String>,
+    /// When each entry of the history was run, for those where it was written down
+    history_times: Vec<Option<i64>>,
+    /// Loaded plugins
+    plugins: Vec<Arc<plugins::Plugin>>,
+    /// Whether the shell is restricted
+    restricted: bool,
+    /// Patterns of commands that need confirming
+    guards: Vec<String>,
+    /// Names of variables whose values are masked
+    secrets: Vec<String>,
+    /// Statements run when the shell exits
+    exit_hooks: Vec<String>,
+    /// The entry that exiting with jobs left was last refused at
+    exit_warned: Option<usize>,
+    /// Statements run before each prompt
+    precmd_hooks: Vec<String>,
+    /// Statements run after the working directory changes
+    chpwd_hooks: Vec<String>,
+    /// What to do when the script they were registered in ends, last first
+    scope_exits: Vec<ScopeExit>,
+    /// Whether a precmd hook was killed for taking too long before the last prompt
+    precmd_timed_out: bool,
+    /// Statements put off with defer until the first prompt is up
+    deferred: Vec<String>,
+    /// How long each statement evaluated took, while profiling startup
+    statement_times: Option<Vec<(String, Duration)>>,
+    /// The builtins being run, outermost first, so nesting can be limited
+    eval_stack: Vec<String>,
+    /// Redirections of the `{ ...; }` groups being evaluated, for statements without their own.
+    group_indirects: Vec<IndirectRes>,
+    /// Variables the shell keeps up to date itself, like STATUS. Looked at before the others and
+    /// read-only.
+    specials: BTreeMap<&'static str, String>,
+    /// What ~/.seshrc changed when it last ran
+    rc: RcChanges,
+    /// Statements scheduled to run later
+    timers: Vec<timer::Timer>,
+    /// The directories pushd left, most recent last
+    dir_stack: Vec<PathBuf>,
+    /// The project the working directory is in
+    project: project::Project,
+    /// What the last statement sent to the next one with `1@` or `2@`
+    pipe: Option<Vec<u8>>,
+    /// Jobs started with `&` that haven't been reported as done
+    jobs: Vec<job::Job>,
+    /// How many loops are running, so break and continue know if there's one to stop
+    loop_depth: usize,
+    /// What break or continue asked the innermost loop to do
+    loop_control: Option<LoopControl>,
+    /// How many subshells are running, so exit knows to leave the innermost one instead
+    subshell_depth: usize,
+    /// The status exit asked the innermost subshell to leave with
+    exit_request: Option<i32>,
+    /// Variables that were unset, so programs don't get them from the environment the shell
+    /// started with
+    unset_vars: Vec<String>,
+    /// Statements saved with remember, by name
+    bookmarks: Vec<(String, String)>,
+    /// What the next line is started with, to be edited and run or cleared
+    prefill: Option<String>,
+}
+
+/// Something to do when the script it was registered in ends.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum ScopeExit {
+    /// A statement registered with scope-exit
+    Statement(String),
+    /// A temporary file or directory from mktempf --cleanup, removed without going through a
+    /// statement so nothing in its name is taken for syntax
+    Remove(PathBuf),
+}
+
+/// What `break` or `continue` asked the innermost loop to do. The statements after them don't run
+/// until the loop has taken it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LoopControl {
+    /// Stop the loop
+    Break,
+    /// Go on to the next time around
+    Continue,
+}
+
+/// What a script like ~/.seshrc changed, so it can be undone, e.g. to reload it.
+#[derive(Clone, Default)]
+struct RcChanges {
+    /// When the file was last modified as of running it
+    modified: Option<SystemTime>,
+    /// The aliases it defined and what they were before
+    aliases: Vec<(String, Option<Alias>)>,
+    /// The variables it set and what they were before
+    vars: Vec<(String, Option<String>)>,
 }
 
 unsafe impl Sync for State {}
 unsafe impl Send for State {}
 
-/// Split a statement.
-fn split_statement(statement: &str) -> Vec<Result<IndirectRes, &str>> {
-    let mut out = vec![String::new()];
-    let mut i = 0usize;
-    let mut in_str = (false, ' ');
-    let mut escape = false;
-    let mut f = 0usize;
-    let mut str_idx = usize::MAX;
-    for ch in statement.chars() {
-        if ch == '\\' && !in_str.0 {
-            escape = true;
-        }
-        if in_str.0 && in_str.1 == ch {
-            in_str.0 = false;
-            if ch == ']' {
-                out[i].push(ch);
-            }
-            if ch == ')' && f == str_idx + 1 {
-                out[i].push('(');
-                out[i].push(ch);
-            }
-            escape = false;
-            f += 1;
-            continue;
+/// The groups and quotes opened so far while going through a statement.
+#[derive(Default)]
+struct Nesting {
+    /// What closes each of them, innermost last
+    closers: Vec<char>,
+    /// Whether the next character is escaped
+    escape: bool,
+}
+
+/// What a character did to the [Nesting].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Step {
+    /// Opened a group or quote
+    Opened,
+    /// Closed the innermost one
+    Closed,
+    /// Nothing
+    Neither,
+}
+
+impl Nesting {
+    /// Take in the next character.
+    fn push(&mut self, ch: char) -> Step {
+        if self.escape {
+            self.escape = false;
+        } else if ch == '\\' {
+            self.escape = true;
+        } else if self.closers.last() == Some(&ch) {
+            self.closers.pop();
+            return Step::Closed;
+        } else if !self.in_quote() {
+            let closer = match ch {
+                '"' | '\'' | '`' => ch,
+                '(' => ')',
+                '[' => ']',
+                '{' => '}',
+                _ => return Step::Neither,
+            };
+            self.closers.push(closer);
+            return Step::Opened;
+        }
+        Step::Neither
+    }
+
+    /// Whether the innermost thing opened is a quote.
+    fn in_quote(&self) -> bool {
+        self.closers
+            .last()
+            .is_some_and(|v| ['"', '\'', '`'].contains(v))
+    }
+
+    /// Whether everything opened has been closed.
+    fn is_empty(&self) -> bool {
+        self.closers.is_empty() && !self.escape
+    }
+}
+
+/// What closes a bracket or quote.
+fn closer_of(ch: char) -> Option<char> {
+    match ch {
+        '(' => Some(')'),
+        '[' => Some(']'),
+        '{' => Some('}'),
+        '"' | '\'' | '`' => Some(ch),
+        _ => None,
+    }
+}
+
+/// Insert text into a line at the cursor, which is a character index, leaving the cursor after it.
+fn insert_at_cursor(line: &mut String, cursor: &mut usize, text: &str) {
+    let at = line.char_indices().nth(*cursor).map_or(line.len(), |v| v.0);
+    line.insert_str(at, text);
+    *cursor += text.chars().count();
+}
+
+/// Apply a typed character to a line at the cursor, which is a character index; DEL deletes the
+/// character before it and Ctrl+D the one under it, Ctrl+W the word before it and Ctrl+U and Ctrl+K
+/// everything before or after it, while Ctrl+A and Ctrl+E go to the start and end. With autopair,
+/// an opening bracket or quote comes with its closing one, typing a closing one that's already
+/// next skips over it and deleting the opening one of an empty pair deletes both. Returns false if
+/// there was nothing to delete or the character can't be typed.
+fn edit_line(line: &mut String, cursor: &mut usize, ch: char, autopair: bool) -> bool {
+    let mut chars = line.chars().collect::<Vec<char>>();
+    let prev = cursor.checked_sub(1).and_then(|i| chars.get(i).copied());
+    let next = chars.get(*cursor).copied();
+    if ch == '\x01' {
+        *cursor = 0;
+    } else if ch == '\x05' {
+        *cursor = chars.len();
+    } else if ch == '\x04' {
+        if next.is_none() {
+            return false;
+        }
+        chars.drain(*cursor..grapheme_boundary(line, *cursor, true));
+    } else if ch == '\x17' {
+        // the whitespace before the cursor goes along with the word
+        let mut start = *cursor;
+        while start > 0 && chars[start - 1].is_whitespace() {
+            start -= 1;
         }
-        if !(!['"', '\'', '`', '(', '['].contains(&ch) || escape || in_str.0 || ch == '[' && f <= 1)
+        while start > 0 && !chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        if start == *cursor {
+            return false;
+        }
+        chars.drain(start..*cursor);
+        *cursor = start;
+    } else if ch == '\x15' {
+        chars.drain(..*cursor);
+        *cursor = 0;
+    } else if ch == '\x0B' {
+        chars.truncate(*cursor);
+    } else if ch.is_control() && ch != '\x7F' {
+        return false;
+    } else if ch == '\x7F' {
+        let Some(prev) = prev else {
+            return false;
+        };
+        if autopair && next.is_some() && closer_of(prev) == next {
+            chars.remove(*cursor);
+        }
+        let start = grapheme_boundary(line, *cursor, false);
+        chars.drain(start..*cursor);
+        *cursor = start;
+    } else if autopair && next == Some(ch) && [')', ']', '}', '"', '\'', '`'].contains(&ch) {
+        *cursor += 1;
+    } else {
+        chars.insert(*cursor, ch);
+        *cursor += 1;
+        // quotes right after a word are usually apostrophes
+        let quote = ['"', '\'', '`'].contains(&ch);
+        if autopair
+            && prev != Some('\\')
+            && !(quote && prev.is_some_and(|v| v.is_alphanumeric()))
+            && let Some(closer) = closer_of(ch)
         {
-            in_str = (true, ch);
-            if ch == '(' {
-                in_str.1 = ')';
-            }
-            if ch == '[' {
-                in_str.1 = ']';
-            }
-            if ch == '[' {
-                out[i].push(ch);
-            }
-            escape = false;
-            str_idx = f;
-            f += 1;
-            continue;
+            chars.insert(*cursor, closer);
         }
-        if !in_str.0 && ch == ' ' {
+    }
+    *line = chars.into_iter().collect();
+    true
+}
+
+/// Where the cursor goes moving a word back or forward, words being runs of letters and digits.
+fn word_boundary(line: &str, cursor: usize, forward: bool) -> usize {
+    let chars = line.chars().collect::<Vec<char>>();
+    let mut i = cursor.min(chars.len());
+    if forward {
+        while i < chars.len() && !chars[i].is_alphanumeric() {
             i += 1;
-            if i >= out.len() {
-                out.push(String::new());
-            }
-            escape = false;
-            f += 1;
-            continue;
         }
-        out[i].push(ch);
-        escape = false;
-        f += 1;
-    }
-    out.iter()
-        .map(|v| v.trim().to_string())
-        .map(|v| is_indirect(v))
-        .collect::<Vec<Result<IndirectRes, &str>>>()
-}
-
-/// An indirect to the value.
-#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
-enum Indirect {
-    /// default to
-    #[default]
-    Default,
-    /// Redirect to stdout(not for stdin!)
-    Stdout,
-    /// Redirect to stderr(not for stdin!)
-    Stderr,
-    /// Redirect to/from a file descriptor
-    Fd(i32),
-    /// Redirect to/from a path
-    Path(PathBuf),
-    /// Redirect to the next statement
-    NextStatement,
-    /// Redirect from the previous statement
-    PrevStatement,
-}
-
-/// A result from [is_indirect]
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
-enum IndirectRes {
-    /// Isn't a indirect; the part
-    Statement(String),
-    /// TO stdin
-    Stdin(Indirect),
-    /// FROM stdout
-    Stdout(Indirect),
-    /// FROM stderr
-    Stderr(Indirect),
-}
-
-impl IndirectRes {
-    /// get the statement value or panic if not
-    fn unwrap_statement(self) -> String {
-        if let Self::Statement(v) = self {
-            v
-        } else {
-            panic!("IndirectRes is not statement");
+        while i < chars.len() && chars[i].is_alphanumeric() {
+            i += 1;
+        }
+    } else {
+        while i > 0 && !chars[i - 1].is_alphanumeric() {
+            i -= 1;
+        }
+        while i > 0 && chars[i - 1].is_alphanumeric() {
+            i -= 1;
         }
     }
+    i
+}
 
-    /// return whether self is a statement
-    fn is_statement(&self) -> bool {
-        matches!(self, Self::Statement(_))
+/// Where the cursor goes moving over a grapheme, a character as it's seen like é or 👍🏽, back or
+/// forward.
+fn grapheme_boundary(line: &str, cursor: usize, forward: bool) -> usize {
+    let mut boundaries = std::iter::once(0).chain(line.graphemes(true).scan(0, |at, v| {
+        *at += v.chars().count();
+        Some(*at)
+    }));
+    if forward {
+        boundaries.find(|v| *v > cursor).unwrap_or(cursor)
+    } else {
+        boundaries.take_while(|v| *v < cursor).last().unwrap_or(0)
     }
 }
 
-/// Return whether a statement is a indirect pointer and if it is what to.
-fn is_indirect(statement: String) -> Result<IndirectRes, &'static str> {
-    fn is_indirect_inner(i: (&str, &str)) -> Indirect {
-        if i.1.is_empty() {
-            if i.0 == "0" {
-                Indirect::PrevStatement
-            } else {
-                Indirect::NextStatement
-            }
-        } else if i.0 == "0" {
-            if let Ok(n) = i.1.parse::<std::os::fd::RawFd>() {
-                Indirect::Fd(n)
-            } else {
-                Indirect::Path(PathBuf::from(i.1))
-            }
-        } else {
-            match i.1 {
-                "1" => Indirect::Stdout,
-                "2" => Indirect::Stderr,
-                v => {
-                    if let Ok(n) = v.parse::<std::os::fd::RawFd>() {
-                        Indirect::Fd(n)
-                    } else {
-                        Indirect::Path(PathBuf::from(v))
-                    }
-                }
-            }
+/// How many columns of the terminal the characters of a line from `from` up to `to` take up.
+fn text_width(line: &str, from: usize, to: usize) -> usize {
+    let text = line
+        .chars()
+        .skip(from)
+        .take(to.saturating_sub(from))
+        .collect::<String>();
+    text.width()
+}
+
+/// Move the cursor on the terminal from one character of a line to another.
+fn move_cursor(out: &mut impl Write, line: &str, from: usize, to: usize) -> std::io::Result<()> {
+    let width = text_width(line, from.min(to), from.max(to));
+    // moving 0 columns moves 1 on most terminals
+    if width > 0 {
+        write!(out, "\x1b[{}{}", width, if to < from { 'D' } else { 'C' })?;
+    }
+    Ok(())
+}
+
+/// Read the rest of a character typed as UTF-8, given its first byte.
+fn read_char(first: u8, keys: &mut dyn term::Input) -> char {
+    let len = match first {
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => 1,
+    };
+    let mut bytes = vec![first];
+    while bytes.len() < len
+        && let Ok(byte) = keys.read_byte()
+    {
+        bytes.push(byte);
+    }
+    std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(|v| v.chars().next())
+        .unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+/// Find the brackets and quotes of a line that match up, by character index, and the closing
+/// brackets that don't match anything.
+fn match_brackets(line: &str) -> (Vec<(usize, usize)>, Vec<usize>) {
+    let mut nesting = Nesting::default();
+    let mut opened = Vec::new();
+    let (mut pairs, mut stray) = (Vec::new(), Vec::new());
+    for (i, ch) in line.chars().enumerate() {
+        let literal = nesting.escape || nesting.in_quote();
+        match nesting.push(ch) {
+            Step::Opened => opened.push(i),
+            Step::Closed => pairs.push((opened.pop().unwrap_or(i), i)),
+            Step::Neither if !literal && [')', ']', '}'].contains(&ch) => stray.push(i),
+            Step::Neither => (),
         }
     }
-    if let Some(i) = statement.split_once("@") {
-        match i.0 {
-            "0" => Ok(IndirectRes::Stdin(is_indirect_inner(i))),
-            "1" => Ok(IndirectRes::Stdout(is_indirect_inner(i))),
-            "2" => Ok(IndirectRes::Stderr(is_indirect_inner(i))),
-            _ => Err("unknown indirect from"),
+    (pairs, stray)
+}
+
+/// Highlight the bracket or quote before the cursor along with its match, and closing brackets
+/// that don't match anything in red.
+fn highlight_brackets(line: &str, cursor: usize) -> String {
+    let (pairs, stray) = match_brackets(line);
+    let pair = cursor
+        .checked_sub(1)
+        .and_then(|i| pairs.into_iter().find(|v| v.0 == i || v.1 == i));
+    let mut out = String::new();
+    for (i, ch) in line.chars().enumerate() {
+        if pair.is_some_and(|v| v.0 == i || v.1 == i) {
+            out += &format!("\x1b[7m{}\x1b[27m", ch);
+        } else if stray.contains(&i) {
+            out += &format!("\x1b[31m{}\x1b[39m", ch);
+        } else {
+            out.push(ch);
         }
-    } else {
-        Ok(IndirectRes::Statement(statement))
     }
+    out
 }
 
-/// Removes comments from a statement
+/// Removes comments from a statement, leaving the bodies of here-documents as they are.
 fn remove_comments(statement: &str) -> String {
     let mut out = String::new();
-    let mut in_comment = false;
-    for ch in statement.chars() {
-        if in_comment {
-            if ch == '\n' {
-                out.push(ch);
-                in_comment = false
+    let mut nesting = Nesting::default();
+    let mut lines = statement.split_inclusive('\n');
+    while let Some(line) = lines.next() {
+        let line = match line.split_once('#') {
+            Some((code, comment)) if comment.ends_with('\n') => code.to_string() + "\n",
+            Some((code, _)) => code.to_string(),
+            None => line.to_string(),
+        };
+        out += &line;
+        for end in parser::document_ends(&line, &mut nesting) {
+            for line in lines.by_ref() {
+                out += line;
+                if line.trim() == end {
+                    break;
+                }
             }
-            continue;
-        }
-        if ch == '#' {
-            in_comment = true;
-            continue;
         }
-        out.push(ch);
     }
     out
 }
 
-/// Split something into lines
-fn split_lines(lines: &str) -> Vec<String> {
-    let mut out = vec![String::new()];
-    let mut i: usize = 0;
-    let mut escape_line = false;
-    for ch in lines.chars() {
-        if ch == '\n' && !escape_line {
-            i += 1;
-            continue;
+/// Split a `{ statements; }` group into the statements and what follows it.
+fn split_group(statement: &str) -> Option<(&str, &str)> {
+    let rest = statement
+        .strip_prefix('{')
+        .filter(|v| v.starts_with(char::is_whitespace))?;
+    let mut nesting = Nesting::default();
+    for (i, ch) in rest.char_indices() {
+        if ch == '}' && nesting.is_empty() {
+            return Some((&rest[..i], &rest[i + 1..]));
+        }
+        nesting.push(ch);
+    }
+    None
+}
+
+/// Evaluate the statements of a `{ ...; }` group in the current shell, with the redirections
+/// after it applying to all of them.
+fn eval_group(statements: &str, rest: &str, state: &mut State) -> bool {
+    let indirects = match parser::command(rest) {
+        Ok(command) => {
+            if let Some(word) = command.words.first() {
+                println!("{}", tr!("sesh: unexpected {} after group", word.text));
+                set_status(state, 1);
+                return false;
+            }
+            command.indirects
         }
-        if ch == '\\' {
-            escape_line = true;
+        Err(e) => {
+            println!("{}", tr!("sesh: {}", e));
+            return false;
+        }
+    };
+    let outer = state.group_indirects.clone();
+    state.group_indirects.retain(|v| {
+        !indirects
+            .iter()
+            .any(|indirect| std::mem::discriminant(indirect) == std::mem::discriminant(v))
+    });
+    state.group_indirects.extend(indirects);
+    eval(statements, state);
+    state.group_indirects = outer;
+    true
+}
+
+/// Substitute in shell variables and `!FOCUS`. A `$` takes the longest name that's set, so
+/// $HOMEBREW_PREFIX isn't taken for $HOME followed by BREW_PREFIX, and is left as it is if none
+/// are.
+fn expand_vars(text: &str, state: &State) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(['$', '!']) {
+        out += &rest[..start];
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("!FOCUS") {
+            out += &state.focus.to_string();
+            rest = after;
+            continue;
         }
-        while i >= out.len() {
-            out.push(String::new());
+        let after = &rest[1..];
+        // specials last, so they're taken over variables with the same name
+        let var = state
+            .shell_env
+            .iter()
+            .map(|v| (v.name.as_str(), &v.value))
+            .chain(state.specials.iter().map(|(name, value)| (*name, value)))
+            .filter(|(name, _)| {
+                rest.starts_with('$') && !name.is_empty() && after.starts_with(name)
+            })
+            .max_by_key(|(name, _)| name.len());
+        match var {
+            Some((name, value)) => {
+                out += value;
+                rest = &after[name.len()..];
+            }
+            None => {
+                out += &rest[..1];
+                rest = after;
+            }
         }
-        out[i].push(ch);
     }
-    out
+    out + rest
 }
 
-/// Split a string into statements
-fn split_statements(statement: &str) -> Vec<String> {
-    split_lines(statement)
-        .into_iter()
-        .map(|val| {
-            val.split(";")
-                .map(|val| val.to_string())
-                .collect::<Vec<String>>()
-        })
-        .collect::<Vec<Vec<String>>>()
-        .iter()
-        .map(|val| {
-            val.iter()
-                .map(|val| val.trim().to_string())
-                .collect::<Vec<String>>()
-        })
-        .collect::<Vec<Vec<String>>>()
-        .concat()
+/// Expand `${NAME}`, which is empty if it isn't set, and the forms with a fallback:
+/// `${NAME:-word}` gives the word if it's unset or empty, `${NAME:=word}` sets it to the word as
+/// well, and `${NAME:?message}` fails with the message.
+fn expand_braced(statement: &str, state: &mut State) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = statement;
+    while let Some(start) = rest.find("${") {
+        out += &rest[..start];
+        let inner = &rest[start + 2..];
+        // the word can have expansions of its own
+        let mut depth = 1;
+        let Some(end) = inner.char_indices().find_map(|(i, v)| {
+            match v {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => (),
+            }
+            (depth == 0).then_some(i)
+        }) else {
+            // unfinished, so left for the parser to complain about
+            out += &rest[start..];
+            return Ok(out);
+        };
+        out += &braced_value(&inner[..end], state, expand_braced)?;
+        rest = &inner[end + 1..];
+    }
+    Ok(out + rest)
 }
 
-/// Substitute in shell variables
-fn substitute_vars(statement: &str, state: State) -> String {
-    let mut out = statement.to_string();
-    for ShellVar { name, value } in state.shell_env {
-        out = out.replace(&("$".to_owned() + &name), &value);
+/// The value of the `${body}` in a statement, with `expand` used on a fallback word.
+fn braced_value(
+    body: &str,
+    state: &mut State,
+    expand: fn(&str, &mut State) -> Result<String, String>,
+) -> Result<String, String> {
+    let name_end = body
+        .find(|v: char| !(v.is_alphanumeric() || v == '_'))
+        .unwrap_or(body.len());
+    // specials like $? and $$ are a single character
+    let name_end = match body.chars().next() {
+        Some(v) if name_end == 0 && ['?', '$', '_'].contains(&v) => 1,
+        _ => name_end,
+    };
+    let (name, form) = body.split_at(name_end);
+    if name.is_empty() {
+        return Err(tr!("bad substitution: ${{{}}}", body));
     }
-    out = out.replace("!FOCUS", &format!("{}", state.focus));
-    out
+    let value = get_var(state, name).filter(|v| !v.is_empty());
+    Ok(match (value, form.get(..2), form.get(2..)) {
+        (value, None, _) if form.is_empty() => value.unwrap_or_default(),
+        (Some(value), Some(":-" | ":=" | ":?"), _) => value,
+        (None, Some(":-"), Some(word)) => expand(word, state)?,
+        (None, Some(":="), Some(word)) => {
+            builtins::restricted_var(state, name).map_err(|e| e.to_string())?;
+            let word = expand(word, state)?;
+            swap_vars(vec![(name.to_string(), Some(word.clone()))], state);
+            word
+        }
+        (None, Some(":?"), Some("")) => return Err(tr!("{}: not set", name)),
+        (None, Some(":?"), Some(message)) => {
+            return Err(tr!("{}: {}", name, expand(message, state)?));
+        }
+        _ => return Err(tr!("bad substitution: ${{{}}}", body)),
+    })
 }
 
 /// remove duplicates, keeping later ones
 fn garbage_collect_vars(state: &mut State) {
+    // setting an exported variable again doesn't stop it being exported
+    let exported = state
+        .shell_env
+        .iter()
+        .filter(|v| v.exported)
+        .map(|v| v.name.clone())
+        .collect::<Vec<String>>();
+    for var in &mut state.shell_env {
+        var.exported |= exported.contains(&var.name);
+    }
     state.shell_env.reverse();
     let mut seen = vec![];
     let mut remove_indexes = vec![];
@@ -347,319 +757,2418 @@ fn garbage_collect_vars(state: &mut State) {
         seen.push(var.name.clone());
         i += 1;
     }
-    for i in remove_indexes {
-        state.shell_env.remove(i);
+    // backwards so the indexes after each one removed stay right
+    for i in remove_indexes.into_iter().rev() {
+        state.shell_env.remove(i);
+    }
+    state.shell_env.sort_by(|v1, v2| v1.name.cmp(&v2.name));
+}
+
+/// Open a file descriptor.
+fn fd_file(fd: i32) -> Option<std::fs::File> {
+    #[cfg(unix)]
+    return Some(unsafe { std::fs::File::from_raw_fd(fd) });
+    #[cfg(not(unix))]
+    {
+        println!(
+            "{}",
+            tr!(
+                "sesh: redirecting file descriptor {} isn't supported on this platform",
+                fd
+            )
+        );
+        None
+    }
+}
+
+/// Open a file descriptor for a child to use.
+fn fd_stdio(fd: i32) -> Option<std::process::Stdio> {
+    fd_file(fd).map(Into::into)
+}
+
+/// Collects what a builtin sends to the next statement.
+#[derive(Clone, Default)]
+struct PipeWriter(Arc<Mutex<Vec<u8>>>);
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The write ends of the pipes being captured, by number. Statements redirected to one get a
+/// copy, so it's closed once they and the capture are done with it.
+static PIPES: Mutex<Vec<(usize, std::io::PipeWriter)>> = Mutex::new(Vec::new());
+
+/// Output collected through a pipe, read from another thread so nothing blocks however much
+/// there is.
+struct Capture {
+    /// Its number, for [Indirect::Pipe]
+    id: usize,
+    /// What's reading it
+    reader: std::thread::JoinHandle<Vec<u8>>,
+}
+
+impl Capture {
+    /// Open a pipe and start reading it.
+    fn open() -> std::io::Result<Self> {
+        /// Tells apart captures running at the same time.
+        static CAPTURES: AtomicUsize = AtomicUsize::new(0);
+        let (mut reader, writer) = std::io::pipe()?;
+        let id = CAPTURES.fetch_add(1, Ordering::SeqCst);
+        PIPES.lock().unwrap().push((id, writer));
+        let reader = std::thread::spawn(move || {
+            let mut out = Vec::new();
+            let _ = reader.read_to_end(&mut out);
+            out
+        });
+        Ok(Self { id, reader })
+    }
+
+    /// Close the pipe and return what was written to it, waiting for anything still holding a
+    /// copy to finish.
+    fn finish(self) -> Vec<u8> {
+        PIPES.lock().unwrap().retain(|v| v.0 != self.id);
+        self.reader.join().unwrap_or_default()
+    }
+}
+
+/// The write end of the pipe to the next statement, opened the first time it's needed.
+fn next_pipe_writer(
+    next: &mut Option<(std::io::PipeReader, std::io::PipeWriter)>,
+) -> std::io::Result<std::io::PipeWriter> {
+    if next.is_none() {
+        *next = Some(std::io::pipe()?);
+    }
+    next.as_ref().unwrap().1.try_clone()
+}
+
+/// Programs started with their output going on to the next statement through a pipe, so they run
+/// alongside it when it reads it with `0@`.
+struct Pipeline {
+    /// The programs, first first. With job control, the first leads their process group.
+    children: Vec<std::process::Child>,
+    /// The read end of the pipe the last one writes to
+    reader: std::io::PipeReader,
+    /// The statement the last one is running
+    statement: String,
+}
+
+thread_local! {
+    /// The pipeline the last statement added to, until the next one reads from it or it's
+    /// finished. Like [ABANDONED], it belongs to the thread running the statements.
+    static PIPELINE: std::cell::RefCell<Option<Pipeline>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Wait for the programs of a pipeline, first first. Returns the status of the last one and what
+/// it used, or of one stopped with job control, which stops the rest too.
+fn wait_pipeline(
+    children: &mut [std::process::Child],
+    job_control: bool,
+) -> Option<(std::process::ExitStatus, Option<Usage>)> {
+    let mut last = None;
+    for child in children {
+        let Ok(waited) = wait_with_usage(child, job_control) else {
+            continue;
+        };
+        if is_stopped(waited.0) {
+            return Some(waited);
+        }
+        last = Some(waited);
+    }
+    last
+}
+
+/// Finish the pipeline the last statement added to when the next one doesn't read from it, keeping
+/// what it sent in [State::pipe] like a builtin's output. The status of its last program is the
+/// statement's.
+fn settle_pipeline(state: &mut State) {
+    let Some(Pipeline {
+        mut children,
+        mut reader,
+        statement,
+    }) = PIPELINE.take()
+    else {
+        return;
+    };
+    // apart from waiting, so the pipe doesn't fill up and a program stopped by Ctrl-Z doesn't
+    // hold up the shell
+    let output = std::thread::spawn(move || {
+        let mut out = Vec::new();
+        let _ = reader.read_to_end(&mut out);
+        out
+    });
+    if let Some(raw_term) = state.raw_term.clone() {
+        let writer = raw_term.write().unwrap();
+        let _ = writer.suspend_raw_mode();
+    }
+    FOREGROUND.store(children.last().unwrap().id() as i32, Ordering::SeqCst);
+    let waited = wait_pipeline(&mut children, state.raw_term.is_some());
+    FOREGROUND.store(0, Ordering::SeqCst);
+    if let Some((status, usage)) = waited {
+        // what a stopped program wrote so far doesn't go anywhere
+        if !is_stopped(status) {
+            state.pipe = Some(output.join().unwrap_or_default());
+        }
+        let status = finish_foreground(children[0].id(), status, &statement, state);
+        set_status(state, status);
+        if let Some(usage) = usage {
+            record_usage(&usage, state);
+        }
+    }
+    if let Some(raw_term) = state.raw_term.clone() {
+        let writer = raw_term.write().unwrap();
+        let _ = writer.activate_raw_mode();
+    }
+}
+
+/// A copy of the write end of a captured pipe.
+fn pipe_writer(id: usize) -> std::io::Result<std::io::PipeWriter> {
+    match PIPES.lock().unwrap().iter().find(|v| v.0 == id) {
+        Some((_, writer)) => writer.try_clone(),
+        None => Err(std::io::Error::new(
+            std::io::ErrorKind::BrokenPipe,
+            i18n::translate("the capture is over"),
+        )),
+    }
+}
+
+/// The streams a builtin uses, opened from the indirects of its statement.
+struct BuiltinStreams {
+    /// Standard input
+    stdin: Box<dyn Read>,
+    /// Standard output
+    stdout: Box<dyn Write>,
+    /// Standard error
+    stderr: Box<dyn Write>,
+    /// Whether stdout is the terminal
+    terminal: bool,
+    /// What's going to the next statement, if anything is
+    piped: Option<PipeWriter>,
+}
+
+/// Open a file to redirect output to, overwriting it if `overwrite` is given. NOCLOBBER set to
+/// true refuses to overwrite an existing file unless it's forced.
+fn open_output(
+    path: &std::path::Path,
+    overwrite: Option<bool>,
+    state: &State,
+) -> std::io::Result<std::fs::File> {
+    let path = state.working_dir.join(path);
+    if let Some(force) = overwrite {
+        if !force && path.is_file() && get_var(state, "NOCLOBBER").as_deref() == Some("true") {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                tr!("{} exists and NOCLOBBER is set", path.display()),
+            ));
+        }
+        // emptied first, and then added to like anything else, so 2@1 can go there too
+        std::fs::File::create(&path)?;
+    }
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+}
+
+/// Open the streams for a builtin. `piped_in` reads what the previous statement sent to this one.
+fn builtin_streams(
+    indirects: &[IndirectRes],
+    mut piped_in: Option<Box<dyn Read>>,
+    state: &State,
+) -> std::io::Result<BuiltinStreams> {
+    let piped = PipeWriter::default();
+    let output =
+        |indirect: &Indirect, default: Box<dyn Write>| -> std::io::Result<Box<dyn Write>> {
+            Ok(match indirect {
+                Indirect::Path(p) => Box::new(open_output(p, None, state)?),
+                Indirect::Overwrite(p, force) => Box::new(open_output(p, Some(*force), state)?),
+                Indirect::Fd(fd) => match fd_file(*fd) {
+                    Some(file) => Box::new(file),
+                    None => default,
+                },
+                Indirect::Stdout => Box::new(std::io::stdout()),
+                Indirect::Stderr => Box::new(std::io::stderr()),
+                Indirect::NextStatement => Box::new(piped.clone()),
+                Indirect::Pipe(id) => Box::new(pipe_writer(*id)?),
+                _ => default,
+            })
+        };
+    let mut streams = BuiltinStreams {
+        stdin: Box::new(std::io::stdin()),
+        stdout: Box::new(std::io::stdout()),
+        stderr: Box::new(std::io::stderr()),
+        terminal: std::io::stdout().is_terminal(),
+        piped: None,
+    };
+    for indirect in indirects {
+        match indirect {
+            IndirectRes::Stdout(i) => {
+                streams.terminal &= matches!(i, Indirect::Default | Indirect::Stdout);
+                streams.stdout = output(i, streams.stdout)?;
+            }
+            IndirectRes::Stderr(i) => streams.stderr = output(i, streams.stderr)?,
+            IndirectRes::Stdin(Indirect::PrevStatement) => {
+                streams.stdin = piped_in
+                    .take()
+                    .unwrap_or_else(|| Box::new(std::io::empty()));
+            }
+            IndirectRes::Stdin(Indirect::Path(p)) => {
+                streams.stdin = Box::new(std::fs::File::open(state.working_dir.join(p))?);
+            }
+            IndirectRes::Stdin(Indirect::Fd(fd)) => {
+                if let Some(file) = fd_file(*fd) {
+                    streams.stdin = Box::new(file);
+                }
+            }
+            IndirectRes::Stdin(_) => (),
+        }
+    }
+    if indirects.iter().any(|v| {
+        matches!(
+            v,
+            IndirectRes::Stdout(Indirect::NextStatement)
+                | IndirectRes::Stderr(Indirect::NextStatement)
+        )
+    }) {
+        streams.piped = Some(piped);
+    }
+    Ok(streams)
+}
+
+/// Give a program the exported shell variables in its environment. The shell's own environment is
+/// left as it started, as other threads may be reading it.
+fn export_env(command: &mut std::process::Command, state: &State) {
+    let names = state
+        .shell_env
+        .iter()
+        .filter(|v| is_exported(state, &v.name))
+        .map(|v| v.name.clone())
+        .collect::<Vec<String>>();
+    // unset ones go even if the shell was started with them
+    for name in state.unset_vars.iter().filter(|v| !names.contains(v)) {
+        command.env_remove(name);
+    }
+    for name in &names {
+        command.env(
+            name,
+            os_str::to_os(&get_var(state, name).unwrap_or_default()),
+        );
+    }
+    // the rest describe this shell, not the child
+    for name in ["SHLVL", "_", "PWD", "OLDPWD"] {
+        if let Some(value) = state.specials.get(name) {
+            command.env(name, os_str::to_os(value));
+        }
+    }
+}
+
+/// The environment the shell started with. Shell variables with the same names are exported
+/// without having to be, like $PATH.
+static INHERITED: LazyLock<Vec<(String, OsString)>> = LazyLock::new(|| {
+    std::env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value)))
+        .collect()
+});
+
+/// Whether a variable still has the value it had in the environment the shell started with.
+fn is_inherited(var: &ShellVar) -> bool {
+    INHERITED
+        .iter()
+        .any(|(name, value)| *name == var.name && os_str::to_string(value) == var.value)
+}
+
+/// Whether a shell variable is given to the programs the shell runs.
+fn is_exported(state: &State, name: &str) -> bool {
+    state.shell_env.iter().any(|v| v.name == name && v.exported)
+        || INHERITED.iter().any(|v| v.0 == name)
+}
+
+/// The keys the line editor handles and what they do.
+const KEYBINDINGS: [(&str, &str); 22] = [
+    ("Enter", "Run the line"),
+    ("\\ Enter", "Continue the line on the next one"),
+    ("Backspace", "Delete the character before the cursor"),
+    ("Up", "Go back in the history"),
+    ("Down", "Go forward in the history"),
+    ("Left", "Move the cursor left"),
+    ("Right", "Move the cursor right"),
+    ("Home", "Move the cursor to the start of the line"),
+    ("End", "Move the cursor to the end of the line"),
+    ("Delete", "Delete the character under the cursor"),
+    ("Alt+B", "Move the cursor back a word"),
+    ("Alt+F", "Move the cursor forward a word"),
+    ("Ctrl+A", "Move the cursor to the start of the line"),
+    ("Ctrl+E", "Move the cursor to the end of the line"),
+    ("Ctrl+W", "Delete the word before the cursor"),
+    ("Ctrl+U", "Delete everything before the cursor"),
+    ("Ctrl+K", "Delete everything after the cursor"),
+    ("Ctrl+L", "Clear the screen"),
+    ("Ctrl+C", "Clear the line"),
+    (
+        "Ctrl+D",
+        "Delete the character under the cursor, or exit on an empty line",
+    ),
+    ("Ctrl+R", "Search the history"),
+    ("Ctrl+T", "Pick a file"),
+];
+
+/// What the values of secret variables are shown as.
+const SECRET_MASK: &str = "********";
+
+/// Mask the values of secret variables in some text.
+fn mask_secrets(text: &str, state: &State) -> String {
+    let mut out = text.to_string();
+    for var in &state.shell_env {
+        if !var.value.is_empty() && state.secrets.contains(&var.name) {
+            out = out.replace(&var.value, SECRET_MASK);
+        }
+    }
+    out
+}
+
+/// Whether a command line sets a secret variable, so it must stay out of the history.
+fn defines_secret(input: &str) -> bool {
+    parser::statements(&remove_comments(input))
+        .iter()
+        .any(|statement| {
+            let words = parser::command(statement)
+                .map(|v| v.words)
+                .unwrap_or_default();
+            words.len() > 1 && words[0].text == "set" && words[1].text == "--secret"
+        })
+}
+
+/// How deeply builtins can nest if MAXDEPTH isn't set.
+const DEFAULT_MAX_DEPTH: usize = 100;
+
+/// Note that a statement is being run by a builtin, failing with the chain of statements that led
+/// to it if that nests them too deeply.
+fn enter_builtin(statement: &str, state: &mut State) -> Result<(), String> {
+    let max_depth = state
+        .shell_env
+        .iter()
+        .find(|var| var.name == "MAXDEPTH")
+        .and_then(|var| var.value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_DEPTH);
+    if state.eval_stack.len() < max_depth {
+        state.eval_stack.push(statement.to_string());
+        return Ok(());
+    }
+    let mut out = tr!("sesh: maximum nesting depth of {} reached", max_depth);
+    // sourcing a file from itself makes long runs of the same statement
+    let mut i = 0;
+    while i < state.eval_stack.len() {
+        let statement = &state.eval_stack[i];
+        let count = state.eval_stack[i..]
+            .iter()
+            .take_while(|v| *v == statement)
+            .count();
+        out += "\n";
+        if count == 1 {
+            out += &tr!("sesh:   in {}", statement);
+        } else {
+            out += &tr!("sesh:   in {} ({} times)", statement, count);
+        }
+        i += count;
+    }
+    Err(out)
+}
+
+/// Split the variable assignments off the front of a statement, e.g. `LOG_LEVEL=debug` in
+/// `LOG_LEVEL=debug ./run`. A statement made only of assignments is left alone.
+fn split_assignments(words: &mut Vec<String>) -> Vec<(String, String)> {
+    let count = words
+        .iter()
+        .take_while(|word| {
+            word.split_once('=').is_some_and(|(name, _)| {
+                !name.is_empty()
+                    && !name.starts_with(|v: char| v.is_ascii_digit())
+                    && name.chars().all(|v| v.is_ascii_alphanumeric() || v == '_')
+            })
+        })
+        .count();
+    if count == words.len() {
+        return Vec::new();
+    }
+    words
+        .drain(..count)
+        .map(|word| {
+            let (name, value) = word.split_once('=').unwrap();
+            (name.to_string(), value.to_string())
+        })
+        .collect()
+}
+
+/// Give variables new values, returning the ones they had before.
+fn swap_vars(
+    vars: Vec<(String, Option<String>)>,
+    state: &mut State,
+) -> Vec<(String, Option<String>)> {
+    let mut old = Vec::new();
+    for (name, value) in vars {
+        let index = state.shell_env.iter().rposition(|v| v.name == name);
+        old.push((
+            name.clone(),
+            index.map(|i| state.shell_env[i].value.clone()),
+        ));
+        let exported = is_exported(state, &name);
+        state.shell_env.retain(|v| v.name != name);
+        if let Some(value) = value {
+            state.shell_env.push(ShellVar {
+                name,
+                value,
+                exported,
+            });
+        }
+    }
+    old
+}
+
+/// Get a variable, special or not.
+fn get_var(state: &State, name: &str) -> Option<String> {
+    state.specials.get(name).cloned().or_else(|| {
+        state
+            .shell_env
+            .iter()
+            .rev()
+            .find(|v| v.name == name)
+            .map(|v| v.value.clone())
+    })
+}
+
+/// Get the STATUS variable.
+fn get_status(state: &State) -> i32 {
+    state
+        .specials
+        .get("STATUS")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Set the STATUS variable and `$?`, and `$STATUS_SIGNAL` to the name of the signal that killed or
+/// stopped the program that gave it, if one did.
+fn set_status(state: &mut State, status: i32) {
+    state.specials.insert("STATUS", status.to_string());
+    state.specials.insert("?", status.to_string());
+    let signal = SIGNALLED.take().filter(|v| status == 128 + v);
+    let name = signal.and_then(explain::signal_name).unwrap_or_default();
+    state.specials.insert("STATUS_SIGNAL", name.to_string());
+}
+
+/// Set the special variables that don't change while the shell runs: `$$`, `$PPID` and `$SHLVL`.
+fn init_special_vars(state: &mut State) {
+    state.specials.insert("$", std::process::id().to_string());
+    #[cfg(unix)]
+    state
+        .specials
+        .insert("PPID", std::os::unix::process::parent_id().to_string());
+    let level = std::env::var("SHLVL")
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .unwrap_or(0);
+    state.specials.insert("SHLVL", (level + 1).to_string());
+    // keep the symlinks in the path the shell was started in, if PWD is right about it
+    if let Some(pwd) = std::env::var_os("PWD").map(PathBuf::from)
+        && pwd.is_absolute()
+        && pwd.canonicalize().ok() == state.working_dir.canonicalize().ok()
+    {
+        state.working_dir = pwd;
+    }
+    state
+        .specials
+        .insert("PWD", state.working_dir.to_string_lossy().to_string());
+}
+
+/// Remove the `.` and `..` components of a path without looking at the filesystem, so symlinks
+/// in it stay as they are.
+fn normalize_path(path: &std::path::Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => (),
+            std::path::Component::ParentDir => {
+                out.pop();
+            }
+            component => out.push(component),
+        }
+    }
+    out
+}
+
+/// Change the working directory, relative to the current one, and update PWD and OLDPWD. With
+/// `physical`, symlinks are resolved; otherwise `..` goes back to where the path came from.
+fn change_dir(dir: &std::path::Path, physical: bool, state: &mut State) -> std::io::Result<()> {
+    let dir = state.working_dir.join(dir);
+    let dir = if physical {
+        dir.canonicalize()?
+    } else {
+        normalize_path(&dir)
+    };
+    if !dir.is_dir() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotADirectory,
+            i18n::translate("not a directory"),
+        ));
+    }
+    let old = std::mem::replace(&mut state.working_dir, dir);
+    state
+        .specials
+        .insert("OLDPWD", old.to_string_lossy().to_string());
+    state
+        .specials
+        .insert("PWD", state.working_dir.to_string_lossy().to_string());
+    Ok(())
+}
+
+/// Evaluate statements, collecting what they write to stdout and stderr instead of showing it.
+fn capture_output(statements: &str, state: &mut State) -> (String, String) {
+    let out = match Capture::open() {
+        Ok(out) => out,
+        Err(e) => {
+            println!("{}\r", tr!("sesh: capturing output failed: {}", e));
+            set_status(state, 1);
+            return Default::default();
+        }
+    };
+    let err = match Capture::open() {
+        Ok(err) => err,
+        Err(e) => {
+            out.finish();
+            println!("{}\r", tr!("sesh: capturing output failed: {}", e));
+            set_status(state, 1);
+            return Default::default();
+        }
+    };
+    let outer = state.group_indirects.clone();
+    state
+        .group_indirects
+        .retain(|v| matches!(v, IndirectRes::Stdin(_)));
+    state
+        .group_indirects
+        .push(IndirectRes::Stdout(Indirect::Pipe(out.id)));
+    state
+        .group_indirects
+        .push(IndirectRes::Stderr(Indirect::Pipe(err.id)));
+    // a pipeline the statement it's in reads from is left for it
+    let pipeline = PIPELINE.take();
+    let pipe = state.pipe.take();
+    eval(statements, state);
+    PIPELINE.set(pipeline);
+    state.pipe = pipe;
+    state.group_indirects = outer;
+    let read = |capture: Capture| String::from_utf8_lossy(&capture.finish()).to_string();
+    (read(out), read(err))
+}
+
+/// Quote a word so none of it is taken as syntax, like quotes, wildcards, indirects or
+/// assignments.
+fn quote_word(word: &str) -> String {
+    if !word.is_empty() && !word.contains(|v: char| "'\"`\\()[]{}@*?!=".contains(v)) {
+        return word.to_string();
+    }
+    // single quotes can't hold a single quote, so those go in double ones
+    let mut out = String::new();
+    let mut rest = word;
+    while !rest.is_empty() {
+        let quote = if rest.starts_with('\'') { '"' } else { '\'' };
+        let end = rest
+            .find(|v: char| (v == '\'') != (quote == '"'))
+            .unwrap_or(rest.len());
+        out += &format!("{0}{1}{0}", quote, &rest[..end]);
+        rest = &rest[end..];
+    }
+    if out.is_empty() {
+        String::from("''")
+    } else {
+        out
+    }
+}
+
+/// Expand a statement when it's about to run. Each `$(statements)` is replaced with what the
+/// statements output, without the newlines at the end, as words of their own, or part of the
+/// word in double quotes. `${NAME}` and its forms are expanded like [expand_braced], and `$NAME`
+/// is substituted in the rest, but not in what they give. What's in groups and single quotes is
+/// left as it is, for when it runs.
+fn expand_statement(statement: &str, state: &mut State) -> Result<String, String> {
+    expand(statement, false, state)
+}
+
+/// Expand the text of a here-document or here-string when its statement runs. It's like a
+/// statement, except everything in it is expanded and what's substituted is kept as it is, since
+/// it isn't split into words.
+fn expand_document(text: &str, state: &mut State) -> Result<String, String> {
+    expand(text, true, state)
+}
+
+/// Expand a statement, or the text of a here-document if `document` is set.
+fn expand(statement: &str, document: bool, state: &mut State) -> Result<String, String> {
+    let vars = |text: &str, state: &State| {
+        if text.contains(['$', '!']) {
+            expand_vars(text, state)
+        } else {
+            text.to_string()
+        }
+    };
+    let chars = statement.chars().collect::<Vec<char>>();
+    let mut out = String::new();
+    // what's been gone through since the last substitution
+    let mut literal = String::new();
+    let mut nesting = Nesting::default();
+    let mut i = 0;
+    while i < chars.len() {
+        let expands =
+            !nesting.escape && (document || nesting.closers.iter().all(|v| ['"', ']'].contains(v)));
+        if expands && chars[i] == '$' && matches!(chars.get(i + 1), Some('(' | '{')) {
+            let mut inner = Nesting::default();
+            if let Some(len) = chars[i + 1..]
+                .iter()
+                .position(|ch| inner.push(*ch) == Step::Closed && inner.is_empty())
+            {
+                out += &vars(&std::mem::take(&mut literal), state);
+                let body = chars[i + 2..i + 1 + len].iter().collect::<String>();
+                if chars[i + 1] == '{' {
+                    let expand = if document {
+                        expand_document
+                    } else {
+                        expand_statement
+                    };
+                    out += &braced_value(&body, state, expand)?;
+                    i += len + 2;
+                    continue;
+                }
+                let output = capture_output(&body, state).0;
+                let output = output.trim_end_matches('\n');
+                out += &match nesting.closers.last() {
+                    _ if document => output.to_string(),
+                    // only a double quote would end the word
+                    Some('"') if output.contains('"') => format!("\"{}\"", quote_word(output)),
+                    Some('"') => output.to_string(),
+                    _ => output
+                        .split_whitespace()
+                        .map(quote_word)
+                        .collect::<Vec<String>>()
+                        .join(" "),
+                };
+                i += len + 2;
+                // output that's all whitespace leaves no word behind, not even an empty one
+                if !document && nesting.closers.is_empty() && (out.is_empty() || out.ends_with(' '))
+                {
+                    while chars.get(i) == Some(&' ') {
+                        i += 1;
+                    }
+                    if i == chars.len() {
+                        out.truncate(out.trim_end_matches(' ').len());
+                    }
+                }
+                continue;
+            }
+        }
+        if expands {
+            literal.push(chars[i]);
+        } else {
+            out += &vars(&std::mem::take(&mut literal), state);
+            out.push(chars[i]);
+        }
+        nesting.push(chars[i]);
+        i += 1;
+    }
+    Ok(out + &vars(&literal, state))
+}
+
+/// Evaluate a statement. May include multiple.
+fn eval(statement: &str, state: &mut State) {
+    eval_chains(statement, state);
+    settle_pipeline(state);
+}
+
+/// Whether a statement reads what the one before it sent with `0@`, itself or through the group
+/// it's in.
+fn reads_previous(statement: &str, state: &State) -> bool {
+    let prev = IndirectRes::Stdin(Indirect::PrevStatement);
+    parser::command(statement).is_ok_and(|v| v.indirects.contains(&prev))
+        || state.group_indirects.contains(&prev)
+}
+
+#[allow(clippy::arc_with_non_send_sync)]
+/// Evaluate the chains of statements in a line, leaving a pipeline the last one added to running.
+fn eval_chains(statement: &str, state: &mut State) {
+    let statement = remove_comments(statement);
+    // nothing runs if any of it doesn't parse
+    let chains = match parser::parse(&statement) {
+        Ok(chains) => chains,
+        Err(e) => {
+            println!("{}\r", tr!("sesh: {}", e));
+            set_status(state, 2);
+            return;
+        }
+    };
+
+    for chain in chains {
+        // a chain run in the background is left whole for the job to run
+        if chain.background {
+            settle_pipeline(state);
+            let statement = chain.text();
+            let start = Instant::now();
+            match job::start(&statement, state) {
+                Ok(id) => {
+                    let job = state.jobs.iter().find(|v| v.id == id).unwrap();
+                    println!("[{}] {}\r", id, job.pid);
+                    set_status(state, 0);
+                }
+                Err(e) => {
+                    println!("{}\r", tr!("sesh: starting job failed: {}", e));
+                    set_status(state, 1);
+                }
+            }
+            let duration = start.elapsed();
+            if let Some(times) = &mut state.statement_times {
+                times.push((statement.clone(), duration));
+            }
+            audit::record(&statement, duration, state);
+            continue;
+        }
+        for parser::Statement {
+            link,
+            text: statement,
+            input,
+            literal_input,
+            ..
+        } in chain.statements
+        {
+            // the status of a pipeline is its last program's
+            if link != parser::Link::First {
+                settle_pipeline(state);
+            }
+            let skip = match link {
+                parser::Link::First => false,
+                parser::Link::And => get_status(state) != 0,
+                parser::Link::Or => get_status(state) == 0,
+            };
+            if skip {
+                continue;
+            }
+            check_terminate(state);
+            if abandoned() {
+                return;
+            }
+            // before expanding it, so it sees the status
+            if input.is_some() || !reads_previous(&statement, state) {
+                settle_pipeline(state);
+            }
+            let expanded = expand_statement(&statement, state).and_then(|statement| match input {
+                Some(input) if !literal_input => {
+                    Ok((statement, Some(expand_document(&input, state)?)))
+                }
+                input => Ok((statement, input)),
+            });
+            let (statement, input) = match expanded {
+                Ok(expanded) => expanded,
+                Err(e) => {
+                    println!("{}\r", tr!("sesh: {}", e));
+                    set_status(state, 1);
+                    return;
+                }
+            };
+            // here-documents and here-strings come in like the output of a statement before it
+            let statement = match input {
+                Some(input) => {
+                    state.pipe = Some(input.into_bytes());
+                    statement + " 0@"
+                }
+                None => statement,
+            };
+            let start = Instant::now();
+            let keep_going = eval_statement(&statement, state);
+            let duration = start.elapsed();
+            if let Some(last) = parser::command(&statement)
+                .ok()
+                .and_then(|mut v| v.words.pop())
+            {
+                state.specials.insert("_", last.text);
+            }
+            if let Some(times) = &mut state.statement_times {
+                times.push((statement.clone(), duration));
+            }
+            audit::record(&statement, duration, state);
+            // builtins running it, like if, don't get to hide it
+            if abandoned() {
+                set_status(state, 130);
+                return;
+            }
+            if !keep_going || state.loop_control.is_some() || state.exit_request.is_some() {
+                return;
+            }
+        }
+    }
+}
+
+#[allow(clippy::arc_with_non_send_sync)]
+/// Evaluate a single statement. Returns whether the statements after it should be evaluated.
+fn eval_statement(statement: &str, state: &mut State) -> bool {
+    // `! statement` inverts its status
+    if let Some(rest) = statement
+        .strip_prefix('!')
+        .filter(|v| v.starts_with(char::is_whitespace))
+    {
+        let keep_going = eval_statement(rest.trim_start(), state);
+        set_status(state, (get_status(state) == 0) as i32);
+        return keep_going;
+    }
+    if let Some((statements, rest)) = split_group(statement) {
+        return eval_group(statements, rest, state);
+    }
+    // what the previous statement sent with `1@` is only there for this one
+    let piped_in = state.pipe.take();
+    let parser::Command {
+        words,
+        mut indirects,
+    } = match parser::command(statement) {
+        Ok(command) => command,
+        Err(e) => {
+            println!("{}\r", tr!("sesh: {}", e));
+            return false;
+        }
+    };
+    if words.is_empty() && !indirects.is_empty() {
+        println!("{}\r", tr!("sesh: program name is indirect"));
+        return false;
+    }
+    if words.first().is_none_or(|v| v.text.is_empty()) {
+        return true;
+    }
+
+    for indirect in &state.group_indirects {
+        if !indirects
+            .iter()
+            .any(|v| std::mem::discriminant(v) == std::mem::discriminant(indirect))
+        {
+            indirects.push(indirect.clone());
+        }
+    }
+    indirects.sort_by(|v1, v2| {
+        if matches!(v1, IndirectRes::Stderr(_)) && matches!(v2, IndirectRes::Stderr(_)) {
+            return std::cmp::Ordering::Equal;
+        }
+        if matches!(v1, IndirectRes::Stdout(_)) && matches!(v2, IndirectRes::Stdout(_)) {
+            return std::cmp::Ordering::Equal;
+        }
+        if matches!(v1, IndirectRes::Stdin(_)) && matches!(v2, IndirectRes::Stdin(_)) {
+            return std::cmp::Ordering::Equal;
+        }
+        v1.cmp(v2)
+    });
+    indirects.dedup();
+    // `2@1` goes wherever stdout does, and `1@2` wherever stderr does, adding to a file the other
+    // one overwrites
+    let duplicate = |to: &Indirect| match to {
+        Indirect::Overwrite(path, _) => Indirect::Path(path.clone()),
+        to => to.clone(),
+    };
+    let stdout = indirects.iter().find_map(|v| match v {
+        IndirectRes::Stdout(to) if !matches!(to, Indirect::Default | Indirect::Stderr) => {
+            Some(duplicate(to))
+        }
+        _ => None,
+    });
+    let stderr = indirects.iter().find_map(|v| match v {
+        IndirectRes::Stderr(to) if !matches!(to, Indirect::Default | Indirect::Stdout) => {
+            Some(duplicate(to))
+        }
+        _ => None,
+    });
+    for indirect in &mut indirects {
+        match indirect {
+            IndirectRes::Stderr(to) if *to == Indirect::Stdout && stdout.is_some() => {
+                *to = stdout.clone().unwrap()
+            }
+            IndirectRes::Stdout(to) if *to == Indirect::Stderr && stderr.is_some() => {
+                *to = stderr.clone().unwrap()
+            }
+            _ => (),
+        }
+    }
+
+    let mut statement_split = words
+        .iter()
+        .map(|v| v.text.clone())
+        .collect::<Vec<String>>();
+    let assignments = split_assignments(&mut statement_split);
+    let words = &words[assignments.len()..];
+    let quoted = words.iter().map(|v| v.quoted).collect::<Vec<bool>>();
+    // what builtins get as the unsplit statement, from the program to its last word, so without
+    // the assignments or the indirects at the end
+    let mut unsplit = statement[words[0].span.start..words[words.len() - 1].span.end].to_string();
+    // unquoted wildcards expand to the paths they match, for builtins too
+    let mut searched = 0;
+    let mut words = vec![statement_split[0].clone()];
+    for (word, quoted) in statement_split[1..].iter().zip(&quoted[1..]) {
+        let at = unsplit[searched..]
+            .find(word.as_str())
+            .map(|v| v + searched);
+        let paths = glob::expand(word, &state.working_dir)
+            .filter(|_| !quoted && !word.contains(char::is_whitespace));
+        match (paths, at) {
+            (Some(paths), at) => {
+                let joined = paths.join(" ");
+                if let Some(at) = at {
+                    unsplit.replace_range(at..at + word.len(), &joined);
+                    searched = at + joined.len();
+                }
+                words.extend(paths);
+            }
+            (None, at) => {
+                searched = at.map_or(searched, |v| v + word.len());
+                words.push(word.clone());
+            }
+        }
+    }
+    statement_split = words;
+    let mut program_name = statement_split[0].clone();
+    // `(statements)` runs them in a subshell
+    if unsplit.starts_with('(') && program_name != "()" {
+        program_name = "subshell".to_string();
+        statement_split.insert(0, program_name.clone());
+    }
+
+    for alias in &state.aliases {
+        if program_name == alias.name {
+            let to_split = parser::command(&alias.to)
+                .map(|v| v.words)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|v| v.text)
+                .collect::<Vec<String>>();
+
+            for (i, item) in to_split[1..].iter().enumerate() {
+                statement_split.insert(i + 1, (*item).clone());
+            }
+            program_name = to_split[0].clone();
+            continue;
+        }
+    }
+
+    if state.restricted && program_name.contains(['/', '\\']) {
+        println!(
+            "{}",
+            tr!(
+                "sesh: {}: restricted: cannot run programs by path",
+                program_name
+            )
+        );
+        set_status(state, 1);
+        return true;
+    }
+    if state.restricted
+        && let Some((name, _)) = assignments
+            .iter()
+            .find(|(name, _)| builtins::RESTRICTED_VARS.contains(&name.as_str()))
+    {
+        println!(
+            "{}",
+            tr!("sesh: {}: restricted: cannot change {}", program_name, name)
+        );
+        set_status(state, 1);
+        return true;
+    }
+    if state.restricted
+        && indirects.iter().any(|v| {
+            matches!(
+                v,
+                IndirectRes::Stdout(Indirect::Path(_) | Indirect::Overwrite(..) | Indirect::Fd(_))
+                    | IndirectRes::Stderr(
+                        Indirect::Path(_) | Indirect::Overwrite(..) | Indirect::Fd(_)
+                    )
+            )
+        })
+    {
+        println!(
+            "{}",
+            tr!("sesh: {}: restricted: cannot redirect output", program_name)
+        );
+        set_status(state, 1);
+        return true;
+    }
+    if !guard::check(
+        &std::iter::once(program_name.clone())
+            .chain(statement_split[1..].iter().cloned())
+            .collect::<Vec<String>>(),
+        state,
+    ) {
+        set_status(state, 1);
+        return true;
+    }
+
+    let builtin = builtins::BUILTINS.iter().find(|v| v.0 == program_name);
+    let plugin_builtin = plugins::find(&state.plugins, &program_name);
+    if builtin.is_some() || plugin_builtin.is_some() {
+        if let Some(raw_term) = state.raw_term.clone() {
+            let writer = raw_term.write().unwrap();
+            let _ = writer.suspend_raw_mode();
+        }
+        if let Err(chain) = enter_builtin(statement, state) {
+            println!("{}", chain);
+            if let Some(raw_term) = state.raw_term.clone() {
+                let writer = raw_term.write().unwrap();
+                let _ = writer.activate_raw_mode();
+            }
+            set_status(state, 1);
+            return false;
+        }
+        let old_vars = swap_vars(
+            assignments
+                .into_iter()
+                .map(|(name, value)| (name, Some(value)))
+                .collect(),
+            state,
+        );
+        let reads_pipeline = indirects.contains(&IndirectRes::Stdin(Indirect::PrevStatement));
+        let pipeline = if reads_pipeline {
+            PIPELINE.take()
+        } else {
+            None
+        };
+        let (mut upstream, piped_in) = match pipeline {
+            Some(pipeline) => (
+                pipeline.children,
+                Some(Box::new(pipeline.reader) as Box<dyn Read>),
+            ),
+            None => (
+                vec![],
+                piped_in.map(|v| Box::new(std::io::Cursor::new(v)) as Box<dyn Read>),
+            ),
+        };
+        let status = if let Some(builtin) = builtin {
+            match builtin_streams(&indirects, piped_in, state) {
+                Ok(mut streams) => {
+                    let mut io = builtins::Io {
+                        stdin: &mut *streams.stdin,
+                        stdout: &mut *streams.stdout,
+                        stderr: &mut *streams.stderr,
+                        terminal: streams.terminal,
+                    };
+                    let status = builtin.1(statement_split, unsplit, &mut io, state)
+                        .unwrap_or_else(|e| {
+                            builtins::report(&program_name, &e, &mut io);
+                            e.status()
+                        });
+                    let _ = io.stdout.flush();
+                    if let Some(piped) = streams.piped {
+                        state.pipe = Some(std::mem::take(&mut *piped.0.lock().unwrap()));
+                    }
+                    status
+                }
+                Err(e) => {
+                    println!("{}", tr!("sesh: {}: {}", program_name, e));
+                    1
+                }
+            }
+        } else if let Some((plugin, i)) = plugin_builtin {
+            if indirects.len() > 1 {
+                println!("{}", tr!("sesh: warning: indirects ignored for builtin"));
+            }
+            plugin.call(i, statement_split, state)
+        } else {
+            unreachable!()
+        };
+        // with what it didn't read gone, the programs before it can finish
+        wait_pipeline(&mut upstream, state.raw_term.is_some());
+        state.eval_stack.pop();
+        swap_vars(old_vars, state);
+        garbage_collect_vars(state);
+        if let Some(raw_term) = state.raw_term.clone() {
+            let writer = raw_term.write().unwrap();
+            let _ = writer.activate_raw_mode();
+        }
+        set_status(state, status);
+        return true;
+    }
+    if let Some(raw_term) = state.raw_term.clone() {
+        let writer = raw_term.write().unwrap();
+        let _ = writer.suspend_raw_mode();
+    }
+    let mut command = std::process::Command::new(os_str::to_os(&program_name));
+    export_env(&mut command, state);
+    command
+        .args(statement_split[1..].iter().map(|v| os_str::to_os(v)))
+        .current_dir(state.working_dir.clone())
+        .envs(
+            assignments
+                .iter()
+                .map(|(name, value)| (name, os_str::to_os(value))),
+        );
+    // the programs already sending output to this one, which runs alongside them
+    let upstream = if indirects.contains(&IndirectRes::Stdin(Indirect::PrevStatement)) {
+        PIPELINE.take()
+    } else {
+        None
+    };
+    let mut next = None;
+    let set_up = |command: &mut std::process::Command| -> std::io::Result<()> {
+        for indirect in indirects {
+            match indirect {
+                IndirectRes::Stderr(i) => match i {
+                    Indirect::Default => (),
+                    Indirect::Fd(fd) => {
+                        if let Some(stdio) = fd_stdio(fd) {
+                            command.stderr(stdio);
+                        }
+                    }
+                    Indirect::NextStatement => {
+                        command.stderr(next_pipe_writer(&mut next)?);
+                    }
+                    Indirect::Pipe(id) => {
+                        command.stderr(pipe_writer(id)?);
+                    }
+                    Indirect::Path(p) => {
+                        command.stderr(open_output(&p, None, state)?);
+                    }
+                    Indirect::Overwrite(p, force) => {
+                        command.stderr(open_output(&p, Some(force), state)?);
+                    }
+                    Indirect::PrevStatement => (),
+                    Indirect::Stderr => (),
+                    Indirect::Stdout => {
+                        command.stderr(std::io::stdout());
+                    }
+                },
+                IndirectRes::Stdout(i) => match i {
+                    Indirect::Default => (),
+                    Indirect::Fd(fd) => {
+                        if let Some(stdio) = fd_stdio(fd) {
+                            command.stdout(stdio);
+                        }
+                    }
+                    Indirect::NextStatement => {
+                        command.stdout(next_pipe_writer(&mut next)?);
+                    }
+                    Indirect::Pipe(id) => {
+                        command.stdout(pipe_writer(id)?);
+                    }
+                    Indirect::Path(p) => {
+                        command.stdout(open_output(&p, None, state)?);
+                    }
+                    Indirect::Overwrite(p, force) => {
+                        command.stdout(open_output(&p, Some(force), state)?);
+                    }
+                    Indirect::PrevStatement => (),
+                    Indirect::Stderr => {
+                        command.stdout(std::io::stderr());
+                    }
+                    Indirect::Stdout => (),
+                },
+                IndirectRes::Stdin(i) => match i {
+                    Indirect::Default => (),
+                    Indirect::Fd(fd) => {
+                        if let Some(stdio) = fd_stdio(fd) {
+                            command.stdin(stdio);
+                        }
+                    }
+                    Indirect::NextStatement | Indirect::Pipe(_) => (),
+                    Indirect::Path(p) | Indirect::Overwrite(p, _) => {
+                        command.stdin(std::fs::File::open(state.working_dir.join(p))?);
+                    }
+                    Indirect::PrevStatement => match &upstream {
+                        Some(upstream) => {
+                            command.stdin(upstream.reader.try_clone()?);
+                        }
+                        None => {
+                            command.stdin(std::process::Stdio::piped());
+                        }
+                    },
+                    Indirect::Stderr => (),
+                    Indirect::Stdout => (),
+                },
+            }
+        }
+        Ok(())
+    };
+    if let Err(e) = set_up(&mut command) {
+        PIPELINE.set(upstream);
+        println!("{}", tr!("sesh: redirecting failed: {}", e));
+        set_status(state, 1);
+        if let Some(raw_term) = state.raw_term.clone() {
+            let writer = raw_term.write().unwrap();
+            let _ = writer.activate_raw_mode();
+        }
+        return true;
+    }
+
+    let job_control = state.raw_term.is_some();
+    // the programs of a pipeline share a process group, led by the first
+    let leader = upstream.as_ref().map(|v| v.children[0].id());
+    if job_control {
+        job::in_foreground(&mut command, leader.map(|v| v as i32));
+    }
+    let spawned = command.spawn();
+    // the shell's copies of the pipes' ends would keep the programs from seeing them close
+    drop(command);
+    let next = next.map(|(reader, _)| reader);
+    match spawned {
+        Ok(mut child) => {
+            if let Some(mut stdin) = child.stdin.take() {
+                let input = piped_in.unwrap_or_default();
+                // from another thread, so a program that doesn't read all of it can't hold up the shell
+                std::thread::spawn(move || stdin.write_all(&input));
+            }
+            let mut children = upstream.map(|v| v.children).unwrap_or_default();
+            // it's waited for along with the statement reading its output, or when that doesn't
+            if let Some(reader) = next {
+                children.push(child);
+                PIPELINE.set(Some(Pipeline {
+                    children,
+                    reader,
+                    statement: statement.to_string(),
+                }));
+                return true;
+            }
+            FOREGROUND.store(child.id() as i32, Ordering::SeqCst);
+            let waited = wait_with_usage(&mut child, job_control);
+            FOREGROUND.store(0, Ordering::SeqCst);
+            let (status, usage) = match waited {
+                Ok(waited) => waited,
+                Err(e) => {
+                    wait_pipeline(&mut children, job_control);
+                    println!("{}", tr!("sesh: waiting for program failed: {}", e));
+                    set_status(state, 1);
+                    if let Some(raw_term) = state.raw_term.clone() {
+                        let writer = raw_term.write().unwrap();
+                        let _ = writer.activate_raw_mode();
+                    }
+                    return true;
+                }
+            };
+            if !is_stopped(status) {
+                wait_pipeline(&mut children, job_control);
+            }
+            let leader = leader.unwrap_or(child.id());
+            let status = finish_foreground(leader, status, statement, state);
+            set_status(state, status);
+            if let Some(usage) = usage {
+                record_usage(&usage, state);
+            }
+            if let Some(raw_term) = state.raw_term.clone() {
+                let writer = raw_term.write().unwrap();
+                let _ = writer.activate_raw_mode();
+            }
+            true
+        }
+        Err(error) => {
+            PIPELINE.set(upstream);
+            println!("{}", tr!("sesh: error spawning program: {}", error));
+            set_status(state, 127);
+            if let Some(raw_term) = state.raw_term.clone() {
+                let writer = raw_term.write().unwrap();
+                let _ = writer.activate_raw_mode();
+            }
+            false
+        }
+    }
+}
+
+/// What a program used while it ran.
+struct Usage {
+    /// CPU time in user mode
+    user: Duration,
+    /// CPU time in the kernel
+    system: Duration,
+    /// The most memory it had resident at once, in KiB
+    max_rss: u64,
+}
+
+/// Wait for a program to exit, or stop too with job control, also getting what it used where
+/// that's supported.
+fn wait_with_usage(
+    child: &mut std::process::Child,
+    job_control: bool,
+) -> std::io::Result<(std::process::ExitStatus, Option<Usage>)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+
+        let mut status = 0;
+        let mut usage = unsafe { std::mem::zeroed::<libc::rusage>() };
+        let flags = if job_control { libc::WUNTRACED } else { 0 };
+        while unsafe { libc::wait4(child.id() as i32, &mut status, flags, &mut usage) } < 0 {
+            let e = std::io::Error::last_os_error();
+            if e.kind() != std::io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+        let time = |v: libc::timeval| {
+            Duration::from_secs(v.tv_sec as u64) + Duration::from_micros(v.tv_usec as u64)
+        };
+        // macOS gives it in bytes
+        let max_rss = usage.ru_maxrss as u64 / if cfg!(target_os = "macos") { 1024 } else { 1 };
+        let usage = Usage {
+            user: time(usage.ru_utime),
+            system: time(usage.ru_stime),
+            max_rss,
+        };
+        Ok((std::process::ExitStatus::from_raw(status), Some(usage)))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = job_control;
+        Ok((child.wait()?, None))
+    }
+}
+
+/// Put what a program used in $RUSAGE_USER, $RUSAGE_SYSTEM and $RUSAGE_MAXRSS if $RUSAGE is true,
+/// and print it as well if it's report.
+fn record_usage(usage: &Usage, state: &mut State) {
+    let option = get_var(state, "RUSAGE").unwrap_or_default();
+    if option != "true" && option != "report" {
+        return;
+    }
+    let user = format!("{:.3}", usage.user.as_secs_f64());
+    let system = format!("{:.3}", usage.system.as_secs_f64());
+    if option == "report" {
+        eprintln!(
+            "{}\r",
+            tr!(
+                "sesh: {}s user, {}s system, {} KiB max resident, status {}",
+                user,
+                system,
+                usage.max_rss,
+                get_status(state)
+            )
+        );
+    }
+    state.specials.insert("RUSAGE_USER", user);
+    state.specials.insert("RUSAGE_SYSTEM", system);
+    state
+        .specials
+        .insert("RUSAGE_MAXRSS", usage.max_rss.to_string());
+}
+
+/// Name of the user running the shell.
+fn username() -> String {
+    #[cfg(unix)]
+    return users::get_effective_username()
+        .unwrap_or(users::get_current_username().unwrap_or("?".into()))
+        .to_string_lossy()
+        .to_string();
+    #[cfg(not(unix))]
+    return std::env::var("USERNAME").unwrap_or("?".to_string());
+}
+
+/// Expand the escapes prompts support: `$u` user, `$h` host, `$p` working directory, `$P` its
+/// last component, `$d` how many directories pushd left on the stack and `$v` the version of sesh.
+fn expand_prompt(prompt: &str, state: &State) -> String {
+    let mut prompt = prompt.replace("$u", &username());
+    prompt = prompt.replace(
+        "$h",
+        &hostname::get().unwrap_or("?".into()).to_string_lossy(),
+    );
+
+    prompt = prompt.replace("$p", &state.working_dir.as_os_str().to_string_lossy());
+    prompt = prompt.replace(
+        "$P",
+        &state
+            .working_dir
+            .file_name()
+            .unwrap_or(OsStr::new("?"))
+            .to_string_lossy(),
+    );
+    prompt = prompt.replace("$d", &state.dir_stack.len().to_string());
+    prompt.replace("$v", env!("CARGO_PKG_VERSION"))
+}
+
+/// How a line being typed looks, shown by the colour of the prompt symbol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LineCheck {
+    /// Nothing has been typed
+    Empty,
+    /// It parses and every command is found
+    Fine,
+    /// A bracket or quote is still open
+    Unfinished,
+    /// It doesn't parse or a command isn't found
+    Broken,
+}
+
+/// Whether a command can be run, as an alias, builtin or program.
+fn resolves(name: &str, state: &State) -> bool {
+    if state.aliases.iter().any(|v| v.name == name)
+        || builtins::BUILTINS.iter().any(|v| v.0 == name)
+        || plugins::find(&state.plugins, name).is_some()
+    {
+        return true;
+    }
+    if name.contains(['/', '\\']) {
+        return state.working_dir.join(name).is_file();
+    }
+    let path = get_var(state, "PATH")
+        .map(OsString::from)
+        .or_else(|| std::env::var_os("PATH"))
+        .unwrap_or_default();
+    std::env::split_paths(&path).any(|v| v.join(name).is_file())
+}
+
+/// Check a line being typed without running anything.
+fn check_line(line: &str, state: &State) -> LineCheck {
+    if line.trim().is_empty() {
+        return LineCheck::Empty;
+    }
+    let line = remove_comments(line);
+    match parser::parse(&line) {
+        Err(e) if e.unfinished => return LineCheck::Unfinished,
+        Err(_) => return LineCheck::Broken,
+        Ok(_) => (),
+    }
+    // without setting anything or failing, as nothing's run yet
+    let line = expand_braced(&line, &mut state.clone()).unwrap_or(line);
+    for statement in parser::statements(&expand_vars(&line, state)) {
+        let statement = statement
+            .strip_prefix('!')
+            .filter(|v| v.starts_with(char::is_whitespace))
+            .unwrap_or(&statement)
+            .trim_start();
+        if let Some((statements, _)) = split_group(statement) {
+            if check_line(statements, state) == LineCheck::Broken {
+                return LineCheck::Broken;
+            }
+            continue;
+        }
+        if statement.starts_with('(') {
+            continue;
+        }
+        let Ok(command) = parser::command(statement) else {
+            return LineCheck::Broken;
+        };
+        let mut words = command
+            .words
+            .into_iter()
+            .map(|v| v.text)
+            .collect::<Vec<String>>();
+        split_assignments(&mut words);
+        if let Some(name) = words.first()
+            && !name.is_empty()
+            && !resolves(name, state)
+        {
+            return LineCheck::Broken;
+        }
+    }
+    LineCheck::Fine
+}
+
+/// Colour the last visible character of a prompt, which is usually its symbol.
+fn colour_prompt_symbol(prompt: &str, colour: &str) -> String {
+    let mut symbol = None;
+    let mut in_escape = false;
+    for (i, ch) in prompt.char_indices() {
+        if ch == '\x1b' {
+            in_escape = true;
+        } else if in_escape {
+            in_escape = ch == '[' || !('\x40'..='\x7e').contains(&ch);
+        } else if !ch.is_whitespace() {
+            symbol = Some((i, ch));
+        }
+    }
+    let Some((i, ch)) = symbol else {
+        return prompt.to_string();
+    };
+    format!(
+        "{}{}{}\x1b[39m{}",
+        &prompt[..i],
+        colour,
+        ch,
+        &prompt[i + ch.len_utf8()..]
+    )
+}
+
+/// Write the prompt to the screen. With PROMPT_CHECK, the prompt symbol shows how the input looks.
+fn write_prompt(state: State, input: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut prompt = expand_prompt(
+        &state
+            .shell_env
+            .iter()
+            .find(|var| var.name == "PROMPT1")
+            .unwrap_or(&ShellVar {
+                name: "PROMPT1".to_string(),
+                value: String::new(),
+                exported: false,
+            })
+            .value,
+        &state,
+    );
+    if get_var(&state, "PROMPT_CHECK").as_deref() == Some("true") {
+        let colour = match check_line(input, &state) {
+            LineCheck::Empty => "",
+            LineCheck::Fine => "\x1b[32m",
+            LineCheck::Unfinished => "\x1b[33m",
+            LineCheck::Broken => "\x1b[31m",
+        };
+        if !colour.is_empty() {
+            prompt = colour_prompt_symbol(&prompt, colour);
+        }
+    }
+    if state.precmd_timed_out {
+        prompt = format!("\x1b[33m[{}]\x1b[39m {}", tr!("precmd timed out"), prompt);
+    }
+    if state.in_mode {
+        let table = [
+            "\x1b[31;1m",
+            "\x1b[38;2;255;165;0;1m",
+            "\x1b[33;1m",
+            "\x1b[32;1m",
+            "\x1b[34;1m",
+            "\x1b[36;1m",
+            "\x1b[35;1m",
+        ];
+        let idx = state.entries % table.len();
+        prompt += table[idx];
+    }
+
+    print!("{}", color::fit(&prompt));
+    std::io::stdout().flush()?;
+    Ok(())
+}
+
+/// Print GREETING followed by a summary of anything unusual about how the shell started.
+fn write_greeting(state: &State) {
+    if let Some(greeting) = state.shell_env.iter().find(|var| var.name == "GREETING")
+        && !greeting.value.is_empty()
+    {
+        println!("{}", expand_prompt(&greeting.value, state));
+    }
+    let mut summary = Vec::new();
+    if !state.plugins.is_empty() {
+        summary.push(tr!("{} plugin(s) loaded", state.plugins.len()));
+    }
+    if state.restricted {
+        summary.push(tr!("restricted mode"));
+    }
+    if !summary.is_empty() {
+        println!("{}", tr!("sesh: {}", summary.join(", ")));
+    }
+}
+
+/// Whether Ctrl+C was pressed since [take_interrupt] was last called.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether Ctrl+C was pressed since this was last called, for builtins that loop until it is.
+fn take_interrupt() -> bool {
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
+
+/// The process ID of the program running in the foreground, or 0.
+static FOREGROUND: AtomicI32 = AtomicI32::new(0);
+
+thread_local! {
+    /// Whether a program in the foreground was stopped with Ctrl+C, which drops the rest of the
+    /// line. It's set by whatever waited for the program, so it belongs to that thread.
+    static ABANDONED: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// Whether the line being run was dropped, for builtins that run statements over and over.
+fn abandoned() -> bool {
+    ABANDONED.get() || OVERDUE.load(Ordering::SeqCst)
+}
+
+/// Pass Ctrl+C on to the program in the foreground. Run from a terminal, it's in the terminal's
+/// foreground process group and gets it already, so this is only for when there's no terminal,
+/// like when another program sends it to the shell.
+fn forward_interrupt() {
+    #[cfg(unix)]
+    {
+        let pid = FOREGROUND.load(Ordering::SeqCst);
+        if pid > 0 && !std::io::stdin().is_terminal() {
+            unsafe { libc::kill(pid, libc::SIGINT) };
+        }
+    }
+}
+
+thread_local! {
+    /// The signal that killed or stopped the last program run in the foreground, until the status
+    /// it gave is set.
+    static SIGNALLED: std::cell::Cell<Option<i32>> = const { std::cell::Cell::new(None) };
+}
+
+/// The status of a program that ran in the foreground, which is 128 plus the signal if one killed
+/// it. One killed by Ctrl+C drops the rest of the line too.
+fn foreground_status(status: std::process::ExitStatus) -> i32 {
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        if signal == libc::SIGINT {
+            ABANDONED.set(true);
+        }
+        SIGNALLED.set(Some(signal));
+        return 128 + signal;
+    }
+    status.code().unwrap_or(255)
+}
+
+/// Say what a program's status likely means if $EXPLAIN_STATUS is true. Being stopped by Ctrl+C or
+/// a closed pipe is left alone, like in other shells, as it's what was meant to happen.
+fn explain_status(status: i32, state: &State) {
+    if status == 0 || get_var(state, "EXPLAIN_STATUS").as_deref() != Some("true") {
+        return;
+    }
+    let signal = SIGNALLED.get();
+    #[cfg(unix)]
+    if matches!(signal, Some(libc::SIGINT | libc::SIGPIPE)) {
+        return;
+    }
+    if let Some(explanation) = explain::explain(status, signal) {
+        eprintln!("{}\r", tr!("sesh: {}", explanation));
+    }
+}
+
+/// Wait for a program run in the foreground, getting the status to give the statement.
+fn wait_foreground(
+    child: &mut std::process::Child,
+    statement: &str,
+    state: &mut State,
+) -> std::io::Result<i32> {
+    FOREGROUND.store(child.id() as i32, Ordering::SeqCst);
+    let waited = wait_with_usage(child, state.raw_term.is_some());
+    FOREGROUND.store(0, Ordering::SeqCst);
+    Ok(finish_foreground(child.id(), waited?.0, statement, state))
+}
+
+/// Whether a program was stopped rather than exiting.
+fn is_stopped(status: std::process::ExitStatus) -> bool {
+    #[cfg(unix)]
+    return std::os::unix::process::ExitStatusExt::stopped_signal(&status).is_some();
+    #[cfg(not(unix))]
+    false
+}
+
+/// Take the terminal back after a program in the foreground exits or stops, keeping track of it as
+/// a job if it stopped. Returns the status to give the statement.
+fn finish_foreground(
+    pid: u32,
+    status: std::process::ExitStatus,
+    statement: &str,
+    state: &mut State,
+) -> i32 {
+    if state.raw_term.is_none() {
+        let status = foreground_status(status);
+        explain_status(status, state);
+        return status;
+    }
+    job::reclaim_terminal();
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::stopped_signal(&status) {
+        let id = job::adopt(pid as i32, statement, state);
+        let job = state.jobs.iter().find(|v| v.id == id).unwrap();
+        println!("\n{}", job);
+        SIGNALLED.set(Some(signal));
+        return 128 + signal;
+    }
+    let _ = pid;
+    let status = foreground_status(status);
+    explain_status(status, state);
+    status
+}
+
+/// The signal that asked the shell to terminate, or 0.
+static TERMINATE_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+/// Remember that a terminating signal arrived so the exit hooks can run.
+#[cfg(unix)]
+extern "C" fn on_terminate(signal: libc::c_int) {
+    TERMINATE_SIGNAL.store(signal, Ordering::SeqCst);
+}
+
+/// Catch SIGTERM and SIGHUP instead of dying straight away.
+fn catch_terminate() {
+    #[cfg(unix)]
+    for signal in [libc::SIGTERM, libc::SIGHUP] {
+        unsafe {
+            libc::signal(signal, on_terminate as *const () as libc::sighandler_t);
+        }
+    }
+}
+
+/// Exit if a terminating signal arrived.
+fn check_terminate(state: &mut State) {
+    let signal = TERMINATE_SIGNAL.load(Ordering::SeqCst);
+    if signal != 0 {
+        exit_shell(state, 128 + signal);
+    }
+}
+
+/// Whether an interactive shell can exit without leaving jobs behind by surprise. With jobs
+/// running or stopped, the first try fails with a warning and trying again straight after works.
+fn can_exit(state: &mut State) -> Result<(), String> {
+    if state.raw_term.is_none() || get_var(state, "CHECK_JOBS").as_deref() == Some("false") {
+        return Ok(());
+    }
+    job::update(state);
+    let count = |status| state.jobs.iter().filter(|v| v.status == status).count();
+    let (stopped, running) = (
+        count(job::JobStatus::Stopped),
+        count(job::JobStatus::Running),
+    );
+    if stopped + running == 0 || state.exit_warned.is_some_and(|v| v + 1 == state.entries) {
+        return Ok(());
+    }
+    state.exit_warned = Some(state.entries);
+    Err(if stopped > 0 {
+        tr!(
+            "sesh: there are {} stopped jobs; exit again to end them",
+            stopped
+        )
+    } else {
+        tr!(
+            "sesh: there are {} running jobs; exit again to end them",
+            running
+        )
+    })
+}
+
+/// Run the exit hooks, clean up and exit.
+fn exit_shell(state: &mut State, code: i32) -> ! {
+    if let Some(raw_term) = state.raw_term.clone() {
+        let writer = raw_term.write().unwrap();
+        let _ = writer.suspend_raw_mode();
+        state.raw_term = None;
+        // in case a program left it in a strange mode
+        term::restore();
+        job::hang_up(state);
+        let _ = merge_history(&history_path(), state);
+    }
+    // a program stopped with Ctrl+C before doesn't keep these from running
+    ABANDONED.set(false);
+    run_scope_exits(0, state);
+    // taken so a hook calling exit doesn't run them all again
+    for hook in std::mem::take(&mut state.exit_hooks) {
+        ABANDONED.set(false);
+        eval(&hook, state);
+    }
+    let _ = std::io::stdout().flush();
+    ctl::cleanup();
+    std::process::exit(code);
+}
+
+/// Wait until there's input to read, answering control socket requests in the meantime.
+/// Returns whether any requests were answered.
+fn wait_input(
+    input: &mut dyn term::Input,
+    control: &Option<ctl::Control>,
+    state: &mut State,
+) -> bool {
+    let mut serviced = false;
+    let idle_since = Instant::now();
+    let mut warned = false;
+    loop {
+        check_terminate(state);
+        if input.poll(Duration::from_millis(100)).unwrap_or(true) {
+            return serviced;
+        }
+        if let Some(timeout) = get_var(state, "TMOUT")
+            .and_then(|v| timer::parse_delay(&v))
+            .filter(|v| !v.is_zero())
+        {
+            let left = timeout.saturating_sub(idle_since.elapsed());
+            if left.is_zero() {
+                println!("\r\n{}\r", tr!("sesh: timed out waiting for input"));
+                exit_shell(state, 0);
+            }
+            // a warning once there's a tenth of the time or 10 seconds left, whichever is less
+            if !warned && left <= (timeout / 10).min(Duration::from_secs(10)) {
+                warned = true;
+                serviced = true;
+                let seconds = left.as_secs_f64().ceil() as u64;
+                print!(
+                    "\r\n{}\r",
+                    tr!("sesh: exiting in {}s without input", seconds)
+                );
+            }
+        }
+        if let Some(control) = control {
+            serviced |= control.service(state);
+        }
+        serviced |= run_timers(state);
+        serviced |= job::report(state);
+    }
+}
+
+/// Statements queued with `then` to run once the current command line is done.
+static QUEUE: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Queue a statement to run once the current command line is done.
+fn enqueue(statement: String) {
+    QUEUE.lock().unwrap().push(statement);
+}
+
+/// The statements queued with `then`.
+fn queued() -> Vec<String> {
+    QUEUE.lock().unwrap().clone()
+}
+
+/// Forget the statements queued with `then`, returning how many there were.
+fn clear_queue() -> usize {
+    QUEUE.lock().unwrap().drain(..).count()
+}
+
+/// Run the queued statements in order. With ERREXIT set to true, a failure drops the rest.
+fn run_queue(state: &mut State) {
+    loop {
+        let Some(statement) = ({
+            let mut queue = QUEUE.lock().unwrap();
+            (!queue.is_empty()).then(|| queue.remove(0))
+        }) else {
+            return;
+        };
+        let status = get_status(state);
+        if status != 0 && get_var(state, "ERREXIT").as_deref() == Some("true") {
+            let dropped = 1 + clear_queue();
+            println!(
+                "{}",
+                tr!(
+                    "sesh: status {}, dropping {} queued statement(s)",
+                    status,
+                    dropped
+                )
+            );
+            return;
+        }
+        eval(&statement, state);
+    }
+}
+
+/// Run the timers that are due. Returns whether any were.
+fn run_timers(state: &mut State) -> bool {
+    let due = timer::take_due(state);
+    for timer in &due {
+        print!("\r\x1b[2K");
+        let _ = std::io::stdout().flush();
+        eval(&timer.statement, state);
+    }
+    !due.is_empty()
+}
+
+/// Run the chpwd hooks after cd, pushd or popd, following CD_SUMMARY at the prompt. They run in
+/// this shell, and cd still gets its own status.
+fn run_chpwd_hooks(state: &mut State) {
+    let status = get_status(state);
+    let summary = get_var(state, "CD_SUMMARY").filter(|v| !v.is_empty() && v != "false");
+    // taken while they run so one that changes directory doesn't set them off again
+    let mut hooks = std::mem::take(&mut state.chpwd_hooks);
+    if let Some(summary) = summary
+        && state.raw_term.is_some()
+    {
+        eval(
+            if summary == "true" {
+                "summary"
+            } else {
+                &summary
+            },
+            state,
+        );
+    }
+    for hook in &hooks {
+        eval(hook, state);
+    }
+    hooks.append(&mut state.chpwd_hooks);
+    state.chpwd_hooks = hooks;
+    set_status(state, status);
+}
+
+/// Run the statements registered with scope-exit after the first `from` of them, last first,
+/// keeping the status the scope ended with.
+fn run_scope_exits(from: usize, state: &mut State) {
+    let status = get_status(state);
+    // taken so one calling exit doesn't run them again
+    let statements = state
+        .scope_exits
+        .split_off(from.min(state.scope_exits.len()));
+    for scope_exit in statements.into_iter().rev() {
+        match scope_exit {
+            ScopeExit::Statement(statement) => eval(&statement, state),
+            ScopeExit::Remove(path) => {
+                let removed = match std::fs::symlink_metadata(&path) {
+                    Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(&path),
+                    Ok(_) => std::fs::remove_file(&path),
+                    // already gone
+                    Err(_) => Ok(()),
+                };
+                if let Err(e) = removed {
+                    println!("{}", tr!("sesh: removing {} failed: {}", path.display(), e));
+                }
+            }
+        }
+    }
+    set_status(state, status);
+}
+
+/// Run the precmd hooks in this shell, so they can set what the prompt shows, stopping them once
+/// PRECMD_TIMEOUT has passed instead of holding up the prompt. Returns whether they were.
+fn run_precmd_hooks(state: &mut State) -> bool {
+    if state.precmd_hooks.is_empty() {
+        return false;
+    }
+    let timeout = get_var(state, "PRECMD_TIMEOUT")
+        .and_then(|v| timer::parse_delay(&v))
+        .unwrap_or(Duration::from_secs(2));
+    // the prompt and the next line still get the status of the last one typed
+    let status = ["STATUS", "?", "STATUS_SIGNAL"].map(|v| (v, state.specials.get(v).cloned()));
+    let finished = Arc::new(AtomicBool::new(false));
+    let watchdog = {
+        let finished = finished.clone();
+        std::thread::spawn(move || {
+            let start = Instant::now();
+            while !finished.load(Ordering::SeqCst) {
+                let left = timeout.saturating_sub(start.elapsed());
+                if left.is_zero() {
+                    stop_overdue(&finished);
+                    return true;
+                }
+                // woken early once they're done
+                std::thread::park_timeout(left);
+            }
+            false
+        })
+    };
+    for hook in state.precmd_hooks.clone() {
+        eval(&hook, state);
+        if abandoned() {
+            break;
+        }
+    }
+    finished.store(true, Ordering::SeqCst);
+    watchdog.thread().unpark();
+    let timed_out = watchdog.join().unwrap_or(false);
+    OVERDUE.store(false, Ordering::SeqCst);
+    ABANDONED.set(false);
+    take_interrupt();
+    for (name, value) in status {
+        if let Some(value) = value {
+            state.specials.insert(name, value);
+        }
+    }
+    timed_out
+}
+
+/// Whether the statements being run ran out of time, which drops the rest of them like Ctrl+C.
+static OVERDUE: AtomicBool = AtomicBool::new(false);
+
+/// Stop statements that ran out of time until they're `finished`: builtins that loop stop as if
+/// Ctrl+C was pressed, and whatever program is in the foreground is killed, along with what it
+/// started when it has a process group of its own.
+fn stop_overdue(finished: &AtomicBool) {
+    OVERDUE.store(true, Ordering::SeqCst);
+    while !finished.load(Ordering::SeqCst) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        #[cfg(unix)]
+        {
+            let pid = FOREGROUND.load(Ordering::SeqCst);
+            if pid > 0 {
+                unsafe {
+                    libc::kill(-pid, libc::SIGKILL);
+                    libc::kill(pid, libc::SIGKILL);
+                }
+            }
+        }
+        // again, as one may start after the last is killed
+        std::thread::park_timeout(Duration::from_millis(10));
+    }
+}
+
+/// Path of the rc file.
+fn rc_path() -> PathBuf {
+    // tests keep away from the real ~/.seshrc
+    #[cfg(test)]
+    return std::env::temp_dir().join(format!("sesh-test-{}-seshrc", std::process::id()));
+    #[cfg(not(test))]
+    std::env::home_dir().unwrap().join(".seshrc")
+}
+
+/// Evaluate a script, remembering the aliases and variables it changes.
+fn eval_recording(script: &str, state: &mut State) -> RcChanges {
+    let aliases = state.aliases.clone();
+    let vars = state.shell_env.clone();
+    eval(script, state);
+
+    let mut changes = RcChanges::default();
+    for alias in &state.aliases {
+        if !aliases.contains(alias) && !changes.aliases.iter().any(|v| v.0 == alias.name) {
+            let old = aliases.iter().rev().find(|v| v.name == alias.name);
+            changes.aliases.push((alias.name.clone(), old.cloned()));
+        }
+    }
+    for var in &state.shell_env {
+        if !vars.contains(var) && !changes.vars.iter().any(|v| v.0 == var.name) {
+            let old = vars.iter().rev().find(|v| v.name == var.name);
+            changes
+                .vars
+                .push((var.name.clone(), old.map(|v| v.value.clone())));
+        }
+    }
+    changes
+}
+
+/// Put back the aliases and variables a script changed.
+fn undo_changes(changes: RcChanges, state: &mut State) {
+    for (name, old) in changes.aliases {
+        state.aliases.retain(|v| v.name != name);
+        state.aliases.extend(old);
+    }
+    swap_vars(changes.vars, state);
+}
+
+/// Run ~/.seshrc, remembering what it changes.
+fn run_rc(state: &mut State) -> std::io::Result<()> {
+    let path = rc_path();
+    let rc = std::fs::read(&path)?;
+    let scope_exits = state.scope_exits.len();
+    state.rc = RcChanges {
+        modified: std::fs::metadata(&path).and_then(|v| v.modified()).ok(),
+        ..eval_recording(&decode_script(rc), state)
+    };
+    // it's a script of its own, so what it registers with scope-exit runs when it ends
+    run_scope_exits(scope_exits, state);
+    Ok(())
+}
+
+/// Undo what ~/.seshrc changed and run it again.
+fn reload_rc(state: &mut State) -> std::io::Result<()> {
+    let changes = std::mem::take(&mut state.rc);
+    undo_changes(changes, state);
+    run_rc(state)
+}
+
+/// With AUTORELOAD set to true, offer to reload ~/.seshrc if it changed since it ran.
+fn check_rc_changed(state: &mut State) {
+    if get_var(state, "AUTORELOAD").as_deref() != Some("true") {
+        return;
+    }
+    let modified = std::fs::metadata(rc_path()).and_then(|v| v.modified()).ok();
+    if modified.is_none() || modified == state.rc.modified {
+        return;
+    }
+    // only ask once for each change
+    state.rc.modified = modified;
+    let Some(raw_term) = state.raw_term.clone() else {
+        return;
+    };
+    {
+        let mut writer = raw_term.write().unwrap();
+        let _ = write!(
+            writer,
+            "{}",
+            tr!("sesh: ~/.seshrc changed. Reload it? [y/N] ")
+        );
+        let _ = writer.flush();
+    }
+    let answer = term::input().read_byte().unwrap_or(b'n');
+    let _ = write!(raw_term.write().unwrap(), "\r\n");
+    if (answer == b'y' || answer == b'Y')
+        && let Err(e) = reload_rc(state)
+    {
+        println!("{}\r", tr!("sesh: reading ~/.seshrc failed: {}", e));
     }
-    state.shell_env.sort_by(|v1, v2| v1.name.cmp(&v2.name));
 }
 
-#[allow(clippy::arc_with_non_send_sync)]
-/// Evaluate a statement. May include multiple.
-fn eval(statement: &str, state: &mut State) {
-    let statement = remove_comments(statement);
-    let statements = split_statements(&substitute_vars(&statement, state.clone()));
+/// Where the history is kept.
+fn history_path() -> PathBuf {
+    std::env::home_dir().unwrap().join(".sesh_history")
+}
 
-    for statement in statements {
-        let statement_split = split_statement(&statement);
-        if let Some(e) = statement_split.iter().find(|v| v.is_err()) {
-            println!("sesh: {}\r", e.clone().unwrap_err());
-            return;
-        }
-        let statement_split = statement_split
-            .iter()
-            .map(|v| v.clone().unwrap())
-            .collect::<Vec<IndirectRes>>();
+/// Whether a line of the history file is the time of the entry after it, like `#1700000000`.
+fn time_line(line: &str) -> Option<i64> {
+    line.strip_prefix('#')?.parse().ok()
+}
 
-        if !statement_split[0].is_statement() {
-            println!("sesh: program name is indirect\r");
-            return;
+/// Read the history file, along with when each entry was run if that was written down.
+fn load_history(path: &std::path::Path) -> Vec<(String, Option<i64>)> {
+    let text = String::from_utf8_lossy(&std::fs::read(path).unwrap_or_default()).to_string();
+    let mut history = Vec::new();
+    let mut time = None;
+    for line in text
+        .split("\n")
+        .map(|v| v.trim_matches(|ch: char| ch.is_control()))
+    {
+        if let Some(v) = time_line(line) {
+            time = Some(v);
+        } else if !line.is_empty() {
+            history.push((line.to_string(), time.take()));
         }
+    }
+    history
+}
 
-        let mut indirects = statement_split
-            .clone()
-            .into_iter()
-            .filter(|v| !v.is_statement())
-            .collect::<Vec<IndirectRes>>();
-        indirects.sort_by(|v1, v2| {
-            if matches!(v1, IndirectRes::Stderr(_)) && matches!(v2, IndirectRes::Stderr(_)) {
-                return std::cmp::Ordering::Equal;
-            }
-            if matches!(v1, IndirectRes::Stdout(_)) && matches!(v2, IndirectRes::Stdout(_)) {
-                return std::cmp::Ordering::Equal;
-            }
-            if matches!(v1, IndirectRes::Stdin(_)) && matches!(v2, IndirectRes::Stdin(_)) {
-                return std::cmp::Ordering::Equal;
-            }
-            v1.cmp(v2)
-        });
-        indirects.dedup();
+/// Drop the oldest entries of the history past $HISTSIZE.
+fn limit_history(state: &mut State) {
+    let Some(size) = get_var(state, "HISTSIZE").and_then(|v| v.parse::<usize>().ok()) else {
+        return;
+    };
+    let over = state.history.len().saturating_sub(size);
+    state.history.drain(..over);
+    state
+        .history_times
+        .drain(..over.min(state.history_times.len()));
+}
 
-        let mut statement_split = statement_split
-            .into_iter()
-            .filter(|v| v.is_statement())
-            .map(|v| v.unwrap_statement())
-            .collect::<Vec<String>>();
+/// Open a history file, locked against other sessions writing to it until it's closed.
+fn lock_history(path: &std::path::Path) -> std::io::Result<std::fs::File> {
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)?;
+    file.lock()?;
+    Ok(file)
+}
 
-        if statement.is_empty() || statement_split[0].is_empty() {
-            continue;
-        }
-        let mut program_name = statement_split[0].clone();
+/// Change the lines of a history file, reading them while it's locked so entries other sessions
+/// added since aren't lost.
+fn rewrite_history(
+    path: &std::path::Path,
+    change: impl FnOnce(&mut Vec<String>),
+) -> std::io::Result<()> {
+    let mut file = lock_history(path)?;
+    let mut text = Vec::new();
+    file.read_to_end(&mut text)?;
+    let mut lines = String::from_utf8_lossy(&text)
+        .lines()
+        .map(|v| v.to_string())
+        .collect::<Vec<String>>();
+    change(&mut lines);
+    file.set_len(0)?;
+    file.write_all(
+        lines
+            .iter()
+            .map(|v| format!("{}\n", v))
+            .collect::<String>()
+            .as_bytes(),
+    )
+}
 
-        for alias in &state.aliases {
-            if program_name == alias.name {
-                let to_split = split_statement(&alias.to)
-                    .iter()
-                    .filter_map(|v| v.clone().ok())
-                    .filter(|v| v.is_statement())
-                    .map(|v| v.unwrap_statement())
-                    .collect::<Vec<String>>();
+/// Add an entry to the history and a history file, unless $HISTDEDUP is true and it's the same
+/// as the last one. With $HISTTIMESTAMPS set to true, the time is written down too.
+fn add_history(entry: &str, path: &std::path::Path, state: &mut State) -> std::io::Result<()> {
+    if get_var(state, "HISTDEDUP").as_deref() == Some("true")
+        && state.history.last().is_some_and(|v| v == entry)
+    {
+        return Ok(());
+    }
+    let time = (get_var(state, "HISTTIMESTAMPS").as_deref() == Some("true"))
+        .then(|| chrono::Local::now().timestamp());
+    state.history_times.resize(state.history.len(), None);
+    state.history.push(entry.to_string());
+    state.history_times.push(time);
+    limit_history(state);
 
-                for (i, item) in to_split[1..].iter().enumerate() {
-                    statement_split.insert(i + 1, (*item).clone());
-                }
-                program_name = to_split[0].clone();
-                continue;
+    let mut text = entry.to_string() + "\n";
+    if let Some(time) = time {
+        text = format!("#{}\n{}", time, text);
+    }
+    // written all at once so it can't end up in the middle of another session's entry
+    lock_history(path)?.write_all(text.as_bytes())
+}
+
+/// Tidy up a history file the sessions have been adding to side by side, done when the shell
+/// exits. With $HISTDEDUP set to true, entries the same as the one before them are dropped, and
+/// only the last $HISTFILESIZE entries are kept.
+fn merge_history(path: &std::path::Path, state: &State) -> std::io::Result<()> {
+    let dedup = get_var(state, "HISTDEDUP").as_deref() == Some("true");
+    let size = get_var(state, "HISTFILESIZE").and_then(|v| v.parse::<usize>().ok());
+    if !dedup && size.is_none() {
+        return Ok(());
+    }
+    rewrite_history(path, |lines| {
+        // the times go with the entries they're before
+        let mut entries = Vec::<(Option<String>, String)>::new();
+        let mut time = None;
+        for line in lines.drain(..) {
+            if time_line(&line).is_some() {
+                time = Some(line);
+            } else if !(dedup && entries.last().is_some_and(|v| v.1 == line)) {
+                entries.push((time.take(), line));
             }
         }
+        let first = entries.len().saturating_sub(size.unwrap_or(usize::MAX));
+        for (time, entry) in entries.drain(first..) {
+            lines.extend(time);
+            lines.push(entry);
+        }
+    })
+}
 
-        if let Some(builtin) = builtins::BUILTINS.iter().find(|v| v.0 == program_name) {
-            if let Some(raw_term) = state.raw_term.clone() {
-                let writer = raw_term.write().unwrap();
-                let _ = writer.suspend_raw_mode();
-            }
-            if indirects.len() > 1 {
-                println!("sesh: warning: indirects ignored for builtin")
-            }
-            let status = builtin.1(statement_split, statement.to_string(), state);
-            garbage_collect_vars(state);
-            if let Some(raw_term) = state.raw_term.clone() {
-                let writer = raw_term.write().unwrap();
-                let _ = writer.activate_raw_mode();
-            }
-            for (i, var) in state.shell_env.clone().into_iter().enumerate() {
-                if var.name == "STATUS" {
-                    state.shell_env.swap_remove(i);
-                }
+/// Forget an entry of the history by its index, or all of it, in the history file too. An entry
+/// that's in the file more than once only loses its last copy there.
+fn forget_history(index: Option<usize>, state: &mut State) -> std::io::Result<()> {
+    let Some(index) = index else {
+        state.history.clear();
+        state.history_times.clear();
+        return rewrite_history(&history_path(), |lines| lines.clear());
+    };
+    let entry = state.history.remove(index);
+    if index < state.history_times.len() {
+        state.history_times.remove(index);
+    }
+    rewrite_history(&history_path(), |lines| {
+        if let Some(i) = lines
+            .iter()
+            .rposition(|v| v.trim_matches(|ch: char| ch.is_control()) == entry)
+        {
+            lines.remove(i);
+            if i > 0 && time_line(&lines[i - 1]).is_some() {
+                lines.remove(i - 1);
             }
+        }
+    })
+}
 
-            state.shell_env.push(ShellVar {
-                name: "STATUS".to_string(),
-                value: status.to_string(),
-            });
+/// Expand references to the history in a line: `!!` for the last entry, `!n` for entry n, `!-n`
+/// for the nth last one and `!prefix` for the last one starting with prefix. Nothing in quotes or
+/// brackets is expanded, and neither is a `!` before a space or `=`. Gives the expanded line if
+/// anything was expanded, or the reference that matched nothing.
+fn expand_history(line: &str, history: &[String]) -> Result<Option<String>, String> {
+    let mut out = String::with_capacity(line.len());
+    let mut nesting = Nesting::default();
+    let mut expanded = false;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, ch)) = chars.next() {
+        let escaped = nesting.escape;
+        let quoted = nesting.in_quote() || nesting.closers.last() == Some(&']');
+        if ch != '!' || escaped || quoted {
+            nesting.push(ch);
+            out.push(ch);
             continue;
         }
-        if let Some(raw_term) = state.raw_term.clone() {
-            let writer = raw_term.write().unwrap();
-            let _ = writer.suspend_raw_mode();
-        }
-        for env in &state.shell_env {
-            unsafe {
-                std::env::set_var(env.name.clone(), env.value.clone());
-            }
-        }
-        let mut command = std::process::Command::new(program_name.clone());
-        command
-            .args(&statement_split[1..])
-            .current_dir(state.working_dir.clone());
-        for indirect in indirects {
-            match indirect {
-                IndirectRes::Statement(_) => (),
-                IndirectRes::Stderr(i) => match i {
-                    Indirect::Default => (),
-                    Indirect::Fd(fd) => {
-                        command.stderr(unsafe { std::os::fd::OwnedFd::from_raw_fd(fd) });
-                    }
-                    Indirect::NextStatement => todo!(),
-                    Indirect::Path(p) => {
-                        command.stderr(
-                            std::fs::OpenOptions::new()
-                                .create(true)
-                                .append(true)
-                                .open(p)
-                                .unwrap(),
-                        );
-                    },
-                    Indirect::PrevStatement => todo!(),
-                    Indirect::Stderr => (),
-                    Indirect::Stdout => {
-                        command.stderr(std::io::stdout());
-                    }
-                },
-                IndirectRes::Stdout(i) => match i {
-                    Indirect::Default => (),
-                    Indirect::Fd(fd) => {
-                        command.stdout(unsafe { std::os::fd::OwnedFd::from_raw_fd(fd) });
-                    }
-                    Indirect::NextStatement => todo!(),
-                    Indirect::Path(p) => {
-                        command.stdout(
-                            std::fs::OpenOptions::new()
-                                .create(true)
-                                .append(true)
-                                .open(p)
-                                .unwrap(),
-                        );
-                    },
-                    Indirect::PrevStatement => todo!(),
-                    Indirect::Stderr => {
-                        command.stdout(std::io::stderr());
-                    },
-                    Indirect::Stdout => ()
-                },
-                IndirectRes::Stdin(i) => match i {
-                    Indirect::Default => (),
-                    Indirect::Fd(fd) => {
-                        command.stdin(unsafe { std::os::fd::OwnedFd::from_raw_fd(fd) });
-                    }
-                    Indirect::NextStatement => todo!(),
-                    Indirect::Path(p) => {
-                        command.stdin(
-                            std::fs::OpenOptions::new()
-                                .read(true)
-                                .open(p)
-                                .unwrap(),
-                        );
-                    },
-                    Indirect::PrevStatement => todo!(),
-                    Indirect::Stderr => (),
-                    Indirect::Stdout => ()
-                }
-            }
+        let rest = &line[i + 1..];
+        let len = if rest.starts_with('!') {
+            1
+        } else {
+            rest.find(|v: char| v.is_whitespace() || ";|&()[]{}\"'`<>=".contains(v))
+                .unwrap_or(rest.len())
+        };
+        let event = &rest[..len];
+        if event.is_empty() {
+            out.push(ch);
+            continue;
         }
-
-        match command.spawn() {
-            Ok(mut child) => {
-                for (i, var) in state.shell_env.clone().into_iter().enumerate() {
-                    if var.name == "STATUS" {
-                        state.shell_env.swap_remove(i);
-                    }
-                }
-
-                state.shell_env.push(ShellVar {
-                    name: "STATUS".to_string(),
-                    value: child.wait().unwrap().code().unwrap_or(255i32).to_string(),
-                });
-                if let Some(raw_term) = state.raw_term.clone() {
-                    let writer = raw_term.write().unwrap();
-                    let _ = writer.activate_raw_mode();
-                }
-                continue;
-            }
-            Err(error) => {
-                println!("sesh: error spawning program: {}", error);
-                for (i, var) in state.shell_env.clone().into_iter().enumerate() {
-                    if var.name == "STATUS" {
-                        state.shell_env.swap_remove(i);
-                    }
-                }
-
-                state.shell_env.push(ShellVar {
-                    name: "STATUS".to_string(),
-                    value: "127".to_string(),
-                });
-                if let Some(raw_term) = state.raw_term.clone() {
-                    let writer = raw_term.write().unwrap();
-                    let _ = writer.activate_raw_mode();
-                }
-                return;
-            }
+        let entry = match event {
+            "!" => history.last(),
+            _ if event.starts_with('-') => event[1..]
+                .parse::<usize>()
+                .ok()
+                .and_then(|v| history.len().checked_sub(v))
+                .and_then(|v| history.get(v)),
+            _ if event.chars().all(|v| v.is_ascii_digit()) => event
+                .parse::<usize>()
+                .ok()
+                .and_then(|v| v.checked_sub(1))
+                .and_then(|v| history.get(v)),
+            _ => history.iter().rev().find(|v| v.starts_with(event)),
+        };
+        let Some(entry) = entry else {
+            return Err(format!("!{}", event));
+        };
+        out += entry;
+        expanded = true;
+        for _ in 0..event.chars().count() {
+            chars.next();
         }
     }
+    Ok(expanded.then_some(out))
 }
 
-/// Write the prompt to the screen.
-fn write_prompt(state: State) -> Result<(), Box<dyn std::error::Error>> {
-    let mut prompt = state
-        .shell_env
-        .iter()
-        .find(|var| var.name == "PROMPT1")
-        .unwrap_or(&ShellVar {
-            name: "PROMPT1".to_string(),
-            value: String::new(),
-        })
-        .value
-        .clone();
-    prompt = prompt.replace(
-        "$u",
-        &users::get_effective_username()
-            .unwrap_or(users::get_current_username().unwrap_or("?".into()))
-            .to_string_lossy(),
-    );
-    prompt = prompt.replace(
-        "$h",
-        &hostname::get().unwrap_or("?".into()).to_string_lossy(),
-    );
-
-    prompt = prompt.replace("$p", &state.working_dir.as_os_str().to_string_lossy());
-    prompt = prompt.replace(
-        "$P",
-        &state
-            .working_dir
-            .file_name()
-            .unwrap_or(OsStr::new("?"))
-            .to_string_lossy(),
-    );
-    if state.in_mode {
-        let table = [
-            "\x1b[31;1m",
-            "\x1b[38;2;255;165;0;1m",
-            "\x1b[33;1m",
-            "\x1b[32;1m",
-            "\x1b[34;1m",
-            "\x1b[36;1m",
-            "\x1b[35;1m",
-        ];
-        let idx = state.entries % table.len();
-        prompt += table[idx];
+/// Record a phase of startup for --profile-startup, along with the statements evaluated during it.
+fn profile_phase(
+    profile: &mut Option<profile::Profile>,
+    name: &str,
+    start: Instant,
+    state: &mut State,
+) {
+    if let Some(profile) = profile {
+        let statements = state
+            .statement_times
+            .replace(Vec::new())
+            .unwrap_or_default();
+        profile.record(i18n::translate(name), start.elapsed(), statements);
     }
+}
 
-    print!("{}", prompt);
-    std::io::stdout().flush()?;
-    Ok(())
+/// Run the statements put off with defer.
+fn run_deferred(state: &mut State) {
+    for statement in std::mem::take(&mut state.deferred) {
+        eval(&statement, state);
+        ABANDONED.set(false);
+    }
 }
 
-/// log data to a file
-#[allow(dead_code)]
-fn log_file(value: &str) {
-    let value = value.to_string() + "\n";
-    std::fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(std::env::current_dir().unwrap().join("sesh.log"))
-        .unwrap()
-        .write_all(value.as_bytes())
-        .unwrap();
+/// Decode a script. Scripts that aren't valid UTF-8 are assumed to be latin-1, which can represent
+/// any sequence of bytes.
+fn decode_script(bytes: Vec<u8>) -> String {
+    match String::from_utf8(bytes) {
+        Ok(script) => script,
+        Err(e) => e.into_bytes().into_iter().map(char::from).collect(),
+    }
 }
 
 #[allow(clippy::arc_with_non_send_sync)]
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // before anything changes it
+    LazyLock::force(&INHERITED);
     let mut options = Args::parse();
+    let mut profile = options.profile_startup.then(profile::Profile::new);
+    if let Some(shell) = options.completions {
+        clap_complete::generate(shell, &mut Args::command(), "sesh", &mut std::io::stdout());
+        return Ok(());
+    }
+    if options.list_builtins {
+        builtins::print_list(options.json);
+        return Ok(());
+    }
+    if let Some(path) = &options.replay {
+        if let Err(e) = transcript::replay(path, options.speed, &mut std::io::stdout()) {
+            println!(
+                "{}",
+                tr!("sesh: replaying {} failed: {}", path.display(), e)
+            );
+        }
+        return Ok(());
+    }
+    if let Some(path) = options.transcript.clone() {
+        transcript::set_recording(path);
+    }
 
-    let mut args = std::env::args();
-    let _ = args.next();
-
-    if let Some(filename) = args.next()
+    let mut script_vars = Vec::new();
+    if let Some(filename) = options.script.clone()
         && options.run_before.is_empty()
         && options.run_expr.is_empty()
     {
-        let rc = std::fs::read(filename.clone());
-        if rc.is_err() {
-            println!("sesh: reading {} failed: {}", filename, rc.unwrap_err());
-            println!("sesh: exiting");
-            return Ok(());
-        } else {
-            let rc = String::from_utf8(rc.unwrap());
-            if rc.is_err() {
-                println!("sesh: reading {} failed: not valid UTF-8", filename);
-                println!("sesh: exiting");
+        match std::fs::read(&filename) {
+            Ok(script) => options.run_expr = decode_script(script),
+            Err(e) => {
+                println!(
+                    "{}",
+                    tr!("sesh: reading {} failed: {}", filename.display(), e)
+                );
+                println!("{}", tr!("sesh: exiting"));
                 return Ok(());
-            } else {
-                let rc = rc.unwrap();
-                options.run_expr = rc;
             }
         }
+        for (i, arg) in std::iter::once(filename.into_os_string())
+            .chain(options.script_args.clone())
+            .enumerate()
+        {
+            script_vars.push(ShellVar {
+                name: format!("{}", i),
+                value: os_str::to_string(&arg),
+                exported: false,
+            });
+        }
     }
 
+    // read while the rc runs, it can be big
+    let history = std::thread::spawn(|| {
+        let start = Instant::now();
+        (load_history(&history_path()), start.elapsed())
+    });
+
     let mut state = State {
         shell_env: Vec::new(),
         focus: Focus::Str(String::new()),
@@ -669,22 +3178,72 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         raw_term: None,
         in_mode: false,
         entries: 0,
-        history: std::fs::read_to_string(std::env::home_dir().unwrap().join(".sesh_history"))
-            .unwrap_or_default()
-            .split("\n")
-            .map(|v| v.trim_matches(|ch: char| ch.is_control()))
+        history: Vec::new(),
+        history_times: Vec::new(),
+        plugins: Vec::new(),
+        restricted: options.restricted
+            || std::env::args_os()
+                .next()
+                .map(PathBuf::from)
+                .is_some_and(|v| v.file_stem().is_some_and(|v| v == "rsesh")),
+        guards: guard::DEFAULT_PATTERNS
+            .iter()
             .map(|v| v.to_string())
-            .filter(|v| !v.is_empty())
             .collect(),
+        secrets: Vec::new(),
+        exit_hooks: Vec::new(),
+        exit_warned: None,
+        precmd_hooks: Vec::new(),
+        chpwd_hooks: Vec::new(),
+        scope_exits: Vec::new(),
+        precmd_timed_out: false,
+        deferred: Vec::new(),
+        statement_times: options.profile_startup.then(Vec::new),
+        eval_stack: Vec::new(),
+        group_indirects: Vec::new(),
+        specials: BTreeMap::new(),
+        rc: RcChanges::default(),
+        timers: Vec::new(),
+        dir_stack: Vec::new(),
+        project: project::Project::default(),
+        pipe: None,
+        jobs: Vec::new(),
+        loop_depth: 0,
+        loop_control: None,
+        subshell_depth: 0,
+        exit_request: None,
+        unset_vars: vec![],
+        bookmarks: Vec::new(),
+        prefill: None,
     };
     state.shell_env.push(ShellVar {
         name: "PROMPT1".to_string(),
         value: "\x1b[32m$u@$h\x1b[39m \x1b[34m$P\x1b[39m> ".to_string(),
+        exported: false,
     });
     state.shell_env.push(ShellVar {
         name: "PROMPT2".to_string(),
         value: "> ".to_string(),
+        exported: false,
+    });
+    state.shell_env.push(ShellVar {
+        name: "GREETING".to_string(),
+        value: "sesh $v - run help for a list of builtins".to_string(),
+        exported: false,
     });
+    // so $HOME and the like can be used, and are passed on
+    state.shell_env.extend(
+        INHERITED
+            .iter()
+            .filter(|v| !builtins::SPECIAL_VARS.contains(&v.0.as_str()))
+            .map(|(name, value)| ShellVar {
+                name: name.clone(),
+                value: os_str::to_string(value),
+                exported: true,
+            }),
+    );
+    state.shell_env.extend(script_vars);
+    init_special_vars(&mut state);
 
     let mut interactive = true;
 
@@ -693,52 +3252,169 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         state.shell_env.push(ShellVar {
             name: "INTERACTIVE".to_string(),
             value: "false".to_string(),
+            exported: false,
         });
     } else {
         state.shell_env.push(ShellVar {
             name: "INTERACTIVE".to_string(),
             value: "true".to_string(),
+            exported: false,
         });
     }
-    let _ = ctrlc::set_handler(|| println!());
+    let _ = ctrlc::set_handler(|| {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        forward_interrupt();
+        println!()
+    });
+    catch_terminate();
+    job::init();
 
-    let rc = std::fs::read(std::env::home_dir().unwrap().join(".seshrc"));
-    if rc.is_err() {
-        println!("sesh: reading ~/.seshrc failed: {}", rc.unwrap_err());
-        println!("sesh: not running .seshrc")
-    } else {
-        let rc = String::from_utf8(rc.unwrap());
-        if rc.is_err() {
-            println!("sesh: reading ~/.seshrc failed: not valid UTF-8");
-            println!("sesh: not running .seshrc")
-        } else {
-            let rc = rc.unwrap();
-            eval(&rc, &mut state);
+    let start = Instant::now();
+    plugins::load_all(&mut state);
+    profile_phase(&mut profile, "plugins", start, &mut state);
+
+    let start = Instant::now();
+    if !options.no_rc
+        && let Err(e) = run_rc(&mut state)
+    {
+        println!("{}", tr!("sesh: reading ~/.seshrc failed: {}", e));
+        println!("{}", tr!("sesh: not running .seshrc"));
+    }
+    profile_phase(&mut profile, "~/.seshrc", start, &mut state);
+    // a program in it stopped with Ctrl+C only drops the rest of it
+    ABANDONED.set(false);
+
+    if let Some(fd) = options.session_fd
+        && let Err(e) = session::receive(fd, &mut state)
+    {
+        println!("{}", tr!("sesh: receiving session failed: {}", e));
+    }
+    if let Some(name) = &options.resume {
+        if state.restricted {
+            println!("{}", tr!("sesh: restricted: cannot restore sessions"));
+        } else if let Err(e) = session::restore(name, &mut state) {
+            println!("{}", tr!("sesh: restoring session {} failed: {}", name, e));
         }
     }
 
+    let start = Instant::now();
+    let (history, history_duration) = history.join().unwrap_or_default();
+    (state.history, state.history_times) = history.into_iter().unzip();
+    limit_history(&mut state);
+    if let Some(profile) = &mut profile {
+        profile.record(
+            &tr!("history (in the background)"),
+            history_duration,
+            Vec::new(),
+        );
+        profile.record(&tr!("waiting for the history"), start.elapsed(), Vec::new());
+    }
+
     if !interactive {
+        let start = Instant::now();
+        run_deferred(&mut state);
+        profile_phase(&mut profile, "defer", start, &mut state);
+        if let Some(profile) = profile {
+            profile.print();
+        }
+        state.statement_times = None;
         eval(&options.run_expr, &mut state);
-        return Ok(());
+        // so jobs, and whatever else runs sesh -c, can tell whether it worked
+        let status = get_status(&state);
+        exit_shell(&mut state, status);
     } else if !options.run_before.is_empty() {
-        eval(&options.run_before, &mut state)
+        let start = Instant::now();
+        eval(&options.run_before, &mut state);
+        ABANDONED.set(false);
+        profile_phase(&mut profile, "--before", start, &mut state);
     }
 
     let mut hist_ptr: usize = state.history.len();
 
-    state.raw_term = Some(Arc::new(RwLock::new(std::io::stdout().into_raw_mode()?)));
+    let mut keys = term::input();
+
+    // the control socket could be used to get around restrictions
+    let control = if state.restricted {
+        None
+    } else {
+        match ctl::listen() {
+            Ok(control) => {
+                state.shell_env.push(ShellVar {
+                    name: "SESH_SOCKET".to_string(),
+                    value: control.path.to_string_lossy().to_string(),
+                    exported: true,
+                });
+                Some(control)
+            }
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::Unsupported {
+                    println!("{}", tr!("sesh: opening control socket failed: {}", e));
+                }
+                None
+            }
+        }
+    };
+
+    if !options.no_greeting {
+        let start = Instant::now();
+        write_greeting(&state);
+        profile_phase(&mut profile, "greeting", start, &mut state);
+    }
+    if let Some(mut profile) = profile {
+        let start = Instant::now();
+        let _ = expand_prompt(
+            &state
+                .shell_env
+                .iter()
+                .find(|var| var.name == "PROMPT1")
+                .map(|var| var.value.clone())
+                .unwrap_or_default(),
+            &state,
+        );
+        profile.record(&tr!("prompt"), start.elapsed(), Vec::new());
+        profile.print();
+    }
+    state.statement_times = None;
+
+    term::save_original();
+    // the terminal goes back to normal before the message, so it can be read
+    let panic_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        term::restore();
+        panic_hook(info);
+    }));
+    job::take_terminal();
+    state.raw_term = Some(Arc::new(RwLock::new(term::raw_terminal()?)));
 
     'mainloop: loop {
-        write_prompt(state.clone())?;
+        check_rc_changed(&mut state);
+        project::update(&mut state);
+        job::report(&mut state);
+        state.precmd_timed_out = run_precmd_hooks(&mut state);
+        if !state.restricted {
+            // errors here would show up at every prompt
+            let _ = session::save(session::LAST, &state);
+        }
+        write_prompt(state.clone(), "")?;
+        if !state.deferred.is_empty() {
+            // the prompt is up and typing is buffered by the terminal, so run them now and redraw
+            print!("\r\x1b[2K");
+            run_deferred(&mut state);
+            write_prompt(state.clone(), "")?;
+        }
 
-        let mut input = String::new();
+        let mut input = state.prefill.take().unwrap_or_default();
+        if !input.is_empty() {
+            print!("{}", input);
+            std::io::stdout().flush()?;
+        }
 
         let mut i0 = [0u8];
         let mut line_escape = false;
         let mut arrow_seq = [0u8; 2];
         let mut in_arrow = (false, 0usize);
         let mut curr_inp_hist = String::new();
-        let mut line_cursor = 0usize;
+        let mut line_cursor = input.chars().count();
         while i0[0] != b'\x0D' || line_escape {
             if i0[0] == 27 {
                 in_arrow = (true, 0);
@@ -751,6 +3427,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .unwrap_or(&ShellVar {
                         name: "PROMPT2".to_string(),
                         value: String::new(),
+                        exported: false,
                     })
                     .value
                     .clone();
@@ -764,8 +3441,58 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 std::io::stdout().flush()?;
                 continue 'mainloop;
             }
-            let amount = std::io::stdin().read(&mut i0).unwrap();
-            if amount == 0 {
+            if wait_input(keys.as_mut(), &control, &mut state) {
+                // a line filled in from elsewhere only replaces one that's still empty
+                if input.is_empty()
+                    && let Some(prefill) = state.prefill.take()
+                {
+                    input = prefill;
+                }
+                print!("\x0D\n");
+                write_prompt(state.clone(), &input)?;
+                print!("{}", input);
+                std::io::stdout().flush()?;
+                line_cursor = input.chars().count();
+            }
+            match keys.read_byte() {
+                Ok(byte) => i0[0] = byte,
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    print!("\x0D\n");
+                    exit_shell(&mut state, 0);
+                }
+                Err(_) => continue,
+            }
+            if i0[0] == 4 && input.is_empty() {
+                // ctrl+d on an empty line exits, like exit would
+                if get_var(&state, "IGNOREEOF").as_deref() == Some("true") {
+                    println!("\x0D\n{}\x0D", tr!("sesh: use exit to leave the shell"));
+                    continue 'mainloop;
+                }
+                println!("exit\x0D");
+                state.entries += 1;
+                if let Err(warning) = can_exit(&mut state) {
+                    println!("{}\x0D", warning);
+                    continue 'mainloop;
+                }
+                exit_shell(&mut state, 0);
+            }
+            if i0[0] == 27 && !in_arrow.0 {
+                // the start of an escape sequence, read from the next byte on
+                continue;
+            }
+            if in_arrow.0 && in_arrow.1 == 0 && b"bf".contains(&i0[0]) {
+                // alt+b, alt+f
+                in_arrow.0 = false;
+                let to = word_boundary(&input, line_cursor, i0[0] == b'f');
+                let writer = state.raw_term.clone().unwrap();
+                let mut writer = writer.write().unwrap();
+                if to != line_cursor {
+                    move_cursor(&mut *writer, &input, line_cursor, to)?;
+                } else {
+                    writer.write_all(b"\x07")?;
+                }
+                writer.flush()?;
+                line_cursor = to;
                 continue;
             }
             if in_arrow.0 {
@@ -781,13 +3508,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                 let writer = state.raw_term.clone().unwrap();
                                 let mut writer = writer.write().unwrap();
 
-                                writer.write_all(b"\x0D")?;
-                                write_prompt(state.clone())?;
-                                writer.write_all(b"\x1b[0K")?;
-
                                 curr_inp_hist = input;
 
                                 input = state.history[hist_ptr].clone();
+                                line_cursor = input.chars().count();
+                                writer.write_all(b"\x0D")?;
+                                write_prompt(state.clone(), &input)?;
+                                writer.write_all(b"\x1b[0K")?;
                                 writer.write_all(input.as_bytes())?;
                                 writer.flush()?;
                             }
@@ -799,11 +3526,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                 let writer = state.raw_term.clone().unwrap();
                                 let mut writer = writer.write().unwrap();
 
+                                input = state.history[hist_ptr].clone();
+                                line_cursor = input.chars().count();
                                 writer.write_all(b"\x0D")?;
-                                write_prompt(state.clone())?;
+                                write_prompt(state.clone(), &input)?;
                                 writer.write_all(b"\x1b[0K")?;
-
-                                input = state.history[hist_ptr].clone();
                                 writer.write_all(input.as_bytes())?;
                                 writer.flush()?;
                             } else {
@@ -811,42 +3538,75 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                 let writer = state.raw_term.clone().unwrap();
                                 let mut writer = writer.write().unwrap();
 
+                                input = curr_inp_hist.clone();
+                                line_cursor = input.chars().count();
                                 writer.write_all(b"\x0D")?;
-                                write_prompt(state.clone())?;
+                                write_prompt(state.clone(), &input)?;
                                 writer.write_all(b"\x1b[0K")?;
-
-                                input = curr_inp_hist.clone();
                                 writer.write_all(input.as_bytes())?;
                                 writer.flush()?;
                             }
                         }
                         [91, 68] => {
                             // left arrow
-                            if line_cursor.checked_sub(1).is_some() {
+                            if line_cursor > 0 {
                                 let writer = state.raw_term.clone().unwrap();
                                 let mut writer = writer.write().unwrap();
-                                line_cursor -= 1;
-                                writer.write_all(b"\x1b[1D")?;
+                                let to = grapheme_boundary(&input, line_cursor, false);
+                                move_cursor(&mut *writer, &input, line_cursor, to)?;
+                                line_cursor = to;
                             } else {
                                 print!("\x07");
                             }
                         }
                         [91, 67] => {
                             // right arrow
-                            if line_cursor + 1 < input.len() {
+                            if line_cursor < input.chars().count() {
                                 let writer = state.raw_term.clone().unwrap();
                                 let mut writer = writer.write().unwrap();
-                                line_cursor += 1;
-                                writer.write_all(b"\x1b[1C")?;
+                                let to = grapheme_boundary(&input, line_cursor, true);
+                                move_cursor(&mut *writer, &input, line_cursor, to)?;
+                                line_cursor = to;
                             } else {
                                 print!("\x07");
                             }
                         }
+                        // home, end and delete edit the line like ctrl+a, ctrl+e and ctrl+d
+                        [91, 72] | [79, 72] => i0[0] = 1,
+                        [91, 70] | [79, 70] => i0[0] = 5,
+                        [91, 51] => {
+                            // ESC [ 3 ~
+                            let _ = keys.read_byte();
+                            i0[0] = 4;
+                        }
                         _ => {
                             continue;
                         }
                     }
                 }
+                if ![1, 4, 5].contains(&i0[0]) {
+                    continue;
+                }
+            }
+            if i0[0] == 0x12 || i0[0] == 0x14 {
+                // ctrl+r, ctrl+t
+                let picked = if i0[0] == 0x12 {
+                    finder::history(&state)
+                } else {
+                    finder::files(&state)
+                };
+                if let Some(picked) = picked {
+                    insert_at_cursor(&mut input, &mut line_cursor, &picked);
+                }
+                i0[0] = 0;
+                let writer = state.raw_term.clone().unwrap();
+                let mut writer = writer.write().unwrap();
+                writer.write_all(b"\x0D")?;
+                write_prompt(state.clone(), &input)?;
+                writer.write_all(b"\x1b[0K")?;
+                writer.write_all(input.replace('\r', "\r\n").as_bytes())?;
+                move_cursor(&mut *writer, &input, input.chars().count(), line_cursor)?;
+                writer.flush()?;
                 continue;
             }
             if i0[0] != b'\x0D' {
@@ -855,36 +3615,100 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             if i0[0] == b'\\' {
                 line_escape = true;
             }
+            if i0[0] == 0x0C {
+                // ctrl+l
+                let writer = state.raw_term.clone().unwrap();
+                let mut writer = writer.write().unwrap();
+                writer.write_all(b"\x1b[2J\x1b[H")?;
+                write_prompt(state.clone(), &input)?;
+                writer.write_all(input.replace('\r', "\r\n").as_bytes())?;
+                move_cursor(&mut *writer, &input, input.chars().count(), line_cursor)?;
+                writer.flush()?;
+                continue;
+            }
+            let typed = read_char(i0[0], keys.as_mut());
+            let autopair = get_var(&state, "AUTOPAIR").as_deref() == Some("true");
             let raw_term = state.raw_term.clone().unwrap();
             let mut raw_term = raw_term.write().unwrap();
-            if i0[0] == b'\x7F' {
-                if input.pop().is_none() {
+            if input.contains(['\r', '\n']) || i0[0] == b'\x0D' {
+                // continued lines can only be added to, and enter ends the line wherever the cursor is
+                if i0[0] == b'\x7F' {
+                    if let Some(last) = input.graphemes(true).next_back() {
+                        let width = last.width().max(1);
+                        input.truncate(input.len() - last.len());
+                        raw_term.write_all("\x08 \x08".repeat(width).as_bytes())?;
+                    } else {
+                        raw_term.write_all(b"\x07")?;
+                    }
+                } else if i0[0] != b'\x0D' && i0[0].is_ascii_control() {
                     raw_term.write_all(b"\x07")?;
                 } else {
-                    raw_term.write_all(b"\x08 \x08")?;
+                    input.push(typed);
+                    write!(raw_term, "{}", typed)?;
+                }
+                line_cursor = input.chars().count();
+                raw_term.flush()?;
+                continue;
+            }
+            let edited = if i0[0] == b'\t' {
+                let completion = complete::complete(&input, line_cursor, &state);
+                if !completion.choices.is_empty() {
+                    let width = crossterm::terminal::size().map_or(80, |v| v.0 as usize);
+                    let choices = complete::columns(&completion.choices, width);
+                    write!(raw_term, "\r\n{}", choices.replace('\n', "\r\n"))?;
                 }
+                let completed = completion.line != input || !completion.choices.is_empty();
+                (input, line_cursor) = (completion.line, completion.cursor);
+                completed
+            } else {
+                edit_line(&mut input, &mut line_cursor, typed, autopair)
+            };
+            if !edited {
+                raw_term.write_all(b"\x07")?;
             } else {
-                input.push(char::from_u32(i0[0] as u32).unwrap());
-                raw_term.write_all(&i0)?;
+                // redraw the line to show which brackets match
+                raw_term.write_all(b"\x0D")?;
+                write_prompt(state.clone(), &input)?;
+                raw_term.write_all(b"\x1b[0K")?;
+                raw_term.write_all(highlight_brackets(&input, line_cursor).as_bytes())?;
+                move_cursor(&mut *raw_term, &input, input.chars().count(), line_cursor)?;
             }
             raw_term.flush()?;
         }
 
         println!("\x0D");
+        // a line starting with a space is kept out of the history if asked
+        let hidden =
+            input.starts_with(' ') && get_var(&state, "HISTIGNORESPACE").as_deref() == Some("true");
         input = input.clone().trim().to_string();
-        state.history.push(input.clone());
-
-        std::fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(std::env::home_dir().unwrap().join(".sesh_history"))
-            .unwrap()
-            .write_all((input.clone() + "\n").into_bytes().as_slice())
-            .unwrap();
-
-        hist_ptr = state.history.len();
+        match expand_history(&input, &state.history) {
+            Ok(Some(expanded)) => {
+                // show what's actually run
+                println!("{}\r", expanded);
+                input = expanded;
+            }
+            Ok(None) => (),
+            Err(event) => {
+                println!("{}\r", tr!("sesh: {}: event not found", event));
+                continue;
+            }
+        }
+        if !defines_secret(&input)
+            && !hidden
+            && let Err(e) = add_history(&input, &history_path(), &mut state)
+        {
+            println!("{}\r", tr!("sesh: writing the history failed: {}", e));
+        }
 
         state.entries += 1;
+        // a hook stopped with Ctrl+C doesn't take the line with it
+        ABANDONED.set(false);
         eval(&input, &mut state);
+        run_queue(&mut state);
+        // back at the prompt, so nothing is left to drop
+        ABANDONED.set(false);
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        // the history builtin may have taken entries out
+        hist_ptr = state.history.len();
     }
 }
@@ -0,0 +1,56 @@
+//! OS string conversions
+//!
+//! The shell works on [String]s, but file names don't have to be valid UTF-8. Bytes that aren't
+//! valid UTF-8 are smuggled through as characters in a private use area (U+10FE00 + byte) and
+//! turned back into the original bytes whenever a string is handed to the OS.
+
+use std::{
+    ffi::{OsStr, OsString},
+    path::PathBuf,
+};
+
+/// Where the smuggled bytes start.
+const BYTE_BASE: u32 = 0x10FE00;
+
+/// Convert an OS string into a string, keeping invalid bytes.
+pub fn to_string(os: &OsStr) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let mut out = String::new();
+        for chunk in os.as_bytes().utf8_chunks() {
+            out.push_str(chunk.valid());
+            for byte in chunk.invalid() {
+                out.push(char::from_u32(BYTE_BASE + *byte as u32).unwrap());
+            }
+        }
+        out
+    }
+    #[cfg(not(unix))]
+    os.to_string_lossy().to_string()
+}
+
+/// Convert a string back into an OS string, restoring smuggled bytes.
+pub fn to_os(s: &str) -> OsString {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        let mut out = Vec::with_capacity(s.len());
+        for ch in s.chars() {
+            if (BYTE_BASE..BYTE_BASE + 0x100).contains(&(ch as u32)) {
+                out.push((ch as u32 - BYTE_BASE) as u8);
+            } else {
+                let mut buf = [0u8; 4];
+                out.extend(ch.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+        OsString::from_vec(out)
+    }
+    #[cfg(not(unix))]
+    OsString::from(s)
+}
+
+/// Convert a string into a path, restoring smuggled bytes.
+pub fn to_path(s: &str) -> PathBuf {
+    PathBuf::from(to_os(s))
+}
@@ -0,0 +1,735 @@
+//! Parsing command lines
+//!
+//! A command line is tokenized into the text of statements, separators (`;` and newlines) and the
+//! `&&`, `||` and `&` operators, which are then parsed into chains of statements. Quotes, brackets
+//! and groups are tracked while tokenizing, so nothing inside them is split and a group can go on
+//! over several lines. Anything left open, closed without being opened, missing on either side of
+//! an operator or redirecting from something that can't be is a syntax error with the line and
+//! column it's at. Each statement is parsed again into a [Command] once it's been expanded, right
+//! before it runs: its words, with their quotes taken off, and its indirects.
+//!
+//! `<<END` and `<<< word` are taken out of a statement here, since a here-document's lines would
+//! otherwise be read as statements. Inside a group they're left for when the group runs. Their
+//! text is expanded when the statement runs, unless the end of the here-document is quoted or the
+//! here-string is in single quotes.
+
+use std::{fmt::Display, ops::Range, path::PathBuf};
+
+use super::i18n::tr;
+use super::{Nesting, Step};
+
+/// Where something is in the text, counting lines and columns from 1.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Position {
+    /// The line
+    pub line: usize,
+    /// The column, in characters
+    pub column: usize,
+}
+
+/// A command line that doesn't parse.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyntaxError {
+    /// What's wrong
+    pub message: String,
+    /// Where
+    pub position: Position,
+    /// Whether more text could still fix it, like closing a quote
+    pub unfinished: bool,
+}
+
+impl Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&tr!(
+            "syntax error at line {}, column {}: {}",
+            self.position.line,
+            self.position.column,
+            self.message
+        ))
+    }
+}
+
+/// How a statement in a chain depends on the one before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Link {
+    /// It's the first one, so it always runs
+    First,
+    /// After `&&`, it runs if the one before succeeded
+    And,
+    /// After `||`, it runs if the one before failed
+    Or,
+}
+
+/// A statement in a chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Statement {
+    /// How it depends on the one before it
+    pub link: Link,
+    /// Its text, without a `<<` or `<<<` redirection
+    pub text: String,
+    /// What it gets on stdin from a here-document or here-string
+    pub input: Option<String>,
+    /// Whether the input is taken as it is instead of being expanded
+    pub literal_input: bool,
+    /// Where it starts
+    pub position: Position,
+}
+
+/// Statements joined by `&&` and `||`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Chain {
+    /// The statements, in order
+    pub statements: Vec<Statement>,
+    /// Whether it ended in `&`, to run in the background
+    pub background: bool,
+}
+
+impl Chain {
+    /// The text of the chain without a `&` at the end. Input from here-documents and here-strings
+    /// is given as here-documents after it.
+    pub fn text(&self) -> String {
+        let mut out = String::new();
+        let mut documents = String::new();
+        for statement in &self.statements {
+            match statement.link {
+                Link::First => (),
+                Link::And => out += " && ",
+                Link::Or => out += " || ",
+            }
+            out += &statement.text;
+            if let Some(input) = &statement.input {
+                // something that isn't a line of it
+                let mut end = String::from("EOF");
+                while input.lines().any(|v| v.trim() == end) {
+                    end.push('_');
+                }
+                match statement.literal_input {
+                    true => out += &format!(" <<'{}'", end),
+                    false => out += &format!(" <<{}", end),
+                }
+                documents += &format!("\n{}{}", input, end);
+            }
+        }
+        out + &documents
+    }
+}
+
+/// Where an indirect sends a stream, or takes stdin from.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Indirect {
+    /// Where it goes anyway
+    #[default]
+    Default,
+    /// To stdout (not for stdin)
+    Stdout,
+    /// To stderr (not for stdin)
+    Stderr,
+    /// To or from a file descriptor
+    Fd(i32),
+    /// To or from a path, appending to it
+    Path(PathBuf),
+    /// To a path, overwriting it. Forced overwrites happen even with NOCLOBBER set.
+    Overwrite(PathBuf, bool),
+    /// To the next statement
+    NextStatement,
+    /// From the previous statement
+    PrevStatement,
+    /// To a pipe the shell is reading, by the number of its capture
+    Pipe(usize),
+}
+
+/// A stream of a statement and its indirect.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IndirectRes {
+    /// Stdin
+    Stdin(Indirect),
+    /// Stdout
+    Stdout(Indirect),
+    /// Stderr
+    Stderr(Indirect),
+}
+
+/// A word of a statement, with its quotes taken off.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Word {
+    /// The text
+    pub text: String,
+    /// Whether a wildcard in it was quoted, so it isn't expanded
+    pub quoted: bool,
+    /// Where it is in the statement, in bytes
+    pub span: Range<usize>,
+}
+
+/// A statement split up to run.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Command {
+    /// The words, the program first
+    pub words: Vec<Word>,
+    /// The indirects, in the order they're written
+    pub indirects: Vec<IndirectRes>,
+}
+
+/// A piece of a command line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    /// The text of a statement, what it gets on stdin from `<<` or `<<<` and whether that's taken
+    /// as it is
+    Text(String, Option<String>, bool),
+    /// `;` or a newline
+    Separator,
+    /// `&&`
+    And,
+    /// `||`
+    Or,
+    /// `&` after a statement
+    Background,
+}
+
+/// Where the input of a statement comes from, and whether it's taken as it is.
+enum Input {
+    /// The lines after this one, up to the one with just this on it
+    Document(String, bool),
+    /// This text
+    Text(String, bool),
+}
+
+/// Read a `<<END` or `<<< word` redirection at the start of `chars`. Returns the input and how
+/// many characters it took, or None if the word is missing.
+fn read_input(chars: &[char]) -> Option<(Input, usize)> {
+    let string = chars.get(2) == Some(&'<');
+    let mut i = if string { 3 } else { 2 };
+    while chars.get(i).is_some_and(|v| *v == ' ' || *v == '\t') {
+        i += 1;
+    }
+    let quote = chars.get(i).copied().filter(|v| ['"', '\''].contains(v));
+    let word = match quote {
+        Some(quote) => {
+            let len = chars[i + 1..].iter().position(|v| *v == quote)?;
+            let word = chars[i + 1..i + 1 + len].iter().collect::<String>();
+            i += len + 2;
+            word
+        }
+        _ => {
+            let start = i;
+            while chars
+                .get(i)
+                .is_some_and(|v| !v.is_whitespace() && !";&|()".contains(*v))
+            {
+                i += 1;
+            }
+            chars[start..i].iter().collect::<String>()
+        }
+    };
+    // a here-string is like a word, so only single quotes keep it as it is
+    match string {
+        true => Some((Input::Text(word + "\n", quote == Some('\'')), i)),
+        false if word.is_empty() => None,
+        false => Some((Input::Document(word, quote.is_some()), i)),
+    }
+}
+
+/// What ends each here-document started in a line of a command line, in order. `nesting` is
+/// what's open from the lines before it, and is updated for the next one.
+pub fn document_ends(line: &str, nesting: &mut Nesting) -> Vec<String> {
+    let chars = line.chars().collect::<Vec<char>>();
+    let mut ends = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] == '<' && chars.get(i + 1) == Some(&'<') && nesting.is_empty() {
+            match read_input(&chars[i..]) {
+                Some((Input::Document(end, _), len)) => {
+                    ends.push(end);
+                    i += len;
+                    continue;
+                }
+                Some((Input::Text(..), len)) => {
+                    i += len;
+                    continue;
+                }
+                None => (),
+            }
+        }
+        nesting.push(chars[i]);
+        i += 1;
+    }
+    ends
+}
+
+/// Split a command line into tokens, each with where it starts.
+fn tokenize(text: &str) -> Result<Vec<(Token, Position)>, SyntaxError> {
+    let chars = text.chars().collect::<Vec<char>>();
+    let mut tokens = Vec::new();
+    let mut nesting = Nesting::default();
+    // what's open, with where, for saying what wasn't closed
+    let mut opened = Vec::new();
+    let mut current = String::new();
+    let mut start = Position::default();
+    let mut position = Position { line: 1, column: 1 };
+    let mut i = 0;
+    // the word being read, where it starts and where an `@` outside quotes and groups is in it
+    let mut word = (String::new(), Position::default(), None);
+    // the input of the current statement and where its redirection is
+    let mut input: Option<(Input, Position)> = None;
+    // here-documents to read after this line: which token they're for and what ends them
+    let mut documents: Vec<(usize, String, Position)> = Vec::new();
+
+    let flush = |current: &mut String,
+                 start: Position,
+                 input: &mut Option<(Input, Position)>,
+                 tokens: &mut Vec<(Token, Position)>,
+                 documents: &mut Vec<(usize, String, Position)>| {
+        let text = current.trim().to_string();
+        current.clear();
+        let (text_input, literal) = match input.take() {
+            Some((_, at)) if text.is_empty() => {
+                return Err(SyntaxError {
+                    message: tr!("expected a statement before <<"),
+                    position: at,
+                    unfinished: false,
+                });
+            }
+            Some((Input::Document(end, literal), at)) => {
+                documents.push((tokens.len(), end, at));
+                (Some(String::new()), literal)
+            }
+            Some((Input::Text(text, literal), _)) => (Some(text), literal),
+            None => (None, false),
+        };
+        if !text.is_empty() {
+            tokens.push((Token::Text(text, text_input, literal), start));
+        }
+        Ok(())
+    };
+
+    while i < chars.len() {
+        let ch = chars[i];
+        let next = chars.get(i + 1).copied();
+        let here = position;
+        position.column += 1;
+        if ch == '\n' {
+            position = Position {
+                line: position.line + 1,
+                column: 1,
+            };
+        }
+        i += 1;
+
+        // a backslash at the end of a line carries the statement on to the next one
+        if ch == '\\' && next == Some('\n') && !nesting.escape && !nesting.in_quote() {
+            position = Position {
+                line: position.line + 1,
+                column: 1,
+            };
+            i += 1;
+            continue;
+        }
+        // inside a group they're left for when it runs, so their lines have to match up there
+        if ch == '<' && next == Some('<') && nesting.is_empty() && !current.ends_with('<') {
+            let Some((redirect, len)) = read_input(&chars[i - 1..]) else {
+                return Err(SyntaxError {
+                    message: tr!("expected a word after <<"),
+                    position: here,
+                    unfinished: false,
+                });
+            };
+            check_word(&std::mem::take(&mut word))?;
+            input = Some((redirect, here));
+            i += len - 1;
+            position.column += len - 1;
+            // so what's after it doesn't start with an empty word
+            while current.ends_with(' ') && chars.get(i).is_some_and(|v| *v == ' ' || *v == '\t') {
+                i += 1;
+                position.column += 1;
+            }
+            continue;
+        }
+        if nesting.is_empty() && (ch.is_whitespace() || is_operator(ch, next, &current)) {
+            check_word(&std::mem::take(&mut word))?;
+        } else if nesting.is_empty() || !word.0.is_empty() {
+            if word.0.is_empty() {
+                word.1 = here;
+            }
+            if ch == '@' && nesting.is_empty() && word.2.is_none() {
+                word.2 = Some(word.0.len());
+            }
+            word.0.push(ch);
+        }
+        if nesting.is_empty() {
+            let token = match ch {
+                ';' | '\n' => Some(Token::Separator),
+                '&' if next == Some('&') => Some(Token::And),
+                '|' if next == Some('|') => Some(Token::Or),
+                '&' if is_operator(ch, next, &current) => Some(Token::Background),
+                _ => None,
+            };
+            if let Some(token) = token {
+                flush(&mut current, start, &mut input, &mut tokens, &mut documents)?;
+                if matches!(token, Token::And | Token::Or) {
+                    i += 1;
+                    position.column += 1;
+                }
+                tokens.push((token, here));
+                if ch == '\n' {
+                    read_documents(&chars, &mut i, &mut position, &mut documents, &mut tokens)?;
+                }
+                continue;
+            }
+        }
+        if current.trim().is_empty() && !ch.is_whitespace() {
+            start = here;
+        }
+        let literal = nesting.escape || nesting.in_quote();
+        match nesting.push(ch) {
+            Step::Opened => opened.push((ch, here)),
+            Step::Closed => {
+                opened.pop();
+            }
+            Step::Neither if !literal && [')', ']', '}'].contains(&ch) => {
+                return Err(SyntaxError {
+                    message: tr!("unexpected {}", ch),
+                    position: here,
+                    unfinished: false,
+                });
+            }
+            Step::Neither => (),
+        }
+        current.push(ch);
+    }
+    if let Some((ch, at)) = opened.pop() {
+        return Err(SyntaxError {
+            message: tr!("{} isn't closed", ch),
+            position: at,
+            unfinished: true,
+        });
+    }
+    check_word(&word)?;
+    flush(&mut current, start, &mut input, &mut tokens, &mut documents)?;
+    if let Some((_, end, at)) = documents.first() {
+        return Err(SyntaxError {
+            message: tr!("expected {} to end the here-document", end),
+            position: *at,
+            unfinished: true,
+        });
+    }
+    Ok(tokens)
+}
+
+/// Read the bodies of the here-documents waiting for the end of the line, starting at `i`.
+fn read_documents(
+    chars: &[char],
+    i: &mut usize,
+    position: &mut Position,
+    documents: &mut Vec<(usize, String, Position)>,
+    tokens: &mut [(Token, Position)],
+) -> Result<(), SyntaxError> {
+    for (token, end, at) in std::mem::take(documents) {
+        let mut body = String::new();
+        loop {
+            if *i >= chars.len() {
+                return Err(SyntaxError {
+                    message: tr!("expected {} to end the here-document", end),
+                    position: at,
+                    unfinished: true,
+                });
+            }
+            let len = chars[*i..].iter().position(|v| *v == '\n');
+            let line = chars[*i..*i + len.unwrap_or(chars.len() - *i)]
+                .iter()
+                .collect::<String>();
+            *i += line.chars().count() + len.map_or(0, |_| 1);
+            *position = Position {
+                line: position.line + 1,
+                column: 1,
+            };
+            if line.trim() == end {
+                break;
+            }
+            body += &line;
+            body.push('\n');
+        }
+        if let Some((Token::Text(_, input, _), _)) = tokens.get_mut(token) {
+            *input = Some(body);
+        }
+    }
+    Ok(())
+}
+
+/// Whether a character outside quotes and groups starts a separator or operator.
+fn is_operator(ch: char, next: Option<char>, current: &str) -> bool {
+    match ch {
+        ';' | '\n' => true,
+        '&' | '|' if next == Some(ch) => true,
+        // `&` only runs in the background on its own
+        '&' => {
+            (current.is_empty() || current.ends_with(char::is_whitespace))
+                && next.is_none_or(|v| v.is_whitespace() || v == ';')
+        }
+        _ => false,
+    }
+}
+
+/// Make sure a word with an `@` in it redirects from something that can be redirected.
+fn check_word((word, start, at): &(String, Position, Option<usize>)) -> Result<(), SyntaxError> {
+    match at.map(|v| &word[..v]) {
+        None | Some("0" | "1" | "2" | "12" | "21") => Ok(()),
+        Some(from) => Err(SyntaxError {
+            message: tr!("unknown indirect from `{}`", from),
+            position: *start,
+            unfinished: false,
+        }),
+    }
+}
+
+/// The text of an operator token, for errors.
+fn operator(token: &Token) -> &'static str {
+    match token {
+        Token::And => "&&",
+        Token::Or => "||",
+        _ => "&",
+    }
+}
+
+/// Parse a command line into chains of statements.
+pub fn parse(text: &str) -> Result<Vec<Chain>, SyntaxError> {
+    let mut chains = Vec::new();
+    let mut statements = Vec::new();
+    // the operator waiting for a statement after it
+    let mut pending: Option<(Token, Position)> = None;
+    for (token, position) in tokenize(text)? {
+        match token {
+            Token::Text(text, input, literal_input) => {
+                let link = match pending.take() {
+                    Some((Token::And, _)) => Link::And,
+                    Some((Token::Or, _)) => Link::Or,
+                    _ => Link::First,
+                };
+                statements.push(Statement {
+                    link,
+                    text,
+                    input,
+                    literal_input,
+                    position,
+                });
+            }
+            Token::And | Token::Or | Token::Background
+                if statements.is_empty() || pending.is_some() =>
+            {
+                return Err(SyntaxError {
+                    message: tr!("expected a statement before {}", operator(&token)),
+                    position,
+                    unfinished: false,
+                });
+            }
+            Token::And | Token::Or => pending = Some((token, position)),
+            Token::Separator if pending.is_some() => {
+                let (token, position) = pending.unwrap();
+                return Err(SyntaxError {
+                    message: tr!("expected a statement after {}", operator(&token)),
+                    position,
+                    unfinished: false,
+                });
+            }
+            Token::Separator | Token::Background => {
+                if !statements.is_empty() {
+                    chains.push(Chain {
+                        statements: std::mem::take(&mut statements),
+                        background: token == Token::Background,
+                    });
+                }
+            }
+        }
+    }
+    if let Some((token, position)) = pending {
+        return Err(SyntaxError {
+            message: tr!("expected a statement after {}", operator(&token)),
+            position,
+            unfinished: true,
+        });
+    }
+    if !statements.is_empty() {
+        chains.push(Chain {
+            statements,
+            background: false,
+        });
+    }
+    Ok(chains)
+}
+
+/// Where a byte of some text is.
+fn position_of(text: &str, at: usize) -> Position {
+    let before = &text[..at];
+    Position {
+        line: before.matches('\n').count() + 1,
+        column: before
+            .rsplit('\n')
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .count()
+            + 1,
+    }
+}
+
+/// Where an indirect from `from` goes, given what's after its `@`.
+fn indirect(from: &str, to: &str) -> Indirect {
+    let path = |path: &str| super::os_str::to_path(path);
+    match (from, to) {
+        ("0", "") => Indirect::PrevStatement,
+        (_, "") => Indirect::NextStatement,
+        ("0", to) => to
+            .parse()
+            .map_or_else(|_| Indirect::Path(path(to)), Indirect::Fd),
+        (_, "1") => Indirect::Stdout,
+        (_, "2") => Indirect::Stderr,
+        (_, to) => {
+            if let Some(to) = to.strip_prefix(">|") {
+                Indirect::Overwrite(path(to), true)
+            } else if let Some(to) = to.strip_prefix('>') {
+                Indirect::Overwrite(path(to), false)
+            } else {
+                to.parse()
+                    .map_or_else(|_| Indirect::Path(path(to)), Indirect::Fd)
+            }
+        }
+    }
+}
+
+/// The indirects of a word like `1@to`. `12@` and `21@` send stdout and stderr to the same place,
+/// which is the one file if it's overwritten.
+fn indirects(from: &str, to: &str) -> Vec<IndirectRes> {
+    match from {
+        "0" => vec![IndirectRes::Stdin(indirect(from, to))],
+        "1" => vec![IndirectRes::Stdout(indirect(from, to))],
+        "2" => vec![IndirectRes::Stderr(indirect(from, to))],
+        _ if to.starts_with('>') => vec![
+            IndirectRes::Stdout(indirect("1", to)),
+            IndirectRes::Stderr(Indirect::Stdout),
+        ],
+        _ => vec![
+            IndirectRes::Stdout(indirect("1", to)),
+            IndirectRes::Stderr(indirect("2", to)),
+        ],
+    }
+}
+
+/// Split an expanded statement into its words and indirects. Words are split by the spaces outside
+/// quotes, groups and brackets. Quotes come off, and so do the parentheses of a group unless it's
+/// empty. A backslash stays, but keeps the character after it from starting a quote or group.
+/// With an `@` outside quotes and groups, a word is an indirect from the streams before the `@`.
+pub fn command(text: &str) -> Result<Command, SyntaxError> {
+    let mut command = Command::default();
+    let mut word = Word {
+        text: String::new(),
+        quoted: false,
+        span: 0..0,
+    };
+    // whether the word has started, and where its `@` is
+    let mut started = false;
+    let mut at = None;
+    // what closes the quote or group the word is in, and where it was opened
+    let mut open: Option<(char, usize)> = None;
+    // what's open inside that group
+    let mut nesting = Nesting::default();
+    let mut escape = false;
+
+    let mut finish = |word: &mut Word, at: &mut Option<usize>, end: usize| {
+        let mut word = std::mem::replace(
+            word,
+            Word {
+                text: String::new(),
+                quoted: false,
+                span: end..end,
+            },
+        );
+        word.span.end = end;
+        match at.take().map(|v| word.text.split_at(v)) {
+            Some((from @ ("0" | "1" | "2" | "12" | "21"), to)) => {
+                command.indirects.extend(indirects(from, &to[1..]));
+            }
+            Some((from, _)) => {
+                return Err(SyntaxError {
+                    message: tr!("unknown indirect from `{}`", from),
+                    position: position_of(text, word.span.start),
+                    unfinished: false,
+                });
+            }
+            None => command.words.push(word),
+        }
+        Ok(())
+    };
+
+    for (i, (byte, ch)) in text.char_indices().enumerate() {
+        if open.is_none() && (ch == ' ' || ch == '\t') {
+            if started {
+                finish(&mut word, &mut at, byte)?;
+            }
+            started = false;
+            escape = false;
+            continue;
+        }
+        if !started {
+            started = true;
+            word.span.start = byte;
+        }
+        match open {
+            Some((quote @ ('"' | '\'' | '`'), _)) => {
+                if ch == quote {
+                    open = None;
+                } else {
+                    word.quoted |= ['*', '?', '['].contains(&ch);
+                    word.text.push(ch);
+                }
+            }
+            // inside a group everything is kept, but nested ones have to be matched up so it
+            // ends at the right place
+            Some((closer, opened)) if nesting.is_empty() && ch == closer => {
+                open = None;
+                if closer == ']' {
+                    word.text.push(ch);
+                } else if i == opened + 1 {
+                    word.text += "()";
+                }
+            }
+            Some((closer, _)) => {
+                word.quoted |= closer == ')' && ['*', '?', '['].contains(&ch);
+                nesting.push(ch);
+                word.text.push(ch);
+            }
+            None if escape => {
+                escape = false;
+                word.text.push(ch);
+            }
+            None => match ch {
+                '"' | '\'' | '`' => open = Some((ch, i)),
+                '(' => open = Some((')', i)),
+                // `[` as the program is the test
+                '[' if i > 1 => {
+                    open = Some((']', i));
+                    word.text.push(ch);
+                }
+                _ => {
+                    if ch == '@' && at.is_none() {
+                        at = Some(word.text.len());
+                    }
+                    escape = ch == '\\';
+                    word.text.push(ch);
+                }
+            },
+        }
+    }
+    if started {
+        finish(&mut word, &mut at, text.len())?;
+    }
+    Ok(command)
+}
+
+/// The text of every statement in a command line, ignoring how they're joined. Empty if it
+/// doesn't parse.
+pub fn statements(text: &str) -> Vec<String> {
+    parse(text)
+        .unwrap_or_default()
+        .into_iter()
+        .flat_map(|v| v.statements)
+        .map(|v| v.text)
+        .collect()
+}
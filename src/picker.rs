@@ -0,0 +1,147 @@
+//! Interactive picker
+//!
+//! Shows a list of items under the cursor and lets the user filter them by typing and pick one
+//! with the arrow keys, like a small fzf.
+
+use std::io::{IsTerminal, Write};
+
+/// How many items are shown at once.
+const HEIGHT: usize = 10;
+
+/// A key read from the terminal.
+enum Key {
+    /// Move the selection up
+    Up,
+    /// Move the selection down
+    Down,
+    /// Pick the selected item
+    Enter,
+    /// Give up
+    Cancel,
+    /// Delete the last character of the query
+    Backspace,
+    /// Part of a character to add to the query
+    Byte(u8),
+    /// Anything else
+    Other,
+}
+
+/// Read a key.
+fn read_key(input: &mut dyn super::term::Input) -> std::io::Result<Key> {
+    Ok(match input.read_byte()? {
+        b'\r' | b'\n' => Key::Enter,
+        0x03 | 0x04 => Key::Cancel,
+        0x7f | 0x08 => Key::Backspace,
+        0x10 => Key::Up,
+        0x0e => Key::Down,
+        0x1b => {
+            // a lone escape cancels, an escape sequence might be an arrow key
+            if !input.poll(std::time::Duration::from_millis(50))? {
+                return Ok(Key::Cancel);
+            }
+            if input.read_byte()? != b'[' {
+                return Ok(Key::Other);
+            }
+            match input.read_byte()? {
+                b'A' => Key::Up,
+                b'B' => Key::Down,
+                _ => Key::Other,
+            }
+        }
+        byte if byte >= 0x20 => Key::Byte(byte),
+        _ => Key::Other,
+    })
+}
+
+/// Indexes of the items matching a query, best first. The query has to be a subsequence of the
+/// item, ignoring case; items containing it as a whole come first.
+fn filter(items: &[String], query: &str) -> Vec<usize> {
+    let query = query.to_lowercase();
+    let mut matching = items
+        .iter()
+        .enumerate()
+        .filter_map(|(i, item)| {
+            let item = item.to_lowercase();
+            let mut chars = item.chars();
+            query
+                .chars()
+                .all(|ch| chars.any(|v| v == ch))
+                .then_some((!item.contains(&query), i))
+        })
+        .collect::<Vec<(bool, usize)>>();
+    matching.sort();
+    matching.into_iter().map(|v| v.1).collect()
+}
+
+/// Draw the picker, leaving the cursor at the end of the query.
+fn draw(
+    out: &mut dyn Write,
+    prompt: &str,
+    query: &str,
+    items: &[String],
+    matching: &[usize],
+    selected: usize,
+) -> std::io::Result<()> {
+    write!(out, "\r\x1b[J{}{}", prompt, query)?;
+    let top = selected.saturating_sub(HEIGHT - 1);
+    let shown = matching.iter().skip(top).take(HEIGHT);
+    let lines = shown.len();
+    for (i, item) in shown.enumerate() {
+        let line = items[*item].replace(['\n', '\r'], " ");
+        if top + i == selected {
+            write!(out, "\r\n\x1b[7m{}\x1b[0m", line)?;
+        } else {
+            write!(out, "\r\n{}", line)?;
+        }
+    }
+    if lines > 0 {
+        write!(out, "\x1b[{}A", lines)?;
+    }
+    write!(out, "\r")?;
+    let column = prompt.chars().count() + query.chars().count();
+    if column > 0 {
+        write!(out, "\x1b[{}C", column)?;
+    }
+    out.flush()
+}
+
+/// Let the user pick one of `items`. Returns the index of the item picked, or None if they gave
+/// up.
+pub fn pick(items: &[String], prompt: &str) -> std::io::Result<Option<usize>> {
+    if !std::io::stdin().is_terminal() {
+        return Err(std::io::Error::other("not a terminal"));
+    }
+    let mut out = super::term::raw_terminal()?;
+    let mut input = super::term::input();
+    let mut query = Vec::<u8>::new();
+    let mut selected = 0;
+    let picked = loop {
+        let text = String::from_utf8_lossy(&query).to_string();
+        let matching = filter(items, &text);
+        selected = selected.min(matching.len().saturating_sub(1));
+        draw(out.as_mut(), prompt, &text, items, &matching, selected)?;
+        match read_key(input.as_mut())? {
+            Key::Up => selected = selected.saturating_sub(1),
+            Key::Down => selected += 1,
+            Key::Enter => break matching.get(selected).copied(),
+            Key::Cancel => break None,
+            Key::Backspace => {
+                // drop a whole character, not just its last byte
+                while let Some(byte) = query.pop() {
+                    if byte & 0xc0 != 0x80 {
+                        break;
+                    }
+                }
+                selected = 0;
+            }
+            Key::Byte(byte) => {
+                query.push(byte);
+                selected = 0;
+            }
+            Key::Other => (),
+        }
+    };
+    write!(out, "\r\x1b[J")?;
+    out.flush()?;
+    Ok(picked)
+}
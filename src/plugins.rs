@@ -0,0 +1,330 @@
+//! Dynamic builtin plugins
+//!
+//! A plugin is a shared library exporting `sesh_plugin_init`, which returns a pointer to a
+//! [PluginInfo] describing the builtins it provides. Everything crossing the boundary is C ABI,
+//! so plugins can be written in anything that can produce a C-compatible shared library:
+//!
+//! ```c
+//! typedef struct SeshHost {
+//!     uint32_t abi_version;
+//!     void *ctx;
+//!     const char *(*get_var)(void *ctx, const char *name);
+//!     void (*set_var)(void *ctx, const char *name, const char *value);
+//! } SeshHost;
+//!
+//! typedef struct SeshBuiltin {
+//!     const char *name;
+//!     const char *usage;
+//!     const char *help;
+//!     int (*handler)(const SeshHost *host, int argc, const char *const *argv);
+//! } SeshBuiltin;
+//!
+//! typedef struct SeshPluginInfo {
+//!     uint32_t abi_version;
+//!     const char *name;
+//!     size_t builtin_count;
+//!     const SeshBuiltin *builtins;
+//! } SeshPluginInfo;
+//!
+//! const SeshPluginInfo *sesh_plugin_init(void);
+//! ```
+//!
+//! Shared libraries run with the full privileges of the shell. For sandboxed plugins, see [wasm].
+//!
+//! Plugins in `~/.config/sesh/plugins/` are loaded on startup. Shared libraries there need the
+//! extension of the platform (`so`, `dylib` or `dll`). WASM plugins there are granted the
+//! capabilities listed in a file next to them with the same name and the extension `allow`.
+
+use std::{
+    ffi::{CStr, CString, c_char, c_int, c_void},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use super::i18n::tr;
+
+pub mod wasm;
+
+/// Version of the plugin ABI. Plugins built against a different version are refused.
+pub const ABI_VERSION: u32 = 1;
+
+/// What the shell passes to builtin handlers.
+#[repr(C)]
+pub struct Host {
+    /// Version of the ABI
+    abi_version: u32,
+    /// Opaque context passed back to the functions below
+    ctx: *mut c_void,
+    /// Get a variable. The returned string is valid until the next call, or null if unset.
+    get_var: extern "C" fn(ctx: *mut c_void, name: *const c_char) -> *const c_char,
+    /// Set a variable.
+    set_var: extern "C" fn(ctx: *mut c_void, name: *const c_char, value: *const c_char),
+}
+
+/// A builtin as described by a plugin.
+#[repr(C)]
+pub struct RawBuiltin {
+    /// Name of the builtin
+    name: *const c_char,
+    /// Usage line
+    usage: *const c_char,
+    /// Description
+    help: *const c_char,
+    /// The function to call
+    handler: Handler,
+}
+
+/// A handler of a plugin builtin.
+type Handler = extern "C" fn(host: *const Host, argc: c_int, argv: *const *const c_char) -> c_int;
+
+/// What `sesh_plugin_init` returns.
+#[repr(C)]
+pub struct PluginInfo {
+    /// Version of the ABI the plugin was built for
+    abi_version: u32,
+    /// Name of the plugin
+    name: *const c_char,
+    /// Amount of builtins
+    builtin_count: usize,
+    /// The builtins
+    builtins: *const RawBuiltin,
+}
+
+/// A builtin provided by a plugin.
+#[derive(Debug)]
+pub struct PluginBuiltin {
+    /// Name of the builtin
+    pub name: String,
+    /// Usage line
+    pub usage: String,
+    /// Description
+    pub help: String,
+    /// The function to call, if it's from a shared library. WASM builtins are called by name.
+    handler: Option<Handler>,
+}
+
+/// What a plugin was loaded from.
+#[derive(Debug)]
+pub enum Backend {
+    /// A shared library. Only kept so it outlives the handlers.
+    Native(#[allow(dead_code)] libloading::Library),
+    /// A sandboxed WASM module
+    Wasm(Box<wasm::WasmPlugin>),
+}
+
+/// A loaded plugin.
+#[derive(Debug)]
+pub struct Plugin {
+    /// Name of the plugin
+    pub name: String,
+    /// Where it was loaded from
+    pub path: PathBuf,
+    /// The builtins it provides
+    pub builtins: Vec<PluginBuiltin>,
+    /// Where the builtins live
+    pub backend: Backend,
+}
+
+/// Convert a C string from a plugin into a [String].
+fn from_c(s: *const c_char) -> String {
+    if s.is_null() {
+        return String::new();
+    }
+    unsafe { CStr::from_ptr(s) }.to_string_lossy().to_string()
+}
+
+/// The directory plugins are loaded from on startup.
+pub fn plugin_dir() -> PathBuf {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .unwrap_or(std::env::home_dir().unwrap_or_default().join(".config"))
+        .join("sesh")
+        .join("plugins")
+}
+
+/// Load a plugin from a path. Capabilities only apply to WASM plugins.
+pub fn load(path: &Path, capabilities: &[wasm::Capability]) -> Result<Plugin, String> {
+    if path.extension().is_some_and(|v| v == "wasm") {
+        let (plugin, builtins) = wasm::load(path, capabilities)?;
+        return Ok(Plugin {
+            name: path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+            path: path.to_path_buf(),
+            builtins: builtins
+                .into_iter()
+                .map(|(name, usage, help)| PluginBuiltin {
+                    name,
+                    usage,
+                    help,
+                    handler: None,
+                })
+                .collect(),
+            backend: Backend::Wasm(Box::new(plugin)),
+        });
+    }
+    let library = unsafe { libloading::Library::new(path) }.map_err(|e| e.to_string())?;
+    let info = unsafe {
+        let init = library
+            .get::<extern "C" fn() -> *const PluginInfo>(b"sesh_plugin_init\0")
+            .map_err(|e| e.to_string())?;
+        init()
+    };
+    if info.is_null() {
+        return Err("plugin refused to initialize".to_string());
+    }
+    let info = unsafe { &*info };
+    if info.abi_version != ABI_VERSION {
+        return Err(format!(
+            "plugin was built for ABI version {}, this is version {}",
+            info.abi_version, ABI_VERSION
+        ));
+    }
+    let raw_builtins = if info.builtins.is_null() {
+        &[][..]
+    } else {
+        unsafe { std::slice::from_raw_parts(info.builtins, info.builtin_count) }
+    };
+    Ok(Plugin {
+        name: from_c(info.name),
+        path: path.to_path_buf(),
+        builtins: raw_builtins
+            .iter()
+            .map(|v| PluginBuiltin {
+                name: from_c(v.name),
+                usage: from_c(v.usage),
+                help: from_c(v.help),
+                handler: Some(v.handler),
+            })
+            .collect(),
+        backend: Backend::Native(library),
+    })
+}
+
+/// Load every plugin in [plugin_dir].
+pub fn load_all(state: &mut super::State) {
+    let Ok(dir) = std::fs::read_dir(plugin_dir()) else {
+        return;
+    };
+    let mut paths = dir
+        .filter_map(|v| v.ok())
+        .map(|v| v.path())
+        .filter(|v| {
+            v.extension()
+                .is_some_and(|v| v == std::env::consts::DLL_EXTENSION || v == "wasm")
+        })
+        .collect::<Vec<PathBuf>>();
+    paths.sort();
+    for path in paths {
+        let capabilities = match std::fs::read_to_string(path.with_extension("allow")) {
+            Ok(list) => match wasm::Capability::parse_list(&list) {
+                Ok(capabilities) => capabilities,
+                Err(e) => {
+                    println!(
+                        "{}",
+                        tr!("sesh: loading plugin {} failed: {}", path.display(), e)
+                    );
+                    continue;
+                }
+            },
+            Err(_) => Vec::new(),
+        };
+        match load(&path, &capabilities) {
+            Ok(plugin) => state.plugins.push(Arc::new(plugin)),
+            Err(e) => println!(
+                "{}",
+                tr!("sesh: loading plugin {} failed: {}", path.display(), e)
+            ),
+        }
+    }
+}
+
+/// Find a builtin provided by a plugin.
+pub fn find(plugins: &[Arc<Plugin>], name: &str) -> Option<(Arc<Plugin>, usize)> {
+    for plugin in plugins {
+        if let Some(i) = plugin.builtins.iter().position(|v| v.name == name) {
+            return Some((plugin.clone(), i));
+        }
+    }
+    None
+}
+
+/// Context handed to plugins through [Host].
+struct HostCtx<'a> {
+    /// The state
+    state: &'a mut super::State,
+    /// Keeps the last string returned by `get_var` alive
+    last: CString,
+}
+
+/// `get_var` of [Host].
+extern "C" fn host_get_var(ctx: *mut c_void, name: *const c_char) -> *const c_char {
+    let ctx = unsafe { &mut *(ctx as *mut HostCtx) };
+    let name = from_c(name);
+    match super::get_var(ctx.state, &name) {
+        Some(value) => {
+            ctx.last = CString::new(value).unwrap_or_default();
+            ctx.last.as_ptr()
+        }
+        None => std::ptr::null(),
+    }
+}
+
+/// `set_var` of [Host].
+extern "C" fn host_set_var(ctx: *mut c_void, name: *const c_char, value: *const c_char) {
+    let ctx = unsafe { &mut *(ctx as *mut HostCtx) };
+    let name = from_c(name);
+    if super::builtins::SPECIAL_VARS.contains(&name.as_str()) {
+        return;
+    }
+    ctx.state.shell_env.push(super::ShellVar {
+        name,
+        value: from_c(value),
+        exported: false,
+    });
+}
+
+impl Plugin {
+    /// Call one of the builtins of this plugin.
+    pub fn call(&self, builtin: usize, args: Vec<String>, state: &mut super::State) -> i32 {
+        let Some(handler) = self.builtins[builtin].handler else {
+            let Backend::Wasm(plugin) = &self.backend else {
+                return 1;
+            };
+            return match plugin.call(&self.builtins[builtin].name, args, state) {
+                Ok(status) => status,
+                Err(e) => {
+                    println!(
+                        "{}",
+                        tr!("sesh: {}: plugin error: {}", self.builtins[builtin].name, e)
+                    );
+                    1
+                }
+            };
+        };
+        let args = args
+            .into_iter()
+            .map(|v| CString::new(v).unwrap_or_default())
+            .collect::<Vec<CString>>();
+        let argv = args
+            .iter()
+            .map(|v| v.as_ptr())
+            .collect::<Vec<*const c_char>>();
+        let mut ctx = HostCtx {
+            state,
+            last: CString::default(),
+        };
+        let host = Host {
+            abi_version: ABI_VERSION,
+            ctx: &mut ctx as *mut HostCtx as *mut c_void,
+            get_var: host_get_var,
+            set_var: host_set_var,
+        };
+        let status = handler(&host, argv.len() as c_int, argv.as_ptr());
+        // plugins writing through C stdio would otherwise show up whenever the buffer fills
+        unsafe { libc::fflush(std::ptr::null_mut()) };
+        status
+    }
+}
@@ -0,0 +1,397 @@
+//! WASM plugins
+//!
+//! WASM plugins run in an interpreter and can only reach the parts of the shell they were granted
+//! capabilities for. A module exports:
+//!
+//! - `memory`
+//! - `sesh_alloc(len: i32) -> i32`, used by the shell to hand over arguments
+//! - `sesh_manifest() -> i64`, a pointer (high 32 bits) and length (low 32 bits) of the manifest:
+//!   one builtin per line, as `name\tusage\thelp`
+//! - `sesh_builtin_<name>(args: i32, args_len: i32) -> i32` for every builtin in the manifest.
+//!   Arguments are NUL-separated and include the name of the builtin.
+//!
+//! It may import these from the `sesh` module. Getters return the full length of the value, so
+//! callers can retry with a bigger buffer, or -1 if there's no such value.
+//!
+//! - `write(fd, ptr, len) -> i32` to stdout(1) or stderr(2), always allowed
+//! - `get_var(name, name_len, buf, buf_len) -> i32` and `set_var(name, name_len, value,
+//!   value_len) -> i32`, needing `vars`
+//! - `get_focus(buf, buf_len) -> i32` and `set_focus(ptr, len) -> i32` with the focus as JSON,
+//!   needing `focus`
+//! - `read_file(path, path_len, buf, buf_len) -> i32` and `write_file(path, path_len, data,
+//!   data_len) -> i32`, needing `fs`
+
+use std::{io::Write, path::Path, ptr::NonNull, sync::Mutex};
+
+use wasmi::{Caller, Config, Engine, Extern, Instance, Linker, Memory, Module, Store};
+
+/// How much fuel a single builtin call gets before it's stopped.
+const FUEL: u64 = 1_000_000_000;
+
+/// Something a WASM plugin may be allowed to do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capability {
+    /// Read and set shell variables
+    Vars,
+    /// Read and set the focus
+    Focus,
+    /// Read and write files
+    Fs,
+}
+
+impl Capability {
+    /// Parse a capability from its name.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "vars" => Some(Self::Vars),
+            "focus" => Some(Self::Focus),
+            "fs" => Some(Self::Fs),
+            _ => None,
+        }
+    }
+
+    /// Parse a comma or whitespace separated list of capabilities.
+    pub fn parse_list(list: &str) -> Result<Vec<Self>, String> {
+        list.split(|ch: char| ch == ',' || ch.is_whitespace())
+            .filter(|v| !v.is_empty())
+            .map(|v| Self::parse(v).ok_or(format!("unknown capability `{}`", v)))
+            .collect()
+    }
+
+    /// Name of the capability.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Vars => "vars",
+            Self::Focus => "focus",
+            Self::Fs => "fs",
+        }
+    }
+
+    /// The capability an import needs, or `None` if it is always allowed. Unknown imports are an
+    /// error.
+    fn of_import(name: &str) -> Result<Option<Self>, ()> {
+        match name {
+            "write" => Ok(None),
+            "get_var" | "set_var" => Ok(Some(Self::Vars)),
+            "get_focus" | "set_focus" => Ok(Some(Self::Focus)),
+            "read_file" | "write_file" => Ok(Some(Self::Fs)),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The `(name, usage, help)` of each builtin in a manifest.
+type Manifest = Vec<(String, String, String)>;
+
+/// Data the host functions get.
+struct Ctx {
+    /// The state during a call, unset otherwise
+    state: Option<NonNull<crate::State>>,
+}
+
+/// A loaded WASM module.
+pub struct WasmPlugin {
+    /// The store. Kept so memory persists between calls.
+    store: Mutex<Store<Ctx>>,
+    /// The instance
+    instance: Instance,
+    /// What it was allowed to do
+    pub capabilities: Vec<Capability>,
+}
+
+// the state pointer is only set while the store is locked for a call
+unsafe impl Sync for WasmPlugin {}
+unsafe impl Send for WasmPlugin {}
+
+impl std::fmt::Debug for WasmPlugin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WasmPlugin")
+            .field("capabilities", &self.capabilities)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The exported memory of the caller.
+fn memory(caller: &Caller<Ctx>) -> Option<Memory> {
+    caller.get_export("memory").and_then(Extern::into_memory)
+}
+
+/// Read bytes out of the memory of the caller.
+fn read_bytes(caller: &Caller<Ctx>, ptr: i32, len: i32) -> Option<Vec<u8>> {
+    let mut buf = vec![0u8; usize::try_from(len).ok()?];
+    memory(caller)?
+        .read(caller, usize::try_from(ptr).ok()?, &mut buf)
+        .ok()?;
+    Some(buf)
+}
+
+/// Read a string out of the memory of the caller.
+fn read_str(caller: &Caller<Ctx>, ptr: i32, len: i32) -> Option<String> {
+    read_bytes(caller, ptr, len).map(|v| String::from_utf8_lossy(&v).to_string())
+}
+
+/// Copy as much of `data` as fits into a buffer of the caller and return the full length.
+fn write_out(caller: &mut Caller<Ctx>, buf: i32, buf_len: i32, data: &[u8]) -> i32 {
+    let Some(memory) = memory(caller) else {
+        return -1;
+    };
+    let len = data.len().min(usize::try_from(buf_len).unwrap_or(0));
+    if memory
+        .write(
+            &mut *caller,
+            usize::try_from(buf).unwrap_or(usize::MAX),
+            &data[..len],
+        )
+        .is_err()
+    {
+        return -1;
+    }
+    data.len() as i32
+}
+
+/// The state of the call in progress, if there is one. There isn't while the module loads.
+fn state<'a>(caller: &Caller<Ctx>) -> Option<&'a mut crate::State> {
+    caller
+        .data()
+        .state
+        .map(|state| unsafe { &mut *state.as_ptr() })
+}
+
+/// Define every host function.
+fn define(linker: &mut Linker<Ctx>) -> Result<(), wasmi::errors::LinkerError> {
+    linker.func_wrap(
+        "sesh",
+        "write",
+        |caller: Caller<Ctx>, fd: i32, ptr: i32, len: i32| -> i32 {
+            let Some(data) = read_bytes(&caller, ptr, len) else {
+                return -1;
+            };
+            let written = match fd {
+                1 => std::io::stdout().write_all(&data),
+                2 => std::io::stderr().write_all(&data),
+                _ => return -1,
+            };
+            if written.is_err() { -1 } else { len }
+        },
+    )?;
+    linker.func_wrap(
+        "sesh",
+        "get_var",
+        |mut caller: Caller<Ctx>, name: i32, name_len: i32, buf: i32, buf_len: i32| -> i32 {
+            let Some(name) = read_str(&caller, name, name_len) else {
+                return -1;
+            };
+            let Some(state) = state(&caller) else {
+                return -1;
+            };
+            match crate::get_var(state, &name) {
+                Some(value) => write_out(&mut caller, buf, buf_len, value.as_bytes()),
+                None => -1,
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "sesh",
+        "set_var",
+        |caller: Caller<Ctx>, name: i32, name_len: i32, value: i32, value_len: i32| -> i32 {
+            let (Some(name), Some(value)) = (
+                read_str(&caller, name, name_len),
+                read_str(&caller, value, value_len),
+            ) else {
+                return -1;
+            };
+            if crate::builtins::SPECIAL_VARS.contains(&name.as_str()) {
+                return -1;
+            }
+            let Some(state) = state(&caller) else {
+                return -1;
+            };
+            state.shell_env.push(crate::ShellVar {
+                name,
+                value,
+                exported: false,
+            });
+            0
+        },
+    )?;
+    linker.func_wrap(
+        "sesh",
+        "get_focus",
+        |mut caller: Caller<Ctx>, buf: i32, buf_len: i32| -> i32 {
+            let Some(state) = state(&caller) else {
+                return -1;
+            };
+            let focus = state.focus.to_json().to_string();
+            write_out(&mut caller, buf, buf_len, focus.as_bytes())
+        },
+    )?;
+    linker.func_wrap(
+        "sesh",
+        "set_focus",
+        |caller: Caller<Ctx>, ptr: i32, len: i32| -> i32 {
+            let Some(json) = read_str(&caller, ptr, len) else {
+                return -1;
+            };
+            let Ok(json) = serde_json::from_str::<serde_json::Value>(&json) else {
+                return -1;
+            };
+            let Some(state) = state(&caller) else {
+                return -1;
+            };
+            state.focus = crate::Focus::from_json(&json);
+            0
+        },
+    )?;
+    linker.func_wrap(
+        "sesh",
+        "read_file",
+        |mut caller: Caller<Ctx>, path: i32, path_len: i32, buf: i32, buf_len: i32| -> i32 {
+            let Some(path) = read_str(&caller, path, path_len) else {
+                return -1;
+            };
+            let Some(state) = state(&caller) else {
+                return -1;
+            };
+            let path = state.working_dir.join(path);
+            match std::fs::read(path) {
+                Ok(data) => write_out(&mut caller, buf, buf_len, &data),
+                Err(_) => -1,
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "sesh",
+        "write_file",
+        |caller: Caller<Ctx>, path: i32, path_len: i32, data: i32, data_len: i32| -> i32 {
+            let (Some(path), Some(data)) = (
+                read_str(&caller, path, path_len),
+                read_bytes(&caller, data, data_len),
+            ) else {
+                return -1;
+            };
+            let Some(state) = state(&caller) else {
+                return -1;
+            };
+            let path = state.working_dir.join(path);
+            if std::fs::write(path, data).is_err() {
+                -1
+            } else {
+                0
+            }
+        },
+    )?;
+    Ok(())
+}
+
+/// Load a WASM plugin, returning it along with the builtins it provides.
+pub fn load(path: &Path, capabilities: &[Capability]) -> Result<(WasmPlugin, Manifest), String> {
+    let wasm = std::fs::read(path).map_err(|e| e.to_string())?;
+    let mut config = Config::default();
+    config.consume_fuel(true);
+    let engine = Engine::new(&config);
+    let module = Module::new(&engine, &wasm).map_err(|e| e.to_string())?;
+
+    for import in module.imports() {
+        if import.module() != "sesh" {
+            return Err(format!(
+                "plugin imports {}::{}, which sesh doesn't provide",
+                import.module(),
+                import.name()
+            ));
+        }
+        match Capability::of_import(import.name()) {
+            Ok(None) => (),
+            Ok(Some(needed)) => {
+                if !capabilities.contains(&needed) {
+                    return Err(format!(
+                        "plugin needs the `{}` capability, which was not granted",
+                        needed.name()
+                    ));
+                }
+            }
+            Err(()) => {
+                return Err(format!(
+                    "plugin imports sesh::{}, which doesn't exist",
+                    import.name()
+                ));
+            }
+        }
+    }
+
+    let mut store = Store::new(&engine, Ctx { state: None });
+    store.set_fuel(FUEL).map_err(|e| e.to_string())?;
+    let mut linker = Linker::new(&engine);
+    define(&mut linker).map_err(|e| e.to_string())?;
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .and_then(|v| v.start(&mut store))
+        .map_err(|e| e.to_string())?;
+
+    let manifest = instance
+        .get_typed_func::<(), i64>(&store, "sesh_manifest")
+        .map_err(|e| format!("sesh_manifest: {}", e))?
+        .call(&mut store, ())
+        .map_err(|e| format!("sesh_manifest: {}", e))?;
+    let memory = instance
+        .get_memory(&store, "memory")
+        .ok_or("plugin doesn't export its memory")?;
+    let mut buf = vec![0u8; (manifest & 0xFFFF_FFFF) as usize];
+    memory
+        .read(&store, (manifest >> 32) as usize, &mut buf)
+        .map_err(|e| format!("sesh_manifest: {}", e))?;
+    let builtins = String::from_utf8_lossy(&buf)
+        .lines()
+        .filter(|v| !v.trim().is_empty())
+        .map(|v| {
+            let mut parts = v.splitn(3, '\t');
+            (
+                parts.next().unwrap_or_default().to_string(),
+                parts.next().unwrap_or_default().to_string(),
+                parts.next().unwrap_or_default().to_string(),
+            )
+        })
+        .collect();
+
+    Ok((
+        WasmPlugin {
+            store: Mutex::new(store),
+            instance,
+            capabilities: capabilities.to_vec(),
+        },
+        builtins,
+    ))
+}
+
+impl WasmPlugin {
+    /// Call a builtin of this plugin.
+    pub fn call(
+        &self,
+        name: &str,
+        args: Vec<String>,
+        state: &mut crate::State,
+    ) -> Result<i32, String> {
+        let mut store = self.store.lock().unwrap();
+        store.set_fuel(FUEL).map_err(|e| e.to_string())?;
+        let args = args.join("\0").into_bytes();
+        let ptr = self
+            .instance
+            .get_typed_func::<i32, i32>(&*store, "sesh_alloc")
+            .map_err(|e| format!("sesh_alloc: {}", e))?
+            .call(&mut *store, args.len() as i32)
+            .map_err(|e| format!("sesh_alloc: {}", e))?;
+        self.instance
+            .get_memory(&*store, "memory")
+            .ok_or("plugin doesn't export its memory")?
+            .write(&mut *store, ptr as usize, &args)
+            .map_err(|e| e.to_string())?;
+        let builtin = self
+            .instance
+            .get_typed_func::<(i32, i32), i32>(&*store, &format!("sesh_builtin_{}", name))
+            .map_err(|e| e.to_string())?;
+
+        store.data_mut().state = Some(NonNull::from(state));
+        let status = builtin.call(&mut *store, (ptr, args.len() as i32));
+        store.data_mut().state = None;
+        status.map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,117 @@
+//! Listing processes
+//!
+//! `procs` reads what `ps aux` shows straight out of /proc, into the focus as one list per
+//! process, so scripts don't have to pick apart its columns. Like ps, the CPU usage is averaged
+//! over the life of the process. It's only available on Linux.
+
+/// A running process.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Process {
+    /// Its process ID
+    pub pid: u32,
+    /// Who it runs as
+    pub user: String,
+    /// The percentage of a CPU it has used since it started
+    pub cpu: f64,
+    /// The percentage of memory it has resident
+    pub mem: f64,
+    /// Its command line, or its name in brackets if it has none, like a kernel thread
+    pub command: String,
+}
+
+/// List the processes running.
+#[cfg(target_os = "linux")]
+pub fn list() -> std::io::Result<Vec<Process>> {
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as f64;
+    let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as f64;
+    let uptime = std::fs::read_to_string("/proc/uptime")?
+        .split_whitespace()
+        .next()
+        .and_then(|v| v.parse::<f64>().ok())
+        .unwrap_or_default();
+    let memory = std::fs::read_to_string("/proc/meminfo")?
+        .lines()
+        .find_map(|v| v.strip_prefix("MemTotal:"))
+        .and_then(|v| v.trim().trim_end_matches("kB").trim().parse::<f64>().ok())
+        .unwrap_or_default()
+        * 1024.0;
+
+    let mut out = Vec::new();
+    for entry in std::fs::read_dir("/proc")? {
+        let Some(pid) = entry?
+            .file_name()
+            .to_str()
+            .and_then(|v| v.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        // it may have exited since the directory was read
+        if let Some(process) = read(pid, ticks, page, uptime, memory) {
+            out.push(process);
+        }
+    }
+    out.sort_by_key(|v| v.pid);
+    Ok(out)
+}
+
+/// Read a process out of /proc.
+#[cfg(target_os = "linux")]
+fn read(pid: u32, ticks: f64, page: f64, uptime: f64, memory: f64) -> Option<Process> {
+    let dir = std::path::Path::new("/proc").join(pid.to_string());
+    let stat = std::fs::read_to_string(dir.join("stat")).ok()?;
+    // the name is in brackets and can have spaces and brackets of its own
+    let (name, fields) = stat.split_once(" (")?.1.rsplit_once(") ")?;
+    let fields = fields.split_whitespace().collect::<Vec<&str>>();
+    let field = |i: usize| fields.get(i).and_then(|v| v.parse::<f64>().ok());
+    let (time, start, rss) = (field(11)? + field(12)?, field(19)?, field(21)?);
+    let elapsed = uptime - start / ticks;
+    let cpu = if elapsed > 0.0 {
+        100.0 * time / ticks / elapsed
+    } else {
+        0.0
+    };
+    let mem = if memory > 0.0 {
+        100.0 * rss * page / memory
+    } else {
+        0.0
+    };
+
+    let uid = std::fs::read_to_string(dir.join("status"))
+        .ok()?
+        .lines()
+        .find_map(|v| v.strip_prefix("Uid:"))
+        .and_then(|v| v.split_whitespace().next())
+        .and_then(|v| v.parse::<u32>().ok())?;
+    let user = users::get_user_by_uid(uid)
+        .map(|v| v.name().to_string_lossy().to_string())
+        .unwrap_or(uid.to_string());
+
+    let cmdline = std::fs::read(dir.join("cmdline")).unwrap_or_default();
+    let command = cmdline
+        .split(|v| *v == 0)
+        .filter(|v| !v.is_empty())
+        .map(|v| String::from_utf8_lossy(v).to_string())
+        .collect::<Vec<String>>()
+        .join(" ");
+    let command = if command.is_empty() {
+        format!("[{}]", name)
+    } else {
+        command
+    };
+    Some(Process {
+        pid,
+        user,
+        cpu,
+        mem,
+        command,
+    })
+}
+
+/// List the processes running.
+#[cfg(not(target_os = "linux"))]
+pub fn list() -> std::io::Result<Vec<Process>> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        super::i18n::translate("listing processes isn't supported on this platform"),
+    ))
+}
@@ -0,0 +1,61 @@
+//! Startup profiling
+//!
+//! With `--profile-startup`, each phase of startup is timed, along with every statement of the
+//! phases that evaluate them, and a breakdown is printed to stderr before the first prompt.
+
+use std::time::{Duration, Instant};
+
+use super::i18n::tr;
+
+/// A timed phase of startup.
+struct Phase {
+    /// What happened
+    name: String,
+    /// How long it took
+    duration: Duration,
+    /// The statements evaluated during it and how long each took
+    statements: Vec<(String, Duration)>,
+}
+
+/// The times of startup phases.
+pub struct Profile {
+    /// When the shell started
+    start: Instant,
+    /// The phases so far
+    phases: Vec<Phase>,
+}
+
+impl Profile {
+    /// Start profiling.
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            phases: Vec::new(),
+        }
+    }
+
+    /// Record a phase.
+    pub fn record(&mut self, name: &str, duration: Duration, statements: Vec<(String, Duration)>) {
+        self.phases.push(Phase {
+            name: name.to_string(),
+            duration,
+            statements,
+        });
+    }
+
+    /// Print the breakdown.
+    pub fn print(&self) {
+        /// Format a duration in milliseconds.
+        fn ms(duration: Duration) -> String {
+            format!("{:>9.3}ms", duration.as_secs_f64() * 1000.0)
+        }
+        eprintln!("{}", tr!("sesh: startup profile:"));
+        for phase in &self.phases {
+            eprintln!("{}  {}", ms(phase.duration), phase.name);
+            for (statement, duration) in &phase.statements {
+                eprintln!("  {}    {}", ms(*duration), statement);
+            }
+        }
+        eprintln!("{}  {}", ms(self.start.elapsed()), tr!("total"));
+    }
+}
@@ -0,0 +1,127 @@
+//! Per-directory profiles
+//!
+//! A project can keep aliases and variables (like PROMPT1) for itself in `.sesh/config.sesh` at its
+//! root. Before each prompt, the nearest one above the working directory is looked for; entering a
+//! project runs its config and leaving it undoes what the config changed. Since anyone can put a
+//! config in a directory, it's only run once it has been trusted with the `trust` builtin, and
+//! changing it takes that back until it's trusted again.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+use super::i18n::tr;
+
+/// Where a project's config is, relative to its root.
+pub const CONFIG: &str = ".sesh/config.sesh";
+
+/// The project the working directory is in.
+#[derive(Clone, Default)]
+pub struct Project {
+    /// The root of the project, whether its config is trusted or not
+    pub root: Option<PathBuf>,
+    /// What its config changed, to undo when leaving it
+    pub changes: super::RcChanges,
+}
+
+/// The file the trusted configs are listed in, one `fingerprint path` per line.
+fn trust_file() -> PathBuf {
+    super::session::state_dir().join("trusted")
+}
+
+/// A fingerprint of a config, so changing it takes back the trust (SHA-256, so a config can't be
+/// made to match one that was trusted).
+fn fingerprint(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|v| format!("{:02x}", v))
+        .collect()
+}
+
+/// The nearest directory at or above `dir` with a config.
+pub fn find_root(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .find(|v| v.join(CONFIG).is_file())
+        .map(|v| v.canonicalize().unwrap_or(v.to_path_buf()))
+}
+
+/// The trusted configs, as fingerprints and roots.
+fn read_trusted() -> Vec<(String, PathBuf)> {
+    std::fs::read_to_string(trust_file())
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|v| v.split_once(' '))
+        .map(|(hash, root)| (hash.to_string(), PathBuf::from(root)))
+        .collect()
+}
+
+/// Write the trusted configs.
+fn write_trusted(trusted: &[(String, PathBuf)]) -> io::Result<()> {
+    std::fs::create_dir_all(super::session::state_dir())?;
+    let text = trusted
+        .iter()
+        .map(|(hash, root)| format!("{} {}\n", hash, root.display()))
+        .collect::<String>();
+    std::fs::write(trust_file(), text)
+}
+
+/// The roots of the trusted projects.
+pub fn trusted() -> Vec<PathBuf> {
+    read_trusted().into_iter().map(|v| v.1).collect()
+}
+
+/// Trust the config of a project as it is now.
+pub fn trust(root: &Path) -> io::Result<()> {
+    let hash = fingerprint(&std::fs::read(root.join(CONFIG))?);
+    let mut trusted = read_trusted();
+    trusted.retain(|v| v.1 != root);
+    trusted.push((hash, root.to_path_buf()));
+    write_trusted(&trusted)
+}
+
+/// Stop trusting the config of a project. Returns whether it was trusted.
+pub fn revoke(root: &Path) -> io::Result<bool> {
+    let mut trusted = read_trusted();
+    let len = trusted.len();
+    trusted.retain(|v| v.1 != root);
+    write_trusted(&trusted)?;
+    Ok(trusted.len() != len)
+}
+
+/// Undo what the config of the current project changed and forget it, so it's looked at again
+/// before the next prompt.
+pub fn leave(state: &mut super::State) {
+    let project = std::mem::take(&mut state.project);
+    super::undo_changes(project.changes, state);
+}
+
+/// Enter or leave projects according to the working directory.
+pub fn update(state: &mut super::State) {
+    let root = find_root(&state.working_dir);
+    if state.restricted || root == state.project.root {
+        return;
+    }
+    leave(state);
+    state.project.root = root.clone();
+    let Some(root) = root else {
+        return;
+    };
+    let Ok(config) = std::fs::read(root.join(CONFIG)) else {
+        return;
+    };
+    let hash = fingerprint(&config);
+    if !read_trusted().iter().any(|v| v.0 == hash && v.1 == root) {
+        println!(
+            "{}\r",
+            tr!(
+                "sesh: {} is not trusted, run trust to use it",
+                root.join(CONFIG).display()
+            )
+        );
+        return;
+    }
+    state.project.changes = super::eval_recording(&super::decode_script(config), state);
+}
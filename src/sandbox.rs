@@ -0,0 +1,210 @@
+//! Sandboxed execution
+//!
+//! Runs a program in fresh user, mount, IPC and network namespaces with every mount remounted
+//! read-only, so it can look around but can't change anything or talk to the network. Unix
+//! sockets can still be connected to on a read-only mount, so the directories sessions and the
+//! desktop keep theirs in are covered with empty ones and the variables pointing at them are taken
+//! out of its environment. Otherwise it could ask the shell to run something through the control
+//! socket. Linux only.
+
+#[cfg(target_os = "linux")]
+use std::{ffi::CString, os::unix::process::CommandExt, path::PathBuf};
+
+/// What the sandboxed program is allowed to do.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Keep network access
+    pub net: bool,
+    /// Paths that stay writable
+    pub writable: Vec<std::path::PathBuf>,
+}
+
+/// Variables that point at sockets outside the sandbox.
+#[cfg(target_os = "linux")]
+const SOCKET_VARS: [&str; 7] = [
+    "SESH_SOCKET",
+    "SSH_AUTH_SOCK",
+    "GPG_AGENT_INFO",
+    "DBUS_SESSION_BUS_ADDRESS",
+    "WAYLAND_DISPLAY",
+    "PULSE_SERVER",
+    "TMUX",
+];
+
+/// Mount points of the current mount namespace.
+#[cfg(target_os = "linux")]
+fn mount_points() -> std::io::Result<Vec<PathBuf>> {
+    Ok(std::fs::read_to_string("/proc/self/mountinfo")?
+        .lines()
+        .filter_map(|v| v.split(' ').nth(4))
+        // mountinfo escapes spaces, tabs, newlines and backslashes in octal
+        .map(|v| {
+            PathBuf::from(
+                v.replace("\\040", " ")
+                    .replace("\\011", "\t")
+                    .replace("\\012", "\n")
+                    .replace("\\134", "\\"),
+            )
+        })
+        .collect())
+}
+
+/// Turn a path into a C string.
+#[cfg(target_os = "linux")]
+fn c_path(path: &std::path::Path) -> std::io::Result<CString> {
+    use std::os::unix::ffi::OsStrExt;
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidInput))
+}
+
+/// Return the last OS error if `ret` is negative.
+#[cfg(target_os = "linux")]
+fn check(ret: libc::c_int) -> std::io::Result<()> {
+    if ret < 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Write a whole buffer to a file, using only syscalls that are fine to make after forking.
+#[cfg(target_os = "linux")]
+fn write_file(path: &CString, data: &[u8]) -> std::io::Result<()> {
+    unsafe {
+        let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+        check(fd)?;
+        let written = libc::write(fd, data.as_ptr() as *const libc::c_void, data.len());
+        libc::close(fd);
+        if written != data.len() as isize {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Set a command up to run in the sandbox.
+#[cfg(target_os = "linux")]
+pub fn confine(command: &mut std::process::Command, options: &Options) -> std::io::Result<()> {
+    let writable = options
+        .writable
+        .iter()
+        .map(|v| v.canonicalize())
+        .collect::<std::io::Result<Vec<PathBuf>>>()?;
+    // where sockets are kept, leaving out ones inside others, which are covered along with them
+    let mut hidden = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .into_iter()
+        .chain([crate::ctl::socket_dir()])
+        .filter_map(|v| v.canonicalize().ok())
+        .collect::<Vec<PathBuf>>();
+    hidden.dedup();
+    let outer = hidden.clone();
+    hidden.retain(|v| !outer.iter().any(|outer| outer != v && v.starts_with(outer)));
+    // flags like nosuid are locked in a user namespace, so they have to be kept when remounting
+    let read_only = mount_points()?
+        .into_iter()
+        .filter(|v| !writable.contains(v))
+        .map(|v| {
+            // what's under a directory that gets covered can't be reached anyway
+            let covered = hidden.iter().any(|dir| v.starts_with(dir));
+            let path = c_path(&v)?;
+            let mut stat = unsafe { std::mem::zeroed::<libc::statvfs>() };
+            let mut flags = libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY;
+            if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } == 0 {
+                for (st, ms) in [
+                    (libc::ST_NOSUID, libc::MS_NOSUID),
+                    (libc::ST_NODEV, libc::MS_NODEV),
+                    (libc::ST_NOEXEC, libc::MS_NOEXEC),
+                    (libc::ST_NOATIME, libc::MS_NOATIME),
+                    (libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+                    (libc::ST_RELATIME, libc::MS_RELATIME),
+                ] {
+                    if stat.f_flag & st != 0 {
+                        flags |= ms;
+                    }
+                }
+            }
+            Ok((path, flags, covered))
+        })
+        .collect::<std::io::Result<Vec<(CString, libc::c_ulong, bool)>>>()?;
+    let writable = writable
+        .iter()
+        .map(|v| c_path(v))
+        .collect::<std::io::Result<Vec<CString>>>()?;
+    let hidden = hidden
+        .iter()
+        .map(|v| c_path(v))
+        .collect::<std::io::Result<Vec<CString>>>()?;
+    for name in SOCKET_VARS {
+        command.env_remove(name);
+    }
+    let tmpfs = CString::new("tmpfs").unwrap();
+    let uid_map = format!("{0} {0} 1", unsafe { libc::getuid() }).into_bytes();
+    let gid_map = format!("{0} {0} 1", unsafe { libc::getgid() }).into_bytes();
+    let uid_map_path = CString::new("/proc/self/uid_map").unwrap();
+    let gid_map_path = CString::new("/proc/self/gid_map").unwrap();
+    let setgroups_path = CString::new("/proc/self/setgroups").unwrap();
+    let root = CString::new("/").unwrap();
+    let mut flags = libc::CLONE_NEWUSER | libc::CLONE_NEWNS | libc::CLONE_NEWIPC;
+    if !options.net {
+        flags |= libc::CLONE_NEWNET;
+    }
+
+    unsafe {
+        command.pre_exec(move || {
+            check(libc::unshare(flags))?;
+            write_file(&setgroups_path, b"deny")?;
+            write_file(&uid_map_path, &uid_map)?;
+            write_file(&gid_map_path, &gid_map)?;
+            check(libc::mount(
+                std::ptr::null(),
+                root.as_ptr(),
+                std::ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                std::ptr::null(),
+            ))?;
+            for path in &writable {
+                check(libc::mount(
+                    path.as_ptr(),
+                    path.as_ptr(),
+                    std::ptr::null(),
+                    libc::MS_BIND | libc::MS_REC,
+                    std::ptr::null(),
+                ))?;
+            }
+            for (path, flags, covered) in &read_only {
+                let ret = libc::mount(
+                    std::ptr::null(),
+                    path.as_ptr(),
+                    std::ptr::null(),
+                    *flags,
+                    std::ptr::null(),
+                );
+                // one left writable would defeat the point, so it doesn't run at all
+                if !covered {
+                    check(ret)?;
+                }
+            }
+            for path in &hidden {
+                check(libc::mount(
+                    tmpfs.as_ptr(),
+                    path.as_ptr(),
+                    tmpfs.as_ptr(),
+                    libc::MS_RDONLY | libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                    std::ptr::null(),
+                ))?;
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+/// Set a command up to run in the sandbox.
+#[cfg(not(target_os = "linux"))]
+pub fn confine(_: &mut std::process::Command, _: &Options) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "sandboxing is only supported on Linux",
+    ))
+}
@@ -0,0 +1,147 @@
+//! Scheduling of programs
+//!
+//! `nice` and `cpus` set a program's priority, IO class and the CPUs it may run on between forking
+//! and running it, so nothing else has to be installed to do it. IO classes and CPUs can only be
+//! set on Linux.
+
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+
+/// How many CPUs there can be in a set, so higher ones can't be asked for.
+#[cfg(target_os = "linux")]
+const MAX_CPUS: usize = libc::CPU_SETSIZE as usize;
+
+/// How many CPUs there can be in a set, so higher ones can't be asked for.
+#[cfg(not(target_os = "linux"))]
+const MAX_CPUS: usize = 1024;
+
+/// How a program is to be scheduled.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    /// What to add to the niceness
+    pub nice: Option<i32>,
+    /// The IO class and the level in it
+    pub io: Option<(IoClass, u8)>,
+    /// The CPUs it may run on
+    pub cpus: Option<Vec<usize>>,
+}
+
+/// Which IO class a program is in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoClass {
+    /// First in line, even before best-effort
+    Realtime = 1,
+    /// The default
+    BestEffort = 2,
+    /// Only when no one else is using the disk
+    Idle = 3,
+}
+
+/// Parse an IO class like `idle`, `best-effort:7` or `realtime:0`. The level goes from 0, first,
+/// to 7, and is 4 if not given.
+pub fn parse_io(text: &str) -> Option<(IoClass, u8)> {
+    let (class, level) = match text.split_once(':') {
+        Some((class, level)) => (class, level.parse().ok().filter(|v| *v < 8)?),
+        None => (text, 4),
+    };
+    let class = match class {
+        "realtime" | "rt" => IoClass::Realtime,
+        "best-effort" | "be" => IoClass::BestEffort,
+        "idle" => IoClass::Idle,
+        _ => return None,
+    };
+    Some((class, level))
+}
+
+/// Parse a list of CPUs like `0-3,6`. CPUs past what a set can hold are refused before a range is
+/// filled in.
+pub fn parse_cpus(text: &str) -> Option<Vec<usize>> {
+    let mut out = Vec::new();
+    for part in text.split(',') {
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (start.parse::<usize>().ok()?, end.parse::<usize>().ok()?);
+                if start > end || end >= MAX_CPUS {
+                    return None;
+                }
+                out.extend(start..=end);
+            }
+            None => out.push(part.parse().ok().filter(|v| *v < MAX_CPUS)?),
+        }
+    }
+    out.sort();
+    out.dedup();
+    Some(out)
+}
+
+/// Set a command up to be scheduled as asked.
+#[cfg(unix)]
+pub fn apply(command: &mut std::process::Command, options: &Options) -> std::io::Result<()> {
+    #[cfg(not(target_os = "linux"))]
+    if options.io.is_some() || options.cpus.is_some() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            super::i18n::translate("IO classes and CPUs can only be set on Linux"),
+        ));
+    }
+    // the child starts out as nice as the shell
+    let nice = options
+        .nice
+        .map(|v| unsafe { (libc::getpriority(libc::PRIO_PROCESS, 0) + v).clamp(-20, 19) });
+    #[cfg(target_os = "linux")]
+    let cpus = match &options.cpus {
+        Some(cpus) => {
+            let mut set = unsafe { std::mem::zeroed::<libc::cpu_set_t>() };
+            for cpu in cpus {
+                if *cpu >= libc::CPU_SETSIZE as usize {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        super::i18n::tr!("there's no CPU {}", cpu),
+                    ));
+                }
+                unsafe { libc::CPU_SET(*cpu, &mut set) };
+            }
+            Some(set)
+        }
+        None => None,
+    };
+    #[cfg(target_os = "linux")]
+    let io = options
+        .io
+        .map(|(class, level)| ((class as libc::c_int) << 13) | level as libc::c_int);
+
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(nice) = nice
+                && libc::setpriority(libc::PRIO_PROCESS, 0, nice) < 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            #[cfg(target_os = "linux")]
+            {
+                const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+                if let Some(io) = io
+                    && libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, io) < 0
+                {
+                    return Err(std::io::Error::last_os_error());
+                }
+                if let Some(set) = &cpus
+                    && libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), set) < 0
+                {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+/// Set a command up to be scheduled as asked.
+#[cfg(not(unix))]
+pub fn apply(_: &mut std::process::Command, _: &Options) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        super::i18n::translate("scheduling programs isn't supported on this platform"),
+    ))
+}
@@ -0,0 +1,191 @@
+//! Saving and restoring sessions
+//!
+//! A session is the working directory, directory stack, variables, aliases, focus and bookmarks of
+//! a shell, saved as JSON in `~/.local/state/sesh/sessions/`. Interactive shells keep the session
+//! `last` up to date before every prompt, so `--resume` can pick up where a crashed terminal left
+//! off. Secret variables are never saved. Background jobs get their shell's session, secrets and
+//! all, through a pipe instead.
+
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+};
+
+/// The session interactive shells keep saving to.
+pub const LAST: &str = "last";
+
+/// Variables that describe the shell they were set in rather than the session.
+const SKIPPED_VARS: [&str; 2] = ["INTERACTIVE", "SESH_SOCKET"];
+
+/// The directory sesh keeps its state in.
+pub fn state_dir() -> PathBuf {
+    // tests keep away from the real sessions and trusted projects
+    #[cfg(test)]
+    return std::env::temp_dir().join(format!("sesh-test-{}-state", std::process::id()));
+    #[cfg(not(test))]
+    std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .unwrap_or(
+            std::env::home_dir()
+                .unwrap_or_default()
+                .join(".local")
+                .join("state"),
+        )
+        .join("sesh")
+}
+
+/// The directory sessions are saved in.
+pub fn session_dir() -> PathBuf {
+    state_dir().join("sessions")
+}
+
+/// The file a session is saved in.
+fn path(name: &str) -> io::Result<PathBuf> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            super::i18n::translate("invalid session name"),
+        ));
+    }
+    Ok(session_dir().join(name.to_string() + ".json"))
+}
+
+/// Save a session.
+pub fn save(name: &str, state: &super::State) -> io::Result<()> {
+    let path = path(name)?;
+    let session = to_json(state, false);
+    std::fs::create_dir_all(session_dir())?;
+    // write then rename so a crash can't leave half a session behind
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, session.to_string())?;
+    std::fs::rename(tmp, path)
+}
+
+/// A shell's session as JSON, with its secret variables only if `secrets` is true.
+fn to_json(state: &super::State, secrets: bool) -> serde_json::Value {
+    let vars = state
+        .shell_env
+        .iter()
+        .filter(|v| secrets || !state.secrets.contains(&v.name))
+        .filter(|v| !SKIPPED_VARS.contains(&v.name.as_str()))
+        // the shell restoring it has its own environment
+        .filter(|v| !super::is_inherited(v))
+        .map(|v| serde_json::json!({ "name": v.name, "value": v.value, "exported": v.exported }))
+        .collect::<Vec<_>>();
+    let aliases = state
+        .aliases
+        .iter()
+        .map(|v| serde_json::json!({ "name": v.name, "to": v.to, "desc": v.desc }))
+        .collect::<Vec<_>>();
+    let bookmarks = state
+        .bookmarks
+        .iter()
+        .map(|(name, statement)| serde_json::json!({ "name": name, "statement": statement }))
+        .collect::<Vec<_>>();
+    serde_json::json!({
+        "cwd": state.working_dir.to_string_lossy(),
+        "dir_stack": state.dir_stack.iter().map(|v| v.to_string_lossy()).collect::<Vec<_>>(),
+        "vars": vars,
+        "secrets": if secrets { state.secrets.clone() } else { Vec::new() },
+        "aliases": aliases,
+        "focus": state.focus.to_json(),
+        "bookmarks": bookmarks,
+    })
+}
+
+/// Restore a session.
+pub fn restore(name: &str, state: &mut super::State) -> io::Result<()> {
+    let session = serde_json::from_slice(&std::fs::read(path(name)?)?)?;
+    apply(&session, state);
+    Ok(())
+}
+
+/// Send a shell's session, secrets and all, through a pipe, for a shell started with the read end
+/// to [receive]. It's written from another thread, so it can't fill the pipe up.
+pub fn send(state: &super::State) -> io::Result<io::PipeReader> {
+    let (reader, mut writer) = io::pipe()?;
+    let session = to_json(state, true).to_string();
+    std::thread::spawn(move || writer.write_all(session.as_bytes()));
+    Ok(reader)
+}
+
+/// Restore the session [send] sent through the pipe the shell got as the file descriptor `fd`.
+pub fn receive(fd: i32, state: &mut super::State) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::{io::Read, os::fd::FromRawFd};
+
+        let mut session = Vec::new();
+        unsafe { std::fs::File::from_raw_fd(fd) }.read_to_end(&mut session)?;
+        apply(&serde_json::from_slice(&session)?, state);
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (fd, state);
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+/// Make a session the shell's, on top of what it has.
+fn apply(session: &serde_json::Value, state: &mut super::State) {
+    let str_of = |v: &serde_json::Value, key: &str| v[key].as_str().unwrap_or_default().to_string();
+
+    if let Some(cwd) = session["cwd"].as_str().map(PathBuf::from) {
+        let _ = super::change_dir(&cwd, false, state);
+    }
+    if let Some(dirs) = session["dir_stack"].as_array() {
+        state.dir_stack = dirs
+            .iter()
+            .filter_map(|v| v.as_str().map(PathBuf::from))
+            .collect();
+    }
+    for var in session["vars"].as_array().into_iter().flatten() {
+        let name = str_of(var, "name");
+        if name.is_empty() || SKIPPED_VARS.contains(&name.as_str()) {
+            continue;
+        }
+        state.shell_env.push(super::ShellVar {
+            name,
+            value: str_of(var, "value"),
+            exported: var["exported"].as_bool().unwrap_or(false),
+        });
+    }
+    super::garbage_collect_vars(state);
+    for name in session["secrets"].as_array().into_iter().flatten() {
+        let name = name.as_str().unwrap_or_default().to_string();
+        if !state.secrets.contains(&name) {
+            state.secrets.push(name);
+        }
+    }
+    for alias in session["aliases"].as_array().into_iter().flatten() {
+        let name = str_of(alias, "name");
+        state.aliases.retain(|v| v.name != name);
+        state.aliases.push(super::Alias {
+            name,
+            to: str_of(alias, "to"),
+            desc: alias["desc"].as_str().map(|v| v.to_string()),
+        });
+    }
+    for bookmark in session["bookmarks"].as_array().into_iter().flatten() {
+        let name = str_of(bookmark, "name");
+        state.bookmarks.retain(|v| v.0 != name);
+        state.bookmarks.push((name, str_of(bookmark, "statement")));
+    }
+    state.focus = super::Focus::from_json(&session["focus"]);
+}
+
+/// The names of the saved sessions.
+pub fn list() -> io::Result<Vec<String>> {
+    let mut names = std::fs::read_dir(session_dir())?
+        .filter_map(|v| v.ok())
+        .filter_map(|v| {
+            v.file_name()
+                .to_str()
+                .and_then(|v| v.strip_suffix(".json"))
+                .map(|v| v.to_string())
+        })
+        .collect::<Vec<String>>();
+    names.sort();
+    Ok(names)
+}
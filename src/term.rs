@@ -0,0 +1,287 @@
+//! Terminal backends
+//!
+//! The interactive loop works on raw bytes: whatever backend is in use turns keypresses into the
+//! same byte sequences a unix terminal would send. termion is used on unix and crossterm
+//! everywhere else. Set `SESH_TERM_BACKEND=crossterm` to use crossterm on unix too.
+
+use std::{
+    collections::VecDeque,
+    io::{Stdout, Write},
+    time::{Duration, Instant},
+};
+
+/// A terminal in raw mode.
+pub trait Terminal: Write {
+    /// Leave raw mode, e.g. while a program runs.
+    fn suspend_raw_mode(&self) -> std::io::Result<()>;
+    /// Go back into raw mode.
+    fn activate_raw_mode(&self) -> std::io::Result<()>;
+}
+
+/// Where keypresses come from.
+pub trait Input {
+    /// Wait up to `timeout` for input. Returns whether there's some to read.
+    fn poll(&mut self, timeout: Duration) -> std::io::Result<bool>;
+    /// Read a single byte of input, blocking until there is one.
+    fn read_byte(&mut self) -> std::io::Result<u8>;
+}
+
+/// Whether crossterm should be used.
+fn use_crossterm() -> bool {
+    !cfg!(unix) || std::env::var("SESH_TERM_BACKEND").is_ok_and(|v| v == "crossterm")
+}
+
+/// Put the terminal into raw mode.
+pub fn raw_terminal() -> std::io::Result<Box<dyn Terminal>> {
+    #[cfg(unix)]
+    if !use_crossterm() {
+        use termion::raw::IntoRawMode;
+        return Ok(Box::new(std::io::stdout().into_raw_mode()?));
+    }
+    crossterm::terminal::enable_raw_mode()?;
+    Ok(Box::new(CrosstermTerminal {
+        out: std::io::stdout(),
+    }))
+}
+
+/// How the terminal is put back when leaving raw mode: how it was when the shell started, or
+/// what `sane` made of it.
+#[cfg(unix)]
+static ORIGINAL: std::sync::Mutex<Option<libc::termios>> = std::sync::Mutex::new(None);
+
+/// Remember how the terminal is set up, before anything changes it.
+pub fn save_original() {
+    #[cfg(unix)]
+    unsafe {
+        let mut ios = std::mem::zeroed();
+        if libc::tcgetattr(0, &mut ios) == 0 {
+            ORIGINAL.lock().unwrap().get_or_insert(ios);
+        }
+    }
+}
+
+/// Set the terminal up how it was saved, if it was.
+fn apply_original() {
+    #[cfg(unix)]
+    if let Some(ios) = *ORIGINAL.lock().unwrap() {
+        unsafe {
+            libc::tcsetattr(0, libc::TCSANOW, &ios);
+        }
+    }
+}
+
+/// Show the cursor and reset colours, in case a program left them changed.
+fn reset_display() {
+    let mut stdout = std::io::stdout();
+    let _ = write!(stdout, "\x1b[0m\x1b[?25h");
+    let _ = stdout.flush();
+}
+
+/// Put the terminal back how it was when the shell started, e.g. after a panic.
+pub fn restore() {
+    let _ = crossterm::terminal::disable_raw_mode();
+    apply_original();
+    reset_display();
+}
+
+/// Put the terminal in cooked mode with echo and signals on, like `stty sane`, and keep it that
+/// way whenever the shell leaves raw mode.
+pub fn sane() -> std::io::Result<()> {
+    #[cfg(unix)]
+    unsafe {
+        let mut original = ORIGINAL.lock().unwrap();
+        let mut ios = match *original {
+            Some(ios) => ios,
+            None => {
+                let mut ios = std::mem::zeroed();
+                if libc::tcgetattr(0, &mut ios) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                ios
+            }
+        };
+        ios.c_iflag |= libc::BRKINT | libc::ICRNL | libc::IXON;
+        ios.c_iflag &= !(libc::INLCR | libc::IGNCR);
+        ios.c_oflag |= libc::OPOST | libc::ONLCR;
+        ios.c_lflag |=
+            libc::ISIG | libc::ICANON | libc::IEXTEN | libc::ECHO | libc::ECHOE | libc::ECHOK;
+        if libc::tcsetattr(0, libc::TCSANOW, &ios) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        *original = Some(ios);
+    }
+    #[cfg(not(unix))]
+    crossterm::terminal::disable_raw_mode()?;
+    reset_display();
+    Ok(())
+}
+
+/// Get the source of keypresses.
+pub fn input() -> Box<dyn Input> {
+    #[cfg(unix)]
+    if !use_crossterm() {
+        return Box::new(FdInput);
+    }
+    Box::new(CrosstermInput {
+        pending: VecDeque::new(),
+    })
+}
+
+#[cfg(unix)]
+impl Terminal for termion::raw::RawTerminal<Stdout> {
+    fn suspend_raw_mode(&self) -> std::io::Result<()> {
+        termion::raw::RawTerminal::suspend_raw_mode(self)?;
+        apply_original();
+        Ok(())
+    }
+
+    fn activate_raw_mode(&self) -> std::io::Result<()> {
+        termion::raw::RawTerminal::activate_raw_mode(self)
+    }
+}
+
+/// Reads stdin directly, unbuffered so polling the fd tells the truth.
+#[cfg(unix)]
+struct FdInput;
+
+#[cfg(unix)]
+impl Input for FdInput {
+    fn poll(&mut self, timeout: Duration) -> std::io::Result<bool> {
+        let mut fds = libc::pollfd {
+            fd: 0,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut fds, 1, timeout.as_millis() as libc::c_int) };
+        if ready < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() == std::io::ErrorKind::Interrupted {
+                return Ok(false);
+            }
+            return Err(error);
+        }
+        Ok(ready > 0)
+    }
+
+    fn read_byte(&mut self) -> std::io::Result<u8> {
+        let mut byte = 0u8;
+        loop {
+            let amount = unsafe { libc::read(0, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+            match amount {
+                1 => return Ok(byte),
+                0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+                _ => {
+                    let error = std::io::Error::last_os_error();
+                    if error.kind() != std::io::ErrorKind::Interrupted {
+                        return Err(error);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A terminal put into raw mode by crossterm.
+struct CrosstermTerminal {
+    /// Where output goes
+    out: Stdout,
+}
+
+impl Write for CrosstermTerminal {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+impl Terminal for CrosstermTerminal {
+    fn suspend_raw_mode(&self) -> std::io::Result<()> {
+        crossterm::terminal::disable_raw_mode()?;
+        apply_original();
+        Ok(())
+    }
+
+    fn activate_raw_mode(&self) -> std::io::Result<()> {
+        crossterm::terminal::enable_raw_mode()
+    }
+}
+
+impl Drop for CrosstermTerminal {
+    fn drop(&mut self) {
+        let _ = crossterm::terminal::disable_raw_mode();
+    }
+}
+
+/// Keypresses read through crossterm, translated into bytes.
+struct CrosstermInput {
+    /// Bytes translated but not read yet
+    pending: VecDeque<u8>,
+}
+
+impl CrosstermInput {
+    /// Translate an event into the bytes a unix terminal would have sent.
+    fn translate(&mut self, event: crossterm::event::Event) {
+        use crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers};
+        let key = match event {
+            Event::Key(key) if key.kind != KeyEventKind::Release => key,
+            Event::Paste(s) => {
+                self.pending.extend(s.as_bytes());
+                return;
+            }
+            _ => return,
+        };
+        let bytes: &[u8] = match key.code {
+            KeyCode::Char(ch) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.pending.push_back(ch.to_ascii_lowercase() as u8 & 0x1f);
+                return;
+            }
+            KeyCode::Char(ch) => {
+                if key.modifiers.contains(KeyModifiers::ALT) {
+                    self.pending.push_back(0x1b);
+                }
+                let mut buf = [0u8; 4];
+                self.pending.extend(ch.encode_utf8(&mut buf).as_bytes());
+                return;
+            }
+            KeyCode::Enter => b"\r",
+            KeyCode::Backspace => b"\x7f",
+            KeyCode::Tab => b"\t",
+            KeyCode::Esc => b"\x1b",
+            KeyCode::Up => b"\x1b[A",
+            KeyCode::Down => b"\x1b[B",
+            KeyCode::Right => b"\x1b[C",
+            KeyCode::Left => b"\x1b[D",
+            KeyCode::Home => b"\x1b[H",
+            KeyCode::End => b"\x1b[F",
+            KeyCode::Delete => b"\x1b[3~",
+            _ => return,
+        };
+        self.pending.extend(bytes);
+    }
+}
+
+impl Input for CrosstermInput {
+    fn poll(&mut self, timeout: Duration) -> std::io::Result<bool> {
+        let deadline = Instant::now() + timeout;
+        while self.pending.is_empty() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if !crossterm::event::poll(left)? {
+                return Ok(false);
+            }
+            self.translate(crossterm::event::read()?);
+        }
+        Ok(true)
+    }
+
+    fn read_byte(&mut self) -> std::io::Result<u8> {
+        loop {
+            if let Some(byte) = self.pending.pop_front() {
+                return Ok(byte);
+            }
+            self.translate(crossterm::event::read()?);
+        }
+    }
+}
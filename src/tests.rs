@@ -16,17 +16,2174 @@ pub fn bench_eval(bencher: &mut test::Bencher) {
             in_mode: false,
             entries: 0,
             history: vec![],
+            history_times: vec![],
+            plugins: vec![],
+            restricted: false,
+            guards: vec![],
+            secrets: vec![],
+            exit_hooks: vec![],
+            exit_warned: None,
+            precmd_hooks: vec![],
+            chpwd_hooks: vec![],
+            scope_exits: vec![],
+            precmd_timed_out: false,
+            deferred: vec![],
+            statement_times: None,
+            eval_stack: vec![],
+            group_indirects: vec![],
+            specials: BTreeMap::new(),
+            rc: RcChanges::default(),
+            timers: vec![],
+            dir_stack: vec![],
+            project: project::Project::default(),
+            pipe: None,
+            jobs: vec![],
+            loop_depth: 0,
+            loop_control: None,
+            subshell_depth: 0,
+            exit_request: None,
+            unset_vars: vec![],
+            bookmarks: vec![],
+            prefill: None,
         };
         state.shell_env.push(ShellVar {
             name: "PROMPT1".to_string(),
             value: "\x1b[32m$u@$h\x1b[39m \x1b[34m$P\x1b[39m> ".to_string(),
+            exported: false,
         });
         state.shell_env.push(ShellVar {
             name: "PROMPT2".to_string(),
             value: "> ".to_string(),
+            exported: false,
         });
         core::hint::black_box(eval("", &mut state));
         core::hint::black_box(eval("()", &mut state));
         core::hint::black_box(eval("echo", &mut state));
     });
 }
+
+/// A state with nothing in it.
+fn empty_state() -> State {
+    State {
+        shell_env: Vec::new(),
+        focus: Focus::Str(String::new()),
+        working_dir: PathBuf::from("/"),
+        aliases: Vec::new(),
+        raw_term: None,
+        in_mode: false,
+        entries: 0,
+        history: vec![],
+        history_times: vec![],
+        plugins: vec![],
+        restricted: false,
+        guards: vec![],
+        secrets: vec![],
+        exit_hooks: vec![],
+        exit_warned: None,
+        precmd_hooks: vec![],
+        chpwd_hooks: vec![],
+        scope_exits: vec![],
+        precmd_timed_out: false,
+        deferred: vec![],
+        statement_times: None,
+        eval_stack: vec![],
+        group_indirects: vec![],
+        specials: BTreeMap::new(),
+        rc: RcChanges::default(),
+        timers: vec![],
+        dir_stack: vec![],
+        project: project::Project::default(),
+        pipe: None,
+        jobs: vec![],
+        loop_depth: 0,
+        loop_control: None,
+        subshell_depth: 0,
+        exit_request: None,
+        unset_vars: vec![],
+        bookmarks: vec![],
+        prefill: None,
+    }
+}
+
+/// Run a builtin with some input. Returns its status and what it wrote to stdout and stderr.
+fn run_builtin(statement: &str, input: &str, state: &mut State) -> (i32, String, String) {
+    let args = parser::command(statement)
+        .unwrap()
+        .words
+        .into_iter()
+        .map(|v| v.text)
+        .collect::<Vec<String>>();
+    let builtin = builtins::BUILTINS.iter().find(|v| v.0 == args[0]).unwrap();
+    let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+    let mut io = builtins::Io {
+        stdin: &mut input.as_bytes(),
+        stdout: &mut stdout,
+        stderr: &mut stderr,
+        terminal: false,
+    };
+    let status =
+        builtin.1(args.clone(), statement.to_string(), &mut io, state).unwrap_or_else(|e| {
+            builtins::report(&args[0], &e, &mut io);
+            e.status()
+        });
+    (
+        status,
+        String::from_utf8(stdout).unwrap(),
+        String::from_utf8(stderr).unwrap(),
+    )
+}
+
+#[test]
+fn builtin_writes_to_its_stdout() {
+    let mut state = empty_state();
+    assert_eq!(
+        run_builtin("echo hello world", "", &mut state),
+        (0, "hello world\n".to_string(), String::new())
+    );
+}
+
+#[test]
+fn builtin_usage_error_shows_usage() {
+    let mut state = empty_state();
+    let (status, stdout, stderr) = run_builtin("set", "", &mut state);
+    assert_eq!(status, 1);
+    assert!(stdout.is_empty());
+    assert_eq!(
+        stderr,
+        "sesh: set: at least one variable required\n\
+        sesh: set: usage: set [--secret] name=value [name=value ...]\n"
+    );
+}
+
+#[test]
+fn builtin_refuses_when_restricted() {
+    let mut state = empty_state();
+    state.restricted = true;
+    let (status, _, stderr) = run_builtin("cd /tmp", "", &mut state);
+    assert_eq!(status, 1);
+    assert_eq!(stderr, "sesh: cd: restricted: cannot change directory\n");
+    assert_eq!(state.working_dir, PathBuf::from("/"));
+    let (_, _, stderr) = run_builtin("set TMOUT=0", "", &mut state);
+    assert_eq!(stderr, "sesh: set: restricted: cannot change TMOUT\n");
+}
+
+#[test]
+fn builtin_reads_its_stdin() {
+    let mut state = empty_state();
+    let (status, stdout, _) = run_builtin("select v in a b ()", "5\n2\n", &mut state);
+    assert_eq!(status, 0);
+    assert_eq!(stdout, "1) a\n2) b\n#? 1) a\n2) b\n#? ");
+    assert!(
+        state
+            .shell_env
+            .iter()
+            .any(|v| v.name == "v" && v.value == "b")
+    );
+}
+
+#[test]
+fn control_requests_read_and_drive_the_session() {
+    let mut state = empty_state();
+    assert_eq!(ctl::respond("set NAME=sesh", &mut state)["ok"], true);
+    assert_eq!(ctl::respond("get NAME", &mut state)["value"], "sesh");
+    eval("set --secret TOKEN=hunter2", &mut state);
+    let reply = ctl::respond("get TOKEN", &mut state);
+    assert_eq!(reply["error"], "secret variable");
+    assert!(!reply.to_string().contains("hunter2"));
+    assert_eq!(
+        ctl::respond("set STATUS=0", &mut state)["error"],
+        "read-only variable"
+    );
+    assert_eq!(ctl::respond("run ! nop", &mut state)["value"], 1);
+    ctl::respond("alias ll ls -l", &mut state);
+    assert!(
+        state
+            .aliases
+            .iter()
+            .any(|v| v.name == "ll" && v.to == "ls -l")
+    );
+    ctl::respond("prefill make", &mut state);
+    assert_eq!(state.prefill.as_deref(), Some("make"));
+    assert_eq!(
+        ctl::respond("frobnicate", &mut state)["error"],
+        "unknown request"
+    );
+}
+
+#[test]
+fn plugins_are_checked_before_loading() {
+    let dir = std::env::temp_dir().join(format!("sesh-test-{}-plugins", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let library = dir.join("broken.so");
+    std::fs::write(&library, "not a library").unwrap();
+    assert!(plugins::load(&library, &[]).is_err());
+
+    // a module that imports sesh::get_var and does nothing else
+    let module = dir.join("vars.wasm");
+    let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+    wasm.extend(b"\x01\x09\x01\x60\x04\x7f\x7f\x7f\x7f\x01\x7f");
+    wasm.extend(b"\x02\x10\x01\x04sesh\x07get_var\x00\x00");
+    std::fs::write(&module, wasm).unwrap();
+    let error = plugins::load(&module, &[]).unwrap_err();
+    assert!(error.contains("`vars` capability"), "{}", error);
+    let granted = plugins::wasm::Capability::parse_list("vars").unwrap();
+    assert!(!plugins::load(&module, &granted).is_err_and(|v| v.contains("capability")));
+    assert!(plugins::wasm::Capability::parse_list("vars,network").is_err());
+
+    // a module whose start function traps unless get_focus refuses, as nothing called it
+    let module = dir.join("start.wasm");
+    let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+    wasm.extend(b"\x01\x0a\x02\x60\x02\x7f\x7f\x01\x7f\x60\x00\x00");
+    wasm.extend(b"\x02\x12\x01\x04sesh\x09get_focus\x00\x00");
+    wasm.extend(b"\x03\x02\x01\x01\x08\x01\x01");
+    wasm.extend(b"\x0a\x11\x01\x0f\x00\x41\x00\x41\x00\x10\x00\x41\x7f\x47\x04\x40\x00\x0b\x0b");
+    std::fs::write(&module, wasm).unwrap();
+    let granted = plugins::wasm::Capability::parse_list("focus").unwrap();
+    let error = plugins::load(&module, &granted).unwrap_err();
+    assert!(error.starts_with("sesh_manifest"), "{}", error);
+
+    let mut state = empty_state();
+    let statement = format!("plugin load {}", module.display());
+    assert_eq!(run_builtin(&statement, "", &mut state).0, 2);
+    assert!(state.plugins.is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+#[allow(clippy::arc_with_non_send_sync)]
+fn programs_run_outside_raw_mode() {
+    /// A terminal that keeps track of whether it's in raw mode.
+    struct Terminal(Arc<std::sync::Mutex<Vec<bool>>>);
+    impl Write for Terminal {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    impl term::Terminal for Terminal {
+        fn suspend_raw_mode(&self) -> std::io::Result<()> {
+            self.0.lock().unwrap().push(false);
+            Ok(())
+        }
+        fn activate_raw_mode(&self) -> std::io::Result<()> {
+            self.0.lock().unwrap().push(true);
+            Ok(())
+        }
+    }
+
+    let modes = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let mut state = empty_state();
+    state.raw_term = Some(Arc::new(RwLock::new(Box::new(Terminal(modes.clone())))));
+    eval("true", &mut state);
+    let modes = modes.lock().unwrap();
+    assert_eq!(modes.first(), Some(&false));
+    assert_eq!(modes.last(), Some(&true));
+}
+
+#[test]
+fn scripts_that_arent_utf8_still_run() {
+    assert_eq!(decode_script(b"echo caf\xe9".to_vec()), "echo caf\u{e9}");
+    assert_eq!(decode_script("echo caf\u{e9}".into()), "echo caf\u{e9}");
+    let args = Args::try_parse_from(["sesh", "script.sesh", "-x", "y"]).unwrap();
+    assert_eq!(args.script, Some(PathBuf::from("script.sesh")));
+    assert_eq!(args.script_args, ["-x", "y"]);
+}
+
+#[test]
+#[cfg(unix)]
+fn invalid_utf8_survives_the_shell() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let name = OsStr::from_bytes(b"caf\xe9.txt");
+    let smuggled = os_str::to_string(name);
+    assert!(smuggled.starts_with("caf") && smuggled.ends_with(".txt"));
+    assert_eq!(os_str::to_os(&smuggled), name);
+
+    let dir = std::env::temp_dir().join(format!("sesh-test-{}-bytes", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join(name), "").unwrap();
+    assert_eq!(glob::expand("caf*", &dir), Some(vec![smuggled.clone()]));
+    let mut state = empty_state();
+    state.working_dir = dir.clone();
+    eval(&format!("echo hi 1@{}", smuggled), &mut state);
+    assert_eq!(std::fs::read_to_string(dir.join(name)).unwrap(), "hi\n");
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unset_variables_leave_the_environment() {
+    let mut state = empty_state();
+    let env = |state: &State| {
+        let mut command = std::process::Command::new("env");
+        super::export_env(&mut command, state);
+        command
+            .get_envs()
+            .find(|v| v.0 == "SESH_TEST_UNSET")
+            .map(|v| v.1.map(|v| v.to_os_string()))
+    };
+    assert_eq!(run_builtin("unset SESH_TEST_UNSET", "", &mut state).0, 0);
+    assert_eq!(env(&state), Some(None));
+    assert_eq!(
+        run_builtin("export SESH_TEST_UNSET=new", "", &mut state).0,
+        0
+    );
+    assert_eq!(env(&state), Some(Some("new".into())));
+    // the shell's own environment isn't touched, as other threads read it
+    assert_eq!(std::env::var_os("SESH_TEST_UNSET"), None);
+}
+
+#[test]
+fn restricted_shells_stay_where_they_are() {
+    let dir = std::env::temp_dir().join(format!("sesh-test-{}-restricted", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut state = empty_state();
+    state.working_dir = dir.clone();
+    state.restricted = true;
+    assert_eq!(run_builtin("cd /", "", &mut state).0, 1);
+    assert_eq!(state.working_dir, dir);
+    assert_eq!(run_builtin("set PATH=/", "", &mut state).0, 1);
+    assert_eq!(run_builtin("set NAME=sesh", "", &mut state).0, 0);
+    eval("/usr/bin/touch made", &mut state);
+    eval("printf hi 1@out", &mut state);
+    assert!(!dir.join("made").exists() && !dir.join("out").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn sandbox_needs_a_program() {
+    let mut state = empty_state();
+    assert_eq!(run_builtin("sandbox --net", "", &mut state).0, 1);
+}
+
+#[test]
+fn statements_are_audited_with_secrets_masked() {
+    let path = std::env::temp_dir().join(format!("sesh-test-{}.audit", std::process::id()));
+    let mut state = empty_state();
+    eval("set --secret TOKEN=hunter2", &mut state);
+    audit::record("echo hunter2", Duration::from_millis(5), &state);
+    assert!(!path.exists());
+
+    state.shell_env.push(ShellVar {
+        name: "AUDIT_LOG".to_string(),
+        value: path.to_string_lossy().to_string(),
+        exported: false,
+    });
+    audit::record("echo hunter2", Duration::from_millis(5), &state);
+    audit::record("  ", Duration::ZERO, &state);
+    let log = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(log.lines().count(), 1);
+    let entry = serde_json::from_str::<serde_json::Value>(&log).unwrap();
+    assert_eq!(entry["command"], format!("echo {}", SECRET_MASK));
+    assert_eq!(entry["pid"], std::process::id());
+
+    // a restricted shell can't stop being audited
+    state.restricted = true;
+    assert_eq!(run_builtin("set AUDIT_LOG=", "", &mut state).0, 1);
+    assert_eq!(run_builtin("unset AUDIT_LOG", "", &mut state).0, 1);
+    audit::record("nop", Duration::ZERO, &state);
+    assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn guard_patterns_match_word_by_word() {
+    let words = |command: &str| {
+        command
+            .split(' ')
+            .map(|v| v.to_string())
+            .collect::<Vec<String>>()
+    };
+    let recursive = guard::DEFAULT_PATTERNS[0];
+    assert!(guard::matches(recursive, &words("rm -rf /")));
+    assert!(guard::matches(recursive, &words("rm -v -r -f -- /")));
+    assert!(guard::matches(
+        recursive,
+        &words("rm -r /tmp/a /usr /tmp/b")
+    ));
+    assert!(!guard::matches(
+        recursive,
+        &words("rm -rf /tmp/sesh.a1b2c3")
+    ));
+    assert!(!guard::matches(recursive, &words("rm -f /")));
+    assert!(!guard::matches(recursive, &words("rm -rf build")));
+    assert!(guard::matches(
+        "dd * of=/dev/sd* *",
+        &words("dd if=x of=/dev/sda bs=1M")
+    ));
+    assert!(!guard::matches(
+        "dd * of=/dev/sd* *",
+        &words("dd if=x of=out.img")
+    ));
+    assert!(guard::matches("a?c", &words("abc")));
+    assert!(!guard::matches("a?c", &words("a/c")));
+
+    let mut state = empty_state();
+    state.guards = guard::DEFAULT_PATTERNS.map(|v| v.to_string()).to_vec();
+    assert!(guard::check(&words("rm -rf /tmp/sesh.a1b2c3"), &state));
+    assert!(!guard::check(&words("rm -rf /"), &state));
+    // arguments are looked at as the paths they name too
+    eval("set HOME=/tmp/sesh-guard-home", &mut state);
+    assert!(!guard::check(
+        &words("rm -rf /tmp/sesh-guard-home/"),
+        &state
+    ));
+    assert!(!guard::check(&words("rm -rf ~/"), &state));
+    assert!(!guard::check(&words("rm -rf /usr/.."), &state));
+    assert!(!guard::check(&words("rm -rf usr"), &state));
+    state.working_dir = PathBuf::from("/tmp");
+    assert!(guard::check(&words("rm -rf usr"), &state));
+    assert!(guard::check(&words("rm -rf ~/build"), &state));
+    run_builtin("guard add \"touch  *\"", "", &mut state);
+    assert!(!guard::check(&words("touch a b"), &state));
+    assert_eq!(run_builtin("guard remove touch *", "", &mut state).0, 0);
+    assert!(guard::check(&words("touch a b"), &state));
+}
+
+#[test]
+fn choose_needs_something_to_choose_from() {
+    let mut state = empty_state();
+    let (status, _, stderr) = run_builtin("choose", "", &mut state);
+    assert_eq!(status, 1);
+    assert!(stderr.contains("nothing to choose from"));
+    state.restricted = true;
+    let stderr = run_builtin("choose --var PATH a b", "", &mut state).2;
+    assert!(stderr.contains("cannot change PATH"));
+}
+
+#[test]
+fn finder_programs_pick_files() {
+    let dir = std::env::temp_dir().join(format!("sesh-test-{}-finder", std::process::id()));
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    for name in ["a b.txt", ".hidden", "src/main.rs"] {
+        std::fs::write(dir.join(name), "").unwrap();
+    }
+    let mut state = empty_state();
+    state.working_dir = dir.clone();
+    eval("set FINDER=\"head -n 1\"", &mut state);
+    assert_eq!(finder::files(&state).as_deref(), Some("\"a b.txt\""));
+    eval("set FINDER=\"tail -n 1\"", &mut state);
+    assert_eq!(finder::files(&state).as_deref(), Some("src/main.rs"));
+    state.history = vec!["ls".to_string(), "make".to_string(), "ls".to_string()];
+    assert_eq!(finder::history(&state).as_deref(), Some("make"));
+    eval("set FINDER=false", &mut state);
+    assert_eq!(finder::history(&state), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn select_needs_a_variable_and_items() {
+    let mut state = empty_state();
+    assert_eq!(run_builtin("select x (nop)", "", &mut state).0, 1);
+    assert_eq!(run_builtin("select x of a b (nop)", "", &mut state).0, 1);
+    state.restricted = true;
+    assert_eq!(run_builtin("select PATH in a b (nop)", "", &mut state).0, 1);
+}
+
+#[test]
+fn exit_hooks_are_listed_and_cleared() {
+    let mut state = empty_state();
+    run_builtin("onexit (set LEFT=true)", "", &mut state);
+    assert_eq!(state.exit_hooks, ["set LEFT=true"]);
+    assert_eq!(
+        run_builtin("onexit list", "", &mut state).1,
+        "set LEFT=true\n"
+    );
+    run_builtin("onexit clear", "", &mut state);
+    assert!(state.exit_hooks.is_empty());
+    assert_eq!(run_builtin("onexit a b", "", &mut state).0, 1);
+}
+
+#[test]
+fn greeting_expands_prompt_escapes() {
+    let state = empty_state();
+    assert_eq!(
+        expand_prompt("sesh $v", &state),
+        format!("sesh {}", env!("CARGO_PKG_VERSION"))
+    );
+    assert!(Args::try_parse_from(["sesh", "--no-greeting"]).is_ok());
+}
+
+#[test]
+fn help_is_given_per_builtin() {
+    let mut state = empty_state();
+    let (status, stdout, _) = run_builtin("help cd", "", &mut state);
+    assert_eq!(status, 0);
+    assert!(stdout.starts_with("cd "));
+    let stdout = run_builtin("help -m cd", "", &mut state).1;
+    assert!(stdout.starts_with("NAME\n       cd - sesh builtin"));
+    assert!(stdout.contains("SYNOPSIS") && stdout.contains("DESCRIPTION"));
+    let list = run_builtin("help --json", "", &mut state).1;
+    let list = serde_json::from_str::<serde_json::Value>(&list).unwrap();
+    assert!(list.as_array().unwrap().iter().any(|v| v["name"] == "cd"));
+    assert_eq!(run_builtin("help nonexistent", "", &mut state).0, 1);
+    // the pager could run anything
+    state.restricted = true;
+    let stderr = run_builtin("set PAGER=tee", "", &mut state).2;
+    assert!(stderr.contains("cannot change PAGER"));
+}
+
+#[test]
+fn builtins_are_listed_for_completion_scripts() {
+    assert!(Args::try_parse_from(["sesh", "--json"]).is_err());
+    assert!(Args::try_parse_from(["sesh", "--list-builtins", "--json"]).is_ok());
+    let mut script = Vec::new();
+    clap_complete::generate(
+        clap_complete::Shell::Bash,
+        &mut Args::command(),
+        "sesh",
+        &mut script,
+    );
+    assert!(
+        String::from_utf8(script)
+            .unwrap()
+            .contains("--list-builtins")
+    );
+}
+
+#[test]
+fn messages_fill_in_their_arguments() {
+    assert_eq!(i18n::format("{} and {}", &[&1, &"two"]), "1 and two");
+    assert_eq!(i18n::format("{1} before {0}", &[&"a", &"b"]), "b before a");
+    assert_eq!(i18n::format("{{}} {}", &[&3]), "{} 3");
+    assert_eq!(i18n::format("{} {}", &[&"one"]), "one ");
+    assert_eq!(i18n::translate("no help for {}"), "no help for {}");
+}
+
+#[test]
+fn sourcing_itself_stops() {
+    let script = std::env::temp_dir().join(format!("sesh-test-{}.sesh", std::process::id()));
+    std::fs::write(&script, format!("source {}", script.display())).unwrap();
+    let mut state = empty_state();
+    state.shell_env.push(ShellVar {
+        name: "MAXDEPTH".to_string(),
+        value: "5".to_string(),
+        exported: false,
+    });
+    eval(&format!("source {}", script.display()), &mut state);
+    std::fs::remove_file(&script).unwrap();
+    assert!(state.eval_stack.is_empty());
+}
+
+#[test]
+fn groups_nest() {
+    let words = parser::command("if (if (test) (a \"(\")) [b [c]] (d) \"1@x\" *\"*\" ()")
+        .unwrap()
+        .words
+        .into_iter()
+        .map(|v| (v.text, v.quoted))
+        .collect::<Vec<(String, bool)>>();
+    assert_eq!(
+        words,
+        [
+            ("if".to_string(), false),
+            ("if (test) (a \"(\")".to_string(), false),
+            ("[b [c]]".to_string(), false),
+            ("d".to_string(), false),
+            ("1@x".to_string(), false),
+            ("**".to_string(), true),
+            ("()".to_string(), false),
+        ]
+    );
+}
+
+#[test]
+fn control_flow_nests() {
+    let mut state = empty_state();
+    eval("if (if (nop) (set a=1)) (set b=2) (set c=3)", &mut state);
+    let vars = |name: &str| state.shell_env.iter().any(|v| v.name == name);
+    assert!(vars("a") && vars("b") && !vars("c"));
+}
+
+#[test]
+fn assignments_only_apply_to_the_command() {
+    let out = std::env::temp_dir().join(format!("sesh-test-{}.out", std::process::id()));
+    let mut state = empty_state();
+    eval(
+        &format!("LOG_LEVEL=debug printenv LOG_LEVEL 1@{}", out.display()),
+        &mut state,
+    );
+    assert_eq!(std::fs::read_to_string(&out).unwrap(), "debug\n");
+    std::fs::remove_file(&out).unwrap();
+    eval("A=hello B=2 getf A", &mut state);
+    assert!(matches!(&state.focus, Focus::Str(v) if v == "hello"));
+    assert!(
+        !state
+            .shell_env
+            .iter()
+            .any(|v| v.name == "A" || v.name == "B")
+    );
+}
+
+#[test]
+fn subshell_keeps_changes_to_itself() {
+    let mut state = empty_state();
+    eval("(cd /tmp; set X=1; getf X)", &mut state);
+    assert_eq!(state.working_dir, PathBuf::from("/"));
+    assert!(!state.shell_env.iter().any(|v| v.name == "X"));
+    assert!(matches!(&state.focus, Focus::Str(v) if v.is_empty()));
+    eval("(nop; sesh-test-missing-program)", &mut state);
+    assert_eq!(get_status(&state), 127);
+
+    // exit only leaves the subshell
+    eval("(exit 3; sesh-test-missing-program)", &mut state);
+    assert_eq!(get_status(&state), 3);
+    eval("(for v in a b (exit 4); nop)", &mut state);
+    assert_eq!(get_status(&state), 4);
+    eval("(exit)", &mut state);
+    assert_eq!(get_status(&state), 0);
+    eval("(exit three)", &mut state);
+    assert_eq!(get_status(&state), 1);
+    assert_eq!(state.exit_request, None);
+}
+
+#[test]
+fn group_shares_state_and_redirection() {
+    let out = std::env::temp_dir().join(format!("sesh-test-{}.group", std::process::id()));
+    let mut state = empty_state();
+    eval(
+        &format!("{{ echo a; set X=1; printf b; }} 1@{}", out.display()),
+        &mut state,
+    );
+    assert_eq!(std::fs::read_to_string(&out).unwrap(), "a\nb");
+    std::fs::remove_file(&out).unwrap();
+    assert!(state.shell_env.iter().any(|v| v.name == "X"));
+    assert!(state.group_indirects.is_empty());
+}
+
+#[test]
+fn negation_inverts_status() {
+    let mut state = empty_state();
+    eval("! nop", &mut state);
+    assert_eq!(get_status(&state), 1);
+    eval("! ! nop", &mut state);
+    assert_eq!(get_status(&state), 0);
+    eval("if (! nop) (set a=1) (set b=1)", &mut state);
+    assert!(state.shell_env.iter().any(|v| v.name == "b"));
+}
+
+#[test]
+fn special_vars_are_kept_up_to_date() {
+    let mut state = empty_state();
+    init_special_vars(&mut state);
+    eval("echo a b 1@/dev/null; ! nop", &mut state);
+    assert_eq!(get_var(&state, "?").as_deref(), Some("1"));
+    assert_eq!(get_var(&state, "_").as_deref(), Some("nop"));
+    assert_eq!(get_var(&state, "$"), Some(std::process::id().to_string()));
+    let (status, _, stderr) = run_builtin("set ?=0", "", &mut state);
+    assert_eq!(
+        (status, stderr.as_str()),
+        (1, "sesh: set: ? is read-only\n")
+    );
+    let (status, _, stderr) = run_builtin("set STATUS=0", "", &mut state);
+    assert_eq!(
+        (status, stderr.as_str()),
+        (1, "sesh: set: STATUS is read-only\n")
+    );
+    assert_eq!(get_status(&state), 1);
+}
+
+#[test]
+fn session_round_trips() {
+    let name = format!("sesh-test-{}", std::process::id());
+    let mut state = empty_state();
+    state.working_dir = std::env::temp_dir();
+    eval(
+        "set A=1; set --secret B=2; alias ll ls; remember up (cd ..)",
+        &mut state,
+    );
+    state.focus = Focus::Vec(vec![Focus::Str("x".to_string())]);
+    state.dir_stack = vec![PathBuf::from("/usr"), PathBuf::from("/")];
+    session::save(&name, &state).unwrap();
+
+    let mut restored = empty_state();
+    session::restore(&name, &mut restored).unwrap();
+    std::fs::remove_file(session::session_dir().join(name + ".json")).unwrap();
+    assert_eq!(restored.working_dir, state.working_dir);
+    assert_eq!(get_var(&restored, "A").as_deref(), Some("1"));
+    assert_eq!(get_var(&restored, "B"), None);
+    assert_eq!(restored.aliases, state.aliases);
+    assert_eq!(restored.bookmarks, state.bookmarks);
+    assert_eq!(restored.dir_stack, state.dir_stack);
+    assert!(matches!(&restored.focus, Focus::Vec(v) if v.len() == 1));
+
+    // jobs get the secrets too
+    let mut job = empty_state();
+    let session = session::send(&state).unwrap();
+    session::receive(std::os::fd::IntoRawFd::into_raw_fd(session), &mut job).unwrap();
+    assert_eq!(get_var(&job, "B").as_deref(), Some("2"));
+    assert_eq!(job.secrets, vec!["B".to_string()]);
+    assert_eq!(job.aliases, state.aliases);
+    assert!(Args::try_parse_from(["sesh", "--no-rc", "--session-fd", "3", "-c", "x"]).is_ok());
+}
+
+#[test]
+fn remote_needs_a_host_and_statement() {
+    let mut state = empty_state();
+    let (status, _, stderr) = run_builtin("remote --focus host", "", &mut state);
+    assert_eq!(status, 1);
+    assert!(stderr.ends_with("usage: remote [--var name ...] [--focus] host (statement)\n"));
+    assert_eq!(builtins::sh_quote("it's"), r"'it'\''s'");
+    assert_eq!(
+        builtins::remote_command(&[("A".to_string(), "x\"; rm y".to_string())], "echo $A"),
+        r#"env 'A=x"; rm y' sesh -c 'echo $A'"#
+    );
+    assert_eq!(builtins::remote_command(&[], "nop"), "sesh -c 'nop'");
+}
+
+#[test]
+fn base64_pads() {
+    assert_eq!(clipboard::base64(b"sesh"), "c2VzaA==");
+    assert_eq!(clipboard::base64(b"shell"), "c2hlbGw=");
+    assert_eq!(clipboard::base64(b"abc"), "YWJj");
+}
+
+#[test]
+fn introspect_masks_secrets() {
+    let mut state = empty_state();
+    eval("set --secret TOKEN=hunter2; alias ll ls", &mut state);
+    let (status, stdout, _) = run_builtin("introspect", "", &mut state);
+    assert_eq!(status, 0);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["aliases"][0]["to"], "ls");
+    assert!(!stdout.contains("hunter2"));
+    assert!(
+        json["builtins"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|v| v["name"] == "introspect")
+    );
+}
+
+#[test]
+fn timers_parse_and_cancel() {
+    use std::time::Duration;
+    assert_eq!(
+        timer::parse_delay("1h30m"),
+        Some(Duration::from_secs(90 * 60))
+    );
+    assert_eq!(timer::parse_delay("45"), Some(Duration::from_secs(45)));
+    assert_eq!(timer::parse_delay("5x"), None);
+    assert_eq!(timer::parse_delay("999999999999999999d"), None);
+    assert_eq!(timer::parse_delay("18446744073709551615s1s"), None);
+    assert!(timer::parse_time("25:00").is_none());
+
+    let mut state = empty_state();
+    eval("defer 1h (echo later); at 12:00 (echo noon)", &mut state);
+    assert_eq!(state.timers.len(), 2);
+    assert!(timer::take_due(&mut state).is_empty());
+    let (_, listed, _) = run_builtin("at list", "", &mut state);
+    assert_eq!(listed.lines().count(), 2);
+    run_builtin("at cancel 1", "", &mut state);
+    assert_eq!(state.timers.len(), 1);
+    assert_eq!(state.timers[0].statement, "echo noon");
+    assert_eq!(
+        run_builtin("defer 9999999999999d (nop)", "", &mut state).0,
+        1
+    );
+    assert_eq!(state.timers.len(), 1);
+}
+
+#[test]
+fn queue_runs_in_order_and_stops_on_errexit() {
+    let mut state = empty_state();
+    eval("then (set A=1); then (set B=2)", &mut state);
+    assert_eq!(queued(), ["set A=1", "set B=2"]);
+    run_queue(&mut state);
+    assert!(get_var(&state, "A").is_some() && get_var(&state, "B").is_some());
+
+    eval("set ERREXIT=true; then (set C=3); ! nop", &mut state);
+    run_queue(&mut state);
+    assert!(get_var(&state, "C").is_none());
+    assert!(queued().is_empty());
+}
+
+#[test]
+fn brackets_match_like_the_parser() {
+    assert_eq!(
+        match_brackets("if (a [b]) \"(\""),
+        (vec![(6, 8), (3, 9), (11, 13)], vec![])
+    );
+    assert_eq!(match_brackets("a) \\] (]"), (vec![], vec![1, 7]));
+    assert_eq!(
+        highlight_brackets("(a)", 3),
+        "\x1b[7m(\x1b[27ma\x1b[7m)\x1b[27m"
+    );
+    assert_eq!(highlight_brackets("a)", 2), "a\x1b[31m)\x1b[39m");
+}
+
+#[test]
+fn autopair_inserts_skips_and_deletes_pairs() {
+    let (mut line, mut cursor) = (String::new(), 0);
+    for ch in "echo (a".chars() {
+        edit_line(&mut line, &mut cursor, ch, true);
+    }
+    assert_eq!((line.as_str(), cursor), ("echo (a)", 7));
+    edit_line(&mut line, &mut cursor, ')', true);
+    assert_eq!((line.as_str(), cursor), ("echo (a)", 8));
+    edit_line(&mut line, &mut cursor, '"', true);
+    edit_line(&mut line, &mut cursor, '\x7F', true);
+    assert_eq!((line.as_str(), cursor), ("echo (a)", 8));
+    edit_line(&mut line, &mut cursor, 's', true);
+    edit_line(&mut line, &mut cursor, '\'', true);
+    assert_eq!(line, "echo (a)s'");
+    assert!(!edit_line(&mut String::new(), &mut 0, '\x7F', true));
+}
+
+#[test]
+fn lines_are_edited_at_the_cursor() {
+    let (mut line, mut cursor) = (String::from("echo ac"), 6);
+    edit_line(&mut line, &mut cursor, 'b', false);
+    assert_eq!((line.as_str(), cursor), ("echo abc", 7));
+    edit_line(&mut line, &mut cursor, '\x01', false);
+    edit_line(&mut line, &mut cursor, '\x04', false);
+    assert_eq!((line.as_str(), cursor), ("cho abc", 0));
+    edit_line(&mut line, &mut cursor, '\x05', false);
+    edit_line(&mut line, &mut cursor, '\x7F', false);
+    assert_eq!((line.as_str(), cursor), ("cho ab", 6));
+    assert!(!edit_line(&mut line, &mut cursor, '\x04', false));
+    assert!(!edit_line(&mut line, &mut cursor, '\x1b', false));
+    assert_eq!(line, "cho ab");
+
+    let (mut line, mut cursor) = (String::from("git commit -m  x"), 15);
+    edit_line(&mut line, &mut cursor, '\x17', false);
+    assert_eq!((line.as_str(), cursor), ("git commit x", 11));
+    edit_line(&mut line, &mut cursor, '\x0B', false);
+    assert_eq!(line, "git commit ");
+    cursor = 4;
+    edit_line(&mut line, &mut cursor, '\x15', false);
+    assert_eq!((line.as_str(), cursor), ("commit ", 0));
+    assert_eq!(word_boundary("echo foo-bar", 0, true), 4);
+    assert_eq!(word_boundary("echo foo-bar", 12, false), 9);
+    assert_eq!(word_boundary("echo foo-bar", 9, false), 5);
+
+    let (mut line, mut cursor) = (String::from("cat  | wc"), 4);
+    insert_at_cursor(&mut line, &mut cursor, "\"a b.txt\"");
+    assert_eq!((line.as_str(), cursor), ("cat \"a b.txt\" | wc", 13));
+}
+
+#[test]
+fn check_line_reports_unfinished_and_unknown() {
+    let state = empty_state();
+    assert_eq!(check_line("", &state), LineCheck::Empty);
+    assert_eq!(check_line("echo hi; cd /", &state), LineCheck::Fine);
+    assert_eq!(check_line("echo \"hi", &state), LineCheck::Unfinished);
+    assert_eq!(check_line("echo hi)", &state), LineCheck::Broken);
+    assert_eq!(
+        check_line("echo hi; no-such-command-here", &state),
+        LineCheck::Broken
+    );
+    assert_eq!(
+        colour_prompt_symbol("\x1b[34m~\x1b[39m> ", "\x1b[32m"),
+        "\x1b[34m~\x1b[39m\x1b[32m>\x1b[39m "
+    );
+}
+
+#[test]
+fn pushd_and_popd_keep_a_stack() {
+    let mut state = empty_state();
+    state.working_dir = PathBuf::from("/");
+    run_builtin("pushd tmp", "", &mut state);
+    assert_eq!(state.working_dir, PathBuf::from("/tmp"));
+    assert_eq!(expand_prompt("$d", &state), "1");
+    assert_eq!(run_builtin("dirs", "", &mut state).1, "/tmp\n/\n");
+    run_builtin("popd", "", &mut state);
+    assert_eq!(state.working_dir, PathBuf::from("/"));
+    assert_eq!(run_builtin("popd", "", &mut state).0, 1);
+}
+
+#[test]
+fn reloading_the_rc_replaces_what_it_defined() {
+    let mut state = empty_state();
+    eval("set B=0", &mut state);
+    std::fs::write(rc_path(), "alias ll ls -l; set A=1; set B=1").unwrap();
+    run_rc(&mut state).unwrap();
+    assert!(state.aliases.iter().any(|v| v.name == "ll"));
+    assert_eq!(get_var(&state, "B").as_deref(), Some("1"));
+
+    std::fs::write(rc_path(), "alias la ls -a; set A=2").unwrap();
+    assert_eq!(run_builtin("reload", "", &mut state).0, 0);
+    std::fs::remove_file(rc_path()).unwrap();
+    assert!(!state.aliases.iter().any(|v| v.name == "ll"));
+    assert!(state.aliases.iter().any(|v| v.name == "la"));
+    assert_eq!(get_var(&state, "A").as_deref(), Some("2"));
+    // what it no longer sets goes back to what it was before
+    assert_eq!(get_var(&state, "B").as_deref(), Some("0"));
+    assert_eq!(run_builtin("reload", "", &mut state).0, 1);
+}
+
+#[test]
+fn untrusted_project_config_does_not_run() {
+    let dir = std::env::temp_dir().join(format!("sesh-project-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join(".sesh")).unwrap();
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    std::fs::write(dir.join(project::CONFIG), "alias build \"echo building\"").unwrap();
+    let root = dir.canonicalize().unwrap();
+    assert_eq!(project::find_root(&dir.join("src")), Some(root.clone()));
+
+    let mut state = empty_state();
+    state.working_dir = dir.join("src");
+    project::update(&mut state);
+    assert_eq!(state.project.root, Some(root.clone()));
+    assert!(state.aliases.is_empty());
+
+    project::trust(&root).unwrap();
+    project::leave(&mut state);
+    project::update(&mut state);
+    assert_eq!(state.aliases.len(), 1);
+    // changing the config takes the trust back
+    std::fs::write(dir.join(project::CONFIG), "alias build \"echo pwned\"").unwrap();
+    project::leave(&mut state);
+    project::update(&mut state);
+    assert!(state.aliases.is_empty());
+    project::revoke(&root).unwrap();
+    state.working_dir = std::env::temp_dir();
+    project::update(&mut state);
+    assert_eq!(state.project.root, None);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn editing_in_place_needs_a_terminal() {
+    let mut state = empty_state();
+    let (status, _, stderr) = run_builtin("alias --edit missing", "", &mut state);
+    assert_eq!(status, 1);
+    assert!(stderr.contains("no alias named missing"));
+    assert_eq!(run_builtin("vared PROMPT1", "", &mut state).0, 1);
+    assert!(
+        run_builtin("vared", "", &mut state)
+            .2
+            .contains("usage: vared name")
+    );
+}
+
+#[test]
+fn showf_indexes_nested_lists() {
+    let mut state = empty_state();
+    state.focus = Focus::Vec(vec![
+        Focus::Str("a\nb".to_string()),
+        Focus::Vec(vec![Focus::Str("c".to_string()), Focus::Vec(vec![])]),
+    ]);
+    assert_eq!(
+        run_builtin("showf", "", &mut state).1,
+        "list (2)\n[0] \"a\\nb\"\n[1] list (2)\n  [0] \"c\"\n  [1] list (0)\n"
+    );
+    assert_eq!(
+        run_builtin("showf -d 1", "", &mut state).1,
+        "list (2)\n[0] \"a\\nb\"\n[1] list (2) ...\n"
+    );
+    state.focus = Focus::Str("text".to_string());
+    assert_eq!(run_builtin("showf", "", &mut state).1, "text\n");
+}
+
+#[test]
+fn statements_pipe_into_the_next() {
+    let mut state = empty_state();
+    eval(
+        "echo 2 1@; select X in a b (nop) 0@ 1@/dev/null",
+        &mut state,
+    );
+    assert_eq!(get_var(&state, "X").as_deref(), Some("b"));
+    assert_eq!(state.pipe, None);
+    eval("echo piped 1@", &mut state);
+    assert_eq!(state.pipe.as_deref(), Some(&b"piped\n"[..]));
+    eval("set Y=1", &mut state);
+    assert_eq!(state.pipe, None);
+    // programs run at once, so one that never ends stops when the next is done reading
+    eval("yes 1@; head -n 2 0@ 1@; tr y n 0@ 1@", &mut state);
+    assert_eq!(state.pipe.as_deref(), Some(&b"n\nn\n"[..]));
+    assert_eq!(get_status(&state), 0);
+    eval("sh -c 'exit 3' 1@; cat 0@", &mut state);
+    assert_eq!(get_status(&state), 0);
+    eval("sh -c 'exit 3' 1@ && set Y=2", &mut state);
+    assert_eq!(
+        (get_status(&state), get_var(&state, "Y")),
+        (3, Some("1".into()))
+    );
+}
+
+#[test]
+fn background_statements_and_job_specs() {
+    let background = |text: &str| {
+        parser::parse(text).map(|v| {
+            v.into_iter()
+                .map(|v| (v.text(), v.background))
+                .collect::<Vec<_>>()
+        })
+    };
+    assert_eq!(
+        background("sleep 10 &"),
+        Ok(vec![("sleep 10".to_string(), true)])
+    );
+    assert_eq!(
+        background("echo a&"),
+        Ok(vec![("echo a&".to_string(), false)])
+    );
+    assert!(background("&").is_err());
+    assert_eq!(
+        background("echo \"&\""),
+        Ok(vec![("echo \"&\"".to_string(), false)])
+    );
+
+    let mut state = empty_state();
+    assert!(run_builtin("fg", "", &mut state).2.contains("no jobs"));
+    state.jobs.push(job::Job {
+        id: 2,
+        pid: 0,
+        statement: "sleep 10".to_string(),
+        status: job::JobStatus::Done(0),
+    });
+    assert_eq!(job::find(Some("%2"), &state), Some(0));
+    assert_eq!(job::find(Some("3"), &state), None);
+    assert_eq!(job::find(None, &state), Some(0));
+    assert_eq!(state.jobs[0].to_string(), "[2]  Done      sleep 10");
+}
+
+#[test]
+fn command_output_is_captured() {
+    let mut state = empty_state();
+    eval("set X=\"$(echo a $(echo b))\"", &mut state);
+    assert_eq!(get_var(&state, "X").as_deref(), Some("a b"));
+    eval("capture OUT (echo out; ls /no-such-dir-here)", &mut state);
+    assert_eq!(get_var(&state, "OUT").as_deref(), Some("out"));
+    assert!(get_var(&state, "OUT_ERR").is_some_and(|v| v.contains("no-such-dir-here")));
+    assert_ne!(get_var(&state, "OUT_STATUS").as_deref(), Some("0"));
+    assert!(state.group_indirects.is_empty());
+    // the output is words, not syntax
+    eval(
+        "set X=$(printf %s '1@out') Y=$(printf %s \"'*\")",
+        &mut state,
+    );
+    assert_eq!(get_var(&state, "X").as_deref(), Some("1@out"));
+    assert_eq!(get_var(&state, "Y").as_deref(), Some("'*"));
+    // and only what runs is substituted, when it runs
+    eval(
+        "set N=1; nop || set X=$(set N=2); set Y=$(echo $N)",
+        &mut state,
+    );
+    assert_eq!(get_var(&state, "N").as_deref(), Some("1"));
+    assert_eq!(get_var(&state, "Y").as_deref(), Some("1"));
+    eval("set X='$(echo no)'", &mut state);
+    assert_eq!(get_var(&state, "X").as_deref(), Some("$(echo no)"));
+    state.restricted = true;
+    eval("set X=$(echo yes)", &mut state);
+    assert_eq!(get_var(&state, "X").as_deref(), Some("yes"));
+}
+
+#[test]
+#[cfg(unix)]
+fn cd_keeps_symlinks_unless_physical() {
+    let dir = std::env::temp_dir().join(format!("sesh-cd-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("real/inner")).unwrap();
+    std::os::unix::fs::symlink(dir.join("real/inner"), dir.join("link")).unwrap();
+    let mut state = empty_state();
+    state.working_dir = dir.clone();
+    run_builtin("cd link", "", &mut state);
+    assert_eq!(state.working_dir, dir.join("link"));
+    assert_eq!(
+        get_var(&state, "OLDPWD"),
+        Some(dir.to_string_lossy().to_string())
+    );
+    run_builtin("cd ..", "", &mut state);
+    assert_eq!(state.working_dir, dir);
+    run_builtin("cd -P link/..", "", &mut state);
+    assert_eq!(state.working_dir, dir.join("real").canonicalize().unwrap());
+    run_builtin("cd -", "", &mut state);
+    assert_eq!(
+        get_var(&state, "PWD"),
+        Some(dir.to_string_lossy().to_string())
+    );
+    assert_eq!(run_builtin("cd no-such-dir", "", &mut state).0, 1);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn tab_completes_commands_and_paths() {
+    let dir = std::env::temp_dir().join(format!("sesh-complete-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    std::fs::write(dir.join("main.rs"), "").unwrap();
+    std::fs::write(dir.join("mains.rs"), "").unwrap();
+    let mut state = empty_state();
+    state.working_dir = dir.clone();
+    state.shell_env.push(ShellVar {
+        name: "PATH".to_string(),
+        value: String::new(),
+        exported: false,
+    });
+
+    let completion = complete::complete("cap", 3, &state);
+    assert_eq!(
+        (completion.line.as_str(), completion.cursor),
+        ("capture ", 8)
+    );
+    let completion = complete::complete("echo a; vare x", 12, &state);
+    assert_eq!(
+        (completion.line.as_str(), completion.cursor),
+        ("echo a; vared  x", 14)
+    );
+    let completion = complete::complete("cat s", 5, &state);
+    assert_eq!(completion.line, "cat src/");
+    let completion = complete::complete("cat m", 5, &state);
+    assert_eq!(completion.line, "cat main");
+    let completion = complete::complete("cat main", 8, &state);
+    assert_eq!(completion.line, "cat main");
+    assert_eq!(completion.choices, ["main.rs", "mains.rs"]);
+    assert_eq!(
+        complete::columns(&completion.choices, 20),
+        "main.rs   mains.rs\n"
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn unquoted_wildcards_expand_to_paths() {
+    assert!(glob::matches("*.rs", "main.rs"));
+    assert!(glob::matches("ma?n.[a-r][!a]", "main.rs"));
+    assert!(!glob::matches("*.rs", "main.rsx"));
+    assert!(glob::matches("\\*", "*"));
+
+    let dir = std::env::temp_dir().join(format!("sesh-glob-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    for name in ["b.rs", "a.rs", ".hidden.rs", "src/c.rs", "notes.txt"] {
+        std::fs::write(dir.join(name), "").unwrap();
+    }
+    let mut state = empty_state();
+    state.working_dir = dir.clone();
+    eval("echo *.rs  src/*.rs 1@", &mut state);
+    assert_eq!(state.pipe.as_deref(), Some(&b"a.rs b.rs  src/c.rs\n"[..]));
+    eval("echo \"*.rs\" *.md 1@", &mut state);
+    assert_eq!(state.pipe.as_deref(), Some(&b"\"*.rs\" *.md\n"[..]));
+    assert_eq!(
+        glob::expand(".*.rs", &dir),
+        Some(vec![".hidden.rs".to_string()])
+    );
+    assert_eq!(glob::expand("*/", &dir), Some(vec!["src/".to_string()]));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn list_variables_push_pop_and_shift() {
+    let mut state = empty_state();
+    run_builtin("pushvar QUEUE a b", "", &mut state);
+    run_builtin("pushvar QUEUE c", "", &mut state);
+    assert_eq!(get_var(&state, "QUEUE").as_deref(), Some("a\nb\nc"));
+    assert_eq!(run_builtin("popvar QUEUE", "", &mut state).1, "c\n");
+    run_builtin("shiftvar QUEUE FIRST", "", &mut state);
+    assert_eq!(get_var(&state, "FIRST").as_deref(), Some("a"));
+    assert_eq!(get_var(&state, "QUEUE").as_deref(), Some("b"));
+    run_builtin("popvar QUEUE", "", &mut state);
+    let (status, _, stderr) = run_builtin("shiftvar QUEUE", "", &mut state);
+    assert_eq!(status, 1);
+    assert!(stderr.contains("QUEUE is empty"));
+}
+
+#[test]
+fn precmd_hooks_are_listed_and_cleared() {
+    let mut state = empty_state();
+    run_builtin("precmd (echo hi)", "", &mut state);
+    assert_eq!(run_builtin("precmd list", "", &mut state).1, "echo hi\n");
+    run_builtin("precmd clear", "", &mut state);
+    assert!(state.precmd_hooks.is_empty());
+    state.precmd_timed_out = true;
+    assert!(!run_precmd_hooks(&mut state));
+
+    // they run in this shell, leaving the status alone
+    set_status(&mut state, 3);
+    run_builtin("precmd (set HOOKED=1; false)", "", &mut state);
+    assert!(!run_precmd_hooks(&mut state));
+    assert_eq!(get_var(&state, "HOOKED").as_deref(), Some("1"));
+    assert_eq!(get_status(&state), 3);
+    // and are stopped, substitutions and all, once they run out of time
+    run_builtin("precmd clear", "", &mut state);
+    run_builtin("precmd (echo $(sleep 10); set AFTER=1)", "", &mut state);
+    eval("set PRECMD_TIMEOUT=1", &mut state);
+    let start = std::time::Instant::now();
+    assert!(run_precmd_hooks(&mut state));
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    assert_eq!(get_var(&state, "AFTER"), None);
+    assert!(!abandoned());
+}
+
+#[test]
+fn and_or_chains_depend_on_the_status() {
+    let chains = parser::parse("a && b || \"c || d\" (e && f)").unwrap();
+    assert_eq!(
+        chains[0]
+            .statements
+            .iter()
+            .map(|v| (v.link, v.text.as_str()))
+            .collect::<Vec<_>>(),
+        [
+            (parser::Link::First, "a"),
+            (parser::Link::And, "b"),
+            (parser::Link::Or, "\"c || d\" (e && f)"),
+        ]
+    );
+    let mut state = empty_state();
+    eval("set A=1 && set B=1 || set C=1", &mut state);
+    assert_eq!(get_var(&state, "B").as_deref(), Some("1"));
+    assert_eq!(get_var(&state, "C"), None);
+    eval("! nop && set D=1 || set E=1 && set F=1", &mut state);
+    assert_eq!(get_var(&state, "D"), None);
+    assert_eq!(get_var(&state, "E").as_deref(), Some("1"));
+    assert_eq!(get_var(&state, "F").as_deref(), Some("1"));
+    assert!(defines_secret("nop && set --secret TOKEN=x"));
+}
+
+#[test]
+fn path_builtins_work_on_args_and_the_focus() {
+    let mut state = empty_state();
+    assert_eq!(
+        run_builtin("basename /a/b.rs c/ /", "", &mut state).1,
+        "b.rs\nc\n/\n"
+    );
+    assert_eq!(
+        run_builtin("basename foo.txt .txt", "", &mut state).1,
+        "foo\n"
+    );
+    assert_eq!(
+        run_builtin("basename -s .rs /a/b.rs c.rs .rs", "", &mut state).1,
+        "b\nc\n.rs\n"
+    );
+    assert_eq!(
+        run_builtin("dirname /a/b.rs c /", "", &mut state).1,
+        "/a\n.\n/\n"
+    );
+    state.focus = Focus::Vec(vec![
+        Focus::Str("x/y".to_string()),
+        Focus::Str("z".to_string()),
+    ]);
+    run_builtin("dirname", "", &mut state);
+    assert_eq!(state.focus.to_string(), "list:[str:\"x\", str:\".\"]");
+
+    let dir = std::env::temp_dir().join(format!("sesh-realpath-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("a/b")).unwrap();
+    std::fs::create_dir_all(dir.join("c")).unwrap();
+    let dir = dir.canonicalize().unwrap();
+    state.working_dir = dir.join("a");
+    assert_eq!(
+        run_builtin("realpath b/../b", "", &mut state).1,
+        format!("{}\n", dir.join("a/b").display())
+    );
+    assert_eq!(
+        run_builtin("realpath --relative-to b ../c .", "", &mut state).1,
+        "../../c\n..\n"
+    );
+    assert_eq!(run_builtin("realpath no-such-file", "", &mut state).0, 1);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn for_loops_over_items_and_the_focus() {
+    let mut state = empty_state();
+    eval("set OUT=; for X in a b c (set OUT=$OUT$X)", &mut state);
+    assert_eq!(get_var(&state, "OUT").as_deref(), Some("abc"));
+    assert_eq!(get_var(&state, "X"), None);
+    state.focus = Focus::Vec(vec![
+        Focus::Str("d".to_string()),
+        Focus::Str("e".to_string()),
+    ]);
+    eval(
+        "set FOCUSED=; for X in (set FOCUSED=$FOCUSED$X)",
+        &mut state,
+    );
+    assert_eq!(get_var(&state, "FOCUSED").as_deref(), Some("de"));
+    assert_eq!(run_builtin("for X of a (nop)", "", &mut state).0, 1);
+}
+
+#[test]
+fn filef_sets_variables_about_a_file() {
+    let dir = std::env::temp_dir().join(format!("sesh-filef-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("five"), "12345").unwrap();
+    let mut state = empty_state();
+    state.working_dir = dir.clone();
+    assert_eq!(run_builtin("filef F five", "", &mut state).0, 0);
+    assert_eq!(get_var(&state, "F").as_deref(), Some("file"));
+    assert_eq!(get_var(&state, "F_SIZE").as_deref(), Some("5"));
+    assert!(get_var(&state, "F_MTIME").is_some_and(|v| v.parse::<u64>().unwrap() > 0));
+    run_builtin("filef F .", "", &mut state);
+    assert_eq!(get_var(&state, "F").as_deref(), Some("dir"));
+    assert_eq!(run_builtin("filef F missing", "", &mut state).0, 1);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn break_and_continue_leave_loops_early() {
+    let mut state = empty_state();
+    eval(
+        "set OUT=; for X in a b c d (if (test $X = b) (continue); if (test $X = d) (break); set OUT=$OUT$X)",
+        &mut state,
+    );
+    assert_eq!(get_var(&state, "OUT").as_deref(), Some("ac"));
+    assert_eq!(state.loop_depth, 0);
+    assert_eq!(state.loop_control, None);
+    eval("set N=0; while (nop) ({ break; }; set N=1)", &mut state);
+    assert_eq!(get_var(&state, "N").as_deref(), Some("0"));
+    assert!(
+        run_builtin("break", "", &mut state)
+            .2
+            .contains("not in a loop")
+    );
+}
+
+#[test]
+fn datef_parses_moves_and_formats_dates() {
+    let mut state = empty_state();
+    let (status, stdout, _) = run_builtin(
+        "datef --parse 2024-02-28 --add 1d12h +%F/%H",
+        "",
+        &mut state,
+    );
+    assert_eq!((status, stdout.as_str()), (0, "2024-02-29/12\n"));
+    run_builtin(
+        "datef --parse \"2024-03-01 00:30\" --add -1h --var D",
+        "",
+        &mut state,
+    );
+    assert_eq!(get_var(&state, "D").as_deref(), Some("2024-02-29 23:30:00"));
+    run_builtin("datef --parse 86400 --focus +%s", "", &mut state);
+    assert_eq!(state.focus.to_string(), "str:\"86400\"");
+    assert_eq!(run_builtin("datef --parse yesterday", "", &mut state).0, 1);
+    assert_eq!(run_builtin("datef +%Q", "", &mut state).0, 1);
+    let (status, _, stderr) = run_builtin("datef --add 999999999999d", "", &mut state);
+    assert_eq!(status, 1);
+    assert!(stderr.contains("date out of range"));
+    assert_eq!(
+        run_builtin("datef --add -999999999999d", "", &mut state).0,
+        1
+    );
+}
+
+#[test]
+fn remembered_statements_are_recalled() {
+    let mut state = empty_state();
+    state.history = vec!["set X=1".to_string(), "remember".to_string()];
+    run_builtin("remember", "", &mut state);
+    run_builtin("remember two (set X=2)", "", &mut state);
+    assert_eq!(
+        run_builtin("recall", "", &mut state).1,
+        "1\tset X=1\ntwo\tset X=2\n"
+    );
+    run_builtin("recall --run two", "", &mut state);
+    assert_eq!(get_var(&state, "X").as_deref(), Some("2"));
+    assert!(
+        run_builtin("recall two", "", &mut state)
+            .2
+            .contains("not a terminal")
+    );
+    run_builtin("remember --forget 1", "", &mut state);
+    assert_eq!(run_builtin("recall --run 1", "", &mut state).0, 1);
+}
+
+#[test]
+fn parser_reports_where_syntax_errors_are() {
+    let chains = parser::parse("a; b &\nif (x\n  y) \\\n  z").unwrap();
+    assert_eq!(chains.len(), 3);
+    assert!(chains[1].background);
+    assert_eq!(chains[2].statements[0].text, "if (x\n  y)   z");
+    assert_eq!(
+        chains[2].statements[0].position,
+        parser::Position { line: 2, column: 1 }
+    );
+
+    let e = parser::parse("echo a\necho (b").unwrap_err();
+    assert_eq!(
+        (e.position.line, e.position.column, e.unfinished),
+        (2, 6, true)
+    );
+    let e = parser::parse("echo a)").unwrap_err();
+    assert_eq!((e.position.column, e.unfinished), (7, false));
+    let e = parser::parse("a && && b").unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "syntax error at line 1, column 6: expected a statement before &&"
+    );
+    let e = parser::parse("echo hi\n  cat 0@in x@out").unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "syntax error at line 2, column 12: unknown indirect from `x`"
+    );
+    assert!(parser::parse("echo \"x@out\" (a@b) \\@ 2@1").is_ok());
+    assert!(parser::parse("a ||").unwrap_err().unfinished);
+    assert!(!parser::parse("a || ; b").unwrap_err().unfinished);
+
+    let mut state = empty_state();
+    eval("set P=1; set Q=1 &&", &mut state);
+    assert_eq!(get_var(&state, "P"), None);
+    assert_eq!(get_status(&state), 2);
+}
+
+#[test]
+fn sync_needs_something_to_send() {
+    let mut state = empty_state();
+    assert!(
+        run_builtin("sync nope", "", &mut state)
+            .2
+            .contains("no alias or variable named nope")
+    );
+    eval("set LINES=\"a\nb\"", &mut state);
+    assert!(
+        run_builtin("sync LINES", "", &mut state)
+            .2
+            .contains("more than one line")
+    );
+    eval("set --secret TOKEN=hunter2", &mut state);
+    assert!(
+        run_builtin("sync TOKEN", "", &mut state)
+            .2
+            .contains("TOKEN is secret")
+    );
+    assert_eq!(run_builtin("sync", "", &mut state).0, 0);
+    state.restricted = true;
+    assert!(run_builtin("sync", "", &mut state).2.contains("restricted"));
+}
+
+#[test]
+fn here_documents_and_strings_feed_stdin() {
+    let chains = parser::parse("cat <<END && wc <<< \"a b\"\none\n  END\necho next").unwrap();
+    assert_eq!(chains.len(), 2);
+    assert_eq!(chains[0].statements[0].text, "cat");
+    assert_eq!(chains[0].statements[0].input.as_deref(), Some("one\n"));
+    assert_eq!(chains[0].statements[1].input.as_deref(), Some("a b\n"));
+    assert_eq!(
+        chains[0].text(),
+        "cat <<EOF && wc <<EOF\none\nEOF\na b\nEOF"
+    );
+    assert_eq!(parser::parse(&chains[0].text()).unwrap(), chains[..1]);
+    assert!(parser::parse("cat <<END\none").unwrap_err().unfinished);
+
+    let mut state = empty_state();
+    eval(
+        "select X in a b c (set PICKED=$X) <<END\n2\nEND",
+        &mut state,
+    );
+    assert_eq!(get_var(&state, "PICKED").as_deref(), Some("b"));
+    eval("select Y in a b c (set PICKED=$Y) <<< 3", &mut state);
+    assert_eq!(get_var(&state, "PICKED").as_deref(), Some("c"));
+
+    // the body is expanded unless the end is quoted, and comments in it are kept
+    eval(
+        "set N=2; cat <<END 1@ # comment\n# $N ${N} $(echo it) it's\nEND",
+        &mut state,
+    );
+    assert_eq!(state.pipe.as_deref(), Some(&b"# 2 2 it it's\n"[..]));
+    eval("cat <<'END' 1@\n# $N $(echo it)\nEND", &mut state);
+    assert_eq!(state.pipe.as_deref(), Some(&b"# $N $(echo it)\n"[..]));
+    let chains = parser::parse("cat <<'END'\n$N\nEND").unwrap();
+    assert!(chains[0].statements[0].literal_input);
+    assert_eq!(parser::parse(&chains[0].text()).unwrap(), chains);
+}
+
+#[test]
+fn resource_usage_is_recorded_when_asked_for() {
+    let mut state = empty_state();
+    eval("true", &mut state);
+    assert_eq!(get_var(&state, "RUSAGE_MAXRSS"), None);
+    eval("set RUSAGE=true", &mut state);
+    eval("sh -c \"exit 3\"", &mut state);
+    assert_eq!(get_status(&state), 3);
+    assert!(get_var(&state, "RUSAGE_MAXRSS").is_some_and(|v| v.parse::<u64>().unwrap() > 0));
+    assert!(get_var(&state, "RUSAGE_USER").is_some_and(|v| v.parse::<f64>().is_ok()));
+    eval("echo piped 1@; cat 0@ 1@", &mut state);
+    assert_eq!(state.pipe.as_deref(), Some(&b"piped\n"[..]));
+}
+
+#[test]
+fn scheduling_options_parse() {
+    assert_eq!(sched::parse_cpus("0-3,6,2"), Some(vec![0, 1, 2, 3, 6]));
+    assert_eq!(sched::parse_cpus("3-1"), None);
+    assert_eq!(sched::parse_cpus("a"), None);
+    assert_eq!(sched::parse_cpus("0-18446744073709551615"), None);
+    assert_eq!(sched::parse_cpus("1,1024"), None);
+    assert_eq!(sched::parse_io("idle"), Some((sched::IoClass::Idle, 4)));
+    assert_eq!(
+        sched::parse_io("be:7"),
+        Some((sched::IoClass::BestEffort, 7))
+    );
+    assert_eq!(sched::parse_io("realtime:8"), None);
+
+    let mut state = empty_state();
+    assert!(
+        run_builtin("nice -n x true", "", &mut state)
+            .2
+            .contains("invalid adjustment: x")
+    );
+    assert_eq!(run_builtin("nice -n 1 true", "", &mut state).0, 0);
+    assert!(run_builtin("cpus 0", "", &mut state).2.contains("usage"));
+}
+
+#[test]
+fn both_streams_and_duplicated_ones_are_redirected() {
+    let command = parser::command("prog 12@out 2@1").unwrap();
+    assert_eq!(command.words.len(), 1);
+    assert_eq!(
+        command.indirects,
+        [
+            IndirectRes::Stdout(Indirect::Path(PathBuf::from("out"))),
+            IndirectRes::Stderr(Indirect::Path(PathBuf::from("out"))),
+            IndirectRes::Stderr(Indirect::Stdout),
+        ]
+    );
+    assert!(parser::parse("prog 21@").is_ok());
+
+    let mut state = empty_state();
+    eval("sh -c \"echo out; echo err >&2\" 1@ 2@1", &mut state);
+    assert_eq!(state.pipe.as_deref(), Some(&b"out\nerr\n"[..]));
+}
+
+#[test]
+fn files_are_overwritten_or_added_to() {
+    let out = std::env::temp_dir().join(format!("sesh-test-{}.clobber", std::process::id()));
+    let mut state = empty_state();
+    eval(
+        &format!("echo a 1@{0}; echo b 1@{0}", out.display()),
+        &mut state,
+    );
+    assert_eq!(std::fs::read_to_string(&out).unwrap(), "a\nb\n");
+    eval(&format!("printf c 1@>{}", out.display()), &mut state);
+    assert_eq!(std::fs::read_to_string(&out).unwrap(), "c");
+
+    eval("set NOCLOBBER=true", &mut state);
+    eval(&format!("printf d 1@>{}", out.display()), &mut state);
+    assert_eq!(std::fs::read_to_string(&out).unwrap(), "c");
+    assert_eq!(get_status(&state), 1);
+    eval(&format!("echo e 1@>{}", out.display()), &mut state);
+    assert_eq!(std::fs::read_to_string(&out).unwrap(), "c");
+    eval(&format!("echo f 1@>|{}", out.display()), &mut state);
+    assert_eq!(std::fs::read_to_string(&out).unwrap(), "f\n");
+    std::fs::remove_file(&out).unwrap();
+}
+
+#[test]
+fn prefill_starts_the_next_line() {
+    let mut state = empty_state();
+    eval("prefill (git push --force-with-lease)", &mut state);
+    assert_eq!(
+        state.prefill.as_deref(),
+        Some("git push --force-with-lease")
+    );
+    eval("prefill clear", &mut state);
+    assert_eq!(state.prefill, None);
+    eval("prefill a b", &mut state);
+    assert_eq!(get_status(&state), 1);
+}
+
+#[test]
+#[cfg(unix)]
+#[allow(clippy::arc_with_non_send_sync)]
+fn exit_warns_once_about_jobs() {
+    struct Terminal;
+    impl Write for Terminal {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    impl term::Terminal for Terminal {
+        fn suspend_raw_mode(&self) -> std::io::Result<()> {
+            Ok(())
+        }
+        fn activate_raw_mode(&self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut state = empty_state();
+    state.raw_term = Some(Arc::new(RwLock::new(Box::new(Terminal))));
+    assert!(can_exit(&mut state).is_ok());
+    let mut child = std::process::Command::new("sleep")
+        .arg("10")
+        .spawn()
+        .unwrap();
+    state.jobs.push(job::Job {
+        id: 1,
+        pid: child.id() as i32,
+        statement: "sleep 10".to_string(),
+        status: job::JobStatus::Running,
+    });
+    state.entries = 5;
+    let warning = can_exit(&mut state).unwrap_err();
+    assert!(warning.contains("1 running jobs"), "{}", warning);
+    // exiting straight after goes ahead
+    state.entries = 6;
+    assert!(can_exit(&mut state).is_ok());
+    // anything else in between warns again
+    state.entries = 8;
+    assert!(can_exit(&mut state).is_err());
+    eval("set CHECK_JOBS=false", &mut state);
+    assert!(can_exit(&mut state).is_ok());
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn transcripts_need_a_terminal() {
+    let mut state = empty_state();
+    eval("transcript start", &mut state);
+    assert_eq!(get_status(&state), 1);
+    eval("transcript start --cast --stdin", &mut state);
+    assert_eq!(get_status(&state), 1);
+    let (_, _, stderr) = run_builtin("transcript start --stdin", "", &mut state);
+    assert!(stderr.contains("usage: transcript"), "{}", stderr);
+    eval("transcript stop", &mut state);
+    assert_eq!(get_status(&state), 1);
+    eval("transcript", &mut state);
+    assert_eq!(get_status(&state), 1);
+}
+
+#[test]
+fn transcripts_replay_what_was_shown() {
+    let dir = std::env::temp_dir().join(format!("sesh-test-{}-replay", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let replay = |name: &str, text: &str| {
+        let path = dir.join(name);
+        std::fs::write(&path, text).unwrap();
+        let mut out = Vec::new();
+        transcript::replay(&path, 1000.0, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    // only the output is shown, whatever was typed
+    let cast = concat!(
+        "{\"version\": 2, \"width\": 80, \"height\": 24}\n",
+        "[0.1, \"o\", \"$ \"]\n",
+        "[0.2, \"i\", \"ls\\r\"]\n",
+        "[0.3, \"o\", \"ls\\r\\nfile\\r\\n\"]\n",
+        "not an event\n",
+    );
+    assert_eq!(replay("a.cast", cast), "$ ls\r\nfile\r\n");
+    // without the lines saying when it started and ended
+    let plain = "Transcript started on now\n$ ls\nfile\n$ \nTranscript ended on later\n";
+    assert_eq!(replay("a.txt", plain), "$ ls\nfile\n$ ");
+    let mut out = Vec::new();
+    assert!(transcript::replay(&dir.join("a.txt"), 0.0, &mut out).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn colours_fit_the_terminal() {
+    use color::{Depth, detect, fit_to, parse_colors};
+    assert_eq!(detect("truecolor", "xterm", |_| Some(8)), Depth::TrueColor);
+    assert_eq!(detect("", "xterm-256color", |_| None), Depth::Palette);
+    assert_eq!(detect("", "xterm-256color", |_| Some(8)), Depth::Basic);
+    assert_eq!(detect("", "vt100", |_| None), Depth::Basic);
+    if let Ok(entry) = std::fs::read("/lib/terminfo/x/xterm-256color") {
+        assert_eq!(parse_colors(&entry), Some(256));
+    }
+    assert_eq!(parse_colors(b"not terminfo"), None);
+
+    let orange = "\x1b[38;2;255;165;0;1mhi\x1b[39m";
+    assert_eq!(fit_to(orange, Depth::TrueColor), orange);
+    assert_eq!(fit_to(orange, Depth::Palette), "\x1b[38;5;214;1mhi\x1b[39m");
+    assert_eq!(fit_to(orange, Depth::Basic), "\x1b[33;1mhi\x1b[39m");
+    assert_eq!(
+        fit_to("\x1b[48;5;196m\x1b[0K", Depth::Basic),
+        "\x1b[101m\x1b[0K"
+    );
+    assert_eq!(fit_to("\x1b[48;5;196m", Depth::Palette), "\x1b[48;5;196m");
+}
+
+#[test]
+fn chpwd_hooks_run_after_changing_directory() {
+    let dir = std::env::temp_dir().join(format!("sesh-chpwd-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    for name in ["a", "b", "c", "d", "e", ".hidden"] {
+        std::fs::write(dir.join(name), "").unwrap();
+    }
+    let mut state = empty_state();
+    run_builtin("chpwd (set LEFT=$OLDPWD)", "", &mut state);
+    eval(&format!("cd {}", dir.display()), &mut state);
+    assert_eq!(get_var(&state, "LEFT").as_deref(), Some("/"));
+    assert_eq!(get_status(&state), 0);
+    assert_eq!(state.chpwd_hooks.len(), 1);
+
+    let (_, out, _) = run_builtin("summary", "", &mut state);
+    assert_eq!(
+        out.lines().next(),
+        Some("6 entries: a, b, c, d, e, and 1 more")
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn history_is_listed_and_searched() {
+    let mut state = empty_state();
+    state.history = ["cd src", "cargo build", "git status", "cargo test"]
+        .map(String::from)
+        .to_vec();
+    assert_eq!(
+        run_builtin("history list 2", "", &mut state).1,
+        "3: git status\n4: cargo test\n"
+    );
+    assert_eq!(
+        run_builtin("history search cargo", "", &mut state).1,
+        "2: cargo build\n4: cargo test\n"
+    );
+    assert_eq!(
+        run_builtin("history search -r ^c.*d$", "", &mut state).1,
+        "2: cargo build\n"
+    );
+    assert_eq!(run_builtin("history search nothing", "", &mut state).0, 1);
+    assert_eq!(run_builtin("history search -r +", "", &mut state).0, 1);
+    assert_eq!(run_builtin("history delete 9", "", &mut state).0, 1);
+    assert_eq!(state.history.len(), 4);
+}
+
+#[test]
+fn history_is_expanded() {
+    let history = ["cd src", "cargo build", "git status"]
+        .map(String::from)
+        .to_vec();
+    let expand = |line: &str| expand_history(line, &history);
+    assert_eq!(expand("!!"), Ok(Some("git status".to_string())));
+    assert_eq!(
+        expand("!1; !-2"),
+        Ok(Some("cd src; cargo build".to_string()))
+    );
+    assert_eq!(
+        expand("sudo !car"),
+        Ok(Some("sudo cargo build".to_string()))
+    );
+    assert_eq!(expand("! nop; echo '!!' \\!! [!a]"), Ok(None));
+    assert_eq!(expand("!4"), Err("!4".to_string()));
+    assert_eq!(expand("!ls"), Err("!ls".to_string()));
+}
+
+#[test]
+fn aliases_carry_descriptions() {
+    let mut state = empty_state();
+    eval(
+        "alias gst=\"git status\" --desc \"what changed\"; alias gl \"git log\"",
+        &mut state,
+    );
+    assert_eq!(state.aliases[0].desc.as_deref(), Some("what changed"));
+    assert_eq!(state.aliases[1].to, "git log");
+
+    let (_, out, _) = run_builtin("alias gst", "", &mut state);
+    assert_eq!(out, "`gst`: `git status` - what changed\n");
+    let (_, out, _) = run_builtin("help gst", "", &mut state);
+    assert_eq!(out, "gst (alias for `git status`): what changed\n");
+    assert!(
+        run_builtin("help", "", &mut state)
+            .1
+            .contains("\ngst: what changed\n")
+    );
+    let completion = complete::complete("g", 1, &state);
+    assert!(
+        completion
+            .choices
+            .contains(&"gst (what changed)".to_string())
+    );
+    assert!(completion.choices.contains(&"gl".to_string()));
+}
+
+#[test]
+fn scope_exits_run_last_first_when_a_script_ends() {
+    let dir = std::env::temp_dir().join(format!("sesh-test-{}-scope", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (script, lock) = (dir.join("script.sesh"), dir.join("lock"));
+    std::fs::write(&lock, "").unwrap();
+    std::fs::write(
+        &script,
+        format!("scope-exit (rm {}); ! nop", lock.display()),
+    )
+    .unwrap();
+    let mut state = empty_state();
+    run_builtin("scope-exit (set LAST=outer)", "", &mut state);
+    eval(&format!("source {}", script.display()), &mut state);
+    assert!(!lock.exists());
+    assert_eq!(
+        state.scope_exits,
+        [ScopeExit::Statement("set LAST=outer".to_string())]
+    );
+
+    run_builtin("scope-exit (set LAST=inner)", "", &mut state);
+    eval("! nop", &mut state);
+    run_scope_exits(0, &mut state);
+    assert_eq!(get_var(&state, "LAST").as_deref(), Some("outer"));
+    assert_eq!(get_status(&state), 1);
+    assert!(state.scope_exits.is_empty());
+    assert_eq!(run_builtin("scope-exit", "", &mut state).0, 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn history_is_limited_and_timed() {
+    let path = std::env::temp_dir().join(format!("sesh-test-{}.history", std::process::id()));
+    let mut state = empty_state();
+    eval("set HISTDEDUP=true HISTSIZE=2 HISTFILESIZE=3", &mut state);
+    for entry in ["a", "a", "b", "c"] {
+        add_history(entry, &path, &mut state).unwrap();
+    }
+    assert_eq!(state.history, ["b", "c"]);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "a\nb\nc\n");
+
+    eval("set HISTTIMESTAMPS=true", &mut state);
+    add_history("d", &path, &mut state).unwrap();
+    // another session running the same thing
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap()
+        .write_all(b"d\n")
+        .unwrap();
+    merge_history(&path, &state).unwrap();
+    let history = load_history(&path);
+    assert_eq!(history.len(), 3);
+    assert_eq!(history[1], ("c".to_string(), None));
+    assert_eq!(history[2].0, "d");
+    assert!(history[2].1.is_some());
+    assert!(
+        run_builtin("history list 1", "", &mut state)
+            .1
+            .starts_with("2: [")
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn temporary_files_are_made_and_cleaned_up() {
+    let mut state = empty_state();
+    run_builtin("mktempf -d --var TMPDIR_TEST sesh-test.", "", &mut state);
+    let dir = PathBuf::from(get_var(&state, "TMPDIR_TEST").unwrap());
+    assert!(dir.is_dir());
+    assert!(
+        dir.file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("sesh-test.")
+    );
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(dir.metadata().unwrap().permissions().mode() & 0o777, 0o700);
+    }
+
+    // removing them isn't a command the guard could refuse
+    state.guards = guard::DEFAULT_PATTERNS.map(|v| v.to_string()).to_vec();
+    run_builtin("mktempf -d --cleanup --var CLEANED", "", &mut state);
+    let cleaned = PathBuf::from(get_var(&state, "CLEANED").unwrap());
+    std::fs::write(cleaned.join("inside"), "").unwrap();
+    run_builtin("mktempf --cleanup", "", &mut state);
+    let Focus::Str(file) = &state.focus else {
+        panic!("the focus isn't a path");
+    };
+    let file = PathBuf::from(file);
+    assert!(file.is_file());
+    run_scope_exits(0, &mut state);
+    assert!(!file.exists());
+    assert!(!cleaned.exists());
+    assert_eq!(run_builtin("mktempf a/b", "", &mut state).0, 1);
+    std::fs::remove_dir(&dir).unwrap();
+}
+
+#[test]
+fn locks_are_taken_in_turn() {
+    let name = format!("sesh-test-{}", std::process::id());
+    let mut state = empty_state();
+    let path = builtins::lock_path(&name, &state).unwrap();
+    let held = std::fs::File::create(&path).unwrap();
+    held.lock().unwrap();
+    let (status, _, stderr) =
+        run_builtin(&format!("lock -n {} (set LOCKED=1)", name), "", &mut state);
+    assert_eq!(status, 1);
+    assert!(stderr.contains("is locked"));
+    assert_eq!(get_var(&state, "LOCKED"), None);
+
+    drop(held);
+    let (status, _, _) = run_builtin(
+        &format!("lock {} (set LOCKED=1; ! nop)", name),
+        "",
+        &mut state,
+    );
+    assert_eq!(status, 1);
+    assert_eq!(get_var(&state, "LOCKED").as_deref(), Some("1"));
+    std::fs::remove_file(&path).unwrap();
+
+    // a directory others can get into isn't trusted with the locks
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = path.parent().unwrap();
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(builtins::lock_path(&name, &state).is_err());
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700)).unwrap();
+    }
+}
+
+#[test]
+fn interrupted_programs_drop_the_rest_of_the_line() {
+    let mut state = empty_state();
+    eval("sh -c 'kill -INT $$'; set AFTER=1", &mut state);
+    assert_eq!(get_status(&state), 130);
+    assert_eq!(get_var(&state, "AFTER"), None);
+    eval(
+        "while (nop) (sh -c 'kill -INT $$'; set AFTER=1)",
+        &mut state,
+    );
+    assert_eq!(get_status(&state), 130);
+    assert_eq!(get_var(&state, "AFTER"), None);
+    ABANDONED.set(false);
+    eval("sh -c 'kill -TERM $$'", &mut state);
+    assert_eq!(get_status(&state), 143);
+    eval("sh -c 'kill -TERM $$'; set AFTER=1", &mut state);
+    assert_eq!(get_var(&state, "AFTER").as_deref(), Some("1"));
+}
+
+#[test]
+fn par_needs_statements_and_a_count() {
+    let mut state = empty_state();
+    assert_eq!(run_builtin("par", "", &mut state).0, 1);
+    let (status, _, stderr) = run_builtin("par -j 0 (nop)", "", &mut state);
+    assert_eq!(status, 1);
+    assert!(stderr.contains("invalid count 0"));
+    assert!(state.jobs.is_empty());
+}
+
+#[test]
+fn confirm_and_prompt_ask_until_the_answer_is_valid() {
+    let mut state = empty_state();
+    let (status, stdout, _) = run_builtin("confirm Sure?", "maybe\nY\n", &mut state);
+    assert_eq!(status, 0);
+    assert_eq!(stdout, "Sure? [y/n] Sure? [y/n] ");
+    assert_eq!(
+        run_builtin("confirm --default n Sure?", "\n", &mut state).0,
+        1
+    );
+    assert_eq!(run_builtin("confirm Sure?", "", &mut state).0, 1);
+
+    let (status, _, stderr) = run_builtin(
+        "prompt Age: AGE --validate [0-9]+",
+        "12a\n 34\n",
+        &mut state,
+    );
+    assert_eq!(status, 0);
+    assert!(stderr.contains("12a isn't valid"));
+    assert_eq!(get_var(&state, "AGE").as_deref(), Some("34"));
+    let (_, stdout, _) = run_builtin("prompt Name: NAME --default sesh", "\n", &mut state);
+    assert_eq!(stdout, "Name: [sesh] ");
+    assert_eq!(get_var(&state, "NAME").as_deref(), Some("sesh"));
+}
+
+#[test]
+fn only_exported_variables_reach_programs() {
+    let mut state = empty_state();
+    eval(
+        "set SESH_TEST_LOCAL=1; export SESH_TEST_EXPORTED=2",
+        &mut state,
+    );
+    let (_, stdout, _) = run_builtin("export", "", &mut state);
+    assert_eq!(stdout, "SESH_TEST_EXPORTED\n");
+    // setting it again keeps it exported
+    eval("set SESH_TEST_EXPORTED=3", &mut state);
+    assert!(is_exported(&state, "SESH_TEST_EXPORTED"));
+    assert!(!is_exported(&state, "SESH_TEST_LOCAL"));
+    assert!(is_exported(&state, "PATH"));
+
+    eval(
+        "unset SESH_TEST_EXPORTED; export SESH_TEST_LOCAL",
+        &mut state,
+    );
+    assert!(!is_exported(&state, "SESH_TEST_EXPORTED"));
+    assert!(is_exported(&state, "SESH_TEST_LOCAL"));
+    assert_eq!(run_builtin("export SESH_TEST_UNSET", "", &mut state).0, 1);
+}
+
+#[test]
+fn units_go_both_ways() {
+    assert_eq!(units::parse_size("1.5G"), Some(1610612736));
+    assert_eq!(units::parse_size("20MiB"), Some(20971520));
+    assert_eq!(units::parse_size("3KB"), Some(3000));
+    assert_eq!(units::parse_size("512"), Some(512));
+    assert_eq!(units::parse_size("2X"), None);
+    assert_eq!(units::format_size(1610612736), "1.5G");
+    assert_eq!(units::format_size(1000), "1000B");
+    assert_eq!(units::parse_duration("90m"), Some(5400.0));
+    assert_eq!(units::parse_duration("1.5h"), Some(5400.0));
+    assert_eq!(units::parse_duration("1h30m15"), Some(5415.0));
+    assert_eq!(units::parse_duration("5y"), None);
+    assert_eq!(units::format_duration(5415.0), "1h30m15s");
+    assert_eq!(units::format_duration(0.25), "0.25s");
+
+    let mut state = empty_state();
+    run_builtin("units --var LIMIT size 2G", "", &mut state);
+    assert_eq!(get_var(&state, "LIMIT").as_deref(), Some("2147483648"));
+    run_builtin("units --human duration 86400", "", &mut state);
+    assert_eq!(state.focus, Focus::Str("1d".to_string()));
+    let (status, _, stderr) = run_builtin("units duration soon", "", &mut state);
+    assert_eq!(status, 1);
+    assert!(stderr.contains("invalid duration soon"));
+}
+
+#[test]
+fn longer_variable_names_are_substituted_first() {
+    let mut state = empty_state();
+    eval("set HOME=/home/me HOMEBREW_PREFIX=/opt/brew", &mut state);
+    assert_eq!(
+        expand_vars("$HOMEBREW_PREFIX $HOME $HOMER", &state),
+        "/opt/brew /home/me /home/meR"
+    );
+    let path = std::env::var("PATH").unwrap();
+    assert!(is_inherited(&ShellVar {
+        name: "PATH".to_string(),
+        value: path,
+        exported: true,
+    }));
+    assert!(!is_inherited(&ShellVar {
+        name: "PATH".to_string(),
+        value: "/nowhere".to_string(),
+        exported: true,
+    }));
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn procs_finds_this_process() {
+    let me = procs::list()
+        .unwrap()
+        .into_iter()
+        .find(|v| v.pid == std::process::id())
+        .unwrap();
+    assert!(me.command.contains("sesh"));
+    assert!(me.mem > 0.0);
+
+    let mut state = empty_state();
+    let (status, _, _) = run_builtin(&format!("procs --user {} sesh", me.user), "", &mut state);
+    assert_eq!(status, 0);
+    let Focus::Vec(rows) = &state.focus else {
+        panic!("the focus isn't a list");
+    };
+    assert!(
+        rows.iter()
+            .any(|v| { matches!(v, Focus::Vec(row) if row[0] == Focus::Str(me.pid.to_string())) })
+    );
+    assert_eq!(run_builtin("procs ^no-such-process$", "", &mut state).0, 1);
+}
+
+#[test]
+#[cfg(unix)]
+fn signals_are_named_and_statuses_explained() {
+    let mut state = empty_state();
+    eval("sh -c \"kill -9 $$\"", &mut state);
+    assert_eq!(get_status(&state), 137);
+    assert_eq!(get_var(&state, "STATUS_SIGNAL").as_deref(), Some("SIGKILL"));
+    eval("sh -c \"exit 137\"", &mut state);
+    assert_eq!(get_var(&state, "STATUS_SIGNAL").as_deref(), Some(""));
+
+    assert_eq!(
+        explain::explain(139, Some(libc::SIGSEGV)).as_deref(),
+        Some("exit 139: segmentation fault (SIGSEGV)")
+    );
+    assert_eq!(
+        explain::explain(137, None).as_deref(),
+        Some("exit 137: likely from a program killed by SIGKILL")
+    );
+    assert_eq!(
+        explain::explain(127, None).as_deref(),
+        Some("exit 127: command not found")
+    );
+    assert_eq!(explain::explain(1, None), None);
+}
+
+#[test]
+fn dfp_and_netif_find_the_root_and_loopback() {
+    let mounted =
+        [("/", 100, 40, 50), ("/home", 10, 5, 5)].map(|(mount, size, used, available)| {
+            machine::Filesystem {
+                mount: mount.into(),
+                kind: "ext4".to_string(),
+                source: "/dev/sda1".to_string(),
+                size,
+                used,
+                available,
+            }
+        });
+    assert_eq!(mounted[0].percent(), 45);
+    assert_eq!(
+        machine::filesystem_of(std::path::Path::new("/"), &mounted),
+        Some(mounted[0].clone())
+    );
+
+    let mut state = empty_state();
+    #[cfg(target_os = "linux")]
+    {
+        assert_eq!(run_builtin("dfp /", "", &mut state).0, 0);
+        let Focus::Vec(rows) = &state.focus else {
+            panic!("the focus isn't a list");
+        };
+        assert!(matches!(&rows[..], [Focus::Vec(row)] if row[0] == Focus::Str("/".to_string())));
+    }
+    #[cfg(unix)]
+    {
+        let loopback = machine::interfaces()
+            .unwrap()
+            .into_iter()
+            .find(|v| v.addresses.contains(&"127.0.0.1/8".to_string()));
+        if let Some(loopback) = loopback {
+            let (status, _, _) = run_builtin(&format!("netif {}", loopback.name), "", &mut state);
+            assert_eq!(status, 0);
+        }
+    }
+    assert_eq!(run_builtin("netif no-such-interface", "", &mut state).0, 1);
+}
+
+#[test]
+fn braced_variables_and_fallbacks_are_expanded() {
+    let mut state = empty_state();
+    eval("set FOO=foo EMPTY=", &mut state);
+    let mut expand = |text: &str| expand_braced(text, &mut state);
+    assert_eq!(expand("${FOO}_bar").as_deref(), Ok("foo_bar"));
+    assert_eq!(expand("[${UNSET}]").as_deref(), Ok("[]"));
+    assert_eq!(expand("${EMPTY:-${FOO}}").as_deref(), Ok("foo"));
+    assert_eq!(expand("${FOO:-other}").as_deref(), Ok("foo"));
+    assert_eq!(expand("${NEW:=made}").as_deref(), Ok("made"));
+    assert_eq!(expand("${NEW:?gone}").as_deref(), Ok("made"));
+    assert_eq!(
+        expand("${UNSET:?needed here}"),
+        Err("UNSET: needed here".to_string())
+    );
+    assert_eq!(expand("${UNSET:?}"), Err("UNSET: not set".to_string()));
+    assert!(expand("${FOO:+x}").is_err());
+    assert_eq!(expand("${STATUS:=1}"), Ok("0".to_string()));
+    assert_eq!(get_var(&state, "NEW").as_deref(), Some("made"));
+
+    // each statement is expanded when it runs, and the rest of the line stops at a failure
+    eval(
+        "set LAST=before; echo ${UNSET:?missing}; set LAST=after",
+        &mut state,
+    );
+    assert_eq!(get_status(&state), 1);
+    assert_eq!(get_var(&state, "LAST").as_deref(), Some("before"));
+    eval(
+        "nop || set X=${SKIPPED:=set}; nop || echo ${UNSET:?missing}; set Y=${LAST}",
+        &mut state,
+    );
+    assert_eq!(get_var(&state, "SKIPPED"), None);
+    assert_eq!(get_var(&state, "Y").as_deref(), Some("before"));
+    eval("set OUT=; for F in a b (set OUT=${OUT}${F})", &mut state);
+    assert_eq!(get_var(&state, "OUT").as_deref(), Some("ab"));
+}
+
+#[test]
+fn editing_goes_by_graphemes() {
+    let mut line = "cafe\u{301} 👍🏽".to_string();
+    let mut cursor = line.chars().count();
+    assert_eq!(grapheme_boundary(&line, cursor, false), 6);
+    edit_line(&mut line, &mut cursor, '\x7F', false);
+    assert_eq!((line.as_str(), cursor), ("cafe\u{301} ", 6));
+    cursor = 3;
+    edit_line(&mut line, &mut cursor, '\x04', false);
+    assert_eq!(line, "caf ");
+    assert_eq!(text_width("日本語 ok", 0, 3), 6);
+    assert_eq!(text_width("e\u{301}", 0, 2), 1);
+
+    let mut out = Vec::new();
+    move_cursor(&mut out, "日本語", 3, 1).unwrap();
+    move_cursor(&mut out, "\u{301}", 0, 1).unwrap();
+    assert_eq!(out, b"\x1b[4D");
+}
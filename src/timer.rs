@@ -0,0 +1,99 @@
+//! Statements scheduled with `defer delay` and `at`
+//!
+//! Timers only run while the shell waits at the prompt, so a long-running command holds them back
+//! until it's done.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime};
+
+/// A statement to run later.
+#[derive(Clone, Debug)]
+pub struct Timer {
+    /// What it's listed and cancelled as
+    pub id: usize,
+    /// When to run it
+    pub when: DateTime<Local>,
+    /// What to run
+    pub statement: String,
+}
+
+/// Parse a delay like `30s`, `10m` or `1h30m`. A number without a unit is in seconds. One too long
+/// to count in seconds is `None`.
+pub fn parse_delay(text: &str) -> Option<Duration> {
+    let mut total = 0u64;
+    let mut number = String::new();
+    for ch in text.chars() {
+        if ch.is_ascii_digit() {
+            number.push(ch);
+            continue;
+        }
+        let unit = match ch {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return None,
+        };
+        let seconds = number.parse::<u64>().ok()?.checked_mul(unit)?;
+        total = total.checked_add(seconds)?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        total = total.checked_add(number.parse::<u64>().ok()?)?;
+    }
+    (!text.is_empty()).then(|| Duration::from_secs(total))
+}
+
+/// The next time the clock shows `HH:MM` or `HH:MM:SS`, today or tomorrow.
+pub fn parse_time(text: &str) -> Option<DateTime<Local>> {
+    let time = NaiveTime::parse_from_str(text, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(text, "%H:%M"))
+        .ok()?;
+    let now = Local::now();
+    let mut day = now.date_naive();
+    if time <= now.time() {
+        day = day.succ_opt()?;
+    }
+    day.and_time(time).and_local_timezone(Local).earliest()
+}
+
+/// Parse a date like `2024-05-01`, `2024-05-01 14:30[:00]`, an RFC 3339 timestamp or a number of
+/// seconds since 1970, in local time unless it says otherwise.
+pub fn parse_date(text: &str) -> Option<DateTime<Local>> {
+    if let Ok(seconds) = text.parse() {
+        return DateTime::from_timestamp(seconds, 0).map(|v| v.with_timezone(&Local));
+    }
+    if let Ok(date) = DateTime::parse_from_rfc3339(text) {
+        return Some(date.with_timezone(&Local));
+    }
+    let naive = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M"))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(text, "%Y-%m-%d").map(|v| v.and_time(NaiveTime::MIN))
+        })
+        .ok()?;
+    naive.and_local_timezone(Local).earliest()
+}
+
+/// Add a timer, returning its id.
+pub fn add(when: DateTime<Local>, statement: String, state: &mut super::State) -> usize {
+    let id = state.timers.iter().map(|v| v.id + 1).max().unwrap_or(1);
+    state.timers.push(Timer {
+        id,
+        when,
+        statement,
+    });
+    id
+}
+
+/// Take out the timers that are due, earliest first.
+pub fn take_due(state: &mut super::State) -> Vec<Timer> {
+    let now = Local::now();
+    let (mut due, pending) = std::mem::take(&mut state.timers)
+        .into_iter()
+        .partition::<Vec<Timer>, _>(|v| v.when <= now);
+    state.timers = pending;
+    due.sort_by_key(|v| v.when);
+    due
+}
@@ -0,0 +1,367 @@
+//! Recording transcripts of the session
+//!
+//! Like `script`, `transcript start` runs a new sesh on a pseudo-terminal and copies everything
+//! between it and the real terminal, writing it down on the way. The new shell picks up the
+//! session, minus jobs and secret variables, and `transcript stop` hands it back before exiting.
+//! Exiting it any other way exits the shell that started the recording too. `sesh --replay` plays
+//! transcripts back. Recording is only available on unix.
+
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::Instant,
+};
+
+use super::i18n::tr;
+
+/// Where the shell, if it's the one being recorded, is recorded to.
+static RECORDING: OnceLock<PathBuf> = OnceLock::new();
+
+/// Note that this shell is being recorded. Called at startup.
+pub fn set_recording(path: PathBuf) {
+    let _ = RECORDING.set(path);
+}
+
+/// Where this shell is being recorded to, if it is.
+pub fn recording() -> Option<&'static Path> {
+    RECORDING.get().map(|v| v.as_path())
+}
+
+/// The session the recorded shell starts from.
+fn session_in(pid: u32) -> String {
+    format!("transcript-{}", pid)
+}
+
+/// The session the recorded shell hands back when it's stopped.
+fn session_out(pid: u32) -> String {
+    format!("transcript-{}-stopped", pid)
+}
+
+/// The session the shell being recorded hands back to the one that started it.
+#[cfg(unix)]
+pub fn handed_back() -> String {
+    session_out(std::os::unix::process::parent_id())
+}
+
+/// How a recording ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Ended {
+    /// With `transcript stop`, handing back the session to restore
+    Stopped(String),
+    /// With the recorded shell exiting, with its status
+    Exited(i32),
+}
+
+/// Writes what goes through the terminal to a file.
+struct Recorder {
+    /// The transcript
+    file: io::BufWriter<std::fs::File>,
+    /// Whether it's in asciinema's format rather than plain text
+    cast: bool,
+    /// Whether what's typed is written down too, passwords and all
+    stdin: bool,
+    /// When the recording started
+    start: Instant,
+    /// The end of the last output, if it stopped in the middle of a character
+    carry: Vec<u8>,
+}
+
+impl Recorder {
+    /// Start a transcript, writing its header.
+    fn new(
+        file: std::fs::File,
+        cast: bool,
+        stdin: bool,
+        (width, height): (u16, u16),
+    ) -> io::Result<Self> {
+        let mut file = io::BufWriter::new(file);
+        let now = chrono::Local::now();
+        if cast {
+            let header = serde_json::json!({
+                "version": 2,
+                "width": width,
+                "height": height,
+                "timestamp": now.timestamp(),
+                "env": { "SHELL": "sesh", "TERM": std::env::var("TERM").unwrap_or_default() },
+            });
+            writeln!(file, "{}", header)?;
+        } else {
+            writeln!(
+                file,
+                "{}",
+                tr!("Transcript started on {}", now.to_rfc3339())
+            )?;
+        }
+        file.flush()?;
+        Ok(Self {
+            file,
+            cast,
+            stdin,
+            start: Instant::now(),
+            carry: Vec::new(),
+        })
+    }
+
+    /// Write down what was typed if that was asked for, which only asciinema's format keeps apart
+    /// from the output.
+    fn input(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if self.cast && self.stdin {
+            self.event("i", &String::from_utf8_lossy(bytes))?;
+        }
+        Ok(())
+    }
+
+    /// Write down some output.
+    fn output(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if !self.cast {
+            self.file.write_all(bytes)?;
+            return self.file.flush();
+        }
+        // events are text, so a character split between reads waits for the rest of it
+        self.carry.extend_from_slice(bytes);
+        let valid = match std::str::from_utf8(&self.carry) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => self.carry.len(),
+        };
+        let rest = self.carry.split_off(valid);
+        let text = String::from_utf8_lossy(&std::mem::replace(&mut self.carry, rest)).to_string();
+        if !text.is_empty() {
+            self.event("o", &text)?;
+        }
+        Ok(())
+    }
+
+    /// Write an asciinema event.
+    fn event(&mut self, kind: &str, text: &str) -> io::Result<()> {
+        let time = self.start.elapsed().as_secs_f64();
+        writeln!(self.file, "{}", serde_json::json!([time, kind, text]))?;
+        self.file.flush()
+    }
+
+    /// Finish the transcript.
+    fn finish(mut self) -> io::Result<()> {
+        if self.cast {
+            let rest = std::mem::take(&mut self.carry);
+            if !rest.is_empty() {
+                self.event("o", &String::from_utf8_lossy(&rest))?;
+            }
+        } else {
+            let now = chrono::Local::now();
+            writeln!(
+                self.file,
+                "\n{}",
+                tr!("Transcript ended on {}", now.to_rfc3339())
+            )?;
+        }
+        self.file.flush()
+    }
+}
+
+/// Play back a transcript to `out`, `speed` times as fast as it was recorded. Plain ones have no
+/// timing and are shown all at once.
+pub fn replay(path: &Path, speed: f64, out: &mut dyn Write) -> io::Result<()> {
+    if !(speed > 0.0 && speed.is_finite()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            super::i18n::translate("the speed has to be more than 0"),
+        ));
+    }
+    let text = std::fs::read(path)?;
+    if !text.starts_with(b"{") {
+        // the lines saying when it started and ended aren't part of what was shown
+        let text = text.splitn(2, |v| *v == b'\n').nth(1).unwrap_or_default();
+        let text = text.strip_suffix(b"\n").unwrap_or(text);
+        let end = text.iter().rposition(|v| *v == b'\n').unwrap_or(text.len());
+        out.write_all(&text[..end])?;
+        return out.flush();
+    }
+    let start = Instant::now();
+    // the first line is the header
+    for line in String::from_utf8_lossy(&text).lines().skip(1) {
+        let Ok(serde_json::Value::Array(event)) = serde_json::from_str(line) else {
+            continue;
+        };
+        let field = |i: usize| event.get(i).unwrap_or(&serde_json::Value::Null);
+        let (Some(time), Some("o"), Some(output)) =
+            (field(0).as_f64(), field(1).as_str(), field(2).as_str())
+        else {
+            continue;
+        };
+        let due = std::time::Duration::from_secs_f64(time.max(0.0) / speed);
+        if let Some(wait) = due.checked_sub(start.elapsed()) {
+            std::thread::sleep(wait);
+        }
+        out.write_all(output.as_bytes())?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// Record a new sesh carrying on the session to a file until it's stopped or exits.
+#[cfg(not(unix))]
+pub fn record(_: &Path, _: bool, _: bool, _: &mut super::State) -> io::Result<Ended> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        super::i18n::translate("transcripts aren't supported on this platform"),
+    ))
+}
+
+/// Record a new sesh carrying on the session to a file until it's stopped or exits. What's typed
+/// is only recorded if `stdin` is true, and only in asciinema's format.
+#[cfg(unix)]
+pub fn record(path: &Path, cast: bool, stdin: bool, state: &mut super::State) -> io::Result<Ended> {
+    use std::os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::process::CommandExt,
+    };
+
+    let file = std::fs::File::create(state.working_dir.join(path))?;
+    let mut size = unsafe { std::mem::zeroed::<libc::winsize>() };
+    let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+    unsafe {
+        if libc::ioctl(0, libc::TIOCGWINSZ, &mut size) < 0 || libc::tcgetattr(0, &mut termios) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    let (mut master, mut slave) = (0, 0);
+    if unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            &termios,
+            &size,
+        )
+    } < 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    let (master, slave) = unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+    unsafe { libc::fcntl(master.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) };
+
+    let pid = std::process::id();
+    super::session::save(&session_in(pid), state)?;
+    let _ = std::fs::remove_file(super::session::session_dir().join(session_out(pid) + ".json"));
+    let mut command = std::process::Command::new(std::env::current_exe()?);
+    super::export_env(&mut command, state);
+    if state.restricted {
+        command.arg("--restricted");
+    }
+    command
+        .arg("--no-greeting")
+        .arg("--resume")
+        .arg(session_in(pid))
+        .arg("--transcript")
+        .arg(state.working_dir.join(path))
+        .current_dir(&state.working_dir)
+        .stdin(slave.try_clone()?)
+        .stdout(slave.try_clone()?)
+        .stderr(slave);
+    unsafe {
+        command.pre_exec(|| {
+            // a session of its own with the pseudo-terminal as its terminal, so job control works
+            if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut recorder = Recorder::new(file, cast, stdin, (size.ws_col, size.ws_row))?;
+    let mut child = command.spawn()?;
+    // the child has its copies, and reading the master only ends once they're all closed
+    drop(command);
+
+    let mut raw = termios;
+    unsafe {
+        libc::cfmakeraw(&mut raw);
+        libc::tcsetattr(0, libc::TCSANOW, &raw);
+    }
+    let relayed = relay(master.as_raw_fd(), &mut size, &mut recorder);
+    unsafe { libc::tcsetattr(0, libc::TCSANOW, &termios) };
+    let status = child.wait()?;
+    let _ = std::fs::remove_file(super::session::session_dir().join(session_in(pid) + ".json"));
+    relayed?;
+    recorder.finish()?;
+
+    let out = session_out(pid);
+    if super::session::session_dir()
+        .join(out.clone() + ".json")
+        .exists()
+    {
+        Ok(Ended::Stopped(out))
+    } else {
+        Ok(Ended::Exited(status.code().unwrap_or(1)))
+    }
+}
+
+/// Copy between the terminal and the pseudo-terminal until the shell on it is done, keeping the
+/// size of the one in step with the other.
+#[cfg(unix)]
+fn relay(master: i32, size: &mut libc::winsize, recorder: &mut Recorder) -> io::Result<()> {
+    let mut buf = [0u8; 4096];
+    loop {
+        let mut fds = [
+            libc::pollfd {
+                fd: 0,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: master,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        if unsafe { libc::poll(fds.as_mut_ptr(), 2, 100) } < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        let mut current = unsafe { std::mem::zeroed::<libc::winsize>() };
+        if unsafe { libc::ioctl(0, libc::TIOCGWINSZ, &mut current) } == 0
+            && (current.ws_col, current.ws_row) != (size.ws_col, size.ws_row)
+        {
+            *size = current;
+            unsafe { libc::ioctl(master, libc::TIOCSWINSZ, &current) };
+        }
+        if fds[0].revents & libc::POLLIN != 0 {
+            let read = unsafe { libc::read(0, buf.as_mut_ptr().cast(), buf.len()) };
+            if read > 0 {
+                let bytes = &buf[..read as usize];
+                write_all(master, bytes)?;
+                recorder.input(bytes)?;
+            }
+        }
+        if fds[1].revents & (libc::POLLIN | libc::POLLHUP | libc::POLLERR) != 0 {
+            let read = unsafe { libc::read(master, buf.as_mut_ptr().cast(), buf.len()) };
+            // the shell closed its end, which reads as EIO
+            if read <= 0 {
+                return Ok(());
+            }
+            let bytes = &buf[..read as usize];
+            write_all(1, bytes)?;
+            recorder.output(bytes)?;
+        }
+    }
+}
+
+/// Write all of some bytes to a file descriptor.
+#[cfg(unix)]
+fn write_all(fd: i32, mut bytes: &[u8]) -> io::Result<()> {
+    while !bytes.is_empty() {
+        let written = unsafe { libc::write(fd, bytes.as_ptr().cast(), bytes.len()) };
+        if written < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        bytes = &bytes[written as usize..];
+    }
+    Ok(())
+}
@@ -0,0 +1,113 @@
+//! Sizes and durations in human units
+//!
+//! `units` turns things like `1.5G` and `1h30m` into plain numbers of bytes and seconds that the
+//! arithmetic can work with, and back again. Sizes go up in powers of 1024, or of 1000 when the
+//! unit ends in B, like `MB`.
+
+/// The units of a size, smallest first, with how many bytes are in one.
+const SIZES: [(&str, f64); 5] = [
+    ("K", 1024.0),
+    ("M", 1048576.0),
+    ("G", 1073741824.0),
+    ("T", 1099511627776.0),
+    ("P", 1125899906842624.0),
+];
+
+/// The units of a duration, biggest first, with how many seconds are in one.
+const DURATIONS: [(&str, f64); 5] = [
+    ("w", 604800.0),
+    ("d", 86400.0),
+    ("h", 3600.0),
+    ("m", 60.0),
+    ("s", 1.0),
+];
+
+/// Split a number off the start of some text, returning it and the rest.
+fn split_number(text: &str) -> Option<(f64, &str)> {
+    let end = text
+        .find(|v: char| !(v.is_ascii_digit() || v == '.'))
+        .unwrap_or(text.len());
+    Some((text[..end].parse().ok()?, &text[end..]))
+}
+
+/// Parse a size like `512`, `1.5G`, `20MiB` or `3KB` into bytes.
+pub fn parse_size(text: &str) -> Option<u64> {
+    let (number, unit) = split_number(text.trim())?;
+    let unit = unit.trim_start();
+    let bytes = match unit {
+        "" | "B" => 1.0,
+        _ => {
+            let (prefix, base) = match unit.len() {
+                2 if unit.ends_with('B') => (&unit[..1], 1000.0),
+                3 if unit.ends_with("iB") => (&unit[..1], 1024.0),
+                _ => (unit, 1024.0),
+            };
+            let power = SIZES
+                .iter()
+                .position(|v| v.0.eq_ignore_ascii_case(prefix))?;
+            f64::powi(base, power as i32 + 1)
+        }
+    };
+    Some((number * bytes).round() as u64)
+}
+
+/// Show a number of bytes in the biggest unit it's at least one of, like `1.5G`.
+pub fn format_size(bytes: u64) -> String {
+    let Some((unit, size)) = SIZES.iter().rev().find(|v| bytes as f64 >= v.1) else {
+        return format!("{}B", bytes);
+    };
+    format!(
+        "{}{}",
+        trim((bytes as f64 / size * 10.0).round() / 10.0),
+        unit
+    )
+}
+
+/// Parse a duration like `90m`, `1.5h` or `1h30m` into seconds. A number without a unit is in
+/// seconds.
+pub fn parse_duration(text: &str) -> Option<f64> {
+    let mut rest = text.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    let mut total = 0.0;
+    while !rest.is_empty() {
+        let (number, after) = split_number(rest)?;
+        let unit = after
+            .find(|v: char| v.is_ascii_digit() || v == '.')
+            .unwrap_or(after.len());
+        let seconds = match &after[..unit] {
+            "" => 1.0,
+            unit => DURATIONS.iter().find(|v| v.0 == unit)?.1,
+        };
+        total += number * seconds;
+        rest = &after[unit..];
+    }
+    Some(total)
+}
+
+/// Show a number of seconds like `1h30m`.
+pub fn format_duration(seconds: f64) -> String {
+    let mut left = seconds.round() as u64;
+    if left == 0 {
+        return format!("{}s", trim(seconds));
+    }
+    let mut out = String::new();
+    for (unit, size) in DURATIONS {
+        let count = left / size as u64;
+        if count > 0 {
+            out += &format!("{}{}", count, unit);
+            left %= size as u64;
+        }
+    }
+    out
+}
+
+/// Show a number without a fraction if it doesn't have one.
+pub fn trim(number: f64) -> String {
+    if number.fract() == 0.0 {
+        format!("{}", number as i64)
+    } else {
+        format!("{}", number)
+    }
+}